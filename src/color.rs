@@ -82,6 +82,11 @@ impl Color {
         Color::new(self.r * scalar, self.g * scalar, self.b * scalar)
     }
 
+    /// Returns the perceived brightness of the color, with the Rec. 709 weights of the channels.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     /// Multiplies the current color with another color, component-wise.
     ///
     /// # Arguments
@@ -0,0 +1,19 @@
+//! The ray tracer behind the `rray` command, for programs that embed it.
+//!
+//! Scenes are usually built from a YAML scene file with `raytracer::scene_builder_yaml::build_scene`,
+//! which returns the camera and the scene. `Camera::render_with` renders them while reporting every
+//! finished tile and stopping when a `CancelToken` is cancelled, and `Camera::render_to_image` renders
+//! them to an image in memory.
+
+extern crate lazy_static;
+
+pub mod tuple;
+pub mod color;
+// The general matrix is only the reference `Matrix4` is tested against
+#[cfg(test)]
+mod matrix;
+pub mod matrix4;
+pub mod raytracer;
+#[cfg(feature = "simd")]
+mod simd;
+pub const EPSILON: f64 = 0.00001; // Small value used for floating-point comparisons
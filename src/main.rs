@@ -1,13 +1,17 @@
-extern crate lazy_static;
-
-use crate::raytracer::scene_builder_yaml::render_scene_from_file;
-use clap::Parser;
-
-mod tuple;
-mod color;
-mod matrix;
-mod raytracer;
-pub const EPSILON: f64 = 0.00001; // Small value used for floating-point comparisons
+use std::fs;
+use std::path::Path;
+use std::process;
+use rray::raytracer::scene_builder_yaml::{describe_scene_file, export_scene_file, load_scene_from_file, render_scene_from_file, RenderOptions};
+use rray::raytracer::progress::ProgressMode;
+use rray::raytracer::scene::DebugMode;
+use rray::raytracer::watch::watch_scene;
+use rray::raytracer::bench::run_benchmarks;
+use rray::raytracer::gpu::Backend;
+use rray::raytracer::canvas::DEFAULT_QUALITY;
+use rray::raytracer::animation::{parse_frame_range, FrameRange};
+use rray::raytracer::profile::start_profiling;
+use rray::raytracer::scene_export::{scene_to_yaml, write_scene_file};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 /// Validates that the provided value is less than or equal to the max allowed value.
 ///
@@ -43,12 +47,195 @@ fn validate_aa(s: &str) -> Result<usize, String> {
     validate_max_value(s, 5).and_then(|_| s.parse().map_err(|_| "expected a number".to_string()))
 }
 
+/// Parses a number of frames per second.
+///
+/// # Arguments
+///
+/// * `s` - A string slice that holds the number of frames per second.
+///
+/// # Returns
+///
+/// * `Ok(f64)` if it is a positive number,
+/// * `Err(String)` otherwise.
+fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err("must be a positive number".to_string()),
+    }
+}
+
+/// Parses a number of seconds.
+///
+/// # Arguments
+///
+/// * `s` - A string slice that holds the number of seconds.
+///
+/// # Returns
+///
+/// * `Ok(f64)` if it is a number that is not negative,
+/// * `Err(String)` otherwise.
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err("must be a number of seconds that is not negative".to_string()),
+    }
+}
+
+/// Parses a render region given as `x0,y0,x1,y1`.
+///
+/// # Arguments
+///
+/// * `s` - A string slice that holds the region, with the corners in pixels of the output image.
+///
+/// # Returns
+///
+/// * `Ok((x0, y0, x1, y1))` if the region is valid,
+/// * `Err(String)` if it does not have four numbers, or the second corner is not below and to the right of the first.
+fn parse_region(s: &str) -> Result<(usize, usize, usize, usize), String> {
+    let values: Vec<usize> = s.split(',')
+        .map(|v| v.trim().parse().map_err(|_| format!("{} is not a positive number", v)))
+        .collect::<Result<_, _>>()?;
+    match values[..] {
+        [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok((x0, y0, x1, y1)),
+        [_, _, _, _] => Err("x1 and y1 must be greater than x0 and y0".to_string()),
+        _ => Err("expected x0,y0,x1,y1".to_string()),
+    }
+}
+
 /// Simple raytracer application.
 ///
-/// Parses command line arguments to configure and render a scene described in a YAML file.
+/// Parses command line arguments and dispatches to one of the subcommands.
 #[derive(Parser, Debug)]
 #[command(version = "1.0", about = "A simple raytracer", long_about = None)]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// The subcommands supported by the raytracer.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Render a scene described in a YAML file
+    Render(Box<RenderArgs>),
+
+    /// Check that a scene file loads without rendering it
+    Validate {
+        /// Scene file in YAML format
+        scene: String,
+    },
+
+    /// Print a summary of a scene file: objects, triangles, bounds, lights, materials and the files it reads
+    Info {
+        /// Scene file in YAML format
+        scene: String,
+    },
+
+    /// Write the scene a file builds back out as YAML or JSON
+    Export {
+        /// Scene file in YAML format
+        scene: String,
+
+        /// File to write, its extension sets the format (yaml, yml or json), default is YAML on standard output
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Name of the camera to export, from the cameras section of the scene
+        #[arg(long)]
+        camera: Option<String>,
+
+        /// Frame of the animation to export
+        #[arg(long, default_value_t = 0)]
+        frame: usize,
+    },
+
+    /// List the example scene files
+    Examples {
+        /// Directory containing the examples, default is examples
+        #[arg(short, long, default_value = "examples")]
+        dir: String,
+    },
+
+    /// Render the built-in benchmark scenes and report timings and rays per second
+    Bench {
+        /// Number of render threads, default is one per core
+        #[arg(short, long, default_value_t = 0)]
+        threads: usize,
+    },
+}
+
+/// The ways rendering progress can be reported on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ProgressArg {
+    /// An interactive progress bar
+    Bar,
+    /// Newline-delimited JSON events on standard output
+    Json,
+    /// No progress output
+    None,
+}
+
+/// The debug views that can be selected on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DebugArg {
+    /// Surface normals as colors
+    Normals,
+    /// Texture coordinates, u in red and v in green
+    Uv,
+    /// Distance from the camera, white is close
+    Depth,
+    /// A different color for every object
+    ObjectId,
+}
+
+/// The renderers that can be selected on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BackendArg {
+    /// The full ray tracer, on the processor
+    Cpu,
+    /// Experimental compute shader renderer for simple scenes, needs the gpu feature
+    Gpu,
+}
+
+/// Quality presets that trade render time for image quality.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PresetArg {
+    /// Quarter size, no anti-aliasing, 2 bounces and 2x2 area light samples
+    Draft,
+    /// Half size, 2x anti-aliasing, 4 bounces and 4x4 area light samples
+    Medium,
+    /// Full size, 3x anti-aliasing, 5 bounces and the area light samples of the scene
+    Final,
+}
+
+/// The settings a quality preset stands for.
+///
+/// # Fields
+///
+/// * `scale` - The factor the image size is multiplied by.
+/// * `aa` - The anti-aliasing level.
+/// * `max_depth` - The number of times rays are followed through reflections and refractions.
+/// * `shadow_samples` - The number of samples along each edge of area lights, or `None` to keep the scene's.
+struct Preset {
+    scale: f64,
+    aa: usize,
+    max_depth: usize,
+    shadow_samples: Option<usize>,
+}
+
+impl PresetArg {
+    /// Returns the settings of the preset.
+    fn settings(self) -> Preset {
+        match self {
+            PresetArg::Draft => Preset { scale: 0.25, aa: 1, max_depth: 2, shadow_samples: Some(2) },
+            PresetArg::Medium => Preset { scale: 0.5, aa: 2, max_depth: 4, shadow_samples: Some(4) },
+            PresetArg::Final => Preset { scale: 1.0, aa: 3, max_depth: 5, shadow_samples: None },
+        }
+    }
+}
+
+/// Arguments of the `render` subcommand.
+#[derive(Args, Debug)]
+struct RenderArgs {
     /// Width of the generated image, default is 800
     #[arg(short = 'W', long, default_value_t = 800)]
     width: usize,
@@ -61,18 +248,256 @@ struct Args {
     #[arg(short, long)]
     scene: String,
 
-    /// Name of the output file, default is output.png
+    /// Name of the output file, its extension sets the format (png, jpg, webp, tiff, bmp, exr, ...) and tokens like {scene}, {width}, {height}, {frame:04} and {date} are filled in, default is output.png
     #[arg(short, long, default_value = "output.png")]
     output: String,
 
     /// Anti-aliasing level (default 1) (max 5)
-    #[arg(short, long, default_value_t = 1, value_parser = validate_aa)]
-    aa: usize,
+    #[arg(short, long, value_parser = validate_aa)]
+    aa: Option<usize>,
+
+    /// Factor the image size is multiplied by, for example 0.25 for quick previews
+    #[arg(long)]
+    scale: Option<f64>,
+
+    /// Quality preset setting the scale, anti-aliasing, bounces and area light samples at once;
+    /// --scale and --aa override it
+    #[arg(long, value_enum)]
+    preset: Option<PresetArg>,
+
+    /// Number of render threads, default is one per core
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+
+    /// Seed for noise and random sampling, makes renders reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Show the image in a window while rendering, press Esc to stop early and save
+    #[arg(long)]
+    window: bool,
+
+    /// Don't show rendering progress, same as --progress none
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// How to report rendering progress, default is bar
+    #[arg(long, value_enum, default_value_t = ProgressArg::Bar)]
+    progress: ProgressArg,
+
+    /// Render a debug view of the scene instead of shading it
+    #[arg(long, value_enum)]
+    debug: Option<DebugArg>,
+
+    /// Draw the edges of triangle meshes over the image, optionally with the given width (default 0.02)
+    #[arg(long, num_args = 0..=1, default_missing_value = "0.02", value_name = "WIDTH")]
+    wireframe: Option<f64>,
+
+    /// Quality of lossy output formats like JPEG and AVIF, from 1 to 100
+    #[arg(long, default_value_t = DEFAULT_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+
+    /// Also write a grayscale depth map (white is close) to this file
+    #[arg(long, value_name = "FILE")]
+    depth: Option<String>,
+
+    /// Write the depth map to the output file instead of the rendered image
+    #[arg(long)]
+    depth_only: bool,
+
+    /// Distance that is black in the depth map, default is the farthest hit
+    #[arg(long, value_name = "DISTANCE")]
+    depth_far: Option<f64>,
+
+    /// Only render the pixels from x0,y0 up to x1,y1, leaving the rest of the image black
+    #[arg(long, value_name = "X0,Y0,X1,Y1", value_parser = parse_region)]
+    region: Option<(usize, usize, usize, usize)>,
+
+    /// Name of the camera to render with, from the cameras section of the scene
+    #[arg(long, value_name = "NAME")]
+    camera: Option<String>,
+
+    /// Stop starting new tiles after this many seconds and write the image, with the missing tiles in magenta
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    max_seconds: Option<f64>,
+
+    /// Renderer to use; the GPU renderer falls back to the CPU for scenes it does not support
+    #[arg(long, value_enum, default_value_t = BackendArg::Cpu)]
+    backend: BackendArg,
+
+    /// Smooth the noise of soft shadows and other sampled effects out of the image, guided by the colors and normals of the surfaces
+    #[arg(long)]
+    denoise: bool,
+
+    /// Also write a heatmap of how noisy each pixel still is to this file, from the spread of its --aa samples
+    #[arg(long, value_name = "FILE")]
+    variance: Option<String>,
+
+    /// Render these frames of the animation, like 1..240 (both included) or a single frame, each to its own file
+    #[arg(long, value_name = "FIRST..LAST", value_parser = parse_frame_range)]
+    frames: Option<FrameRange>,
+
+    /// Frames per second of the animation, overriding the fps of the scene (default 24)
+    #[arg(long, value_parser = parse_fps)]
+    fps: Option<f64>,
+
+    /// Re-render a preview whenever the scene or the files it uses change
+    #[arg(long)]
+    watch: bool,
+
+    /// Factor the image size is divided by for watch mode previews (default 4)
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    preview_scale: u16,
+
+    /// Write a chrome trace of where the time is spent to this file, needs the profile feature
+    #[arg(long, value_name = "FILE")]
+    profile: Option<String>,
+
+    /// Also record the intersection and shading of every ray in the profile, which makes it much larger
+    #[arg(long, requires = "profile")]
+    profile_shading: bool,
+}
+
+impl RenderArgs {
+    /// Collects the render settings given on the command line.
+    fn render_options(&self) -> RenderOptions {
+        let preset = self.preset.map(PresetArg::settings);
+        let scale = self.scale.or(preset.as_ref().map(|p| p.scale)).unwrap_or(1.0);
+        RenderOptions {
+            width: ((self.width as f64 * scale).round() as usize).max(1),
+            height: ((self.height as f64 * scale).round() as usize).max(1),
+            aa: self.aa.or(preset.as_ref().map(|p| p.aa)).unwrap_or(1),
+            threads: self.threads,
+            seed: self.seed,
+            window: self.window,
+            progress: match self.progress {
+                _ if self.quiet => ProgressMode::Quiet,
+                ProgressArg::Bar => ProgressMode::Bar,
+                ProgressArg::Json => ProgressMode::Json,
+                ProgressArg::None => ProgressMode::Quiet,
+            },
+            debug: self.debug.map(|debug| match debug {
+                DebugArg::Normals => DebugMode::Normals,
+                DebugArg::Uv => DebugMode::Uv,
+                DebugArg::Depth => DebugMode::Depth,
+                DebugArg::ObjectId => DebugMode::ObjectId,
+            }),
+            wireframe: self.wireframe,
+            depth: self.depth.clone(),
+            depth_only: self.depth_only,
+            depth_far: self.depth_far,
+            camera: self.camera.clone(),
+            region: self.region,
+            max_depth: preset.as_ref().map_or(5, |p| p.max_depth),
+            shadow_samples: preset.and_then(|p| p.shadow_samples),
+            max_seconds: self.max_seconds,
+            backend: match self.backend {
+                BackendArg::Cpu => Backend::Cpu,
+                BackendArg::Gpu => Backend::Gpu,
+            },
+            quality: self.quality,
+            frame: self.frames.map_or(0, |frames| frames.first),
+            frames: self.frames,
+            fps: self.fps,
+            denoise: self.denoise,
+            variance: self.variance.clone(),
+        }
+    }
+}
+
+/// Loads a scene file without rendering it and reports whether it is valid.
+///
+/// Scene loading reports problems by panicking, so the panic is caught here, its message printed,
+/// and turned into a non-zero exit status.
+///
+/// # Arguments
+///
+/// * `scene` - The path to the YAML scene file.
+fn validate_scene(scene: &str) {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        eprintln!("error: {}", message);
+    }));
+    let result = std::panic::catch_unwind(|| {
+        load_scene_from_file(scene, 1, 1);
+    });
+    match result {
+        Ok(_) => println!("{}: ok", scene),
+        Err(_) => {
+            eprintln!("{}: invalid", scene);
+            process::exit(1);
+        }
+    }
+}
+
+/// Recursively collects the YAML files found below a directory.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to search.
+/// * `files` - The vector the paths are appended to.
+fn find_scene_files(dir: &Path, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_scene_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml") {
+            files.push(path.display().to_string());
+        }
+    }
+}
+
+/// Prints the example scene files found in a directory.
+///
+/// # Arguments
+///
+/// * `dir` - The directory containing the examples.
+fn list_examples(dir: &str) {
+    let mut files = vec![];
+    find_scene_files(Path::new(dir), &mut files);
+    if files.is_empty() {
+        eprintln!("No example scenes found in {}", dir);
+        process::exit(1);
+    }
+    files.sort();
+    for file in files {
+        println!("{}", file);
+    }
 }
 
 fn main() {
-   let args = Args::parse();
+    let cli = Cli::parse();
 
-    // Render the scene based on the provided command line arguments
-    render_scene_from_file(&args.scene, args.width, args.height, &args.output, args.aa);
-}
\ No newline at end of file
+    let _profiler = match &cli.command {
+        Command::Render(args) => args.profile.as_deref().map(|file| start_profiling(file, args.profile_shading)),
+        _ => None,
+    };
+
+    match cli.command {
+        // Render the scene based on the provided command line arguments
+        Command::Render(args) if args.watch => {
+            watch_scene(&args.scene, &args.output, &args.render_options(), args.preview_scale as usize)
+        }
+        Command::Render(args) => render_scene_from_file(&args.scene, &args.output, &args.render_options()),
+        Command::Validate { scene } => validate_scene(&scene),
+        Command::Info { scene } => print!("{}", describe_scene_file(&scene)),
+        Command::Export { scene, output, camera, frame } => {
+            let doc = export_scene_file(&scene, camera.as_deref(), frame);
+            match output {
+                Some(file) => write_scene_file(&file, &doc),
+                None => print!("{}", scene_to_yaml(&doc)),
+            }
+        }
+        Command::Examples { dir } => list_examples(&dir),
+        Command::Bench { threads } => {
+            run_benchmarks(threads);
+        }
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use crate::EPSILON;
 use crate::tuple::Tuple;
 use std::ops::Mul;
@@ -9,6 +7,8 @@ use std::sync::Mutex;
 ///
 /// This structure holds the dimensions of the matrix (rows and columns) and the matrix data itself.
 /// It also caches the inverse of the matrix for efficiency, using a thread-safe `Mutex`.
+/// The ray tracer itself transforms points and vectors with the fixed size `Matrix4`; this type is only
+/// compiled for tests, as the straightforward implementation `Matrix4` is checked against.
 ///
 /// # Fields
 ///
@@ -601,6 +601,26 @@ impl Matrix {
         let translation = Matrix::translate(-from.x, -from.y, -from.z);
         orientation.multiply(&translation)
     }
+
+    /// Creates a view transformation matrix for a camera that is also rolled around its viewing direction.
+    ///
+    /// This is `view_transform` followed by a rotation around the camera's z axis, which tilts the camera
+    /// for a Dutch angle shot without having to work out a tilted up vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - A `Tuple` representing the camera's position in world space.
+    /// * `to` - A `Tuple` representing the point in world space the camera is looking at.
+    /// * `up` - A `Tuple` representing the up direction for the camera before it is rolled.
+    /// * `roll` - The roll angle in radians. Positive angles tilt the top of the camera to its right, which
+    ///   makes the scene turn counterclockwise in the image.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Matrix` instance representing the view transformation.
+    pub fn view_transform_rolled(from: Tuple, to: Tuple, up: Tuple, roll: f64) -> Matrix {
+        Matrix::rotate_z(-roll).multiply(&Matrix::view_transform(from, to, up))
+    }
 }
 
 /// Implements the multiplication operator for `Matrix` structs.
@@ -1029,6 +1049,18 @@ mod tests {
         assert_eq!(result, Tuple::point(2.0, 3.0, 7.0));
     }
 
+    #[test]
+    fn rolling_the_view_transform() {
+        let from = Tuple::point(0.0, 0.0, 0.0);
+        let to = Tuple::point(0.0, 0.0, -1.0);
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+        let t = Matrix::view_transform_rolled(from, to, up, 0.0);
+        assert_eq!(t, Matrix::identity(4));
+        // Rolled a quarter turn to the right, what was on the camera's right (-x) is now above it
+        let t = Matrix::view_transform_rolled(from, to, up, std::f64::consts::PI / 2.0);
+        assert_eq!(t.multiply_tuple(&Tuple::vector(-1.0, 0.0, 0.0)), Tuple::vector(0.0, 1.0, 0.0));
+    }
+
     #[test]
     fn test_matrix_view_transform() {
         let from = Tuple::point(0.0, 0.0, 0.0);
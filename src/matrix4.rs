@@ -0,0 +1,330 @@
+// This module defines the `Matrix4` struct, the 4x4 transformation matrix used by the ray tracer. Unlike the
+// general `Matrix`, it lives on the stack, is `Copy`, and has no cache to lock, which keeps it cheap in the
+// inner loops.
+
+use crate::EPSILON;
+use crate::tuple::Tuple;
+#[cfg(feature = "simd")]
+use crate::simd;
+use std::ops::Mul;
+
+/// A 4x4 matrix that transforms points and vectors in 3D space.
+///
+/// The 16 elements are stored in row-major order.
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4(pub [f64; 16]);
+
+impl PartialEq for Matrix4 {
+    /// Compares two matrices element by element, within `EPSILON`.
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other)
+    }
+}
+
+impl Matrix4 {
+    /// Creates the identity matrix, which leaves points and vectors unchanged.
+    pub fn identity() -> Matrix4 {
+        Matrix4([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ])
+    }
+
+    /// Returns the element at the given row and column.
+    #[inline]
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.0[row * 4 + col]
+    }
+
+    /// Sets the element at the given row and column.
+    #[inline]
+    pub fn set(&mut self, row: usize, col: usize, value: f64) {
+        self.0[row * 4 + col] = value;
+    }
+
+    /// Checks if two matrices are equal, allowing each element to differ by at most `EPSILON`.
+    pub fn equals(&self, other: &Matrix4) -> bool {
+        self.0.iter().zip(other.0.iter()).all(|(a, b)| (a - b).abs() <= EPSILON)
+    }
+
+    /// Multiplies this matrix by another matrix.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The matrix on the right of the product.
+    ///
+    /// # Returns
+    ///
+    /// The product `self * other`, which applies `other` first when transforming a tuple.
+    #[cfg(not(feature = "simd"))]
+    pub fn multiply(&self, other: &Matrix4) -> Matrix4 {
+        let mut result = [0.0; 16];
+        for i in 0..4 {
+            for j in 0..4 {
+                result[i * 4 + j] = self.get(i, 0) * other.get(0, j)
+                    + self.get(i, 1) * other.get(1, j)
+                    + self.get(i, 2) * other.get(2, j)
+                    + self.get(i, 3) * other.get(3, j);
+            }
+        }
+        Matrix4(result)
+    }
+
+    /// Multiplies this matrix by another matrix, with SIMD instructions.
+    #[cfg(feature = "simd")]
+    pub fn multiply(&self, other: &Matrix4) -> Matrix4 {
+        Matrix4(simd::multiply(&self.0, &other.0))
+    }
+
+    /// Transforms a point or vector by this matrix.
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn multiply_tuple(&self, t: &Tuple) -> Tuple {
+        let m = &self.0;
+        Tuple::new(
+            m[0] * t.x + m[1] * t.y + m[2] * t.z + m[3] * t.w,
+            m[4] * t.x + m[5] * t.y + m[6] * t.z + m[7] * t.w,
+            m[8] * t.x + m[9] * t.y + m[10] * t.z + m[11] * t.w,
+            m[12] * t.x + m[13] * t.y + m[14] * t.z + m[15] * t.w,
+        )
+    }
+
+    /// Transforms a point or vector by this matrix, with SIMD instructions.
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub fn multiply_tuple(&self, t: &Tuple) -> Tuple {
+        simd::multiply_tuple(&self.0, t)
+    }
+
+    /// Returns the matrix with its rows and columns swapped.
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = [0.0; 16];
+        for i in 0..4 {
+            for j in 0..4 {
+                result[j * 4 + i] = self.0[i * 4 + j];
+            }
+        }
+        Matrix4(result)
+    }
+
+    /// Calculates the determinant of the matrix.
+    #[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+    pub fn determinant(&self) -> f64 {
+        let (s, c) = self.sub_determinants();
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]
+    }
+
+    /// Calculates the determinants of the 2x2 submatrices of the top two rows (`s`) and the bottom two rows (`c`),
+    /// from which the determinant and the cofactors of the matrix are built.
+    fn sub_determinants(&self) -> ([f64; 6], [f64; 6]) {
+        let m = &self.0;
+        let s = [
+            m[0] * m[5] - m[4] * m[1],
+            m[0] * m[6] - m[4] * m[2],
+            m[0] * m[7] - m[4] * m[3],
+            m[1] * m[6] - m[5] * m[2],
+            m[1] * m[7] - m[5] * m[3],
+            m[2] * m[7] - m[6] * m[3],
+        ];
+        let c = [
+            m[8] * m[13] - m[12] * m[9],
+            m[8] * m[14] - m[12] * m[10],
+            m[8] * m[15] - m[12] * m[11],
+            m[9] * m[14] - m[13] * m[10],
+            m[9] * m[15] - m[13] * m[11],
+            m[10] * m[15] - m[14] * m[11],
+        ];
+        (s, c)
+    }
+
+    /// Calculates the inverse of the matrix.
+    ///
+    /// The inverse is calculated from the cofactors, expanded from the determinants of the 2x2 submatrices
+    /// so that no intermediate matrices are needed. The matrix must be invertible; if its determinant is
+    /// zero the elements of the result are infinite or NaN.
+    ///
+    /// # Returns
+    ///
+    /// The matrix that undoes the transformation of this matrix.
+    pub fn inverse(&self) -> Matrix4 {
+        let m = &self.0;
+        let (s, c) = self.sub_determinants();
+        let inv_det = 1.0 / (s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]);
+        Matrix4([
+            (m[5] * c[5] - m[6] * c[4] + m[7] * c[3]) * inv_det,
+            (-m[1] * c[5] + m[2] * c[4] - m[3] * c[3]) * inv_det,
+            (m[13] * s[5] - m[14] * s[4] + m[15] * s[3]) * inv_det,
+            (-m[9] * s[5] + m[10] * s[4] - m[11] * s[3]) * inv_det,
+            (-m[4] * c[5] + m[6] * c[2] - m[7] * c[1]) * inv_det,
+            (m[0] * c[5] - m[2] * c[2] + m[3] * c[1]) * inv_det,
+            (-m[12] * s[5] + m[14] * s[2] - m[15] * s[1]) * inv_det,
+            (m[8] * s[5] - m[10] * s[2] + m[11] * s[1]) * inv_det,
+            (m[4] * c[4] - m[5] * c[2] + m[7] * c[0]) * inv_det,
+            (-m[0] * c[4] + m[1] * c[2] - m[3] * c[0]) * inv_det,
+            (m[12] * s[4] - m[13] * s[2] + m[15] * s[0]) * inv_det,
+            (-m[8] * s[4] + m[9] * s[2] - m[11] * s[0]) * inv_det,
+            (-m[4] * c[3] + m[5] * c[1] - m[6] * c[0]) * inv_det,
+            (m[0] * c[3] - m[1] * c[1] + m[2] * c[0]) * inv_det,
+            (-m[12] * s[3] + m[13] * s[1] - m[14] * s[0]) * inv_det,
+            (m[8] * s[3] - m[9] * s[1] + m[10] * s[0]) * inv_det,
+        ])
+    }
+
+    /// Creates a translation matrix that moves points by the given x, y, and z distances.
+    pub fn translate(x: f64, y: f64, z: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(0, 3, x);
+        m.set(1, 3, y);
+        m.set(2, 3, z);
+        m
+    }
+
+    /// Creates a scaling matrix that scales by the given x, y, and z factors.
+    pub fn scale(x: f64, y: f64, z: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(0, 0, x);
+        m.set(1, 1, y);
+        m.set(2, 2, z);
+        m
+    }
+
+    /// Creates a rotation matrix for a rotation of `r` radians around the x-axis.
+    pub fn rotate_x(r: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(1, 1, r.cos());
+        m.set(1, 2, -r.sin());
+        m.set(2, 1, r.sin());
+        m.set(2, 2, r.cos());
+        m
+    }
+
+    /// Creates a rotation matrix for a rotation of `r` radians around the y-axis.
+    pub fn rotate_y(r: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(0, 0, r.cos());
+        m.set(0, 2, r.sin());
+        m.set(2, 0, -r.sin());
+        m.set(2, 2, r.cos());
+        m
+    }
+
+    /// Creates a rotation matrix for a rotation of `r` radians around the z-axis.
+    pub fn rotate_z(r: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(0, 0, r.cos());
+        m.set(0, 1, -r.sin());
+        m.set(1, 0, r.sin());
+        m.set(1, 1, r.cos());
+        m
+    }
+
+    /// Creates a shearing matrix, which moves each coordinate in proportion to the other two.
+    ///
+    /// # Arguments
+    ///
+    /// * `xy` - The factor by which coordinates in the x direction are displaced in proportion to their y coordinate.
+    /// * `xz` - The factor by which coordinates in the x direction are displaced in proportion to their z coordinate.
+    /// * `yx` - The factor by which coordinates in the y direction are displaced in proportion to their x coordinate.
+    /// * `yz` - The factor by which coordinates in the y direction are displaced in proportion to their z coordinate.
+    /// * `zx` - The factor by which coordinates in the z direction are displaced in proportion to their x coordinate.
+    /// * `zy` - The factor by which coordinates in the z direction are displaced in proportion to their y coordinate.
+    pub fn shear(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Matrix4 {
+        let mut m = Matrix4::identity();
+        m.set(0, 1, xy);
+        m.set(0, 2, xz);
+        m.set(1, 0, yx);
+        m.set(1, 2, yz);
+        m.set(2, 0, zx);
+        m.set(2, 1, zy);
+        m
+    }
+
+    /// Constructs the view transformation of a camera, which transforms world space into camera space.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The camera's position in world space.
+    /// * `to` - The point in world space the camera is looking at.
+    /// * `up` - The up direction for the camera, typically (0, 1, 0) for an upright camera.
+    pub fn view_transform(from: Tuple, to: Tuple, up: Tuple) -> Matrix4 {
+        let forward = (to - from).normalize();
+        let left = forward.cross(&up.normalize());
+        let true_up = left.cross(&forward);
+        let orientation = Matrix4([
+            left.x, left.y, left.z, 0.0,
+            true_up.x, true_up.y, true_up.z, 0.0,
+            -forward.x, -forward.y, -forward.z, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]);
+        orientation.multiply(&Matrix4::translate(-from.x, -from.y, -from.z))
+    }
+
+    /// Constructs the view transformation of a camera that is also rolled around its viewing direction.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The camera's position in world space.
+    /// * `to` - The point in world space the camera is looking at.
+    /// * `up` - The up direction for the camera before it is rolled.
+    /// * `roll` - The roll angle in radians. Positive angles tilt the top of the camera to its right.
+    pub fn view_transform_rolled(from: Tuple, to: Tuple, up: Tuple, roll: f64) -> Matrix4 {
+        Matrix4::rotate_z(-roll).multiply(&Matrix4::view_transform(from, to, up))
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        self.multiply(&other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matrix4;
+    use crate::matrix::Matrix;
+    use crate::tuple::Tuple;
+
+    /// Converts a `Matrix4` to the general `Matrix`, to check the results against it.
+    fn to_matrix(m: &Matrix4) -> Matrix {
+        let mut result = Matrix::new(4, 4);
+        result.data.copy_from_slice(&m.0);
+        result
+    }
+
+    #[test]
+    fn transformations_match_the_general_matrix() {
+        let m = Matrix4::translate(1.0, -2.0, 3.0)
+            * Matrix4::rotate_x(0.3)
+            * Matrix4::rotate_y(-1.1)
+            * Matrix4::rotate_z(2.0)
+            * Matrix4::shear(0.1, 0.2, 0.3, 0.4, 0.5, 0.6)
+            * Matrix4::scale(2.0, 0.5, 3.0);
+        let general = Matrix::translate(1.0, -2.0, 3.0)
+            * Matrix::rotate_x(0.3)
+            * Matrix::rotate_y(-1.1)
+            * Matrix::rotate_z(2.0)
+            * Matrix::shear(0.1, 0.2, 0.3, 0.4, 0.5, 0.6)
+            * Matrix::scale(2.0, 0.5, 3.0);
+        assert_eq!(to_matrix(&m), general);
+        assert!((m.determinant() - general.determinant()).abs() < 1e-9);
+        assert_eq!(to_matrix(&m.inverse()), general.inverse());
+        assert_eq!(to_matrix(&m.transpose()), general.transpose());
+
+        let p = Tuple::point(1.0, 2.0, 3.0);
+        assert_eq!(m.multiply_tuple(&p), general.multiply_tuple(&p));
+        assert_eq!(m * m.inverse(), Matrix4::identity());
+    }
+
+    #[test]
+    fn view_transform_matches_the_general_matrix() {
+        let from = Tuple::point(1.0, 3.0, 2.0);
+        let to = Tuple::point(4.0, -2.0, 8.0);
+        let up = Tuple::vector(1.0, 1.0, 0.0);
+        assert_eq!(to_matrix(&Matrix4::view_transform(from, to, up)), Matrix::view_transform(from, to, up));
+        assert_eq!(to_matrix(&Matrix4::view_transform_rolled(from, to, up, 0.4)), Matrix::view_transform_rolled(from, to, up, 0.4));
+    }
+}
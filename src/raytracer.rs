@@ -17,15 +17,51 @@
 /// - `load_obj`: Utilities for loading object models from .obj files.
 /// - `scene_builder_yaml`: Provides functionality for building scenes from YAML configuration files.
 /// - `canvas`: A module for creating and manipulating the canvas on which scenes are rendered.
+/// - `watch`: Re-renders a scene preview whenever its files change.
+/// - `sampling`: Per-thread random numbers that can be seeded for reproducible renders, and low-discrepancy samplers.
+/// - `preview_window`: Shows the image in a window while it is being rendered (`preview` feature).
+/// - `progress`: Reports rendering progress as a progress bar or as JSON events.
+/// - `environment`: An equirectangular image surrounding the scene, used as background and as a light.
+/// - `volume`: Regions of fog and smoke that rays are marched through.
+/// - `bench`: Built-in benchmark scenes for measuring rendering performance.
+/// - `gpu`: An experimental renderer that traces camera and shadow rays on the GPU (`gpu` feature).
+/// - `animation`: Frame ranges and the time of the frame being built, for animated scenes.
+/// - `output_template`: Expands tokens like `{scene}` and `{frame:04}` in output file names.
+/// - `profile`: Spans around the phases of a render, written to a chrome trace (`profile` feature).
+/// - `scene_export`: Writes built scenes back to YAML or JSON scene files.
+/// - `generate`: Lays out many copies of an object as grids, random scatters, rings and spirals.
+/// - `sweep`: Tubes swept along helices and torus knots, made of triangles.
+/// - `denoise`: Smooths the noise of sampled effects out of rendered images, guided by surface colors and normals.
+/// - `sky`: An analytic model of a clear sky with a sun, used as an environment.
+/// - `film`: Per-pixel sample counts, means and variances, and heatmaps of where an image is still noisy.
+/// - `json`: Quoting of strings in the JSON written by scene exports and progress events.
 
 mod object;
 mod ray;
 mod intersection;
 mod computations;
 mod material;
-mod scene;
+pub mod scene;
 mod light;
-mod camera;
+pub mod camera;
 mod load_obj;
-pub(crate) mod scene_builder_yaml;
-mod canvas;
\ No newline at end of file
+pub mod scene_builder_yaml;
+pub mod canvas;
+pub mod watch;
+mod sampling;
+mod preview_window;
+pub mod progress;
+mod environment;
+mod volume;
+pub mod bench;
+pub mod gpu;
+pub mod profile;
+mod output_template;
+pub mod animation;
+pub mod scene_export;
+pub mod generate;
+mod sweep;
+mod denoise;
+pub mod film;
+mod sky;
+mod json;
@@ -0,0 +1,361 @@
+use std::ops::{Add, Mul, Sub};
+use std::cell::Cell;
+use yaml_rust2::Yaml;
+use crate::tuple::Tuple;
+
+/// The number of frames per second of animations that do not set one.
+pub const DEFAULT_FPS: f64 = 24.0;
+
+// The time, in seconds, of the frame whose scene is being built, and the frames per second of the animation.
+// Scenes are built on one thread, so each thread keeps its own, like the unit of the scene.
+thread_local! {
+    static SCENE_FRAME: Cell<(f64, f64)> = const { Cell::new((0.0, DEFAULT_FPS)) };
+}
+
+/// Sets the frame whose scene is about to be built on this thread.
+///
+/// # Arguments
+///
+/// * `frame` - The number of the frame.
+/// * `fps` - The number of frames per second of the animation.
+pub fn set_scene_frame(frame: usize, fps: f64) {
+    SCENE_FRAME.with(|scene_frame| scene_frame.set((frame_time(frame, fps), fps)));
+}
+
+/// Returns the time of the frame whose scene is being built, which animated values are evaluated at.
+pub fn scene_time() -> f64 {
+    SCENE_FRAME.with(Cell::get).0
+}
+
+/// Returns the number of frames per second of the animation whose scene is being built, which keyframes
+/// given by frame number are converted to times with.
+pub fn scene_frame_rate() -> f64 {
+    SCENE_FRAME.with(Cell::get).1
+}
+
+/// Returns the time of a frame: frame 0 is at 0 seconds, and each frame is `1 / fps` seconds after the one before.
+///
+/// # Arguments
+///
+/// * `frame` - The number of the frame.
+/// * `fps` - The number of frames per second.
+pub fn frame_time(frame: usize, fps: f64) -> f64 {
+    frame as f64 / fps
+}
+
+/// Returns the number of frames per second set in the `animation` section of a scene, or `DEFAULT_FPS`.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+///
+/// # Panics
+///
+/// Panics if the number of frames per second is not a positive number.
+pub fn scene_fps(doc: &Yaml) -> f64 {
+    let fps = match &doc["animation"]["fps"] {
+        Yaml::Integer(fps) => *fps as f64,
+        Yaml::Real(fps) => fps.parse().unwrap_or(0.0),
+        Yaml::BadValue => DEFAULT_FPS,
+        _ => 0.0,
+    };
+    if fps <= 0.0 {
+        panic!("animation fps must be a positive number");
+    }
+    fps
+}
+
+/// A range of frames to render, including both the first and the last frame.
+///
+/// # Fields
+///
+/// * `first` - The first frame to render.
+/// * `last` - The last frame to render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl FrameRange {
+    /// Returns the number of frames in the range.
+    pub fn count(&self) -> usize {
+        self.last - self.first + 1
+    }
+}
+
+/// Parses a range of frames given as `first..last`, including both ends, or as a single frame.
+///
+/// # Arguments
+///
+/// * `s` - The range, for example `1..240` or `100`.
+///
+/// # Returns
+///
+/// * `Ok(FrameRange)` if the range is valid,
+/// * `Err(String)` if the frames are not numbers or the last frame comes before the first.
+pub fn parse_frame_range(s: &str) -> Result<FrameRange, String> {
+    let parse = |frame: &str| frame.trim().parse::<usize>().map_err(|_| format!("{} is not a frame number", frame.trim()));
+    let (first, last) = match s.split_once("..") {
+        Some((first, last)) => (parse(first)?, parse(last.strip_prefix('=').unwrap_or(last))?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if last < first {
+        return Err(format!("the last frame {} comes before the first frame {}", last, first));
+    }
+    Ok(FrameRange { first, last })
+}
+
+/// Makes sure every frame of an animation is written to its own file.
+///
+/// When the output file name has no `{frame}` token, the frame number is added before the extension,
+/// as in `output_0012.png`. This is also done for a single frame, so that re-rendering part of an
+/// animation replaces the files of those frames.
+///
+/// # Arguments
+///
+/// * `template` - The output file name given by the user.
+///
+/// # Returns
+///
+/// The output file name to use for the frames.
+pub fn frame_file_template(template: &str) -> String {
+    if template.contains("{frame") {
+        return template.to_string();
+    }
+    let name_start = template.rfind(['/', '\\']).map_or(0, |i| i + 1);
+    match template[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}_{{frame:04}}{}", &template[..dot], &template[dot..])
+        }
+        _ => format!("{}_{{frame:04}}", template),
+    }
+}
+
+/// How values are interpolated between keyframes.
+///
+/// # Variants
+///
+/// * `Linear` - Straight from one keyframe to the next, which makes the motion change direction abruptly at the keyframes.
+/// * `CatmullRom` - A Catmull-Rom spline through the keyframes, which changes direction and speed smoothly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+/// The position and orientation of the camera at a point in time.
+///
+/// # Fields
+///
+/// * `time` - The time of the keyframe in seconds.
+/// * `from` - The position of the camera.
+/// * `to` - The point the camera looks at.
+/// * `up` - The direction that is up for the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKey {
+    pub time: f64,
+    pub from: Tuple,
+    pub to: Tuple,
+    pub up: Tuple,
+}
+
+/// A path the camera follows through an animation, given by keyframes.
+///
+/// Before the first keyframe the camera stays at the first one, and after the last keyframe at the last one.
+///
+/// # Fields
+///
+/// * `keys` - The keyframes, sorted by time.
+/// * `interpolation` - How the camera moves between the keyframes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraPath {
+    pub keys: Vec<CameraKey>,
+    pub interpolation: Interpolation,
+}
+
+impl CameraPath {
+    /// Creates a camera path from keyframes.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keyframes, in any order.
+    /// * `interpolation` - How the camera moves between the keyframes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no keyframes, or two of them have the same time.
+    pub fn new(mut keys: Vec<CameraKey>, interpolation: Interpolation) -> CameraPath {
+        if keys.is_empty() {
+            panic!("a camera path needs at least one keyframe");
+        }
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if keys.windows(2).any(|pair| pair[0].time == pair[1].time) {
+            panic!("two camera keyframes have the same time");
+        }
+        CameraPath { keys, interpolation }
+    }
+
+    /// Returns the position, the point looked at and the up direction of the camera at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time in seconds.
+    ///
+    /// # Returns
+    ///
+    /// The camera's `(from, to, up)` at that time.
+    pub fn at(&self, time: f64) -> (Tuple, Tuple, Tuple) {
+        let track = |get: fn(&CameraKey) -> Tuple| Track {
+            keys: self.keys.iter().map(|key| (key.time, get(key))).collect(),
+            interpolation: self.interpolation,
+        };
+        (track(|key| key.from).at(time), track(|key| key.to).at(time), track(|key| key.up).at(time))
+    }
+}
+
+/// A value that changes over time, given by keyframes.
+///
+/// Before the first keyframe the value is that of the first one, and after the last keyframe that of the last one.
+///
+/// # Fields
+///
+/// * `keys` - The time in seconds and the value of each keyframe, sorted by time.
+/// * `interpolation` - How the value changes between the keyframes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    pub keys: Vec<(f64, T)>,
+    pub interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    /// Creates a track from keyframes.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The time in seconds and the value of each keyframe, in any order.
+    /// * `interpolation` - How the value changes between the keyframes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no keyframes, or two of them have the same time.
+    pub fn new(mut keys: Vec<(f64, T)>, interpolation: Interpolation) -> Track<T> {
+        if keys.is_empty() {
+            panic!("an animation track needs at least one keyframe");
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if keys.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            panic!("two keyframes of an animation track have the same time");
+        }
+        Track { keys, interpolation }
+    }
+
+    /// Returns the value at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time in seconds.
+    pub fn at(&self, time: f64) -> T {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        let i = keys.partition_point(|key| key.0 <= time);
+        if i == 0 {
+            return keys[0].1;
+        }
+        if i > last {
+            return keys[last].1;
+        }
+        // The time is between keys i - 1 and i
+        let ((t1, p1), (t2, p2)) = (keys[i - 1], keys[i]);
+        let s = (time - t1) / (t2 - t1);
+        match self.interpolation {
+            Interpolation::Linear => p1 + (p2 - p1) * s,
+            Interpolation::CatmullRom => {
+                // The rate of change at a keyframe comes from its neighbours, which allows keyframes that are
+                // not evenly spaced in time
+                let (t0, p0) = keys[i.saturating_sub(2)];
+                let (t3, p3) = keys[(i + 1).min(last)];
+                let m1 = (p2 - p0) * (1.0 / (t2 - t0));
+                let m2 = (p3 - p1) * (1.0 / (t3 - t1));
+                hermite(p1, p2, m1, m2, t2 - t1, s)
+            }
+        }
+    }
+}
+
+/// Evaluates the cubic Hermite curve from `p1` to `p2` with the rates of change `m1` and `m2`, over a span of
+/// `duration` seconds, at the fraction `s` of the span.
+fn hermite<T>(p1: T, p2: T, m1: T, m2: T, duration: f64, s: f64) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+{
+    let (s2, s3) = (s * s, s * s * s);
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+    p1 * h00 + m1 * (h10 * duration) + p2 * h01 + m2 * (h11 * duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tuple::Tuple;
+    use super::{frame_file_template, parse_frame_range, CameraKey, CameraPath, FrameRange, Interpolation, Track};
+
+    #[test]
+    fn parsing_frame_ranges() {
+        assert_eq!(parse_frame_range("1..240"), Ok(FrameRange { first: 1, last: 240 }));
+        assert_eq!(parse_frame_range("100..=120"), Ok(FrameRange { first: 100, last: 120 }));
+        assert_eq!(parse_frame_range("42"), Ok(FrameRange { first: 42, last: 42 }));
+        assert!(parse_frame_range("10..5").is_err());
+        assert!(parse_frame_range("a..5").is_err());
+        assert_eq!(FrameRange { first: 100, last: 120 }.count(), 21);
+    }
+
+    #[test]
+    fn every_frame_gets_its_own_file() {
+        assert_eq!(frame_file_template("out/render.png"), "out/render_{frame:04}.png");
+        assert_eq!(frame_file_template("v1.2/render"), "v1.2/render_{frame:04}");
+        assert_eq!(frame_file_template("f{frame:03}.png"), "f{frame:03}.png");
+    }
+
+    fn key(time: f64, x: f64, z: f64) -> CameraKey {
+        CameraKey { time, from: Tuple::point(x, 1.0, z), to: Tuple::point(0.0, 0.0, 0.0), up: Tuple::vector(0.0, 1.0, 0.0) }
+    }
+
+    #[test]
+    fn camera_paths_pass_through_their_keyframes() {
+        let keys = vec![key(2.0, 0.0, -5.0), key(0.0, -5.0, 0.0), key(4.0, 5.0, 0.0), key(6.0, 0.0, 5.0)];
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let path = CameraPath::new(keys.clone(), interpolation);
+            assert_eq!(path.at(-1.0).0, Tuple::point(-5.0, 1.0, 0.0));
+            assert_eq!(path.at(2.0).0, Tuple::point(0.0, 1.0, -5.0));
+            assert_eq!(path.at(4.0).0, Tuple::point(5.0, 1.0, 0.0));
+            assert_eq!(path.at(10.0).0, Tuple::point(0.0, 1.0, 5.0));
+        }
+        let linear = CameraPath::new(keys.clone(), Interpolation::Linear).at(3.0).0;
+        assert_eq!(linear, Tuple::point(2.5, 1.0, -2.5));
+        // The spline bulges outwards, around the point looked at, instead of cutting the corner
+        let smooth = CameraPath::new(keys, Interpolation::CatmullRom).at(3.0).0;
+        assert!(smooth.x.hypot(smooth.z) > linear.x.hypot(linear.z));
+    }
+
+    #[test]
+    fn evenly_spaced_keyframes_on_a_line_give_steady_motion() {
+        let path = CameraPath::new(vec![key(0.0, 0.0, 0.0), key(1.0, 1.0, 0.0), key(2.0, 2.0, 0.0)], Interpolation::CatmullRom);
+        assert_eq!(path.at(1.5).0, Tuple::point(1.5, 1.0, 0.0));
+    }
+
+    #[test]
+    fn tracks_interpolate_numbers() {
+        let track = Track::new(vec![(0.0, 0.0), (2.0, 360.0)], Interpolation::CatmullRom);
+        assert!((track.at(0.5) - 90.0).abs() < 1e-9);
+        assert_eq!(track.at(3.0), 360.0);
+        let bounce = Track::new(vec![(0.0, 0.0), (0.5, 2.0), (1.0, 0.0)], Interpolation::Linear);
+        assert_eq!(bounce.at(0.75), 1.0);
+    }
+}
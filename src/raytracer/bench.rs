@@ -0,0 +1,303 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+use crate::raytracer::material::noise::set_noise_seed;
+use crate::raytracer::progress::ProgressMode;
+use crate::raytracer::scene::rays_cast;
+use crate::raytracer::scene_builder_yaml::{build_scene, load_scene_doc};
+
+/// The size of the benchmark images, fixed so that results can be compared between releases.
+const BENCH_WIDTH: usize = 320;
+const BENCH_HEIGHT: usize = 240;
+
+/// The seed used for noise and random sampling, so every run traces the same rays.
+const BENCH_SEED: u64 = 1;
+
+/// The low resolution teapot used by the mesh benchmark, built in so that the benchmark runs from anywhere.
+const TEAPOT_OBJ: &str = include_str!("../../examples/teapot-low.obj");
+
+/// The camera and lights shared by the benchmark scenes.
+const BENCH_SETUP: &str = r#"
+camera:
+  fov: 60
+  from: [0, 4, -9]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+lights:
+  - type: point
+    position: [-8, 10, -10]
+    color: [0.8, 0.8, 0.8]
+  - type: point
+    position: [6, 6, -8]
+    color: [0.3, 0.3, 0.3]
+"#;
+
+/// Objects cut out of each other with constructive solid geometry.
+const CSG_SCENE: &str = r#"
+scene:
+  - type: plane
+    material:
+      pattern: { type: solid, color: [0.8, 0.8, 0.8] }
+  - type: csg
+    operation: difference
+    transforms:
+      - { type: rotate, axis: 'y', angle: 30 }
+      - { type: translate, amount: [-2, 1, 0] }
+    left:
+      type: cube
+      material:
+        pattern: { type: solid, color: [0.9, 0.3, 0.2] }
+    right:
+      type: sphere
+      transforms:
+        - { type: scale, amount: [1.3, 1.3, 1.3] }
+      material:
+        pattern: { type: solid, color: [0.9, 0.8, 0.2] }
+  - type: csg
+    operation: intersection
+    transforms:
+      - { type: translate, amount: [1.5, 1, 0] }
+    left:
+      type: cylinder
+      minimum: -1
+      maximum: 1
+      closed: true
+      material:
+        pattern: { type: solid, color: [0.2, 0.5, 0.9] }
+    right:
+      type: cylinder
+      minimum: -1
+      maximum: 1
+      closed: true
+      transforms:
+        - { type: rotate, axis: 'x', angle: 90 }
+      material:
+        pattern: { type: solid, color: [0.2, 0.9, 0.5] }
+  - type: csg
+    operation: union
+    transforms:
+      - { type: translate, amount: [0, 0.5, 2.5] }
+    left:
+      type: sphere
+      transforms:
+        - { type: translate, amount: [-0.4, 0, 0] }
+        - { type: scale, amount: [0.5, 0.5, 0.5] }
+    right:
+      type: cone
+      minimum: -1
+      maximum: 0
+      closed: true
+      transforms:
+        - { type: translate, amount: [0.4, 1, 0] }
+"#;
+
+/// Reflective and refractive spheres over a checkered floor, for deep secondary rays.
+const GLASS_SCENE: &str = r#"
+scene:
+  - type: plane
+    material:
+      pattern:
+        type: checker
+        color_a: [0.9, 0.9, 0.9]
+        color_b: [0.2, 0.2, 0.2]
+      reflective: 0.3
+  - type: glass_sphere
+    transforms:
+      - { type: translate, amount: [0, 1, 0] }
+    material:
+      pattern: { type: solid, color: [0.05, 0.05, 0.05] }
+      reflective: 0.9
+      transparency: 0.9
+      refractive_index: 1.5
+  - type: glass_sphere
+    transforms:
+      - { type: scale, amount: [0.6, 0.6, 0.6] }
+      - { type: translate, amount: [-2, 0.6, 1] }
+    material:
+      pattern: { type: solid, color: [0.05, 0.1, 0.05] }
+      reflective: 0.9
+      transparency: 0.9
+      refractive_index: 1.33
+  - type: sphere
+    transforms:
+      - { type: scale, amount: [0.8, 0.8, 0.8] }
+      - { type: translate, amount: [2, 0.8, 1.5] }
+    material:
+      pattern: { type: solid, color: [0.7, 0.7, 0.8] }
+      reflective: 0.8
+"#;
+
+/// The timings of one benchmark scene.
+///
+/// # Fields
+///
+/// * `name` - The name of the benchmark scene.
+/// * `load` - The time spent parsing the scene description.
+/// * `build` - The time spent creating the camera, lights and objects, including loading meshes.
+/// * `render` - The time spent rendering the image.
+/// * `rays` - The number of rays intersected with the scene while rendering.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: String,
+    pub load: Duration,
+    pub build: Duration,
+    pub render: Duration,
+    pub rays: u64,
+}
+
+impl BenchResult {
+    /// Returns the number of rays traced per second while rendering.
+    pub fn rays_per_second(&self) -> f64 {
+        self.rays as f64 / self.render.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Formats the result as a row of the table printed by `run_benchmarks`.
+    pub fn to_row(&self) -> String {
+        format!("{:<14}{:>10.1}{:>10.1}{:>12.1}{:>14}{:>12.3}",
+                self.name,
+                self.load.as_secs_f64() * 1000.0,
+                self.build.as_secs_f64() * 1000.0,
+                self.render.as_secs_f64() * 1000.0,
+                self.rays,
+                self.rays_per_second() / 1e6)
+    }
+}
+
+/// Builds the sphere field scene: a grid of small, differently colored spheres on a plane.
+fn sphere_field_scene() -> String {
+    let mut scene = String::from("scene:\n  - type: plane\n");
+    for i in 0..10 {
+        for j in 0..10 {
+            let (x, z) = (i as f64 - 4.5, j as f64 - 2.0);
+            scene += &format!(
+                "  - type: sphere\n    transforms:\n      - {{ type: scale, amount: [0.4, 0.4, 0.4] }}\n      - {{ type: translate, amount: [{}, 0.4, {}] }}\n    material:\n      pattern: {{ type: solid, color: [{}, 0.5, {}] }}\n",
+                x, z, i as f64 / 9.0, j as f64 / 9.0,
+            );
+        }
+    }
+    scene
+}
+
+/// Returns the path the teapot of the mesh scene is written to, in the temporary directory.
+///
+/// The name includes the process id, so benchmarks running at the same time do not overwrite each
+/// other's file.
+fn teapot_file() -> PathBuf {
+    env::temp_dir().join(format!("rray-bench-teapot-{}.obj", process::id()))
+}
+
+/// Builds the mesh scene: a teapot loaded from an OBJ file, standing on a plane.
+///
+/// The OBJ file is written to the temporary directory first, see `teapot_file`, so that it is loaded like
+/// any other mesh.
+///
+/// # Panics
+///
+/// Panics if the OBJ file cannot be written.
+fn mesh_scene() -> String {
+    let obj = teapot_file();
+    fs::write(&obj, TEAPOT_OBJ).unwrap_or_else(|e| panic!("Cannot write {}: {}", obj.display(), e));
+    format!(
+        "scene:\n  - type: plane\n  - type: obj_file\n    obj_file: '{}'\n    transforms:\n      - {{ type: rotate, axis: 'x', angle: -90 }}\n      - {{ type: scale, amount: [0.15, 0.15, 0.15] }}\n    material:\n      pattern: {{ type: solid, color: [0.8, 0.5, 0.3] }}\n",
+        obj.display(),
+    )
+}
+
+/// Returns the names and scene descriptions (without camera and lights) of the benchmark scenes.
+fn bench_scenes() -> Vec<(&'static str, String)> {
+    vec![
+        ("sphere_field", sphere_field_scene()),
+        ("obj_mesh", mesh_scene()),
+        ("csg", CSG_SCENE.to_string()),
+        ("glass", GLASS_SCENE.to_string()),
+    ]
+}
+
+/// Loads, builds and renders one benchmark scene, timing each stage.
+///
+/// # Arguments
+///
+/// * `name` - The name of the scene.
+/// * `scene` - The scene description, without camera and lights.
+/// * `threads` - The number of render threads, or 0 to use one per core.
+///
+/// # Returns
+///
+/// The timings and ray count of the scene.
+fn run_bench(name: &str, scene: &str, threads: usize) -> BenchResult {
+    let start = Instant::now();
+    let doc = load_scene_doc(&format!("{}{}", BENCH_SETUP, scene), Path::new("."));
+    let load = start.elapsed();
+
+    let start = Instant::now();
+    let (mut camera, scene) = build_scene(&doc, None, BENCH_WIDTH, BENCH_HEIGHT);
+    let build = start.elapsed();
+
+    camera.threads = threads;
+    camera.progress = ProgressMode::Quiet;
+    camera.seed = Some(BENCH_SEED);
+    set_noise_seed(camera.seed);
+    let rays_before = rays_cast();
+    let start = Instant::now();
+    camera.render(&scene);
+    let render = start.elapsed();
+
+    BenchResult { name: name.to_string(), load, build, render, rays: rays_cast() - rays_before }
+}
+
+/// Renders the built-in benchmark scenes at fixed settings and prints the timings of each one.
+///
+/// For every scene the time spent loading, building and rendering it is printed, together with the
+/// number of rays traced and the rays per second, so that performance can be compared between releases.
+///
+/// # Arguments
+///
+/// * `threads` - The number of render threads, or 0 to use one per core.
+///
+/// # Returns
+///
+/// The results of all scenes.
+pub fn run_benchmarks(threads: usize) -> Vec<BenchResult> {
+    // The scenes are made once, since making them writes the teapot file again and would change its modification
+    // time under the mesh cache
+    let scenes = bench_scenes();
+    println!("Rendering {} benchmark scenes at {}x{}", scenes.len(), BENCH_WIDTH, BENCH_HEIGHT);
+    println!("{:<14}{:>10}{:>10}{:>12}{:>14}{:>12}", "scene", "load ms", "build ms", "render ms", "rays", "Mrays/s");
+    let mut results = vec![];
+    for (name, scene) in scenes {
+        let result = run_bench(name, &scene, threads);
+        println!("{}", result.to_row());
+        results.push(result);
+    }
+    let _ = fs::remove_file(teapot_file());
+    let total = BenchResult {
+        name: "total".to_string(),
+        load: results.iter().map(|r| r.load).sum(),
+        build: results.iter().map(|r| r.build).sum(),
+        render: results.iter().map(|r| r.render).sum(),
+        rays: results.iter().map(|r| r.rays).sum(),
+    };
+    println!("{}", total.to_row());
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::{bench_scenes, teapot_file, BENCH_SETUP};
+    use crate::raytracer::scene_builder_yaml::{build_scene, load_scene_doc};
+
+    #[test]
+    fn every_benchmark_scene_builds() {
+        let names: Vec<&str> = bench_scenes().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["sphere_field", "obj_mesh", "csg", "glass"]);
+        for (_, scene) in bench_scenes() {
+            let doc = load_scene_doc(&format!("{}{}", BENCH_SETUP, scene), Path::new("."));
+            let (_, scene) = build_scene(&doc, None, 4, 3);
+            assert_eq!(scene.light.len(), 2);
+        }
+        let _ = std::fs::remove_file(teapot_file());
+    }
+}
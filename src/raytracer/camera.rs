@@ -1,12 +1,27 @@
-use std::sync::{Arc, Mutex};
-use crate::matrix::Matrix;
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::color::Color;
+use crate::matrix4::Matrix4;
 use crate::tuple::Tuple;
 use crate::raytracer::canvas::Canvas;
-use indicatif::ProgressBar;
+use crate::raytracer::denoise::denoise;
+use crate::raytracer::film::FilmBuffer;
 use rayon::iter::ParallelBridge;
-use rayon::prelude::ParallelIterator;
-use crate::raytracer::ray::Ray;
-use crate::raytracer::scene::Scene;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::ThreadPoolBuilder;
+use image::RgbaImage;
+use crate::raytracer::ray::{Ray, RayDifferentials};
+use crate::raytracer::profile::profile_span;
+use crate::raytracer::progress::{CancelToken, Progress, ProgressMode, TileProgress};
+use crate::raytracer::sampling::{seed_pixel, start_pixel};
+use crate::raytracer::scene::{flush_rays_cast, Scene};
+
+/// The width and height, in pixels, of the tiles the image is rendered in.
+const TILE_SIZE: usize = 16;
+
+/// The color of the tiles that were not rendered because the render ran out of time.
+const UNRENDERED: Color = Color { r: 1.0, g: 0.0, b: 1.0 };
 
 /// Represents a camera in the raytracer scene.
 ///
@@ -14,18 +29,84 @@ use crate::raytracer::scene::Scene;
 /// field of view (`field_of_view`), and a transformation matrix (`transform`) that
 /// positions and orients the camera in the scene. The `pixel_size`, `half_width`,
 /// and `half_height` are calculated based on the camera's field of view and aspect ratio.
+/// `threads` is the number of threads used by `render`, where 0 means one thread per core.
+/// When `seed` is set, the random sampling done for each pixel is seeded from it, making renders reproducible.
+/// `progress` selects how rendering progress is reported.
+/// `exposure` scales the rendered colors to the brightness of the display, for scenes lit with physical units.
+/// `shift_x` and `shift_y` move the image sideways and up within the view, like the lens shift of an architectural
+/// camera, in fractions of the longer side of the image. Unlike turning the camera, this keeps vertical lines vertical.
+/// When `stereo` is set the scene is rendered once for each eye, see `Stereo`.
+/// When `region` is set only the pixels from `(x0, y0)` up to but not including `(x1, y1)` are rendered.
+/// `max_depth` is the number of times rays are followed through reflections and refractions.
+/// When `deadline` is set, tiles that would start after it are not rendered but filled with magenta instead.
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Camera {
     pub hsize: usize,
     pub vsize: usize,
     pub field_of_view: f64,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub pixel_size: f64,
     pub half_width: f64,
     pub half_height: f64,
+    pub threads: usize,
+    pub seed: Option<u64>,
+    pub progress: ProgressMode,
+    pub exposure: Option<Exposure>,
+    pub shift_x: f64,
+    pub shift_y: f64,
+    pub stereo: Option<Stereo>,
+    pub region: Option<(usize, usize, usize, usize)>,
+    pub max_depth: usize,
+    pub deadline: Option<Instant>,
+}
+
+/// Settings for rendering a stereo pair, with one image for each eye.
+///
+/// The eyes look in parallel, and their images are shifted so that they line up at the `convergence`
+/// distance, which avoids the vertical parallax of toed-in eyes. Objects closer than that seem to come
+/// out of the screen, objects farther away seem to be behind it.
+///
+/// # Fields
+///
+/// * `interocular` - The distance between the eyes, in scene units.
+/// * `convergence` - The distance from the camera at which the two images line up.
+/// * `output` - How the two images are combined into one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stereo {
+    pub interocular: f64,
+    pub convergence: f64,
+    pub output: StereoOutput,
+}
+
+/// How the two images of a stereo pair are combined into one image.
+///
+/// # Variants
+///
+/// * `SideBySide` - The left eye image on the left and the right eye image on the right, for stereo viewers.
+/// * `Anaglyph` - The red channel of the left eye image and the green and blue channels of the right eye image,
+///   for red-cyan glasses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoOutput {
+    SideBySide,
+    Anaglyph,
+}
+
+/// How the rendered colors are scaled to the brightness of the display.
+///
+/// # Variants
+///
+/// * `Ev100` - A fixed exposure value at ISO 100, as given by a light meter: each step up halves the brightness.
+/// * `Auto` - The exposure is chosen after rendering so that the average brightness of the image is middle grey.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exposure {
+    Ev100(f64),
+    Auto,
 }
 
+/// The brightness the average of an image is mapped to by automatic exposure.
+const MIDDLE_GREY: f64 = 0.18;
+
 impl Camera {
     /// Constructs a new `Camera` with the given size and field of view.
     ///
@@ -55,15 +136,51 @@ impl Camera {
             hsize,
             vsize,
             field_of_view,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             pixel_size,
             half_width,
             half_height,
+            threads: 0,
+            seed: None,
+            progress: ProgressMode::Bar,
+            exposure: None,
+            shift_x: 0.0,
+            shift_y: 0.0,
+            stereo: None,
+            region: None,
+            max_depth: 5,
+            deadline: None,
         }
     }
 
+    /// Returns the camera of one eye of a stereo pair.
+    ///
+    /// The eye is moved sideways from the camera, and its image is shifted so that points straight ahead
+    /// of the camera at the convergence distance are in the center of the image of both eyes.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - How far the eye is to the right of the camera; negative for the left eye.
+    /// * `convergence` - The distance from the camera at which the images of both eyes line up.
+    ///
+    /// # Returns
+    ///
+    /// A copy of the camera, moved and shifted for the eye, without stereo settings.
+    pub fn eye(&self, offset: f64, convergence: f64) -> Camera {
+        let mut eye = self.clone();
+        // The camera looks toward -z, so its right is -x
+        eye.transform = Matrix4::translate(offset, 0.0, 0.0).multiply(&self.transform);
+        let view_size = 2.0 * self.half_width.max(self.half_height);
+        eye.shift_x = self.shift_x - offset / convergence / view_size;
+        eye.stereo = None;
+        eye
+    }
+
     /// Calculates the ray from the camera to a specific pixel on the canvas.
     ///
+    /// The ray carries the rays through the next pixels to the right and below as its differentials,
+    /// so patterns can be filtered over the area the pixel covers.
+    ///
     /// # Arguments
     ///
     /// * `px` - The x-coordinate of the pixel on the canvas.
@@ -73,29 +190,44 @@ impl Camera {
     ///
     /// A `Ray` instance representing the ray from the camera to the specified pixel.
     pub fn ray_for_pixel(&self, px: usize, py: usize) -> Ray {
-        // the offset from the edge of the canvas to the pixel's center
-        let xoffset = (px as f64 + 0.5) * self.pixel_size;
-        let yoffset = (py as f64 + 0.5) * self.pixel_size;
+        let inverse_transform = self.transform.inverse();
+        let (x, y) = (px as f64 + 0.5, py as f64 + 0.5);
+        let (origin, direction) = self.ray_through(&inverse_transform, x, y);
+        let (dx_origin, dx_direction) = self.ray_through(&inverse_transform, x + 1.0, y);
+        let (dy_origin, dy_direction) = self.ray_through(&inverse_transform, x, y + 1.0);
+        Ray::new(origin, direction)
+            .with_differentials(Some(RayDifferentials { dx_origin, dx_direction, dy_origin, dy_direction }))
+    }
+
+    /// Finds the origin and direction of the ray through a point on the canvas, measured in pixels
+    /// from its top left corner.
+    fn ray_through(&self, inverse_transform: &Matrix4, x: f64, y: f64) -> (Tuple, Tuple) {
+        // the offset from the edge of the canvas to the point
+        let xoffset = x * self.pixel_size;
+        let yoffset = y * self.pixel_size;
 
-        // the untransformed coordinates of the pixel in world space
+        // the lens shift moves the canvas within the view, in units of its longer side
+        let view_size = 2.0 * self.half_width.max(self.half_height);
+
+        // the untransformed coordinates of the point in world space
         // (remember that the camera looks toward -z, so +x is to the *left*)
-        let world_x = self.half_width - xoffset;
-        let world_y = self.half_height - yoffset;
+        let world_x = self.half_width - xoffset - self.shift_x * view_size;
+        let world_y = self.half_height - yoffset + self.shift_y * view_size;
 
         // using the camera matrix, transform the canvas point and the origin,
         // and then compute the ray's direction vector
         // (remember that the canvas is at z=-1)
-        let inverse_transform = self.transform.inverse();
         let pixel = inverse_transform.multiply_tuple(&Tuple::point(world_x, world_y, -1.0));
         let origin = inverse_transform.multiply_tuple(&Tuple::point(0.0, 0.0, 0.0));
         let direction = pixel.subtract(&origin).normalize();
-        Ray::new(origin, direction)
+        (origin, direction)
     }
 
     /// Renders the scene from the perspective of the camera.
     ///
     /// This method utilizes parallel processing to render the scene, improving performance
-    /// for large images. It returns a `Canvas` that represents the rendered image.
+    /// for large images. The work is done on a dedicated thread pool with `threads` threads.
+    /// It returns a `Canvas` that represents the rendered image.
     ///
     /// # Arguments
     ///
@@ -105,20 +237,345 @@ impl Camera {
     ///
     /// A `Canvas` instance representing the rendered image.
     pub fn render(&self, scene: &Scene) -> Canvas {
-        let image = Arc::new(Mutex::new(Canvas::new(self.hsize, self.vsize)));
-        let bar = ProgressBar::new((self.vsize * self.hsize) as u64);
-        let iter = pixel_coordinates(self.vsize, self.hsize).par_bridge();
-        iter.for_each(|(x, y)| {
-            let ray = self.ray_for_pixel(x, y);
-            let color = scene.color_at(&ray, 5);
-            let mut image = image.lock().unwrap();
-            image.write_pixel(x, y, color);
-            drop(image); // unlock the mutex
-            bar.inc(1);
+        self.render_with(scene, None, None)
+    }
+
+    /// Renders the scene as `render` does, reporting every finished tile and stopping when cancelled.
+    ///
+    /// This is meant for programs that embed the ray tracer, such as GUIs that show a progress bar and a
+    /// cancel button. The callback is called on the render threads, so it must be quick and thread safe.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    /// * `on_tile` - Called with the bounds of each tile once it is rendered, and how much of the image is done.
+    /// * `cancel` - A token that stops the render when it is cancelled from another thread.
+    ///
+    /// # Returns
+    ///
+    /// A `Canvas` instance representing the rendered image, in which the tiles that were skipped because the
+    /// render was cancelled are black.
+    pub fn render_with(&self, scene: &Scene, on_tile: Option<&(dyn Fn(&TileProgress) + Sync)>, cancel: Option<&CancelToken>) -> Canvas {
+        let image = Mutex::new(Canvas::new(self.hsize, self.vsize));
+        let cancel = cancel.cloned().unwrap_or_default();
+        self.render_into(scene, &image, on_tile, &cancel);
+        image.into_inner().unwrap()
+    }
+
+    /// Renders the scene into an existing canvas, one tile at a time.
+    ///
+    /// The image is divided into square tiles which are rendered in parallel. Each finished tile
+    /// is copied into `image` at once, so other threads can watch the image being built up.
+    /// Cancelling `cancel` stops the render: tiles that have not been started yet are skipped and
+    /// left untouched in `image`. Pixels outside of the camera's `region` are left untouched as well.
+    /// Once the camera's `deadline` has passed, the tiles that are being rendered are finished, and the
+    /// tiles that have not been started yet are filled with magenta.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    /// * `image` - The canvas the rendered pixels are written to. It must be `hsize` by `vsize` pixels.
+    /// * `on_tile` - Called with the bounds of each tile once it is rendered, and how much of the image is done.
+    /// * `cancel` - A token that can be cancelled from another thread to stop rendering early.
+    pub fn render_into(&self, scene: &Scene, image: &Mutex<Canvas>, on_tile: Option<&(dyn Fn(&TileProgress) + Sync)>, cancel: &CancelToken) {
+        self.render_tiles(scene, image, None, on_tile, cancel);
+    }
+
+    /// Renders the scene as `render` does, also collecting the samples of each pixel of the final image.
+    ///
+    /// The camera renders the image `aa` times larger in each direction than the final image, and each
+    /// `aa` x `aa` block of rendered pixels are the samples of one pixel. They are added to the film buffer as
+    /// the tiles finish, before any denoising or exposure, so its variances show how noisy the samples are.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    /// * `aa` - The anti-aliasing factor.
+    ///
+    /// # Returns
+    ///
+    /// The rendered canvas, `aa` times larger than the final image, and the film buffer with the samples of the
+    /// final image.
+    pub fn render_with_film(&self, scene: &Scene, aa: usize) -> (Canvas, FilmBuffer) {
+        let image = Mutex::new(Canvas::new(self.hsize, self.vsize));
+        let film = Mutex::new(FilmBuffer::new(self.hsize / aa, self.vsize / aa));
+        self.render_tiles(scene, &image, Some((&film, aa)), None, &CancelToken::new());
+        (image.into_inner().unwrap(), film.into_inner().unwrap())
+    }
+
+    /// Renders the tiles of the image for `render_into`, adding the rendered pixels to `film` as well when
+    /// it is given, with the anti-aliasing factor that maps them to the pixels of the film.
+    fn render_tiles(&self, scene: &Scene, image: &Mutex<Canvas>, film: Option<(&Mutex<FilmBuffer>, usize)>,
+                    on_tile: Option<&(dyn Fn(&TileProgress) + Sync)>, cancel: &CancelToken) {
+        let _span = profile_span!(INFO, "render");
+        let (rx0, ry0, rx1, ry1) = self.render_region();
+        let total = ((rx1 - rx0) * (ry1 - ry0)) as u64;
+        let progress = Progress::new(self.progress, total);
+        let exposure_scale = self.exposure_scale();
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .expect("Failed to create render thread pool");
+        pool.install(|| {
+            let iter = tile_coordinates(self.vsize, self.hsize, TILE_SIZE).par_bridge();
+            iter.for_each(|(x0, y0, x1, y1)| {
+                // Only the part of the tile inside the render region is traced
+                let (x0, y0, x1, y1) = (x0.max(rx0), y0.max(ry0), x1.min(rx1), y1.min(ry1));
+                if x0 >= x1 || y0 >= y1 || cancel.is_cancelled() {
+                    return;
+                }
+                if self.out_of_time() {
+                    let mut image = image.lock().unwrap();
+                    for (x, y) in (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))) {
+                        image.write_pixel(x, y, UNRENDERED);
+                    }
+                    return;
+                }
+                let _span = profile_span!(INFO, "tile", x0, y0);
+                let mut tile = Vec::with_capacity((x1 - x0) * (y1 - y0));
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        if let Some(seed) = self.seed {
+                            seed_pixel(seed, x, y);
+                        }
+                        start_pixel(scene.sampler, x, y);
+                        let ray = self.ray_for_pixel(x, y);
+                        tile.push((x, y, scene.color_at(&ray, self.max_depth).multiply(exposure_scale)));
+                    }
+                }
+                flush_rays_cast();
+                let mut image = image.lock().unwrap();
+                for (x, y, color) in tile.iter() {
+                    image.write_pixel(*x, *y, *color);
+                }
+                drop(image); // unlock the mutex
+                if let Some((film, aa)) = film {
+                    let mut film = film.lock().unwrap();
+                    let (width, height) = (film.width, film.height);
+                    for (x, y, color) in tile.iter().filter(|(x, y, _)| x / aa < width && y / aa < height) {
+                        film.add_sample(x / aa, y / aa, *color);
+                    }
+                }
+                let done = progress.inc(tile.len() as u64);
+                if let Some(on_tile) = on_tile {
+                    on_tile(&TileProgress { x0, y0, x1, y1, done, total });
+                }
+            });
         });
-        bar.finish();
-        Arc::try_unwrap(image).unwrap().into_inner().unwrap()
+        progress.finish();
+    }
+
+    /// Renders the scene to an image in memory, instead of a file.
+    ///
+    /// This renders on the CPU like `rray render` without `--denoise`: both eyes are rendered for a stereo
+    /// camera, and automatic exposure is applied. The image has the size of the camera, without further
+    /// anti-aliasing. Once the camera's `deadline` has passed, the tiles that have not been started yet are
+    /// magenta, as in `render_into`, but unlike `rray render` nothing is printed; check `out_of_time` after
+    /// the render to find out.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    ///
+    /// # Returns
+    ///
+    /// The rendered image, with 8 bits per channel.
+    pub fn render_to_image(&self, scene: &Scene) -> RgbaImage {
+        let mut image = match &self.stereo {
+            Some(stereo) => self.render_stereo(stereo, |camera| camera.render(scene)),
+            None => self.render(scene),
+        };
+        self.auto_expose(&mut image);
+        image.to_image(1)
+    }
+
+    /// Returns true if the camera has a `deadline` and it has passed.
+    pub fn out_of_time(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns the part of the image that is rendered, as `(x0, y0, x1, y1)`.
+    ///
+    /// This is the `region` limited to the size of the image, or the whole image if there is no region.
+    pub fn render_region(&self) -> (usize, usize, usize, usize) {
+        let (x0, y0, x1, y1) = self.region.unwrap_or((0, 0, self.hsize, self.vsize));
+        let (x1, y1) = (x1.min(self.hsize), y1.min(self.vsize));
+        (x0.min(x1), y0.min(y1), x1, y1)
+    }
+
+    /// Returns the factor the colors are multiplied by while rendering.
+    ///
+    /// For a fixed exposure this follows the usual photographic calibration, with the shading treated as
+    /// illuminance that a white surface reflects as luminance, so a white wall lit by 500 lux looks right
+    /// at around EV 7. It is 1 without exposure, and also for automatic exposure, which is applied afterwards.
+    pub fn exposure_scale(&self) -> f64 {
+        match self.exposure {
+            Some(Exposure::Ev100(ev)) => 1.0 / (PI * 1.2 * 2_f64.powf(ev)),
+            _ => 1.0,
+        }
+    }
+
+    /// Renders the scene for both eyes of a stereo pair and combines the two images.
+    ///
+    /// # Arguments
+    ///
+    /// * `stereo` - The stereo settings.
+    /// * `render_eye` - Renders the scene with the camera of one eye.
+    ///
+    /// # Returns
+    ///
+    /// The combined image: twice as wide as the camera's image for side by side output, the same size for anaglyphs.
+    pub fn render_stereo(&self, stereo: &Stereo, render_eye: impl Fn(&Camera) -> Canvas) -> Canvas {
+        let left = render_eye(&self.eye(-stereo.interocular / 2.0, stereo.convergence));
+        let right = render_eye(&self.eye(stereo.interocular / 2.0, stereo.convergence));
+        match stereo.output {
+            StereoOutput::SideBySide => Canvas::side_by_side(&left, &right),
+            StereoOutput::Anaglyph => Canvas::anaglyph(&left, &right),
+        }
+    }
+
+    /// Scales a rendered image so that its average brightness is middle grey, if the exposure is automatic.
+    ///
+    /// The average is the geometric mean of the luminance of the pixels, so a few very bright pixels, like
+    /// the lights themselves, do not darken the rest of the image. Black pixels, like an empty background,
+    /// are left out, so they do not brighten it either.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The rendered image.
+    pub fn auto_expose(&self, image: &mut Canvas) {
+        if self.exposure != Some(Exposure::Auto) {
+            return;
+        }
+        let lit: Vec<f64> = image.pixels.iter().map(|p| p.luminance()).filter(|l| *l > 0.0).collect();
+        if lit.is_empty() {
+            return;
+        }
+        let average = (lit.iter().map(|l| l.ln()).sum::<f64>() / lit.len() as f64).exp();
+        let scale = MIDDLE_GREY / average;
+        image.pixels.iter_mut().for_each(|p| *p = p.multiply(scale));
     }
+
+    /// Renders a depth map of the scene from the perspective of the camera.
+    ///
+    /// A single ray is traced through the center of each pixel and the distance to the first hit is
+    /// recorded. The distances are normalized into a grayscale image where the closest hit is white,
+    /// and the farthest hit (or anything beyond `far`) and the background are black.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    /// * `far` - The distance that maps to black, or `None` to use the farthest hit.
+    ///
+    /// # Returns
+    ///
+    /// A `Canvas` instance holding the depth map.
+    pub fn render_depth(&self, scene: &Scene, far: Option<f64>) -> Canvas {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .expect("Failed to create render thread pool");
+        let depths: Vec<Option<f64>> = pool.install(|| {
+            (0..self.vsize * self.hsize).into_par_iter()
+                .map(|i| scene.depth_at(&self.ray_for_pixel(i % self.hsize, i / self.hsize)))
+                .collect()
+        });
+        Canvas::from_depth_map(self.hsize, self.vsize, &depths, far)
+    }
+
+    /// Smooths the noise of sampled effects out of a rendered image, see `denoise`.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - The scene the image was rendered from.
+    /// * `image` - The image rendered by this camera.
+    ///
+    /// # Returns
+    ///
+    /// The denoised image.
+    pub fn denoise(&self, scene: &Scene, image: &Canvas) -> Canvas {
+        let _span = profile_span!(INFO, "denoise");
+        let (albedo, normals) = self.render_guides(scene);
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .expect("Failed to create render thread pool");
+        pool.install(|| denoise(image, &albedo, &normals))
+    }
+
+    /// Renders the passes that guide the denoiser: the color of the surfaces without lighting, and their normals.
+    ///
+    /// # Arguments
+    ///
+    /// * `scene` - A reference to the `Scene` that will be rendered.
+    ///
+    /// # Returns
+    ///
+    /// The surface colors, and the normals stored as colors with x, y and z in red, green and blue, see
+    /// `Scene::guides_at`.
+    pub fn render_guides(&self, scene: &Scene) -> (Canvas, Canvas) {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .expect("Failed to create render thread pool");
+        let guides: Vec<(Color, Tuple)> = pool.install(|| {
+            (0..self.vsize * self.hsize).into_par_iter()
+                .map(|i| scene.guides_at(&self.ray_for_pixel(i % self.hsize, i / self.hsize)))
+                .collect()
+        });
+        let mut albedo = Canvas::new(self.hsize, self.vsize);
+        let mut normals = Canvas::new(self.hsize, self.vsize);
+        for (i, (color, normal)) in guides.into_iter().enumerate() {
+            albedo.pixels[i] = color;
+            normals.pixels[i] = Color::new(normal.x, normal.y, normal.z);
+        }
+        (albedo, normals)
+    }
+}
+
+/// Computes the field of view of a camera from the focal length of its lens and the width of its sensor.
+///
+/// The sensor width is matched to the width of the image, as in most 3D tools. The result follows the
+/// convention of `Camera::new`, where the field of view spans the longer side of the image.
+///
+/// # Arguments
+///
+/// * `focal_length` - The focal length of the lens, in millimeters.
+/// * `sensor_width` - The width of the sensor, in millimeters; 36 for a full frame camera.
+/// * `hsize` - The horizontal size of the image.
+/// * `vsize` - The vertical size of the image.
+///
+/// # Returns
+///
+/// The field of view in radians.
+pub fn field_of_view_from_lens(focal_length: f64, sensor_width: f64, hsize: usize, vsize: usize) -> f64 {
+    let half_width = sensor_width / (2.0 * focal_length);
+    let aspect = hsize as f64 / vsize as f64;
+    let half_view = if aspect >= 1.0 { half_width } else { half_width / aspect };
+    2.0 * half_view.atan()
+}
+
+/// Generates an iterator over the tiles of the canvas, row by row.
+///
+/// Each tile is returned as `(x0, y0, x1, y1)`, covering the pixels from `x0` up to but not including
+/// `x1`, and from `y0` up to but not including `y1`. Tiles on the right and bottom edges are smaller
+/// when the canvas size is not a multiple of `tile_size`.
+///
+/// # Arguments
+///
+/// * `vsize` - The vertical size of the canvas.
+/// * `hsize` - The horizontal size of the canvas.
+/// * `tile_size` - The width and height of a tile.
+///
+/// # Returns
+///
+/// An iterator that yields the bounds of each tile.
+pub fn tile_coordinates(vsize: usize, hsize: usize, tile_size: usize) -> impl Iterator<Item = (usize, usize, usize, usize)> {
+    (0..vsize).step_by(tile_size).flat_map(move |y0| {
+        (0..hsize).step_by(tile_size).map(move |x0| {
+            (x0, y0, (x0 + tile_size).min(hsize), (y0 + tile_size).min(vsize))
+        })
+    })
 }
 
 /// Generates an iterator over the coordinates of each pixel in the canvas.
@@ -131,6 +588,7 @@ impl Camera {
 /// # Returns
 ///
 /// An iterator that yields tuples of (x, y) coordinates for each pixel.
+#[allow(dead_code)]
 pub fn pixel_coordinates(vsize: usize, hsize: usize) -> impl Iterator<Item = (usize, usize)> {
     (0..vsize).flat_map(move |y| (0..hsize).map(move |x| (x, y)))
 }
@@ -139,13 +597,18 @@ pub fn pixel_coordinates(vsize: usize, hsize: usize) -> impl Iterator<Item = (us
 mod tests {
     use crate::EPSILON;
     use std::sync::Arc;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::light::Light;
     use crate::raytracer::material::pattern::Pattern;
     use crate::raytracer::object::plane::Plane;
     use crate::raytracer::object::sphere::Sphere;
     use crate::raytracer::scene::{Scene};
-    use super::Camera;
+    use super::{field_of_view_from_lens, Camera, Exposure, Stereo, StereoOutput};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::raytracer::progress::{CancelToken, ProgressMode, TileProgress};
+    use crate::color::Color;
+    use crate::raytracer::canvas::Canvas;
     use crate::tuple::Tuple;
 
     #[test]
@@ -154,7 +617,7 @@ mod tests {
         assert_eq!(c.hsize, 160);
         assert_eq!(c.vsize, 120);
         assert_eq!(c.field_of_view, std::f64::consts::PI / 2.0);
-        assert_eq!(c.transform, Matrix::identity(4));
+        assert_eq!(c.transform, Matrix4::identity());
 
         let c = Camera::new(200, 125, std::f64::consts::PI / 2.0);
         assert_eq!((c.pixel_size - 0.01).abs() < EPSILON, true);
@@ -179,10 +642,19 @@ mod tests {
         assert_eq!(r.direction, Tuple::vector(0.66519, 0.33259, -0.66851));
     }
 
+    #[test]
+    fn rays_for_pixels_carry_the_rays_of_their_neighbours() {
+        let c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+        let differentials = c.ray_for_pixel(100, 50).differentials.unwrap();
+        assert_eq!(differentials.dx_direction, c.ray_for_pixel(101, 50).direction);
+        assert_eq!(differentials.dy_direction, c.ray_for_pixel(100, 51).direction);
+        assert_eq!(differentials.dx_origin, Tuple::point(0.0, 0.0, 0.0));
+    }
+
     #[test]
     fn test_ray_for_pixel_transformed() {
         let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
-        c.transform = Matrix::rotate_y(std::f64::consts::PI / 4.0).multiply(&Matrix::translate(0.0, -2.0, 5.0));
+        c.transform = Matrix4::rotate_y(std::f64::consts::PI / 4.0).multiply(&Matrix4::translate(0.0, -2.0, 5.0));
         let r = c.ray_for_pixel(100, 50);
         assert_eq!(r.origin, Tuple::point(0.0, 2.0, -5.0));
         assert_eq!(r.direction, Tuple::vector(2f64.sqrt() / 2.0, 0.0, -2f64.sqrt() / 2.0));
@@ -216,6 +688,53 @@ mod tests {
         assert_eq!(pixels, vec![(0, 0),(1, 0),(0, 1),(1, 1),(0, 2),(1, 2)]);
     }
 
+    #[test]
+    fn tiles_cover_the_canvas() {
+        let tiles = super::tile_coordinates(3, 5, 2).collect::<Vec<_>>();
+        assert_eq!(tiles, vec![(0, 0, 2, 2), (2, 0, 4, 2), (4, 0, 5, 2),
+                               (0, 2, 2, 3), (2, 2, 4, 3), (4, 2, 5, 3)]);
+    }
+
+    #[test]
+    fn rendering_a_scene_with_a_single_thread() {
+        use crate::color::Color;
+        let scene = Scene::default_scene();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+        let from = Tuple::point(0.0, 0.0, -5.0);
+        let to = Tuple::point(0.0, 0.0, 0.0);
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+        c.transform = Matrix4::view_transform(from, to, up);
+        c.threads = 1;
+        let image = c.render(&scene);
+        assert_eq!(image.pixel_at(5, 5), Color::new(0.38066, 0.47583, 0.2855));
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        use crate::color::Color;
+        let mut scene = Scene::new();
+        scene.add_light(Light::new_area_light(Tuple::point(-2.0, 5.0, -2.0),
+                                              Tuple::vector(4.0, 0.0, 0.0),
+                                              Tuple::vector(0.0, 0.0, 4.0),
+                                              Color::new(1.0, 1.0, 1.0),
+                                              3));
+        scene.add_object(Arc::new(Sphere::new()));
+        let mut floor = Plane::new();
+        floor.transform = Matrix4::translate(0.0, -1.0, 0.0);
+        scene.add_object(Arc::new(floor));
+        let mut c = Camera::new(21, 21, std::f64::consts::PI / 2.0);
+        c.transform = Matrix4::view_transform(Tuple::point(0.0, 3.0, -6.0),
+                                             Tuple::point(0.0, -1.0, 0.0),
+                                             Tuple::vector(0.0, 1.0, 0.0));
+        c.seed = Some(7);
+        let first = c.render(&scene);
+        let second = c.render(&scene);
+        for (x, y) in super::pixel_coordinates(21, 21) {
+            let (a, b) = (first.pixel_at(x, y), second.pixel_at(x, y));
+            assert!(a.r == b.r && a.g == b.g && a.b == b.b);
+        }
+    }
+
     #[test]
     #[ignore]
     fn test_render_chap7() {
@@ -225,52 +744,52 @@ mod tests {
         let from = Tuple::point(0.0, 1.5, -5.0);
         let to = Tuple::point(0.0, 1.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut floor = Sphere::new();
-        floor.transform = Matrix::scale(10.0, 0.01, 10.0);
-        floor.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        floor.transform = Matrix4::scale(10.0, 0.01, 10.0);
+        floor.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         floor.material.specular = 0.0;
         w.add_object(Arc::new(floor));
 
         let mut left_wall = Sphere::new();
-        left_wall.transform = Matrix::translate(0.0, 0.0, 5.0)
-            .multiply(&Matrix::rotate_y(-std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
-            .multiply(&Matrix::scale(10.0, 0.01, 10.0));
-        left_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        left_wall.transform = Matrix4::translate(0.0, 0.0, 5.0)
+            .multiply(&Matrix4::rotate_y(-std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
+            .multiply(&Matrix4::scale(10.0, 0.01, 10.0));
+        left_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         left_wall.material.specular = 0.0;
         w.add_object(Arc::new(left_wall));
 
         let mut right_wall = Sphere::new();
-        right_wall.transform = Matrix::translate(0.0, 0.0, 5.0)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
-            .multiply(&Matrix::scale(10.0, 0.01, 10.0));
-        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        right_wall.transform = Matrix4::translate(0.0, 0.0, 5.0)
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
+            .multiply(&Matrix4::scale(10.0, 0.01, 10.0));
+        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         right_wall.material.specular = 0.0;
         w.add_object(Arc::new(right_wall));
 
         let mut middle = Sphere::new();
-        middle.transform = Matrix::translate(-0.5, 1.0, 0.5);
-        middle.material.pattern = Pattern::solid(Color::new(0.1, 1.0, 0.5), Matrix::identity(4));
+        middle.transform = Matrix4::translate(-0.5, 1.0, 0.5);
+        middle.material.pattern = Pattern::solid(Color::new(0.1, 1.0, 0.5), Matrix4::identity());
         middle.material.diffuse = 0.7;
         middle.material.specular = 0.3;
         w.add_object(Arc::new(middle));
 
         let mut right = Sphere::new();
-        right.transform = Matrix::translate(1.5, 0.5, -0.5).multiply(&Matrix::scale(0.5, 0.5, 0.5));
-        right.material.pattern = Pattern::solid(Color::new(0.5, 1.0, 0.1), Matrix::identity(4));
+        right.transform = Matrix4::translate(1.5, 0.5, -0.5).multiply(&Matrix4::scale(0.5, 0.5, 0.5));
+        right.material.pattern = Pattern::solid(Color::new(0.5, 1.0, 0.1), Matrix4::identity());
         right.material.diffuse = 0.7;
         right.material.specular = 0.3;
         w.add_object(Arc::new(right));
 
         let mut left = Sphere::new();
-        left.transform = Matrix::translate(-1.5, 0.33, -0.75).multiply(&Matrix::scale(0.33, 0.33, 0.33));
-        left.material.pattern = Pattern::solid(Color::new(1.0, 0.8, 0.1), Matrix::identity(4));
+        left.transform = Matrix4::translate(-1.5, 0.33, -0.75).multiply(&Matrix4::scale(0.33, 0.33, 0.33));
+        left.material.pattern = Pattern::solid(Color::new(1.0, 0.8, 0.1), Matrix4::identity());
         left.material.diffuse = 0.7;
         left.material.specular = 0.3;
         w.add_object(Arc::new(left));
@@ -291,61 +810,61 @@ mod tests {
         let from = Tuple::point(0.0, 1.5, -5.0);
         let to = Tuple::point(0.0, 1.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut floor = Plane::new();
-        floor.transform = Matrix::translate(0.0, 0.0, 0.0);
-        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                 Matrix::scale(0.1, 0.1, 0.1).multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0)));
+        floor.transform = Matrix4::translate(0.0, 0.0, 0.0);
+        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                 Matrix4::scale(0.1, 0.1, 0.1).multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0)));
         floor.material.specular = 0.0;
         w.add_object(Arc::new(floor));
 
         let mut left_wall = Plane::new();
-        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                       Matrix::identity(4)
-                                                           .multiply(&Matrix::translate(124.0, 124.0, 124.0)
-                                                               .multiply(&Matrix::scale(7.0, 7.0, 7.0))
+        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                       Matrix4::identity()
+                                                           .multiply(&Matrix4::translate(124.0, 124.0, 124.0)
+                                                               .multiply(&Matrix4::scale(7.0, 7.0, 7.0))
                                                            ));
-        left_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / -4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        left_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / -4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
         left_wall.material.specular = 0.0;
         w.add_object(Arc::new(left_wall));
 
         let mut right_wall = Plane::new();
-        right_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        right_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
-        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         right_wall.material.specular = 0.0;
         w.add_object(Arc::new(right_wall));
 
         let mut middle = Sphere::new();
-        middle.transform = Matrix::translate(-0.5, 1.0, 0.5);
-        middle.material.pattern = Pattern::solid(Color::new(0.1, 1.0, 0.5), Matrix::identity(4));
+        middle.transform = Matrix4::translate(-0.5, 1.0, 0.5);
+        middle.material.pattern = Pattern::solid(Color::new(0.1, 1.0, 0.5), Matrix4::identity());
         middle.material.diffuse = 0.7;
         middle.material.specular = 0.3;
         w.add_object(Arc::new(middle));
 
         let mut right = Sphere::new();
-        right.transform = Matrix::translate(1.5, 0.5, -0.5).multiply(&Matrix::scale(0.5, 0.5, 0.5));
-        right.material.pattern = Pattern::solid(Color::new(0.5, 1.0, 0.1), Matrix::identity(4));
+        right.transform = Matrix4::translate(1.5, 0.5, -0.5).multiply(&Matrix4::scale(0.5, 0.5, 0.5));
+        right.material.pattern = Pattern::solid(Color::new(0.5, 1.0, 0.1), Matrix4::identity());
         right.material.diffuse = 0.7;
         right.material.specular = 0.3;
         w.add_object(Arc::new(right));
 
         let mut left = Sphere::new();
-        left.transform = Matrix::translate(-1.5, 0.33, -0.75).multiply(&Matrix::scale(0.33, 0.33, 0.33));
-        left.material.pattern = Pattern::solid(Color::new(1.0, 0.8, 0.1), Matrix::identity(4));
+        left.transform = Matrix4::translate(-1.5, 0.33, -0.75).multiply(&Matrix4::scale(0.33, 0.33, 0.33));
+        left.material.pattern = Pattern::solid(Color::new(1.0, 0.8, 0.1), Matrix4::identity());
         left.material.diffuse = 0.7;
         left.material.specular = 0.3;
         w.add_object(Arc::new(left));
@@ -356,4 +875,160 @@ mod tests {
 
         image.write_to_file("canvas.png",1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn exposing_an_image() {
+        let mut c = Camera::new(3, 1, std::f64::consts::PI / 2.0);
+        assert_eq!(c.exposure_scale(), 1.0);
+        c.exposure = Some(Exposure::Ev100(0.0));
+        assert!((c.exposure_scale() - 1.0 / (1.2 * std::f64::consts::PI)).abs() < EPSILON);
+
+        c.exposure = Some(Exposure::Auto);
+        assert_eq!(c.exposure_scale(), 1.0);
+        let mut image = Canvas::new(3, 1);
+        image.write_pixel(0, 0, Color::new(40.0, 40.0, 40.0));
+        image.write_pixel(1, 0, Color::new(10.0, 10.0, 10.0));
+        c.auto_expose(&mut image);
+        // The geometric mean of the two lit pixels, 20, becomes middle grey
+        assert_eq!(image.pixel_at(0, 0), Color::new(0.36, 0.36, 0.36));
+        assert_eq!(image.pixel_at(1, 0), Color::new(0.09, 0.09, 0.09));
+        assert_eq!(image.pixel_at(2, 0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn the_field_of_view_of_a_lens() {
+        // A 18mm lens on a full frame sensor sees 90 degrees across
+        let landscape = field_of_view_from_lens(18.0, 36.0, 200, 100);
+        assert!((landscape - std::f64::consts::PI / 2.0).abs() < EPSILON);
+        // In portrait the field of view spans the height, which is twice the width
+        let portrait = field_of_view_from_lens(18.0, 36.0, 100, 200);
+        assert!((portrait - 2.0 * 2_f64.atan()).abs() < EPSILON);
+        let c = Camera::new(100, 200, portrait);
+        assert!((c.half_width - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn shifting_the_lens_moves_the_image_without_turning_the_camera() {
+        let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+        c.shift_y = 0.25;
+        let r = c.ray_for_pixel(100, 50);
+        assert_eq!(r.origin, Tuple::point(0.0, 0.0, 0.0));
+        assert_eq!(r.direction, Tuple::vector(0.0, 0.5, -1.0).normalize());
+        c.shift_y = 0.0;
+        c.shift_x = 0.25;
+        let r = c.ray_for_pixel(100, 50);
+        // The camera looks toward -z, so its right is -x
+        assert_eq!(r.direction, Tuple::vector(-0.5, 0.0, -1.0).normalize());
+    }
+
+    #[test]
+    fn the_eyes_of_a_stereo_pair_converge_ahead_of_the_camera() {
+        let c = Camera::new(201, 101, std::f64::consts::PI / 2.0);
+        let right = c.eye(0.5, 5.0);
+        let r = right.ray_for_pixel(100, 50);
+        // The right eye is at -x, and its center ray meets the camera's at the convergence distance
+        assert_eq!(r.origin, Tuple::point(-0.5, 0.0, 0.0));
+        assert_eq!(r.direction, Tuple::vector(0.5, 0.0, -5.0).normalize());
+        let left = c.eye(-0.5, 5.0);
+        assert_eq!(left.ray_for_pixel(100, 50).direction, Tuple::vector(-0.5, 0.0, -5.0).normalize());
+    }
+
+    #[test]
+    fn combining_the_images_of_a_stereo_pair() {
+        let c = Camera::new(2, 1, std::f64::consts::PI / 2.0);
+        let red = Color::new(1.0, 0.0, 0.0);
+        let cyan = Color::new(0.0, 1.0, 1.0);
+        let mut stereo = Stereo { interocular: 0.1, convergence: 5.0, output: StereoOutput::SideBySide };
+        let render_eye = |eye: &Camera| {
+            let mut image = Canvas::new(eye.hsize, eye.vsize);
+            let color = if eye.shift_x > 0.0 { red } else { cyan };
+            image.pixels.iter_mut().for_each(|p| *p = color);
+            image
+        };
+        let pair = c.render_stereo(&stereo, render_eye);
+        assert_eq!((pair.width, pair.height), (4, 1));
+        assert_eq!(pair.pixel_at(1, 0), red);
+        assert_eq!(pair.pixel_at(2, 0), cyan);
+        stereo.output = StereoOutput::Anaglyph;
+        let anaglyph = c.render_stereo(&stereo, render_eye);
+        assert_eq!((anaglyph.width, anaglyph.height), (2, 1));
+        assert_eq!(anaglyph.pixel_at(0, 0), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn rendering_only_a_region_of_the_image() {
+        let w = Scene::default_scene();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+        c.transform = Matrix4::view_transform(Tuple::point(0.0, 0.0, -5.0), Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 1.0, 0.0));
+        c.progress = ProgressMode::Quiet;
+        c.region = Some((4, 4, 7, 20));
+        assert_eq!(c.render_region(), (4, 4, 7, 11));
+        let image = c.render(&w);
+        assert_eq!(image.pixel_at(5, 5), Color::new(0.38066, 0.47583, 0.2855));
+        assert_eq!(image.pixel_at(3, 5), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(image.pixel_at(5, 3), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn tiles_are_marked_when_the_render_runs_out_of_time() {
+        let w = Scene::default_scene();
+        let mut c = Camera::new(20, 20, std::f64::consts::PI / 2.0);
+        c.progress = ProgressMode::Quiet;
+        c.deadline = Some(std::time::Instant::now());
+        assert!(c.out_of_time());
+        let image = c.render(&w);
+        assert!(image.pixels.iter().all(|p| *p == Color::new(1.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn rendering_to_an_image_in_memory() {
+        let w = Scene::default_scene();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.0);
+        c.transform = Matrix4::view_transform(Tuple::point(0.0, 0.0, -5.0), Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 1.0, 0.0));
+        c.progress = ProgressMode::Quiet;
+        let image = c.render_to_image(&w);
+        assert_eq!(image.dimensions(), (11, 11));
+        // The color at the center is (0.38066, 0.47583, 0.2855)
+        assert_eq!(image.get_pixel(5, 5).0, [97, 121, 72, 255]);
+    }
+
+    #[test]
+    fn renders_report_finished_tiles_and_stop_when_cancelled() {
+        let w = Scene::default_scene();
+        let mut c = Camera::new(40, 20, std::f64::consts::PI / 2.0);
+        c.transform = Matrix4::view_transform(Tuple::point(0.0, 0.0, -5.0), Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 1.0, 0.0));
+        c.progress = ProgressMode::Quiet;
+        c.threads = 1;
+        let tiles = Mutex::new(vec![]);
+        c.render_with(&w, Some(&|tile: &TileProgress| tiles.lock().unwrap().push(*tile)), None);
+        let tiles = tiles.into_inner().unwrap();
+        assert_eq!(tiles.len(), 6);
+        let pixels: usize = tiles.iter().map(|t| (t.x1 - t.x0) * (t.y1 - t.y0)).sum();
+        assert_eq!(pixels, 800);
+        assert_eq!(tiles.last().unwrap().percent(), 100.0);
+
+        let cancel = CancelToken::new();
+        let finished = AtomicUsize::new(0);
+        let image = c.render_with(&w, Some(&|_: &TileProgress| {
+            finished.fetch_add(1, Ordering::Relaxed);
+            cancel.cancel();
+        }), Some(&cancel));
+        assert_eq!(finished.into_inner(), 1);
+        assert!(image.pixels.iter().filter(|p| **p != Color::new(0.0, 0.0, 0.0)).count() <= 256);
+    }
+
+    #[test]
+    fn rendering_with_a_film_collects_the_samples_of_each_pixel() {
+        let w = Scene::default_scene();
+        let mut c = Camera::new(22, 22, std::f64::consts::PI / 2.0);
+        c.transform = Matrix4::view_transform(Tuple::point(0.0, 0.0, -5.0), Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 1.0, 0.0));
+        c.progress = ProgressMode::Quiet;
+        let (image, film) = c.render_with_film(&w, 2);
+        assert_eq!((image.width, image.height), (22, 22));
+        assert_eq!((film.width, film.height), (11, 11));
+        assert!((0..11).all(|y| (0..11).all(|x| film.sample_count(x, y) == 4)));
+        let mean = image.pixel_at(10, 10).add(&image.pixel_at(11, 10)).add(&image.pixel_at(10, 11)).add(&image.pixel_at(11, 11)).multiply(0.25);
+        assert_eq!(film.mean(5, 5), mean);
+        assert_eq!(film.to_canvas().pixel_at(5, 5), mean);
+    }
+}
@@ -1,7 +1,14 @@
 #![allow(dead_code)]
 
+use std::fs::File;
+use std::io::BufWriter;
 use crate::color::Color;
-use image::{ImageBuffer, Rgba};
+use image::{DynamicImage, ExtendedColorType, ImageEncoder, ImageError, ImageFormat, Rgb32FImage, RgbaImage};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+
+/// The quality lossy image formats are written with, unless another one is given.
+pub const DEFAULT_QUALITY: u8 = 90;
 
 /// Represents a canvas for drawing in a ray tracing application.
 ///
@@ -40,6 +47,83 @@ impl Canvas {
         Canvas { width, height, pixels }
     }
 
+    /// Creates a grayscale `Canvas` from the distances to the first hit of each pixel.
+    ///
+    /// The depths are normalized so that the closest hit is white and the farthest hit, or `far`
+    /// when it is given, is black. Hits beyond `far` and pixels without a hit are black as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the canvas in pixels.
+    /// * `height` - The height of the canvas in pixels.
+    /// * `depths` - The distance to the first hit of each pixel, row by row, or `None` for a miss.
+    /// * `far` - The distance that maps to black, or `None` to use the farthest hit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Canvas` instance holding the normalized depth map.
+    pub fn from_depth_map(width: usize, height: usize, depths: &[Option<f64>], far: Option<f64>) -> Canvas {
+        let hits = depths.iter().flatten();
+        let near = hits.clone().fold(f64::INFINITY, |a, &b| a.min(b));
+        let far = far.unwrap_or_else(|| hits.fold(f64::NEG_INFINITY, |a, &b| a.max(b)));
+        let range = far - near;
+        let pixels = depths.iter()
+            .map(|depth| match depth {
+                Some(d) if range > 0.0 => (1.0 - (d - near) / range).max(0.0),
+                Some(_) => 1.0,
+                None => 0.0,
+            })
+            .map(|v| Color::new(v, v, v))
+            .collect();
+        Canvas { width, height, pixels }
+    }
+
+    /// Creates a `Canvas` with two images of the same size next to each other.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - The image on the left.
+    /// * `right` - The image on the right.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Canvas` instance twice as wide as the images.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images are not the same size.
+    pub fn side_by_side(left: &Canvas, right: &Canvas) -> Canvas {
+        assert_eq!((left.width, left.height), (right.width, right.height), "The images are not the same size");
+        let pixels = left.pixels.chunks(left.width)
+            .zip(right.pixels.chunks(right.width))
+            .flat_map(|(l, r)| l.iter().chain(r.iter()).copied())
+            .collect();
+        Canvas { width: left.width * 2, height: left.height, pixels }
+    }
+
+    /// Creates a red-cyan anaglyph `Canvas` from the images of the left and the right eye.
+    ///
+    /// # Arguments
+    ///
+    /// * `left` - The image of the left eye, which gives the red channel.
+    /// * `right` - The image of the right eye, which gives the green and blue channels.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `Canvas` instance the size of the images.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images are not the same size.
+    pub fn anaglyph(left: &Canvas, right: &Canvas) -> Canvas {
+        assert_eq!((left.width, left.height), (right.width, right.height), "The images are not the same size");
+        let pixels = left.pixels.iter()
+            .zip(right.pixels.iter())
+            .map(|(l, r)| Color::new(l.r, r.g, r.b))
+            .collect();
+        Canvas { width: left.width, height: left.height, pixels }
+    }
+
     /// Writes a pixel with a specified color at the given coordinates.
     ///
     /// This method modifies the color of a single pixel in the canvas's pixel buffer.
@@ -73,8 +157,9 @@ impl Canvas {
         self.pixels[index]
     }
 
-    fn get_u8_colors(&self, aa: usize) -> Vec<u8> {
-        let mut data = Vec::new();
+    /// Averages each `aa` x `aa` block of pixels, row by row.
+    fn averaged_colors(&self, aa: usize) -> Vec<Color> {
+        let mut colors = Vec::new();
         let total_pixels = (aa * aa) as f64;
         for y in (0..self.height).step_by(aa) {
             for x in (0..self.width).step_by(aa) {
@@ -91,26 +176,60 @@ impl Canvas {
                     }
                 }
 
-                r /= total_pixels;
-                g /= total_pixels;
-                b /= total_pixels;
-
-                data.push((r * 255.0) as u8);
-                data.push((g * 255.0) as u8);
-                data.push((b * 255.0) as u8);
-                data.push(255u8);
+                colors.push(Color::new(r / total_pixels, g / total_pixels, b / total_pixels));
             }
         }
-        data
+        colors
     }
 
+    fn get_u8_colors(&self, aa: usize) -> Vec<u8> {
+        self.averaged_colors(aa).iter()
+            .flat_map(|c| [(c.r * 255.0) as u8, (c.g * 255.0) as u8, (c.b * 255.0) as u8, 255u8])
+            .collect()
+    }
 
-    /// Writes the canvas content to a PNG file with anti-aliasing.
+    /// Converts the canvas to an 8 bit RGBA image, with anti-aliasing.
     ///
-    /// This method saves the current state of the canvas to a PNG file, applying anti-aliasing
-    /// based on the `aa` (anti-aliasing factor) provided. The anti-aliasing process averages
-    /// the colors of `aa` x `aa` blocks of pixels to smooth out the transitions between colors.
-    /// The resulting image is saved to the specified filename.
+    /// Each pixel of the image is the average of an `aa` x `aa` block of pixels of the canvas, and its
+    /// color channels are clamped to the range 0 to 1. This is the image `write_to_file` saves.
+    ///
+    /// # Arguments
+    ///
+    /// * `aa` - The anti-aliasing factor, specifying the size of the pixel blocks to average.
+    ///
+    /// # Returns
+    ///
+    /// An image `aa` times smaller than the canvas in each direction.
+    pub fn to_image(&self, aa: usize) -> RgbaImage {
+        let width = (self.width / aa) as u32;
+        let height = (self.height / aa) as u32;
+        RgbaImage::from_raw(width, height, self.get_u8_colors(aa)).unwrap()
+    }
+
+    /// Converts the canvas to a floating point RGB image, with anti-aliasing.
+    ///
+    /// Like `to_image`, but the colors are kept as they are, so lights and highlights brighter than 1
+    /// keep their brightness.
+    ///
+    /// # Arguments
+    ///
+    /// * `aa` - The anti-aliasing factor, specifying the size of the pixel blocks to average.
+    ///
+    /// # Returns
+    ///
+    /// An image `aa` times smaller than the canvas in each direction.
+    pub fn to_hdr_image(&self, aa: usize) -> Rgb32FImage {
+        let width = (self.width / aa) as u32;
+        let height = (self.height / aa) as u32;
+        let data = self.averaged_colors(aa).iter()
+            .flat_map(|c| [c.r as f32, c.g as f32, c.b as f32])
+            .collect();
+        Rgb32FImage::from_raw(width, height, data).unwrap()
+    }
+
+    /// Writes the canvas content to an image file with anti-aliasing, in the default quality.
+    ///
+    /// See `write_to_file_with_quality`.
     ///
     /// # Arguments
     ///
@@ -120,23 +239,84 @@ impl Canvas {
     ///
     /// # Panics
     ///
-    /// Panics if the file cannot be created, or if there is an error writing the PNG data to the file.
+    /// Panics if the format is unknown, or if the file cannot be written.
     pub fn write_to_file(&self, filename: &str, aa: usize) {
-        let width = (self.width / aa) as u32;
-        let height = (self.height / aa) as u32;
-        let data = self.get_u8_colors(aa);
+        self.write_to_file_with_quality(filename, aa, DEFAULT_QUALITY);
+    }
 
-        let img_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data).unwrap();
-        img_buffer.save(filename).unwrap();
+    /// Writes the canvas content to an image file with anti-aliasing.
+    ///
+    /// The anti-aliasing process averages the colors of `aa` x `aa` blocks of pixels to smooth out the
+    /// transitions between colors. The format of the file is chosen by its extension, from those the
+    /// `image` crate can write, such as PNG, JPEG, WebP, TIFF and BMP. JPEG and AVIF are compressed with
+    /// the given quality; JPEG has no alpha channel and WebP is always lossless. OpenEXR (`.exr`) and
+    /// Radiance HDR (`.hdr`) files get floating point colors, which keep values brighter than 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The path and name of the file where the canvas should be saved.
+    /// * `aa` - The anti-aliasing factor, specifying the size of the pixel blocks to average
+    ///   for anti-aliasing. A higher value results in more smoothing but can lead to loss of detail.
+    /// * `quality` - The quality of lossy formats, from 1 (smallest file) to 100 (best image).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format is unknown or cannot be written, or if the file cannot be written.
+    pub fn write_to_file_with_quality(&self, filename: &str, aa: usize, quality: u8) {
+        let format = output_format(filename);
+        let result = match format {
+            ImageFormat::Jpeg => File::create(filename).map_err(ImageError::IoError).and_then(|file| {
+                let image = DynamicImage::ImageRgba8(self.to_image(aa)).to_rgb8();
+                JpegEncoder::new_with_quality(BufWriter::new(file), quality).write_image(
+                    image.as_raw(), image.width(), image.height(), ExtendedColorType::Rgb8)
+            }),
+            ImageFormat::Avif => File::create(filename).map_err(ImageError::IoError).and_then(|file| {
+                let image = self.to_image(aa);
+                AvifEncoder::new_with_speed_quality(BufWriter::new(file), 4, quality).write_image(
+                    image.as_raw(), image.width(), image.height(), ExtendedColorType::Rgba8)
+            }),
+            ImageFormat::OpenExr | ImageFormat::Hdr => self.to_hdr_image(aa).save_with_format(filename, format),
+            ImageFormat::Farbfeld => DynamicImage::ImageRgba8(self.to_image(aa)).to_rgba16().save_with_format(filename, format),
+            _ => self.to_image(aa).save_with_format(filename, format),
+        };
+        result.unwrap_or_else(|e| panic!("cannot write image {}: {}", filename, e));
     }
 }
 
+/// Finds the format of an image file from its extension, checking that images can be written in it.
+///
+/// # Arguments
+///
+/// * `filename` - The name of the image file.
+///
+/// # Returns
+///
+/// The format of the file.
+///
+/// # Panics
+///
+/// Panics if the format is unknown or cannot be written.
+pub fn output_format(filename: &str) -> ImageFormat {
+    let format = ImageFormat::from_path(filename)
+        .unwrap_or_else(|_| panic!("unknown image format for {}", filename));
+    if !format.writing_enabled() {
+        panic!("cannot write {:?} images", format);
+    }
+    format
+}
 
 #[cfg(test)]
 mod tests {
-    use super::Canvas;
+    use super::{output_format, Canvas};
     use crate::color::Color;
 
+    #[test]
+    #[should_panic(expected = "unknown image format for render.xyz")]
+    fn output_formats_are_checked_by_extension() {
+        assert_eq!(output_format("render.jpeg"), image::ImageFormat::Jpeg);
+        output_format("render.xyz");
+    }
+
     #[test]
     fn test_canvas() {
         let c = Canvas::new(10, 20);
@@ -156,4 +336,50 @@ mod tests {
         let red = Color::new(1.0, 0.0, 0.0);
         assert_eq!(c.pixel_at(2, 3), red);
     }
+
+    #[test]
+    fn converting_to_an_image_averages_blocks_of_pixels() {
+        let mut c = Canvas::new(4, 2);
+        c.write_pixel(0, 0, Color::new(1.0, 1.0, 1.0));
+        c.write_pixel(1, 1, Color::new(1.0, 0.0, 2.0));
+        let image = c.to_image(2);
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [127, 63, 191, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn the_file_format_is_chosen_by_the_extension() {
+        let mut c = Canvas::new(8, 8);
+        for pixel in c.pixels.iter_mut() {
+            *pixel = Color::new(2.0, 0.0, 0.0);
+        }
+        let dir = std::env::temp_dir();
+        for extension in ["png", "jpg", "webp", "tiff", "bmp"] {
+            let file = dir.join(format!("rray_canvas_test.{}", extension));
+            c.write_to_file(file.to_str().unwrap(), 2);
+            let image = image::open(&file).unwrap().to_rgb8();
+            assert_eq!(image.dimensions(), (4, 4));
+            let pixel = image.get_pixel(1, 1).0;
+            assert!(pixel[0] > 240 && pixel[1] < 16 && pixel[2] < 16, "{} {:?}", extension, pixel);
+            std::fs::remove_file(file).unwrap();
+        }
+        let file = dir.join("rray_canvas_test.exr");
+        c.write_to_file(file.to_str().unwrap(), 1);
+        let image = image::open(&file).unwrap().to_rgb32f();
+        assert_eq!(image.get_pixel(0, 0).0, [2.0, 0.0, 0.0]);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn depth_maps_are_normalized() {
+        let c = Canvas::from_depth_map(2, 2, &[Some(2.0), Some(4.0), Some(3.0), None], None);
+        assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(0, 1), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(c.pixel_at(1, 1), Color::new(0.0, 0.0, 0.0));
+
+        let c = Canvas::from_depth_map(2, 1, &[Some(2.0), Some(4.0)], Some(3.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(0.0, 0.0, 0.0));
+    }
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+use crate::color::Color;
+use crate::raytracer::material::pattern_at_object_filtered;
+use crate::raytracer::ray::{Footprint, RayDifferentials};
 use crate::tuple::Tuple;
 
 /// Represents the various computations needed for shading an intersection point.
@@ -22,9 +25,18 @@ pub struct Computations {
     pub reflectv: Tuple,    // The reflection vector at the point of intersection.
     pub n1: f64,            // The refractive index of the medium the ray is coming from.
     pub n2: f64,            // The refractive index of the medium the ray is entering.
+    pub vertex_color: Color, // The color of the mesh at the point of intersection, white if it has no vertex colors.
+    pub footprint: Option<Footprint>, // The area of the surface covered by the pixel, for rays with differentials.
+    pub reflected_differentials: Option<RayDifferentials>, // The differentials of the reflected ray, for rays with differentials.
 }
 
 impl Computations {
+    /// Returns the color of the surface at the intersection: the material's pattern, averaged over the
+    /// footprint of the pixel when there is one, times the vertex colors.
+    pub fn surface_color(&self) -> Color {
+        pattern_at_object_filtered(self.object, &self.over_point, self.footprint.as_ref()).product(&self.vertex_color)
+    }
+
     /// Calculates the Schlick approximation for the reflectance.
     ///
     /// This function computes the Schlick approximation, which is an estimation
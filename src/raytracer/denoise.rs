@@ -0,0 +1,132 @@
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use crate::color::Color;
+use crate::raytracer::canvas::Canvas;
+
+/// The number of passes of the filter. Each pass looks twice as far as the one before, so the last one
+/// reaches 2 * 2^4 = 32 pixels away.
+const PASSES: usize = 5;
+
+/// The weights of the pixels of the 5x5 kernel along each axis, from a cubic B-spline.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// How different the lighting of two pixels may be before they stop being blended, halved in every pass.
+const SIGMA_LIGHT: f64 = 0.6;
+
+/// How different the normals of two pixels may be before they stop being blended.
+const SIGMA_NORMAL: f64 = 0.3;
+
+/// How different the surface colors of two pixels may be before they stop being blended.
+const SIGMA_ALBEDO: f64 = 0.1;
+
+/// The smallest surface color channel the lighting is worked out for, so that black surfaces do not divide by 0.
+const MIN_ALBEDO: f64 = 0.01;
+
+/// Removes the noise of soft shadows, glossy refraction and other sampled effects from a rendered image.
+///
+/// The image is divided by the surface colors, which leaves the lighting, so that textures are not blurred.
+/// The lighting is then smoothed by an edge-avoiding à-trous wavelet filter: in each pass every pixel is
+/// blended with the pixels around it, weighted by how similar their lighting, normals and surface colors
+/// are, so edges between objects and creases stay sharp. Finally the lighting is multiplied by the surface
+/// colors again.
+///
+/// # Arguments
+///
+/// * `beauty` - The rendered image.
+/// * `albedo` - The color of the surfaces seen in each pixel, without lighting, see `Camera::render_guides`.
+/// * `normals` - The normal of the surfaces seen in each pixel, stored as colors.
+///
+/// # Returns
+///
+/// The denoised image, the same size as `beauty`.
+///
+/// # Panics
+///
+/// Panics if the images are not all the same size.
+pub fn denoise(beauty: &Canvas, albedo: &Canvas, normals: &Canvas) -> Canvas {
+    assert!(beauty.width == albedo.width && beauty.height == albedo.height, "the albedo pass must have the size of the image");
+    assert!(beauty.width == normals.width && beauty.height == normals.height, "the normal pass must have the size of the image");
+    let divisor = |a: &Color| Color::new(a.r.max(MIN_ALBEDO), a.g.max(MIN_ALBEDO), a.b.max(MIN_ALBEDO));
+    let mut light: Vec<Color> = beauty.pixels.iter().zip(&albedo.pixels)
+        .map(|(c, a)| {
+            let d = divisor(a);
+            Color::new(c.r / d.r, c.g / d.g, c.b / d.b)
+        })
+        .collect();
+    for pass in 0..PASSES {
+        light = filter_pass(&light, albedo, normals, 1 << pass, SIGMA_LIGHT / (1 << pass) as f64);
+    }
+    let pixels = light.iter().zip(&albedo.pixels).map(|(l, a)| l.product(&divisor(a))).collect();
+    Canvas { width: beauty.width, height: beauty.height, pixels }
+}
+
+/// Runs one pass of the edge-avoiding filter over the lighting, with the kernel pixels `step` pixels apart.
+fn filter_pass(light: &[Color], albedo: &Canvas, normals: &Canvas, step: usize, sigma_light: f64) -> Vec<Color> {
+    let (width, height) = (albedo.width, albedo.height);
+    (0..width * height).into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut total = Color::new(0.0, 0.0, 0.0);
+            let mut total_weight = 0.0;
+            for (ky, wy) in KERNEL.iter().enumerate() {
+                let qy = y as isize + (ky as isize - 2) * step as isize;
+                if qy < 0 || qy >= height as isize {
+                    continue;
+                }
+                for (kx, wx) in KERNEL.iter().enumerate() {
+                    let qx = x as isize + (kx as isize - 2) * step as isize;
+                    if qx < 0 || qx >= width as isize {
+                        continue;
+                    }
+                    let j = qy as usize * width + qx as usize;
+                    let weight = wx * wy
+                        * similarity(&light[i], &light[j], sigma_light)
+                        * similarity(&normals.pixels[i], &normals.pixels[j], SIGMA_NORMAL)
+                        * similarity(&albedo.pixels[i], &albedo.pixels[j], SIGMA_ALBEDO);
+                    total = total.add(&light[j].multiply(weight));
+                    total_weight += weight;
+                }
+            }
+            // The pixel itself always has a weight, so the total is never 0
+            total.multiply(1.0 / total_weight)
+        })
+        .collect()
+}
+
+/// Returns how alike two colors are, from 1 when they are equal towards 0 as they grow more than `sigma` apart.
+fn similarity(a: &Color, b: &Color, sigma: f64) -> f64 {
+    let d = a.subtract(b);
+    (-(d.r * d.r + d.g * d.g + d.b * d.b) / (sigma * sigma)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denoising_smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 16);
+        let mut beauty = Canvas::new(width, height);
+        let mut albedo = Canvas::new(width, height);
+        let mut normals = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                // Two walls facing different ways, the left one dark and the right one bright, both noisy
+                let noise = if (x * 7 + y * 13) % 5 < 2 { 0.1 } else { -0.05 };
+                let (level, normal) = if x < width / 2 { (0.2, Color::new(1.0, 0.0, 0.0)) } else { (0.8, Color::new(0.0, 1.0, 0.0)) };
+                beauty.write_pixel(x, y, Color::new(level + noise, level + noise, level + noise));
+                albedo.write_pixel(x, y, Color::new(1.0, 1.0, 1.0));
+                normals.write_pixel(x, y, normal);
+            }
+        }
+        let denoised = denoise(&beauty, &albedo, &normals);
+        let spread = |canvas: &Canvas, range: std::ops::Range<usize>| {
+            let values: Vec<f64> = (0..height).flat_map(|y| range.clone().map(move |x| (x, y))).map(|(x, y)| canvas.pixel_at(x, y).r).collect();
+            values.iter().cloned().fold(f64::NEG_INFINITY, f64::max) - values.iter().cloned().fold(f64::INFINITY, f64::min)
+        };
+        assert!(spread(&denoised, 0..width / 2) < spread(&beauty, 0..width / 2) / 4.0);
+        assert!(spread(&denoised, width / 2..width) < spread(&beauty, width / 2..width) / 4.0);
+        // The edge between the walls stays sharp
+        assert!(denoised.pixel_at(width / 2 - 1, 8).r < 0.3);
+        assert!(denoised.pixel_at(width / 2, 8).r > 0.7);
+    }
+}
@@ -0,0 +1,312 @@
+use std::f64::consts::PI;
+use image::ImageReader;
+use crate::color::Color;
+use crate::tuple::Tuple;
+use crate::raytracer::sampling::{random_range, sample_2d};
+use crate::raytracer::sky::{sun_solid_angle, Sky};
+
+/// The width of the image a sky is sampled from when it lights the scene, which is twice its height.
+const SKY_WIDTH: usize = 512;
+
+/// An equirectangular (latitude/longitude) image that surrounds the scene.
+///
+/// Rays that do not hit any object return the radiance of the environment in their direction, and the
+/// environment can also light the scene. The image is kept in floating point, so HDR images keep their
+/// full range. The center of the image faces the positive z axis, the top row is straight up.
+///
+/// # Fields
+///
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `pixels` - The radiance of each pixel, row by row, already scaled by the intensity.
+/// * `rotation` - The rotation of the environment around the y axis, in radians.
+/// * `light_samples` - The number of directions sampled to light each hit, or 0 to not use the environment as a light.
+/// * `file` - The image file the environment was loaded from, or `None` if it was made in memory.
+/// * `sky` - The sky model the environment was made from, which is looked up directly instead of the image.
+/// * `ground` - The radiance of the ground below the sky, before the intensity is applied.
+/// * `intensity` - The factor the pixels of the image were multiplied by.
+/// * `row_cdf` - The cumulative distribution of the sampling weights over the rows.
+/// * `column_cdf` - The cumulative distribution of the sampling weights within each row, row by row.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    pub rotation: f64,
+    pub light_samples: usize,
+    pub file: Option<String>,
+    pub sky: Option<Sky>,
+    ground: Color,
+    pub intensity: f64,
+    row_cdf: Vec<f64>,
+    column_cdf: Vec<f64>,
+}
+
+/// Turns a list of weights into a normalized cumulative distribution, or all zeros if the weights sum to zero.
+fn cumulative(weights: &[f64]) -> Vec<f64> {
+    let mut sum = 0.0;
+    let mut cdf: Vec<f64> = weights.iter().map(|w| { sum += w; sum }).collect();
+    if sum > 0.0 {
+        cdf.iter_mut().for_each(|c| *c /= sum);
+    }
+    cdf
+}
+
+/// Finds the index of the bucket of a cumulative distribution that `xi` falls into.
+fn pick(cdf: &[f64], xi: f64) -> usize {
+    cdf.partition_point(|&c| c <= xi).min(cdf.len() - 1)
+}
+
+impl Environment {
+    /// Creates a new `Environment` from the pixels of an equirectangular image.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the image in pixels.
+    /// * `height` - The height of the image in pixels.
+    /// * `pixels` - The radiance of each pixel, row by row.
+    /// * `intensity` - A factor every pixel is multiplied by.
+    /// * `rotation` - The rotation of the environment around the y axis, in radians.
+    /// * `light_samples` - The number of directions sampled to light each hit, or 0 to not use the environment as a light.
+    ///
+    /// # Returns
+    ///
+    /// A new `Environment` instance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image is empty or the number of pixels does not match its size.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, intensity: f64, rotation: f64, light_samples: usize) -> Environment {
+        assert!(width > 0 && height > 0, "The environment image is empty");
+        assert_eq!(pixels.len(), width * height, "The environment image has the wrong number of pixels");
+        let pixels: Vec<Color> = pixels.iter().map(|p| p.multiply(intensity)).collect();
+
+        // Bright pixels are sampled more, and rows close to the poles less, since their pixels cover a smaller solid angle
+        let mut column_cdf = Vec::with_capacity(width * height);
+        let mut row_weights = Vec::with_capacity(height);
+        for y in 0..height {
+            let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+            let weights: Vec<f64> = pixels[y * width..(y + 1) * width].iter()
+                .map(|p| p.luminance().max(0.0) * sin_theta)
+                .collect();
+            row_weights.push(weights.iter().sum());
+            column_cdf.extend(cumulative(&weights));
+        }
+        let row_cdf = cumulative(&row_weights);
+
+        Environment { width, height, pixels, rotation, light_samples, file: None, sky: None, ground: Color::new(0.0, 0.0, 0.0), intensity, row_cdf, column_cdf }
+    }
+
+    /// Loads an `Environment` from an equirectangular image file, such as a `.hdr` or `.exr` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the image file.
+    /// * `intensity` - A factor every pixel is multiplied by.
+    /// * `rotation` - The rotation of the environment around the y axis, in radians.
+    /// * `light_samples` - The number of directions sampled to light each hit, or 0 to not use the environment as a light.
+    ///
+    /// # Returns
+    ///
+    /// A new `Environment` instance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be read or decoded.
+    pub fn load(path: &str, intensity: f64, rotation: f64, light_samples: usize) -> Environment {
+        let image = ImageReader::open(path)
+            .unwrap_or_else(|e| panic!("Cannot open environment image {}: {}", path, e))
+            .decode()
+            .unwrap_or_else(|e| panic!("Cannot decode environment image {}: {}", path, e))
+            .to_rgb32f();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        let mut environment = Environment::new(width as usize, height as usize, pixels, intensity, rotation, light_samples);
+        environment.file = Some(path.to_string());
+        environment
+    }
+
+    /// Creates an `Environment` from an analytic sky with a sun.
+    ///
+    /// Rays that miss every object look the sky up directly, so it stays smooth however closely it is seen.
+    /// For lighting, the sky without the sun is sampled from an image of it, and the sun is sampled on its
+    /// own, see `Environment::sample_sun`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sky` - The sky model.
+    /// * `intensity` - A factor the brightness of the sky is multiplied by.
+    /// * `rotation` - The rotation of the environment around the y axis, in radians.
+    /// * `light_samples` - The number of directions sampled to light each hit, or 0 to not use the environment as a light.
+    ///
+    /// # Returns
+    ///
+    /// A new `Environment` instance.
+    pub fn from_sky(sky: Sky, intensity: f64, rotation: f64, light_samples: usize) -> Environment {
+        let (width, height) = (SKY_WIDTH, SKY_WIDTH / 2);
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut sky_irradiance = Color::new(0.0, 0.0, 0.0);
+        for y in 0..height / 2 {
+            let theta = (y as f64 + 0.5) / height as f64 * PI;
+            let solid_angle = 2.0 * PI / width as f64 * PI / height as f64 * theta.sin();
+            for x in 0..width {
+                let phi = ((x as f64 + 0.5) / width as f64 - 0.5) * 2.0 * PI;
+                let d = Tuple::vector(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos());
+                let radiance = sky.sky_radiance(&d);
+                sky_irradiance = sky_irradiance.add(&radiance.multiply(d.y * solid_angle));
+                pixels.push(radiance);
+            }
+        }
+        let ground = sky.ground_radiance(&sky_irradiance);
+        pixels.resize(width * height, ground);
+
+        let mut environment = Environment::new(width, height, pixels, intensity, rotation, light_samples);
+        environment.sky = Some(sky);
+        environment.ground = ground;
+        environment
+    }
+
+    /// Turns a direction around the y axis by the rotation of the environment, from the environment to the scene.
+    fn rotate(&self, direction: &Tuple) -> Tuple {
+        let (sin, cos) = self.rotation.sin_cos();
+        Tuple::vector(direction.x * cos + direction.z * sin, direction.y, direction.z * cos - direction.x * sin)
+    }
+
+    /// Maps a direction to texture coordinates in the image, both in the range [0, 1].
+    fn direction_to_uv(&self, direction: &Tuple) -> (f64, f64) {
+        let d = direction.normalize();
+        let phi = d.x.atan2(d.z) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// Maps texture coordinates in the image back to a normalized direction.
+    fn uv_to_direction(&self, u: f64, v: f64) -> Tuple {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Tuple::vector(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos())
+    }
+
+    /// Returns the radiance of the environment in a direction.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - The direction to look in; it does not need to be normalized.
+    ///
+    /// # Returns
+    ///
+    /// The color of the environment in that direction.
+    pub fn radiance(&self, direction: &Tuple) -> Color {
+        if let Some(sky) = &self.sky {
+            let d = direction.normalize();
+            let (sin, cos) = self.rotation.sin_cos();
+            let local = Tuple::vector(d.x * cos - d.z * sin, d.y, d.z * cos + d.x * sin);
+            return sky.radiance(&local, &self.ground).multiply(self.intensity);
+        }
+        let (u, v) = self.direction_to_uv(direction);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    /// Picks a random direction, with bright parts of the environment more likely than dark ones.
+    ///
+    /// # Returns
+    ///
+    /// The normalized direction, the radiance in that direction, and the probability density of picking
+    /// it per unit solid angle, or `None` if the environment is completely black.
+    pub fn sample_direction(&self) -> Option<(Tuple, Color, f64)> {
+        if self.row_cdf.last().is_none_or(|&total| total <= 0.0) {
+            return None;
+        }
+        let (s, t) = sample_2d();
+        let y = pick(&self.row_cdf, s);
+        let row = &self.column_cdf[y * self.width..(y + 1) * self.width];
+        let x = pick(row, t);
+
+        let row_pdf = self.row_cdf[y] - if y > 0 { self.row_cdf[y - 1] } else { 0.0 };
+        let column_pdf = row[x] - if x > 0 { row[x - 1] } else { 0.0 };
+        let u = (x as f64 + random_range(0.0, 1.0)) / self.width as f64;
+        let v = (y as f64 + random_range(0.0, 1.0)) / self.height as f64;
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        // The pixel covers 2π / width by π / height of the sphere, shrunk by sin(θ) towards the poles
+        let pdf = row_pdf * column_pdf * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta);
+        Some((self.uv_to_direction(u, v), self.pixels[y * self.width + x], pdf))
+    }
+
+    /// Picks a random direction towards the sun of a sky, which is much brighter than the rest of the sky and so
+    /// is sampled separately from it.
+    ///
+    /// # Returns
+    ///
+    /// The normalized direction, the radiance of the sun and the probability density of picking the direction
+    /// per unit solid angle, or `None` if the environment has no sky or the sun is below the horizon.
+    pub fn sample_sun(&self) -> Option<(Tuple, Color, f64)> {
+        let sky = self.sky.as_ref().filter(|sky| sky.elevation > 0.0)?;
+        let (s, t) = sample_2d();
+        let solid_angle = sun_solid_angle();
+        Some((self.rotate(&sky.sample_sun(s, t)), sky.sun_irradiance().multiply(self.intensity / solid_angle), 1.0 / solid_angle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use super::Environment;
+    use crate::color::Color;
+    use crate::raytracer::sky::Sky;
+    use crate::tuple::Tuple;
+
+    fn two_tone_environment() -> Environment {
+        // The left half of the image is red, the right half blue
+        let red = Color::new(1.0, 0.0, 0.0);
+        let blue = Color::new(0.0, 0.0, 1.0);
+        Environment::new(2, 1, vec![red, blue], 2.0, 0.0, 0)
+    }
+
+    #[test]
+    fn looking_up_the_radiance_in_a_direction() {
+        let env = two_tone_environment();
+        assert_eq!(env.radiance(&Tuple::vector(-1.0, 0.0, 0.0)), Color::new(2.0, 0.0, 0.0));
+        assert_eq!(env.radiance(&Tuple::vector(1.0, 0.0, 0.0)), Color::new(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn rotating_the_environment() {
+        let mut env = two_tone_environment();
+        env.rotation = PI;
+        assert_eq!(env.radiance(&Tuple::vector(-1.0, 0.0, 0.0)), Color::new(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn sampling_only_picks_lit_directions() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let env = Environment::new(2, 1, vec![black, white], 1.0, 0.0, 1);
+        for _ in 0..20 {
+            let (direction, radiance, pdf) = env.sample_direction().unwrap();
+            assert!(direction.x >= 0.0);
+            assert_eq!(radiance, white);
+            assert!(pdf > 0.0);
+        }
+    }
+
+    #[test]
+    fn a_sky_environment_samples_its_sun_separately() {
+        let sky = Sky::new(PI / 4.0, 0.0, 3.0, 0.3);
+        // Turned a quarter around, the sun is towards positive x
+        let env = Environment::from_sky(sky, 1.0, PI / 2.0, 4);
+        let (direction, radiance, pdf) = env.sample_sun().unwrap();
+        assert!((direction.x - (PI / 4.0).cos()).abs() < 0.01 && direction.z.abs() < 0.01);
+        assert!((radiance.luminance() / pdf - sky.sun_irradiance().luminance()).abs() < 1e-9);
+        assert!(env.radiance(&direction).luminance() > 1000.0);
+        assert!(env.radiance(&Tuple::vector(-1.0, 1.0, 0.0)).luminance() < 1.0);
+        assert!(Environment::from_sky(Sky::new(-0.1, 0.0, 3.0, 0.3), 1.0, 0.0, 4).sample_sun().is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::color::Color;
+use crate::raytracer::canvas::Canvas;
+
+/// Collects the samples taken for each pixel of an image: how many there are, their mean and their variance.
+///
+/// The mean and variance are updated with every sample using Welford's method, so the samples themselves are
+/// not kept. The variance shows where an image is still noisy and needs more samples.
+///
+/// # Fields
+///
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `counts` - The number of samples of each pixel, row by row.
+/// * `means` - The mean of the samples of each pixel.
+/// * `squared_deviations` - The sum of the squared differences of the samples of each pixel from their mean.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilmBuffer {
+    pub width: usize,
+    pub height: usize,
+    counts: Vec<usize>,
+    means: Vec<Color>,
+    squared_deviations: Vec<Color>,
+}
+
+impl FilmBuffer {
+    /// Creates a film buffer without any samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the image in pixels.
+    /// * `height` - The height of the image in pixels.
+    pub fn new(width: usize, height: usize) -> FilmBuffer {
+        let black = Color::new(0.0, 0.0, 0.0);
+        FilmBuffer {
+            width,
+            height,
+            counts: vec![0; width * height],
+            means: vec![black; width * height],
+            squared_deviations: vec![black; width * height],
+        }
+    }
+
+    /// Creates a film buffer from an anti-aliased render, with each `aa` x `aa` block of the canvas as the
+    /// samples of one pixel.
+    ///
+    /// # Arguments
+    ///
+    /// * `canvas` - The render, `aa` times larger than the image in each direction.
+    /// * `aa` - The anti-aliasing factor.
+    pub fn from_canvas(canvas: &Canvas, aa: usize) -> FilmBuffer {
+        let mut film = FilmBuffer::new(canvas.width / aa, canvas.height / aa);
+        for y in 0..film.height * aa {
+            for x in 0..film.width * aa {
+                film.add_sample(x / aa, y / aa, canvas.pixel_at(x, y));
+            }
+        }
+        film
+    }
+
+    /// Adds a sample to a pixel.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - The x-coordinate of the pixel.
+    /// * `y` - The y-coordinate of the pixel.
+    /// * `color` - The color of the sample.
+    pub fn add_sample(&mut self, x: usize, y: usize, color: Color) {
+        let index = y * self.width + x;
+        self.counts[index] += 1;
+        let delta = color.subtract(&self.means[index]);
+        self.means[index] = self.means[index].add(&delta.multiply(1.0 / self.counts[index] as f64));
+        let delta_after = color.subtract(&self.means[index]);
+        self.squared_deviations[index] = self.squared_deviations[index].add(&delta.product(&delta_after));
+    }
+
+    /// Returns the number of samples of a pixel.
+    pub fn sample_count(&self, x: usize, y: usize) -> usize {
+        self.counts[y * self.width + x]
+    }
+
+    /// Returns the mean of the samples of a pixel, black when it has none.
+    pub fn mean(&self, x: usize, y: usize) -> Color {
+        self.means[y * self.width + x]
+    }
+
+    /// Returns the sample variance of each color channel of a pixel, black when it has fewer than two samples.
+    pub fn variance(&self, x: usize, y: usize) -> Color {
+        let index = y * self.width + x;
+        match self.counts[index] {
+            0 | 1 => Color::new(0.0, 0.0, 0.0),
+            count => self.squared_deviations[index].multiply(1.0 / (count - 1) as f64),
+        }
+    }
+
+    /// Returns the image made of the means of the pixels.
+    pub fn to_canvas(&self) -> Canvas {
+        Canvas { width: self.width, height: self.height, pixels: self.means.clone() }
+    }
+
+    /// Draws how uncertain the brightness of each pixel still is, from black for pixels whose samples agree,
+    /// through red and yellow, to white for the noisiest pixel of the image.
+    ///
+    /// The uncertainty is the standard error of the mean luminance, which falls as more samples are taken.
+    /// Pixels with fewer than two samples are black.
+    ///
+    /// # Returns
+    ///
+    /// A canvas the size of the image.
+    pub fn variance_heatmap(&self) -> Canvas {
+        let errors: Vec<f64> = (0..self.width * self.height)
+            .map(|i| {
+                let count = self.counts[i].max(1) as f64;
+                let variance = self.variance(i % self.width, i / self.width);
+                (variance.luminance().max(0.0) / count).sqrt()
+            })
+            .collect();
+        let largest = errors.iter().cloned().fold(0.0, f64::max);
+        let pixels = errors.iter()
+            .map(|error| {
+                let t = if largest > 0.0 { 3.0 * error / largest } else { 0.0 };
+                Color::new(t.clamp(0.0, 1.0), (t - 1.0).clamp(0.0, 1.0), (t - 2.0).clamp(0.0, 1.0))
+            })
+            .collect();
+        Canvas { width: self.width, height: self.height, pixels }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn film_buffer_keeps_the_mean_and_variance_of_each_pixel() {
+        let mut film = FilmBuffer::new(2, 1);
+        for value in [0.2, 0.4, 0.9] {
+            film.add_sample(1, 0, Color::new(value, 0.5, 0.0));
+        }
+        assert_eq!(film.sample_count(1, 0), 3);
+        assert_eq!(film.mean(1, 0), Color::new(0.5, 0.5, 0.0));
+        // ((0.2 - 0.5)^2 + (0.4 - 0.5)^2 + (0.9 - 0.5)^2) / 2
+        assert_eq!(film.variance(1, 0), Color::new(0.13, 0.0, 0.0));
+        assert_eq!(film.sample_count(0, 0), 0);
+        assert_eq!(film.variance(0, 0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn variance_heatmap_of_an_anti_aliased_render() {
+        let mut canvas = Canvas::new(4, 2);
+        // The left pixel's samples agree, the right pixel's samples are black and white
+        for (x, y) in [(2, 0), (3, 1)] {
+            canvas.write_pixel(x, y, Color::new(1.0, 1.0, 1.0));
+        }
+        let film = FilmBuffer::from_canvas(&canvas, 2);
+        assert_eq!((film.width, film.height), (2, 1));
+        assert_eq!(film.sample_count(0, 0), 4);
+        assert_eq!(film.to_canvas().pixel_at(1, 0), Color::new(0.5, 0.5, 0.5));
+        let heatmap = film.variance_heatmap();
+        assert_eq!(heatmap.pixel_at(0, 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(heatmap.pixel_at(1, 0), Color::new(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,301 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::matrix4::Matrix4;
+use crate::raytracer::object::Object;
+use crate::raytracer::ray::Ray;
+use crate::raytracer::scene::Scene;
+use crate::tuple::Tuple;
+
+/// The ways copies of an object can be laid out by `Scene::generate`.
+///
+/// # Variants
+///
+/// * `Grid` - `counts` objects along x, y and z, `spacing` apart, centered on the origin.
+/// * `Box` - `count` objects at random points inside the box between the corners `min` and `max`. The same
+///   `seed` always gives the same points.
+/// * `Plane` - `count` objects at random points on the parallelogram with a corner at `corner` and edges
+///   `uvec` and `vvec`, like an area light.
+/// * `Ring` - `count` objects evenly spaced on a circle of `radius` around the y axis, in the xz plane.
+/// * `Spiral` - `count` objects evenly spaced on a spiral around the y axis, going out from `start_radius`
+///   to `end_radius` in `turns` turns while rising by `height`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Grid { counts: [usize; 3], spacing: Tuple },
+    Box { count: usize, min: Tuple, max: Tuple, seed: u64 },
+    Plane { count: usize, corner: Tuple, uvec: Tuple, vvec: Tuple, seed: u64 },
+    Ring { count: usize, radius: f64 },
+    Spiral { count: usize, start_radius: f64, end_radius: f64, turns: f64, height: f64 },
+}
+
+impl Layout {
+    /// Returns the points the objects are placed at, in the order they are generated.
+    pub fn positions(&self) -> Vec<Tuple> {
+        match *self {
+            Layout::Grid { counts: [nx, ny, nz], spacing } => {
+                // The middle of the grid is at the origin
+                let offset = |i: usize, n: usize, spacing: f64| (i as f64 - (n as f64 - 1.0) / 2.0) * spacing;
+                let mut positions = Vec::with_capacity(nx * ny * nz);
+                for x in 0..nx {
+                    for y in 0..ny {
+                        for z in 0..nz {
+                            positions.push(Tuple::point(offset(x, nx, spacing.x), offset(y, ny, spacing.y), offset(z, nz, spacing.z)));
+                        }
+                    }
+                }
+                positions
+            }
+            Layout::Box { count, min, max, seed } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..count).map(|_| {
+                    let x = min.x + rng.gen::<f64>() * (max.x - min.x);
+                    let y = min.y + rng.gen::<f64>() * (max.y - min.y);
+                    let z = min.z + rng.gen::<f64>() * (max.z - min.z);
+                    Tuple::point(x, y, z)
+                }).collect()
+            }
+            Layout::Plane { count, corner, uvec, vvec, seed } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..count).map(|_| {
+                    let (u, v) = (rng.gen::<f64>(), rng.gen::<f64>());
+                    corner.add(&uvec.multiply(u)).add(&vvec.multiply(v))
+                }).collect()
+            }
+            Layout::Ring { count, radius } => {
+                (0..count).map(|i| {
+                    let angle = 2.0 * PI * i as f64 / count as f64;
+                    Tuple::point(radius * angle.cos(), 0.0, radius * angle.sin())
+                }).collect()
+            }
+            Layout::Spiral { count, start_radius, end_radius, turns, height } => {
+                (0..count).map(|i| {
+                    // How far along the spiral the object is, from 0 at the start to 1 at the end
+                    let t = if count > 1 { i as f64 / (count - 1) as f64 } else { 0.0 };
+                    let angle = 2.0 * PI * turns * t;
+                    let radius = start_radius + (end_radius - start_radius) * t;
+                    Tuple::point(radius * angle.cos(), height * t, radius * angle.sin())
+                }).collect()
+            }
+        }
+    }
+}
+
+/// Creates an object for every position of a layout, moved to that position.
+///
+/// # Arguments
+///
+/// * `layout` - Where the objects are placed.
+/// * `make` - Creates the object with the given index, which is then moved by the position also given.
+///   Its own transformation is applied before the move, so it can scale or turn the object in place.
+///
+/// # Returns
+///
+/// The objects, in the order of the positions of the layout.
+///
+/// # Panics
+///
+/// Panics if `make` returns an object that is shared, so its transformation cannot be changed.
+pub fn generate_objects<F>(layout: &Layout, mut make: F) -> Vec<Arc<dyn Object + Send>>
+where
+    F: FnMut(usize, &Tuple) -> Arc<dyn Object + Send>,
+{
+    layout.positions().iter().enumerate().map(|(i, position)| {
+        let mut object = make(i, position);
+        let transform = Matrix4::translate(position.x, position.y, position.z).multiply(object.get_transform());
+        Arc::get_mut(&mut object).expect("generated objects must not be shared").set_transform(transform);
+        object
+    }).collect()
+}
+
+/// Where `scatter_objects` places the copies of an object.
+///
+/// # Variants
+///
+/// * `Box` - At random points inside the box between the corners `min` and `max`.
+/// * `Surface` - At random points on top of `object`, found by dropping each copy straight down from the
+///   height of `max` at random x and z between those of `min` and `max`. Copies that would miss the object
+///   are dropped again somewhere else.
+pub enum ScatterRegion {
+    Box { min: Tuple, max: Tuple },
+    Surface { object: Arc<dyn Object + Send>, min: Tuple, max: Tuple },
+}
+
+/// How `scatter_objects` places and varies the copies of an object.
+///
+/// # Fields
+///
+/// * `count` - The number of copies.
+/// * `region` - Where the copies are placed.
+/// * `rotation` - The largest angles, in radians, each copy is turned by around the x, y and z axes. Every
+///   copy is turned by a random angle between 0 and these.
+/// * `scale` - The smallest and largest size each copy is scaled to, the same on every axis.
+/// * `seed` - The seed of the random numbers, the same seed always gives the same copies.
+pub struct Scatter {
+    pub count: usize,
+    pub region: ScatterRegion,
+    pub rotation: Tuple,
+    pub scale: (f64, f64),
+    pub seed: u64,
+}
+
+/// The number of times a point on the surface is looked for, for every copy, before giving up.
+const SURFACE_ATTEMPTS: usize = 100;
+
+/// Creates copies of an object at random places, each turned and scaled at random.
+///
+/// Each copy is scaled, then turned around the x, y and z axes, then moved to its place, after its own
+/// transformation. If the surface of a `Surface` region cannot be found for every copy, a warning is printed
+/// and fewer copies are made.
+///
+/// # Arguments
+///
+/// * `scatter` - How the copies are placed and varied.
+/// * `make` - Creates the copy with the given index.
+///
+/// # Returns
+///
+/// The copies.
+///
+/// # Panics
+///
+/// Panics if `make` returns an object that is shared, so its transformation cannot be changed.
+pub fn scatter_objects<F>(scatter: &Scatter, mut make: F) -> Vec<Arc<dyn Object + Send>>
+where
+    F: FnMut(usize) -> Arc<dyn Object + Send>,
+{
+    let mut rng = StdRng::seed_from_u64(scatter.seed);
+    let mut random = |low: f64, high: f64| low + rng.gen::<f64>() * (high - low);
+    let mut objects: Vec<Arc<dyn Object + Send>> = Vec::with_capacity(scatter.count);
+    let mut attempts = 0;
+    while objects.len() < scatter.count && attempts < scatter.count * SURFACE_ATTEMPTS {
+        attempts += 1;
+        let position = match &scatter.region {
+            ScatterRegion::Box { min, max } => {
+                Tuple::point(random(min.x, max.x), random(min.y, max.y), random(min.z, max.z))
+            }
+            ScatterRegion::Surface { object, min, max } => {
+                let (x, z) = (random(min.x, max.x), random(min.z, max.z));
+                let ray = Ray::new(Tuple::point(x, max.y, z), Tuple::vector(0.0, -1.0, 0.0));
+                let hit = object.intersect(&ray).iter().map(|i| i.t).filter(|t| *t >= 0.0).min_by(f64::total_cmp);
+                match hit {
+                    Some(t) => ray.position(t),
+                    None => continue,
+                }
+            }
+        };
+        let size = random(scatter.scale.0, scatter.scale.1);
+        let rotation = Matrix4::rotate_z(random(0.0, scatter.rotation.z))
+            * Matrix4::rotate_y(random(0.0, scatter.rotation.y))
+            * Matrix4::rotate_x(random(0.0, scatter.rotation.x));
+        let mut object = make(objects.len());
+        let transform = Matrix4::translate(position.x, position.y, position.z) * rotation * Matrix4::scale(size, size, size);
+        let transform = transform.multiply(object.get_transform());
+        Arc::get_mut(&mut object).expect("scattered objects must not be shared").set_transform(transform);
+        objects.push(object);
+    }
+    if objects.len() < scatter.count {
+        eprintln!("Warning: only {} of {} scattered objects could be placed on the surface", objects.len(), scatter.count);
+    }
+    objects
+}
+
+impl Scene {
+    /// Adds many objects to the scene at once, laid out as a grid, scattered at random, or along a ring or
+    /// a spiral. See `generate_objects`.
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - Where the objects are placed.
+    /// * `make` - Creates the object with the given index, which is then moved to the position also given.
+    ///
+    /// # Returns
+    ///
+    /// The ids of the objects added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let layout = Layout::Grid { counts: [10, 1, 10], spacing: Tuple::vector(2.0, 0.0, 2.0) };
+    /// scene.generate(&layout, |_, _| Arc::new(Sphere::new()));
+    /// ```
+    pub fn generate<F>(&mut self, layout: &Layout, make: F) -> Vec<usize>
+    where
+        F: FnMut(usize, &Tuple) -> Arc<dyn Object + Send>,
+    {
+        generate_objects(layout, make).into_iter().map(|object| self.add_object(object)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::matrix4::Matrix4;
+    use crate::raytracer::object::db::get_object;
+    use crate::raytracer::object::sphere::Sphere;
+    use crate::raytracer::scene::Scene;
+    use crate::tuple::Tuple;
+    use super::{scatter_objects, Layout, Scatter, ScatterRegion};
+
+    #[test]
+    fn grids_are_centered_on_the_origin() {
+        let positions = Layout::Grid { counts: [3, 1, 2], spacing: Tuple::vector(2.0, 1.0, 1.0) }.positions();
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions[0], Tuple::point(-2.0, 0.0, -0.5));
+        assert_eq!(positions[5], Tuple::point(2.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn scattered_objects_are_inside_the_box_and_depend_on_the_seed() {
+        let layout = |seed| Layout::Box { count: 100, min: Tuple::point(-1.0, 0.0, 2.0), max: Tuple::point(1.0, 0.5, 3.0), seed };
+        let positions = layout(7).positions();
+        assert!(positions.iter().all(|p| (-1.0..1.0).contains(&p.x) && (0.0..0.5).contains(&p.y) && (2.0..3.0).contains(&p.z)));
+        assert_eq!(layout(7).positions(), positions);
+        assert_ne!(layout(8).positions(), positions);
+    }
+
+    #[test]
+    fn spirals_go_from_the_start_to_the_end_radius() {
+        let positions = Layout::Spiral { count: 5, start_radius: 1.0, end_radius: 3.0, turns: 2.0, height: 4.0 }.positions();
+        assert_eq!(positions[0], Tuple::point(1.0, 0.0, 0.0));
+        assert_eq!(positions[2], Tuple::point(2.0, 2.0, 0.0));
+        assert_eq!(positions[4], Tuple::point(3.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn generated_objects_are_moved_after_their_own_transformation() {
+        let mut scene = Scene::new();
+        let layout = Layout::Ring { count: 4, radius: 2.0 };
+        let ids = scene.generate(&layout, |_, _| {
+            let mut sphere = Sphere::new();
+            sphere.transform = Matrix4::scale(0.5, 0.5, 0.5);
+            Arc::new(sphere)
+        });
+        assert_eq!(scene.ids, ids);
+        assert_eq!(*get_object(ids[1]).get_transform(), Matrix4::translate(0.0, 0.0, 2.0).multiply(&Matrix4::scale(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn scattered_objects_are_dropped_onto_the_surface() {
+        let mut ground = Sphere::new();
+        ground.transform = Matrix4::scale(10.0, 1.0, 10.0);
+        let scatter = Scatter {
+            count: 20,
+            region: ScatterRegion::Surface { object: Arc::new(ground), min: Tuple::point(-5.0, 0.0, -5.0), max: Tuple::point(5.0, 5.0, 5.0) },
+            rotation: Tuple::vector(0.0, std::f64::consts::PI, 0.0),
+            scale: (0.5, 2.0),
+            seed: 3,
+        };
+        let objects = scatter_objects(&scatter, |_| Arc::new(Sphere::new()));
+        assert_eq!(objects.len(), 20);
+        for object in objects {
+            let m = object.get_transform();
+            let (x, y, z) = (m.get(0, 3), m.get(1, 3), m.get(2, 3));
+            // On the top of the squashed sphere, with no turn around x or z so the y axis stays upright
+            assert!((x * x / 100.0 + y * y + z * z / 100.0 - 1.0).abs() < 1e-9 && y > 0.0);
+            let size = m.get(1, 1);
+            assert!((0.5..2.0).contains(&size) && m.get(0, 1) == 0.0 && m.get(2, 1) == 0.0);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(feature = "gpu")]
+mod compute;
+
+use crate::raytracer::camera::Camera;
+use crate::raytracer::canvas::Canvas;
+use crate::raytracer::scene::Scene;
+
+/// The renderers a scene can be rendered with.
+///
+/// # Variants
+///
+/// * `Cpu` - The ray tracer running on the processor, which supports every feature.
+/// * `Gpu` - An experimental compute shader that traces camera and shadow rays on the graphics card.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Cpu,
+    Gpu,
+}
+
+/// Renders a scene on the GPU.
+///
+/// The objects of the scene are flattened into a list of primitives in world space, sorted into a
+/// bounding volume hierarchy, and uploaded together with the materials and lights to a compute shader
+/// that traces one ray through each pixel and shades the hit with the Phong model and hard shadows.
+/// Scenes that use anything else, like reflections, patterns or area lights, are rendered on the CPU
+/// instead, as are all scenes when no GPU can be found.
+///
+/// # Arguments
+///
+/// * `camera` - The camera to render the scene with.
+/// * `scene` - A reference to the `Scene` that will be rendered.
+///
+/// # Returns
+///
+/// A `Canvas` instance representing the rendered image.
+#[cfg(feature = "gpu")]
+pub fn render_on_gpu(camera: &Camera, scene: &Scene) -> Canvas {
+    let gpu_scene = match compute::GpuScene::new(camera, scene) {
+        Ok(gpu_scene) => gpu_scene,
+        Err(unsupported) => {
+            eprintln!("The GPU backend does not support {}, rendering on the CPU", unsupported);
+            return camera.render(scene);
+        }
+    };
+    match pollster::block_on(gpu_scene.render()) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Unable to render on the GPU: {}, rendering on the CPU", e);
+            camera.render(scene)
+        }
+    }
+}
+
+/// Renders a scene on the CPU, because rray was built without the `gpu` feature.
+#[cfg(not(feature = "gpu"))]
+pub fn render_on_gpu(camera: &Camera, scene: &Scene) -> Canvas {
+    eprintln!("rray was built without the gpu feature, rendering on the CPU");
+    camera.render(scene)
+}
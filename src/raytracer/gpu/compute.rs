@@ -0,0 +1,603 @@
+use std::borrow::Cow;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::color::Color;
+use crate::matrix4::Matrix4;
+use crate::tuple::Tuple;
+use crate::raytracer::camera::Camera;
+use crate::raytracer::canvas::Canvas;
+use crate::raytracer::light::{Light, LightType};
+use crate::raytracer::material::{DiffuseModel, Material, ShadingModel, SpecularModel};
+use crate::raytracer::material::pattern::PatternType;
+use crate::raytracer::object::{AABB, Geometry};
+use crate::raytracer::object::db::get_object;
+use crate::raytracer::profile::profile_span;
+use crate::raytracer::scene::{Background, Scene};
+
+/// The compute shader that traces and shades the rays.
+const SHADER: &str = include_str!("raytrace.wgsl");
+
+/// The width and height of the workgroups of the compute shader, in pixels.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The `kind` of the primitives, as used by the compute shader.
+const SPHERE: u32 = 0;
+const PLANE: u32 = 1;
+const CUBE: u32 = 2;
+const TRIANGLE: u32 = 3;
+
+/// The `flags` of the primitives, as used by the compute shader.
+const CAST_SHADOWS: u32 = 1;
+const VISIBLE_TO_CAMERA: u32 = 2;
+const CULL_BACKFACES: u32 = 4;
+
+/// The smallest distance secondary rays are started away from a surface, as single precision
+/// intersections are not accurate enough for the default epsilon of the CPU ray tracer.
+const MIN_EPSILON: f32 = 0.0005;
+
+/// The `primitive` of the nodes of the bounding volume hierarchy that are not leaves.
+const NO_PRIMITIVE: u32 = u32::MAX;
+
+/// The camera and the sizes of the other buffers, laid out as the `Camera` uniform of the compute shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuCamera {
+    inverse: [[f32; 4]; 4],
+    background: [f32; 4],
+    region: [u32; 4],
+    half_width: f32,
+    half_height: f32,
+    pixel_size: f32,
+    epsilon: f32,
+    shift_x: f32,
+    shift_y: f32,
+    exposure: f32,
+    _padding: f32,
+    hsize: u32,
+    vsize: u32,
+    light_count: u32,
+    plane_start: u32,
+    primitive_count: u32,
+    node_count: u32,
+    _padding2: [u32; 2],
+}
+
+/// A sphere, plane, cube or triangle, laid out as the `Primitive` of the compute shader.
+///
+/// Spheres, planes and cubes are placed by the inverse of their transformation. Triangles are
+/// given by their corners and the normals at the corners in world space instead.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuPrimitive {
+    inverse: [[f32; 4]; 4],
+    points: [[f32; 4]; 3],
+    normals: [[f32; 4]; 3],
+    kind: u32,
+    material: u32,
+    flags: u32,
+    _padding: u32,
+}
+
+/// A node of the bounding volume hierarchy, laid out as the `Node` of the compute shader.
+///
+/// The nodes are stored depth first, so the first child of a node follows it directly. `skip` is the
+/// node after the subtree of the node, where the traversal continues when the ray misses the node's box.
+/// Leaves hold a single primitive.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuNode {
+    min: [f32; 3],
+    skip: u32,
+    max: [f32; 3],
+    primitive: u32,
+}
+
+/// The Phong settings of a material with a solid color, laid out as the `Material` of the compute shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuMaterial {
+    color: [f32; 4],
+    ambient: f32,
+    diffuse: f32,
+    specular: f32,
+    shininess: f32,
+}
+
+/// A point light, laid out as the `Light` of the compute shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuLight {
+    position: [f32; 4],
+    intensity: [f32; 4],
+    inverse_square: u32,
+    diffuse: u32,
+    specular: u32,
+    _padding: u32,
+}
+
+/// A scene converted to the buffers used by the compute shader.
+///
+/// # Fields
+///
+/// * `camera` - The camera and the number of entries of the other buffers.
+/// * `primitives` - The primitives in the order of the bounding volume hierarchy, followed by the planes.
+/// * `nodes` - The nodes of the bounding volume hierarchy over all primitives except the planes.
+/// * `materials` - The materials of the primitives.
+/// * `lights` - The point lights.
+pub struct GpuScene {
+    camera: GpuCamera,
+    primitives: Vec<GpuPrimitive>,
+    nodes: Vec<GpuNode>,
+    materials: Vec<GpuMaterial>,
+    lights: Vec<GpuLight>,
+}
+
+fn to_vec4(t: &Tuple) -> [f32; 4] {
+    [t.x as f32, t.y as f32, t.z as f32, t.w as f32]
+}
+
+fn color_to_vec4(c: &Color) -> [f32; 4] {
+    [c.r as f32, c.g as f32, c.b as f32, 1.0]
+}
+
+/// Converts a matrix to the column major layout of WGSL matrices.
+fn to_mat4(m: &Matrix4) -> [[f32; 4]; 4] {
+    let mut columns = [[0.0; 4]; 4];
+    for (col, column) in columns.iter_mut().enumerate() {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = m.get(row, col) as f32;
+        }
+    }
+    columns
+}
+
+/// Converts a material, or describes the first of its features that the compute shader does not support.
+fn gpu_material(material: &Material) -> Result<GpuMaterial, String> {
+    let color = match &material.pattern.pattern_type {
+        PatternType::Solid(color) => *color,
+        _ => return Err("patterns other than solid colors".to_string()),
+    };
+    let unsupported = [
+        (material.reflective > 0.0 || material.channel_maps.reflective.is_some(), "reflective materials"),
+        (material.transparency > 0.0 || material.channel_maps.transparency.is_some(), "transparent materials"),
+        (material.channel_maps.specular.is_some(), "specular maps"),
+        (material.normal_map.is_some() || material.bump.is_some(), "normal and bump maps"),
+        (material.model != ShadingModel::Phong, "PBR materials"),
+        (material.diffuse_model != DiffuseModel::Lambert, "the Oren-Nayar diffuse model"),
+        (material.specular_model != SpecularModel::Phong, "Blinn-Phong highlights"),
+        (!material.light_groups.is_empty(), "light groups"),
+    ];
+    if let Some((_, feature)) = unsupported.iter().find(|(used, _)| *used) {
+        return Err(feature.to_string());
+    }
+    Ok(GpuMaterial {
+        color: color_to_vec4(&color),
+        ambient: material.ambient as f32,
+        diffuse: material.diffuse as f32,
+        specular: material.specular as f32,
+        shininess: material.shininess as f32,
+    })
+}
+
+/// Converts a light, or describes why the compute shader does not support it.
+fn gpu_light(light: &Light) -> Result<GpuLight, String> {
+    if let LightType::Area(_) = light.light_type {
+        return Err("area lights".to_string());
+    }
+    if !light.groups.is_empty() {
+        return Err("light groups".to_string());
+    }
+    Ok(GpuLight {
+        position: to_vec4(&light.position),
+        intensity: color_to_vec4(&light.intensity),
+        inverse_square: light.inverse_square as u32,
+        diffuse: light.diffuse as u32,
+        specular: light.specular as u32,
+        _padding: 0,
+    })
+}
+
+/// Checks that the scene does not use any of the scene wide features the compute shader does not support.
+fn check_scene_features(scene: &Scene) -> Result<(), String> {
+    let unsupported = [
+        (scene.debug.is_some(), "debug views"),
+        (scene.wireframe.is_some(), "wireframes"),
+        (scene.ambient_occlusion.is_some(), "ambient occlusion"),
+        (scene.environment.is_some(), "environments"),
+        (!matches!(scene.background, Background::Color(_)), "gradient and pattern backgrounds"),
+        (scene.fog.is_some(), "fog"),
+        (!scene.volumes.is_empty(), "volumes"),
+    ];
+    match unsupported.iter().find(|(used, _)| *used) {
+        Some((_, feature)) => Err(feature.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Adds an object, or all objects of a group, to the primitives, in world space.
+///
+/// # Arguments
+///
+/// * `id` - The id of the object.
+/// * `parent_transform` - The transformation from the space of the object's parent to world space.
+/// * `primitives` - The primitives and their world space bounds, or `None` for infinite planes, which are unbounded.
+/// * `materials` - The materials of the primitives.
+fn flatten_object(id: usize, parent_transform: &Matrix4, primitives: &mut Vec<(GpuPrimitive, Option<AABB>)>, materials: &mut Vec<GpuMaterial>) -> Result<(), String> {
+    let object = get_object(id);
+    let transform = parent_transform.multiply(object.get_transform());
+    let geometry = match object.geometry() {
+        Some(Geometry::Cylinder { .. } | Geometry::Cone { .. } | Geometry::Torus(_) | Geometry::RoundedBox(_) | Geometry::Csg(..)) | None => {
+            return Err("cylinders, cones, tori, rounded boxes and CSG objects".to_string());
+        }
+        Some(geometry) => geometry,
+    };
+    if let Geometry::Group(children) = geometry {
+        for child in children {
+            flatten_object(child, &transform, primitives, materials)?;
+        }
+        return Ok(());
+    }
+
+    let material = object.get_material();
+    let mut flags = 0;
+    if material.cast_shadows {
+        flags |= CAST_SHADOWS;
+    }
+    if material.visible_to_camera {
+        flags |= VISIBLE_TO_CAMERA;
+    }
+    if !material.double_sided {
+        flags |= CULL_BACKFACES;
+    }
+    materials.push(gpu_material(material)?);
+    let mut primitive = GpuPrimitive {
+        inverse: to_mat4(&transform.inverse()),
+        points: [[0.0; 4]; 3],
+        normals: [[0.0; 4]; 3],
+        kind: SPHERE,
+        material: materials.len() as u32 - 1,
+        flags,
+        _padding: 0,
+    };
+    let unit_cube = AABB::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0));
+    let bounds = match geometry {
+        Geometry::Sphere => Some(unit_cube.apply_transform(&transform)),
+        Geometry::Cube => {
+            primitive.kind = CUBE;
+            Some(unit_cube.apply_transform(&transform))
+        }
+        Geometry::Plane { width, depth } => {
+            // The half extents of the plane, with infinite ones as the largest number a shader can hold
+            primitive.kind = PLANE;
+            primitive.points[0] = [(width / 2.0) as f32, 0.0, (depth / 2.0) as f32, 0.0].map(|v| v.min(f32::MAX));
+            let extents = AABB::new(Tuple::point(-width / 2.0, 0.0, -depth / 2.0), Tuple::point(width / 2.0, 0.0, depth / 2.0));
+            (width.is_finite() && depth.is_finite()).then(|| extents.apply_transform(&transform))
+        }
+        Geometry::Triangle(points, normals) => {
+            primitive.kind = TRIANGLE;
+            let normal_transform = transform.inverse().transpose();
+            // A mirroring transformation turns the corners around, so they are swapped to keep the front in front
+            let corners = if transform.determinant() < 0.0 { [0, 2, 1] } else { [0, 1, 2] };
+            let mut bounds = AABB::new(Tuple::point(f64::INFINITY, f64::INFINITY, f64::INFINITY), Tuple::point(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY));
+            for (i, corner) in corners.into_iter().enumerate() {
+                let point = transform.multiply_tuple(&points[corner]);
+                let mut normal = normal_transform.multiply_tuple(&normals[corner]);
+                normal.w = 0.0;
+                primitive.points[i] = to_vec4(&point);
+                primitive.normals[i] = to_vec4(&normal.normalize());
+                bounds.adjust_aabb(&AABB::new(point, point));
+            }
+            Some(bounds)
+        }
+        Geometry::Group(_) | Geometry::Cylinder { .. } | Geometry::Cone { .. } | Geometry::Torus(_) | Geometry::RoundedBox(_) | Geometry::Csg(..) => unreachable!(),
+    };
+    primitives.push((primitive, bounds));
+    Ok(())
+}
+
+/// Returns the center of a bounding box along an axis.
+fn center(bounds: &AABB, axis: usize) -> f64 {
+    let (min, max) = match axis {
+        0 => (bounds.min.x, bounds.max.x),
+        1 => (bounds.min.y, bounds.max.y),
+        _ => (bounds.min.z, bounds.max.z),
+    };
+    (min + max) / 2.0
+}
+
+/// Builds the nodes of a bounding volume hierarchy over some primitives, depth first.
+///
+/// The primitives are split in half along the axis in which their centers are spread the most,
+/// until every leaf holds a single primitive. The primitives are reordered to match the leaves.
+///
+/// # Arguments
+///
+/// * `items` - The primitives with their bounds.
+/// * `nodes` - The list the nodes are appended to.
+/// * `ordered` - The list the primitives are appended to, in the order of the leaves.
+fn build_bvh(items: &mut [(GpuPrimitive, AABB)], nodes: &mut Vec<GpuNode>, ordered: &mut Vec<GpuPrimitive>) {
+    let mut bounds = items[0].1;
+    for (_, b) in items.iter() {
+        bounds.adjust_aabb(b);
+    }
+    let index = nodes.len();
+    nodes.push(GpuNode {
+        min: [bounds.min.x as f32, bounds.min.y as f32, bounds.min.z as f32],
+        skip: 0,
+        max: [bounds.max.x as f32, bounds.max.y as f32, bounds.max.z as f32],
+        primitive: NO_PRIMITIVE,
+    });
+    if items.len() == 1 {
+        nodes[index].primitive = ordered.len() as u32;
+        ordered.push(items[0].0);
+    } else {
+        let spread = |axis: usize| {
+            let centers = items.iter().map(|(_, b)| center(b, axis));
+            centers.clone().fold(f64::NEG_INFINITY, f64::max) - centers.fold(f64::INFINITY, f64::min)
+        };
+        let axis = (0..3).max_by(|a, b| spread(*a).total_cmp(&spread(*b))).unwrap();
+        items.sort_by(|a, b| center(&a.1, axis).total_cmp(&center(&b.1, axis)));
+        let (left, right) = items.split_at_mut(items.len() / 2);
+        build_bvh(left, nodes, ordered);
+        build_bvh(right, nodes, ordered);
+    }
+    nodes[index].skip = nodes.len() as u32;
+}
+
+impl GpuScene {
+    /// Converts a scene and a camera to the buffers used by the compute shader.
+    ///
+    /// # Arguments
+    ///
+    /// * `camera` - The camera to render the scene with.
+    /// * `scene` - The scene.
+    ///
+    /// # Returns
+    ///
+    /// The converted scene, or a description of the first feature of the scene that the compute shader
+    /// does not support.
+    pub fn new(camera: &Camera, scene: &Scene) -> Result<GpuScene, String> {
+        check_scene_features(scene)?;
+        let lights = scene.light.iter().map(gpu_light).collect::<Result<Vec<_>, _>>()?;
+
+        let mut flattened = vec![];
+        let mut materials = vec![];
+        for id in &scene.ids {
+            flatten_object(*id, &Matrix4::identity(), &mut flattened, &mut materials)?;
+        }
+        let (mut bounded, planes): (Vec<_>, Vec<_>) = flattened.into_iter().partition(|(_, bounds)| bounds.is_some());
+        let mut bounded: Vec<(GpuPrimitive, AABB)> = bounded.drain(..).map(|(p, b)| (p, b.unwrap())).collect();
+        let mut nodes = vec![];
+        let mut primitives = vec![];
+        if !bounded.is_empty() {
+            let _span = profile_span!(INFO, "build_bvh");
+            build_bvh(&mut bounded, &mut nodes, &mut primitives);
+        }
+        let plane_start = primitives.len() as u32;
+        primitives.extend(planes.into_iter().map(|(p, _)| p));
+
+        let background = match scene.background {
+            Background::Color(color) => color,
+            _ => unreachable!(),
+        };
+        let (x0, y0, x1, y1) = camera.render_region();
+        let view_size = 2.0 * camera.half_width.max(camera.half_height);
+        let gpu_camera = GpuCamera {
+            inverse: to_mat4(&camera.transform.inverse()),
+            background: color_to_vec4(&background),
+            region: [x0 as u32, y0 as u32, x1 as u32, y1 as u32],
+            half_width: camera.half_width as f32,
+            half_height: camera.half_height as f32,
+            pixel_size: camera.pixel_size as f32,
+            epsilon: (scene.epsilon as f32).max(MIN_EPSILON),
+            shift_x: (camera.shift_x * view_size) as f32,
+            shift_y: (camera.shift_y * view_size) as f32,
+            exposure: camera.exposure_scale() as f32,
+            _padding: 0.0,
+            hsize: camera.hsize as u32,
+            vsize: camera.vsize as u32,
+            light_count: lights.len() as u32,
+            plane_start,
+            primitive_count: primitives.len() as u32,
+            node_count: nodes.len() as u32,
+            _padding2: [0; 2],
+        };
+        Ok(GpuScene { camera: gpu_camera, primitives, nodes, materials, lights })
+    }
+
+    /// Renders the scene with the compute shader on the first GPU found.
+    ///
+    /// # Returns
+    ///
+    /// The rendered image, or a description of the error if no GPU could be used.
+    pub async fn render(&self) -> Result<Canvas, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("rray"),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let (width, height) = (self.camera.hsize, self.camera.vsize);
+        let output_size = (width as u64) * (height as u64) * std::mem::size_of::<[f32; 4]>() as u64;
+        let limits = device.limits();
+        if output_size > limits.max_storage_buffer_binding_size || output_size > limits.max_buffer_size {
+            return Err(format!("the image is too large, the GPU can write at most {} bytes at once", limits.max_storage_buffer_binding_size));
+        }
+
+        // Buffers cannot be empty, so empty lists get a single unused entry
+        fn storage<T: Pod + Zeroable>(device: &wgpu::Device, label: &str, items: &[T]) -> wgpu::Buffer {
+            let placeholder = [T::zeroed()];
+            let contents = if items.is_empty() { &placeholder[..] } else { items };
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(contents),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        }
+        let camera = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("camera"),
+            contents: bytemuck::bytes_of(&self.camera),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let primitives = storage(&device, "primitives", &self.primitives);
+        let nodes = storage(&device, "nodes", &self.nodes);
+        let materials = storage(&device, "materials", &self.materials);
+        let lights = storage(&device, "lights", &self.lights);
+        let pixels = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pixels"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("raytrace"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("raytrace"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("scene"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: camera.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: primitives.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: nodes.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: materials.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: lights.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: pixels.as_entire_binding() },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("render") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("raytrace"), timestamp_writes: None });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&pixels, 0, &readback, 0, output_size);
+        queue.submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::PollType::wait_indefinitely()).map_err(|e| e.to_string())?;
+        let data = slice.get_mapped_range().map_err(|e| e.to_string())?;
+        let colors: &[[f32; 4]] = bytemuck::cast_slice(&data);
+        let mut image = Canvas::new(width as usize, height as usize);
+        for (i, c) in colors.iter().enumerate() {
+            image.write_pixel(i % width as usize, i / width as usize, Color::new(c[0] as f64, c[1] as f64, c[2] as f64));
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::mem::size_of;
+    use super::{GpuCamera, GpuLight, GpuMaterial, GpuNode, GpuPrimitive, GpuScene, NO_PRIMITIVE, PLANE, SHADER, TRIANGLE};
+    use crate::color::Color;
+    use crate::matrix4::Matrix4;
+    use crate::tuple::Tuple;
+    use crate::raytracer::camera::Camera;
+    use crate::raytracer::light::Light;
+    use crate::raytracer::material::pattern::Pattern;
+    use crate::raytracer::object::Object;
+    use crate::raytracer::object::group::Group;
+    use crate::raytracer::object::plane::Plane;
+    use crate::raytracer::object::sphere::Sphere;
+    use crate::raytracer::object::triangle::Triangle;
+    use crate::raytracer::object::cylinder::Cylinder;
+    use crate::raytracer::scene::Scene;
+
+    fn lit_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
+        scene
+    }
+
+    #[test]
+    fn the_shader_is_valid_and_matches_the_buffer_layouts() {
+        use wgpu::naga;
+        let module = naga::front::wgsl::parse_str(SHADER).unwrap();
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .unwrap();
+        let span = |name: &str| module.types.iter()
+            .find_map(|(_, t)| match (&t.name, &t.inner) {
+                (Some(n), naga::TypeInner::Struct { span, .. }) if n == name => Some(*span as usize),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(span("Camera"), size_of::<GpuCamera>());
+        assert_eq!(span("Primitive"), size_of::<GpuPrimitive>());
+        assert_eq!(span("Node"), size_of::<GpuNode>());
+        assert_eq!(span("Material"), size_of::<GpuMaterial>());
+        assert_eq!(span("Light"), size_of::<GpuLight>());
+    }
+
+    #[test]
+    fn flattening_a_scene_into_a_hierarchy() {
+        let mut scene = lit_scene();
+        scene.add_object(Arc::new(Plane::new()));
+        let mut group = Group::new();
+        group.set_transform(Matrix4::translate(0.0, 2.0, 0.0));
+        group.add_child(Arc::new(Sphere::new()));
+        group.add_child(Arc::new(Triangle::new(Tuple::point(0.0, 1.0, 0.0), Tuple::point(-1.0, 0.0, 0.0), Tuple::point(1.0, 0.0, 0.0))));
+        scene.add_object(Arc::new(group));
+
+        let gpu = GpuScene::new(&Camera::new(4, 3, 1.0), &scene).unwrap();
+        assert_eq!(gpu.primitives.len(), 3);
+        assert_eq!(gpu.camera.plane_start, 2);
+        assert_eq!(gpu.primitives[2].kind, PLANE);
+        // A root with two leaves, each holding one of the bounded primitives
+        assert_eq!(gpu.nodes.len(), 3);
+        assert_eq!(gpu.nodes[0].primitive, NO_PRIMITIVE);
+        assert_eq!(gpu.nodes[0].skip, 3);
+        assert_eq!((gpu.nodes[1].skip, gpu.nodes[2].skip), (2, 3));
+        assert_eq!(gpu.nodes[0].min[1], 1.0);
+        // The triangle is moved up with its group
+        let triangle = gpu.primitives.iter().find(|p| p.kind == TRIANGLE).unwrap();
+        assert_eq!(triangle.points[0], [0.0, 3.0, 0.0, 1.0]);
+        assert_eq!(triangle.normals[0], [0.0, 0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn unsupported_features_are_reported() {
+        let mut scene = lit_scene();
+        scene.add_object(Arc::new(Cylinder::new(0.0, 1.0, true)));
+        assert_eq!(GpuScene::new(&Camera::new(4, 3, 1.0), &scene).err().unwrap(), "cylinders, cones, tori, rounded boxes and CSG objects");
+
+        let mut scene = lit_scene();
+        let mut sphere = Sphere::new();
+        let mut material = sphere.get_material().clone();
+        material.pattern = Pattern::stripe(Pattern::solid(Color::white(), Matrix4::identity()), Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix4::identity()), Matrix4::identity());
+        sphere.set_material(material);
+        scene.add_object(Arc::new(sphere));
+        assert_eq!(GpuScene::new(&Camera::new(4, 3, 1.0), &scene).err().unwrap(), "patterns other than solid colors");
+    }
+}
@@ -0,0 +1,308 @@
+// Traces one camera ray through each pixel and shades the closest hit with the Phong model,
+// with a shadow ray towards each light. This follows the CPU ray tracer for the features it supports.
+
+struct Camera {
+    inverse: mat4x4<f32>,
+    background: vec4<f32>,
+    region: vec4<u32>,
+    half_width: f32,
+    half_height: f32,
+    pixel_size: f32,
+    epsilon: f32,
+    shift_x: f32,
+    shift_y: f32,
+    exposure: f32,
+    padding: f32,
+    hsize: u32,
+    vsize: u32,
+    light_count: u32,
+    plane_start: u32,
+    primitive_count: u32,
+    node_count: u32,
+    padding2: vec2<u32>,
+}
+
+struct Primitive {
+    inverse: mat4x4<f32>,
+    points: array<vec4<f32>, 3>,
+    normals: array<vec4<f32>, 3>,
+    kind: u32,
+    material: u32,
+    flags: u32,
+    padding: u32,
+}
+
+struct Node {
+    min: vec3<f32>,
+    skip: u32,
+    max: vec3<f32>,
+    primitive: u32,
+}
+
+struct Material {
+    color: vec4<f32>,
+    ambient: f32,
+    diffuse: f32,
+    specular: f32,
+    shininess: f32,
+}
+
+struct Light {
+    position: vec4<f32>,
+    intensity: vec4<f32>,
+    inverse_square: u32,
+    diffuse: u32,
+    specular: u32,
+    padding: u32,
+}
+
+struct Hit {
+    t: f32,
+    primitive: u32,
+    u: f32,
+    v: f32,
+}
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(0) @binding(1) var<storage, read> primitives: array<Primitive>;
+@group(0) @binding(2) var<storage, read> nodes: array<Node>;
+@group(0) @binding(3) var<storage, read> materials: array<Material>;
+@group(0) @binding(4) var<storage, read> lights: array<Light>;
+@group(0) @binding(5) var<storage, read_write> pixels: array<vec4<f32>>;
+
+// The same tolerance as the CPU ray tracer
+const EPSILON: f32 = 0.00001;
+const FAR: f32 = 1e30;
+const NO_HIT: u32 = 0xffffffffu;
+
+const SPHERE: u32 = 0u;
+const PLANE: u32 = 1u;
+const CUBE: u32 = 2u;
+const TRIANGLE: u32 = 3u;
+
+const CAST_SHADOWS: u32 = 1u;
+const VISIBLE_TO_CAMERA: u32 = 2u;
+const CULL_BACKFACES: u32 = 4u;
+
+// Keeps the closest of the hits in [t_min, hit.t)
+fn record(hit: ptr<function, Hit>, t: f32, t_min: f32, index: u32, u: f32, v: f32) {
+    if t >= t_min && t < (*hit).t {
+        *hit = Hit(t, index, u, v);
+    }
+}
+
+// Divides by a ray direction component, or returns an infinite distance with the right sign for parallel rays
+fn slab(numerator: f32, direction: f32) -> f32 {
+    if abs(direction) >= EPSILON {
+        return numerator / direction;
+    }
+    return select(-FAR, FAR, numerator >= 0.0);
+}
+
+fn intersect_primitive(index: u32, origin: vec3<f32>, direction: vec3<f32>, t_min: f32, hit: ptr<function, Hit>) {
+    let p = primitives[index];
+    if p.kind == TRIANGLE {
+        let p1 = p.points[0].xyz;
+        let e1 = p.points[1].xyz - p1;
+        let e2 = p.points[2].xyz - p1;
+        let dir_cross_e2 = cross(direction, e2);
+        let det = dot(e1, dir_cross_e2);
+        if abs(det) < EPSILON || (det < 0.0 && (p.flags & CULL_BACKFACES) != 0u) {
+            return;
+        }
+        let f = 1.0 / det;
+        let p1_to_origin = origin - p1;
+        let u = f * dot(p1_to_origin, dir_cross_e2);
+        if u < 0.0 || u > 1.0 {
+            return;
+        }
+        let origin_cross_e1 = cross(p1_to_origin, e1);
+        let v = f * dot(direction, origin_cross_e1);
+        if v < 0.0 || u + v > 1.0 {
+            return;
+        }
+        record(hit, f * dot(e2, origin_cross_e1), t_min, index, u, v);
+        return;
+    }
+
+    // The other shapes are intersected in object space
+    let o = (p.inverse * vec4<f32>(origin, 1.0)).xyz;
+    let d = (p.inverse * vec4<f32>(direction, 0.0)).xyz;
+    if p.kind == SPHERE {
+        let a = dot(d, d);
+        let b = 2.0 * dot(d, o);
+        let c = dot(o, o) - 1.0;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return;
+        }
+        let root = sqrt(discriminant);
+        record(hit, (-b - root) / (2.0 * a), t_min, index, 0.0, 0.0);
+        record(hit, (-b + root) / (2.0 * a), t_min, index, 0.0, 0.0);
+    } else if p.kind == PLANE {
+        if abs(d.y) < EPSILON {
+            return;
+        }
+        // points[0] holds the half width and depth of the plane
+        let t = -o.y / d.y;
+        let q = o + t * d;
+        if abs(q.x) <= p.points[0].x && abs(q.z) <= p.points[0].z {
+            record(hit, t, t_min, index, 0.0, 0.0);
+        }
+    } else if p.kind == CUBE {
+        let x1 = slab(-1.0 - o.x, d.x);
+        let x2 = slab(1.0 - o.x, d.x);
+        let y1 = slab(-1.0 - o.y, d.y);
+        let y2 = slab(1.0 - o.y, d.y);
+        let z1 = slab(-1.0 - o.z, d.z);
+        let z2 = slab(1.0 - o.z, d.z);
+        let t0 = max(max(min(x1, x2), min(y1, y2)), min(z1, z2));
+        let t1 = min(min(max(x1, x2), max(y1, y2)), max(z1, z2));
+        if t0 > t1 {
+            return;
+        }
+        record(hit, t0, t_min, index, 0.0, 0.0);
+        record(hit, t1, t_min, index, 0.0, 0.0);
+    }
+}
+
+fn hits_box(node: Node, origin: vec3<f32>, inverse_direction: vec3<f32>, t_max: f32) -> bool {
+    let t1 = (node.min - origin) * inverse_direction;
+    let t2 = (node.max - origin) * inverse_direction;
+    let near = min(t1, t2);
+    let far = max(t1, t2);
+    let t0 = max(max(near.x, near.y), near.z);
+    let t3 = min(min(far.x, far.y), far.z);
+    return t0 <= t3 && t3 >= 0.0 && t0 <= t_max;
+}
+
+// Finds the closest hit in [0, t_max) with a primitive that has all of the `required` flags
+fn trace(origin: vec3<f32>, direction: vec3<f32>, t_max: f32, required: u32) -> Hit {
+    var hit = Hit(t_max, NO_HIT, 0.0, 0.0);
+    // Avoid dividing by zero for directions parallel to an axis
+    let tiny = vec3<f32>(1e-20);
+    let safe_direction = select(direction, tiny, abs(direction) < tiny);
+    let inverse_direction = 1.0 / safe_direction;
+    var i = 0u;
+    loop {
+        if i >= camera.node_count {
+            break;
+        }
+        let node = nodes[i];
+        if hits_box(node, origin, inverse_direction, hit.t) {
+            if node.primitive != NO_HIT && (primitives[node.primitive].flags & required) == required {
+                intersect_primitive(node.primitive, origin, direction, 0.0, &hit);
+            }
+            i = i + 1u;
+        } else {
+            i = node.skip;
+        }
+    }
+    for (var j = camera.plane_start; j < camera.primitive_count; j = j + 1u) {
+        if (primitives[j].flags & required) == required {
+            intersect_primitive(j, origin, direction, 0.0, &hit);
+        }
+    }
+    return hit;
+}
+
+fn normal_at(hit: Hit, point: vec3<f32>) -> vec3<f32> {
+    let p = primitives[hit.primitive];
+    if p.kind == TRIANGLE {
+        let n = p.normals[1].xyz * hit.u + p.normals[2].xyz * hit.v + p.normals[0].xyz * (1.0 - hit.u - hit.v);
+        return normalize(n);
+    }
+    let local = (p.inverse * vec4<f32>(point, 1.0)).xyz;
+    var n = vec3<f32>(0.0, 1.0, 0.0);
+    if p.kind == SPHERE {
+        n = local;
+    } else if p.kind == CUBE {
+        let a = abs(local);
+        let largest = max(max(a.x, a.y), a.z);
+        if largest == a.x {
+            n = vec3<f32>(local.x, 0.0, 0.0);
+        } else if largest == a.y {
+            n = vec3<f32>(0.0, local.y, 0.0);
+        } else {
+            n = vec3<f32>(0.0, 0.0, local.z);
+        }
+    }
+    return normalize((transpose(p.inverse) * vec4<f32>(n, 0.0)).xyz);
+}
+
+fn shade(hit: Hit, origin: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    let material = materials[primitives[hit.primitive].material];
+    let point = origin + direction * hit.t;
+    let eyev = -direction;
+    var normalv = normal_at(hit, point);
+    if dot(normalv, eyev) < 0.0 {
+        normalv = -normalv;
+    }
+    let over_point = point + normalv * camera.epsilon;
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < camera.light_count; i = i + 1u) {
+        let light = lights[i];
+        let to_light = light.position.xyz - over_point;
+        let distance = length(to_light);
+        let lightv = to_light / distance;
+        var intensity = light.intensity.xyz;
+        if light.inverse_square != 0u {
+            intensity = intensity / max(distance * distance, EPSILON);
+        }
+        let effective_color = material.color.xyz * intensity;
+        var ambient = effective_color * material.ambient;
+        var diffuse = vec3<f32>(0.0);
+        var specular = vec3<f32>(0.0);
+        let light_dot_normal = dot(lightv, normalv);
+        if light_dot_normal >= 0.0 {
+            diffuse = effective_color * material.diffuse * light_dot_normal;
+            let reflect_dot_eye = dot(reflect(-lightv, normalv), eyev);
+            if reflect_dot_eye > 0.0 {
+                specular = intensity * material.specular * pow(reflect_dot_eye, material.shininess);
+            }
+        }
+        if light.diffuse == 0u {
+            ambient = vec3<f32>(0.0);
+            diffuse = vec3<f32>(0.0);
+        }
+        if light.specular == 0u {
+            specular = vec3<f32>(0.0);
+        }
+        // The shadow ray is only needed when the light adds more than ambient light
+        if any(diffuse + specular > vec3<f32>(0.0)) && trace(over_point, lightv, distance, CAST_SHADOWS).primitive != NO_HIT {
+            diffuse = vec3<f32>(0.0);
+            specular = vec3<f32>(0.0);
+        }
+        color = color + ambient + diffuse + specular;
+    }
+    return color;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    let y = id.y;
+    if x >= camera.hsize || y >= camera.vsize {
+        return;
+    }
+    let index = y * camera.hsize + x;
+    if x < camera.region.x || y < camera.region.y || x >= camera.region.z || y >= camera.region.w {
+        pixels[index] = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    // The camera looks toward -z, so +x is to the left
+    let world_x = camera.half_width - (f32(x) + 0.5) * camera.pixel_size - camera.shift_x;
+    let world_y = camera.half_height - (f32(y) + 0.5) * camera.pixel_size + camera.shift_y;
+    let pixel = (camera.inverse * vec4<f32>(world_x, world_y, -1.0, 1.0)).xyz;
+    let origin = (camera.inverse * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    let direction = normalize(pixel - origin);
+
+    let hit = trace(origin, direction, FAR, VISIBLE_TO_CAMERA);
+    var color = camera.background.xyz;
+    if hit.primitive != NO_HIT {
+        color = shade(hit, origin, direction);
+    }
+    pixels[index] = vec4<f32>(color * camera.exposure, 1.0);
+}
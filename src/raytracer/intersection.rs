@@ -1,8 +1,19 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
 use crate::EPSILON;
+use crate::color::Color;
 use crate::raytracer::computations::Computations;
+use crate::raytracer::material::{apply_bump, apply_normal_map};
 use crate::raytracer::ray::Ray;
 use crate::raytracer::object::db::get_object;
 
+// Each render thread keeps the lists it has finished with and reuses them, so casting and shading a ray
+// does not need to allocate.
+thread_local! {
+    static SPARE_LISTS: RefCell<Vec<Vec<Intersection>>> = const { RefCell::new(Vec::new()) };
+    static CONTAINERS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
 /// Represents an intersection point on an object.
 ///
 /// This struct captures the intersection of a ray with an object in the scene,
@@ -47,50 +58,140 @@ impl Intersection {
     /// # Returns
     ///
     /// A `Computations` struct containing the calculated properties.
+    #[allow(dead_code)]
     pub fn prepare_computations(&self, r: &Ray, xs: &Vec<Intersection>) -> Computations {
+        self.prepare_computations_with_epsilon(r, xs, EPSILON)
+    }
+
+    /// Prepares the computations for shading this intersection, offsetting the over and under
+    /// points by `epsilon` along the normal.
+    ///
+    /// The offset keeps secondary rays from hitting the surface they start on. It has to grow with
+    /// the size of the scene to avoid shadow acne, and shrink with it to avoid light leaking through
+    /// thin objects.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The ray that produced this intersection.
+    /// * `xs` - A list of all intersections with the object, for refraction calculations.
+    /// * `epsilon` - The distance the over and under points are moved away from the surface.
+    ///
+    /// # Returns
+    ///
+    /// A `Computations` struct containing the calculated properties.
+    pub fn prepare_computations_with_epsilon(&self, r: &Ray, xs: &Vec<Intersection>, epsilon: f64) -> Computations {
         let point = r.position(self.t);
         let eyev = r.direction.negate();
         let object = get_object(self.object);
         let normalv = object.normal_at(&point, self);
-        let inside = normalv.dot(&eyev) < 0.0;
-        let normalv = if inside { normalv.negate() } else { normalv };
-        let over_point = point.add(&normalv.multiply(EPSILON));
-        let under_point = point.subtract(&normalv.multiply(EPSILON));
+        // The face decides which side is hit, and the normal is turned to that side, so the blended normals of
+        // smooth triangles are flipped along with their face
+        let face_normal = object.face_normal_at(&point, self);
+        let inside = face_normal.dot(&eyev) < 0.0;
+        let normalv = if (normalv.dot(&face_normal) < 0.0) != inside { normalv.negate() } else { normalv };
+        // The footprint lies in the plane of the geometric normal, like the over and under points below
+        let footprint = r.differentials.as_ref().and_then(|d| d.footprint(&point, &normalv));
+        let over_point = point.add(&normalv.multiply(epsilon));
+        let under_point = point.subtract(&normalv.multiply(epsilon));
+        // Normal maps and bumps only change the shading, the geometric normal still places the over and under points
+        let material = object.get_material();
+        let normalv = match &material.normal_map {
+            Some(normal_map) => apply_normal_map(self.object, &point, self, &normalv, normal_map),
+            None => normalv,
+        };
+        let normalv = match &material.bump {
+            Some(bump) => apply_bump(self.object, &point, &normalv, bump),
+            None => normalv,
+        };
         let reflectv = r.direction.reflect(&normalv);
+        let reflected_differentials = match (&r.differentials, &footprint) {
+            (Some(differentials), Some(footprint)) => Some(differentials.reflect(footprint, &point, &normalv)),
+            _ => None,
+        };
+        // Vertex colors are blended with the barycentric coordinates of the hit, like the normals of smooth triangles
+        let vertex_color = match object.vertex_colors() {
+            Some([c1, c2, c3]) => c2.multiply(self.u).add(&c3.multiply(self.v)).add(&c1.multiply(1.0 - self.u - self.v)),
+            None => Color::new(1.0, 1.0, 1.0),
+        };
 
         let mut n1 = 1.0;
         let mut n2 = 1.0;
-        let mut containers: Vec<usize> = vec![];
-        for i in xs {
-            if *i == *self {
-                if containers.is_empty() {
-                    n1 = 1.0;
-                } else {
-                    let id = containers.last().unwrap();
-                    let object = get_object(*id);
-                    n1 = object.get_material().refractive_index;
+        CONTAINERS.with_borrow_mut(|containers| {
+            containers.clear();
+            for i in xs {
+                if *i == *self {
+                    if containers.is_empty() {
+                        n1 = 1.0;
+                    } else {
+                        let id = containers.last().unwrap();
+                        let object = get_object(*id);
+                        n1 = object.get_material().refractive_index;
+                    }
                 }
-            }
 
-            if containers.contains(&i.object) {
-                if let Some(index) = containers.iter().position(|shape| *shape == i.object) {
-                    containers.remove(index);
+                if containers.contains(&i.object) {
+                    if let Some(index) = containers.iter().position(|shape| *shape == i.object) {
+                        containers.remove(index);
+                    }
+                } else {
+                    containers.push(i.object);
                 }
-            } else {
-                containers.push(i.object);
-            }
 
-            if *i == *self {
-                if containers.is_empty() {
-                    n2 = 1.0;
-                } else {
-                    let id = containers.last().unwrap();
-                    let object = get_object(*id);
-                    n2 = object.get_material().refractive_index;
+                if *i == *self {
+                    if containers.is_empty() {
+                        n2 = 1.0;
+                    } else {
+                        let id = containers.last().unwrap();
+                        let object = get_object(*id);
+                        n2 = object.get_material().refractive_index;
+                    }
                 }
             }
-        }
+        });
+
+        Computations { t: self.t, object: self.object, point, eyev, normalv, inside, over_point, under_point, reflectv, n1, n2, vertex_color, footprint, reflected_differentials }
+    }
+}
+
+/// A list of intersections whose memory is borrowed from the current thread.
+///
+/// Every ray cast needs a list to collect its intersections in. The list is taken from the lists the
+/// thread has finished with, and given back when it is dropped, so rendering does not allocate a new
+/// one for each ray. It is used like a `Vec<Intersection>`.
+pub struct Intersections {
+    xs: Vec<Intersection>,
+}
+
+impl Intersections {
+    /// Takes an empty list of intersections from the current thread.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Intersections`, reusing the memory of a list that was dropped before if there is one.
+    pub fn from_pool() -> Intersections {
+        let xs = SPARE_LISTS.with_borrow_mut(|spare| spare.pop()).unwrap_or_default();
+        Intersections { xs }
+    }
+}
+
+impl Deref for Intersections {
+    type Target = Vec<Intersection>;
+
+    fn deref(&self) -> &Vec<Intersection> {
+        &self.xs
+    }
+}
+
+impl DerefMut for Intersections {
+    fn deref_mut(&mut self) -> &mut Vec<Intersection> {
+        &mut self.xs
+    }
+}
 
-        Computations { t: self.t, object: self.object, point, eyev, normalv, inside, over_point, under_point, reflectv, n1, n2 }
+impl Drop for Intersections {
+    fn drop(&mut self) {
+        let mut xs = std::mem::take(&mut self.xs);
+        xs.clear();
+        SPARE_LISTS.with_borrow_mut(|spare| spare.push(xs));
     }
 }
@@ -0,0 +1,34 @@
+/// Quotes a string for JSON output, escaping quotes, backslashes and control characters.
+///
+/// # Arguments
+///
+/// * `value` - The string to quote.
+///
+/// # Returns
+///
+/// The string in double quotes, as a JSON string literal.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json_string;
+
+    #[test]
+    fn strings_are_quoted_and_escaped_for_json() {
+        assert_eq!(json_string("scene.yaml"), "\"scene.yaml\"");
+        assert_eq!(json_string("a \"b\"\\c\nd\te"), "\"a \\\"b\\\"\\\\c\\nd\\u0009e\"");
+    }
+}
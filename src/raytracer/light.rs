@@ -1,26 +1,64 @@
-use rand::{Rng, thread_rng};
+use std::f64::consts::PI;
 use crate::color::Color;
 use crate::tuple::Tuple;
 use crate::raytracer::object::db::get_object;
-use crate::raytracer::material::pattern_at_object;
+use crate::raytracer::material::{pattern_at_object, DiffuseModel, Material, ShadingModel, SpecularModel};
+use crate::raytracer::sampling::sample_2d;
+use crate::EPSILON;
 
 /// Enum representing the different types of light sources.
-/// Currently only supports point lights.
+/// Point lights and rectangular area lights are supported.
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum LightType {
     Point,
-    Area(Tuple, Tuple, Tuple, usize) // corner, u vector, v vector, sample level
+    Area(AreaLight),
+}
+
+/// The shape and sampling settings of a rectangular area light.
+///
+/// The light is a parallelogram spanned by `uvec` and `vvec` from `corner`. It is divided into a grid
+/// of `usteps` by `vsteps` cells, and shadows are computed by sampling one point in each cell.
+///
+/// # Fields
+///
+/// * `corner` - The position of one corner of the light.
+/// * `uvec` - The vector along the first edge of the light.
+/// * `vvec` - The vector along the second edge of the light.
+/// * `usteps` - The number of cells along `uvec`.
+/// * `vsteps` - The number of cells along `vvec`.
+/// * `jitter` - Whether each sample is at a random position in its cell instead of at its center. Jitter
+///   replaces the banding of a regular grid with noise.
+/// * `adaptive` - Whether to sample only the corner cells first, and the full grid only when they
+///   disagree, which is the case in the penumbra.
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct AreaLight {
+    pub corner: Tuple,
+    pub uvec: Tuple,
+    pub vvec: Tuple,
+    pub usteps: usize,
+    pub vsteps: usize,
+    pub jitter: bool,
+    pub adaptive: bool,
 }
 
 /// Represents a light source in the scene.
 ///
 /// This struct encapsulates the properties of a light source, including its type
 /// (e.g., point light), intensity (color and brightness), and position in the scene.
-#[derive(Debug, Clone, PartialEq, Copy)]
+/// The `diffuse` and `specular` flags select which parts of the shading the light adds,
+/// so that a light can be used only for highlights, or only to light the surfaces.
+/// The `groups` link the light to the objects in the same light groups, see `illuminates`.
+/// When `inverse_square` is set the light falls off with the square of the distance, and
+/// `intensity` is the light received at a distance of 1, see `intensity_at`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Light {
     pub light_type: LightType,
     pub intensity: Color,
     pub position: Tuple,
+    pub diffuse: bool,
+    pub specular: bool,
+    pub groups: Vec<String>,
+    pub inverse_square: bool,
 }
 
 impl Light {
@@ -35,43 +73,106 @@ impl Light {
     ///
     /// A new `Light` instance configured as a point light source.
     pub fn new_point_light(position: Tuple, intensity: Color) -> Light {
-        Light { light_type: LightType::Point, intensity, position }
+        Light { light_type: LightType::Point, intensity, position, diffuse: true, specular: true, groups: vec![], inverse_square: false }
     }
 
+    /// Constructs a new area light source with a square grid of jittered samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `corner` - The position of one corner of the light.
+    /// * `u` - The vector along the first edge of the light.
+    /// * `v` - The vector along the second edge of the light.
+    /// * `intensity` - The color and intensity of the light.
+    /// * `level` - The number of cells along each edge of the light.
+    ///
+    /// # Returns
+    ///
+    /// A new `Light` instance configured as an area light source.
+    #[allow(dead_code)]
     pub fn new_area_light(corner: Tuple, u: Tuple, v: Tuple, intensity: Color, level: usize) -> Light {
+        Light::from_area(AreaLight { corner, uvec: u, vvec: v, usteps: level, vsteps: level, jitter: true, adaptive: false }, intensity)
+    }
+
+    /// Constructs a new area light source from its shape and sampling settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `area` - The shape and sampling settings of the light.
+    /// * `intensity` - The color and intensity of the light.
+    ///
+    /// # Returns
+    ///
+    /// A new `Light` instance positioned at the center of the area.
+    pub fn from_area(area: AreaLight, intensity: Color) -> Light {
         //find the center of the area light
-        let center = corner.add(&u.multiply(0.5)).add(&v.multiply(0.5));
-        Light { light_type: LightType::Area(corner, u, v, level), intensity, position: center }
+        let center = area.corner.add(&area.uvec.multiply(0.5)).add(&area.vvec.multiply(0.5));
+        Light { light_type: LightType::Area(area), intensity, position: center, diffuse: true, specular: true, groups: vec![], inverse_square: false }
     }
 
-    pub fn sample_point(&self, sample: usize, amount: usize) -> Tuple {
-        match self.light_type {
-            LightType::Point => self.position,
-            LightType::Area(corner, u, v, _samples) => {
-                //divide the light into a grid of samples, amount wide and amount high
-                //find the row and column of the sample using sample var which starts at 0
-                let row = sample / amount;
-                let col = sample % amount;
-
-                //find a random position in the grid square specified by row and col
-                let mut rng = thread_rng();
-                let u_rand = rng.gen_range(0.0..1.0);
-                let v_rand = rng.gen_range(0.0..1.0);
-                let u_rand = (col as f64 + u_rand) / amount as f64;
-                let v_rand = (row as f64 + v_rand) / amount as f64;
-                corner.add(&u.multiply(u_rand)).add(&v.multiply(v_rand))
-            }
+    /// Returns the intensity of the light that arrives at a point.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - The lit point.
+    ///
+    /// # Returns
+    ///
+    /// The intensity divided by the square of the distance to the light when `inverse_square` is set,
+    /// and the plain intensity otherwise.
+    pub fn intensity_at(&self, point: &Tuple) -> Color {
+        if !self.inverse_square {
+            return self.intensity;
         }
+        let distance_squared = self.position.subtract(point).magnitude().powi(2).max(EPSILON);
+        self.intensity.multiply(1.0 / distance_squared)
     }
-}
 
-#[allow(dead_code)]
-fn random_in_unit_sphere() -> Tuple {
-    let mut rng = thread_rng();
-    loop {
-        let p = Tuple::vector(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
-        if p.magnitude() < 1.0 {
-            return p;
+    /// Checks whether the light illuminates an object, according to their light groups.
+    ///
+    /// A light in some groups only lights the objects in at least one of them, and an object in some
+    /// groups is only lit by the lights in at least one of them. Lights and objects without groups
+    /// are linked to each other.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_groups` - The light groups of the object.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the light illuminates the object.
+    pub fn illuminates(&self, object_groups: &[String]) -> bool {
+        if self.groups.is_empty() && object_groups.is_empty() {
+            return true;
+        }
+        self.groups.iter().any(|group| object_groups.contains(group))
+    }
+
+    /// Returns the point sampled on the light for one cell of its grid.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The cell index along the `uvec` edge, from 0 to `usteps - 1`.
+    /// * `v` - The cell index along the `vvec` edge, from 0 to `vsteps - 1`.
+    ///
+    /// # Returns
+    ///
+    /// A point in the cell, at a random position when `jitter` is set or at its center otherwise.
+    /// For point lights this is always the position of the light.
+    pub fn point_on_light(&self, u: usize, v: usize) -> Tuple {
+        match self.light_type {
+            LightType::Point => self.position,
+            LightType::Area(area) => {
+                //find a position in the grid cell specified by u and v
+                let (u_offset, v_offset) = if area.jitter {
+                    sample_2d()
+                } else {
+                    (0.5, 0.5)
+                };
+                let u_rand = (u as f64 + u_offset) / area.usteps as f64;
+                let v_rand = (v as f64 + v_offset) / area.vsteps as f64;
+                area.corner.add(&area.uvec.multiply(u_rand)).add(&area.vvec.multiply(v_rand))
+            }
         }
     }
 }
@@ -79,8 +180,8 @@ fn random_in_unit_sphere() -> Tuple {
 /// Computes the color at a point on an object, taking into account the light source,
 /// the viewer's position, and whether the point is in shadow.
 ///
-/// This function implements the Phong reflection model to calculate the color of a point
-/// on an object's surface. It considers the object's material properties, the light's
+/// This function implements the Phong reflection model, or the physically based model for materials
+/// that select it, to calculate the color of a point on an object's surface. It considers the object's material properties, the light's
 /// intensity and position, and whether the point is in shadow.
 ///
 /// # Arguments
@@ -95,17 +196,46 @@ fn random_in_unit_sphere() -> Tuple {
 /// # Returns
 ///
 /// The computed color at the given point on the object.
+#[allow(dead_code)]
 pub fn lighting(object_id: usize, light: &Light, point: &Tuple, eyev: &Tuple, normalv: &Tuple, in_shadow: f64) -> Color {
+    let transmission = Color::new(1.0, 1.0, 1.0).multiply(1.0 - in_shadow);
+    let color = pattern_at_object(object_id, point);
+    lighting_with_transmission(object_id, light, point, eyev, normalv, &color, &transmission, 1.0)
+}
+
+/// Computes the color at a point on an object, with the light filtered by the objects between
+/// the point and the light source.
+///
+/// This is the same reflection model as `lighting`, but instead of a single shadow amount
+/// the diffuse and specular contributions are multiplied by the color of the light that reaches
+/// the point, which allows shadows cast by transparent and tinted objects.
+///
+/// # Arguments
+///
+/// * `object_id` - The ID of the object being illuminated.
+/// * `light` - A reference to the light source illuminating the object.
+/// * `point` - The point on the object's surface being illuminated.
+/// * `eyev` - The vector from the point to the viewer's eye.
+/// * `normalv` - The normal vector at the point on the object's surface.
+/// * `color` - The color of the surface at the point, from the material's pattern and any vertex colors,
+///   see `Computations::surface_color`.
+/// * `transmission` - The fraction of the light that reaches the point, per color channel.
+/// * `ambient_visibility` - The fraction of the ambient light that reaches the point, lowered by ambient occlusion.
+///
+/// # Returns
+///
+/// The computed color at the given point on the object.
+#[allow(clippy::too_many_arguments)]
+pub fn lighting_with_transmission(object_id: usize, light: &Light, point: &Tuple, eyev: &Tuple, normalv: &Tuple, color: &Color, transmission: &Color, ambient_visibility: f64) -> Color {
     let object = get_object(object_id);
     let material = object.get_material();
     // Combine the surface color with the light's color/intensity
-    let color = pattern_at_object(object_id, point);
-
-    let effective_color = color.product(&light.intensity);
+    let intensity = light.intensity_at(point);
+    let effective_color = color.product(&intensity);
     // Find the direction to the light source
     let lightv = (light.position.subtract(point)).normalize();
     // Compute the ambient contribution
-    let ambient = effective_color.multiply(material.ambient);
+    let ambient = effective_color.multiply(material.ambient * ambient_visibility);
 
     // Light_dot_normal represents the cosine of the angle between
     // the light vector and the normal vector.
@@ -117,37 +247,135 @@ pub fn lighting(object_id: usize, light: &Light, point: &Tuple, eyev: &Tuple, no
     if light_dot_normal < 0.0 {
         diffuse = Color::new(0.0, 0.0, 0.0);
         specular = Color::new(0.0, 0.0, 0.0);
+    } else if material.model == ShadingModel::Pbr {
+        let (pbr_diffuse, pbr_specular) = cook_torrance(material, color, &lightv, eyev, normalv);
+        diffuse = pbr_diffuse.product(&intensity).multiply(light_dot_normal);
+        specular = pbr_specular.product(&intensity).multiply(light_dot_normal);
     } else {
         // Compute the diffuse contribution
-        diffuse = effective_color.multiply(material.diffuse).multiply(light_dot_normal);
-        let reflectv = lightv.negate().reflect(&normalv);
+        let diffuse_factor = match material.diffuse_model {
+            DiffuseModel::Lambert => 1.0,
+            DiffuseModel::OrenNayar(sigma) => oren_nayar(sigma, &lightv, eyev, normalv),
+        };
+        diffuse = effective_color.multiply(material.diffuse * diffuse_factor).multiply(light_dot_normal);
         // reflect_dot_eye represents the cosine of the angle between the
         // reflection vector and the eye vector. A negative number means the
-        // light reflects away from the eye.
-        let reflect_dot_eye = reflectv.dot(eyev);
+        // light reflects away from the eye. Blinn-Phong uses the angle between
+        // the normal and the half vector instead.
+        let reflect_dot_eye = match material.specular_model {
+            SpecularModel::Phong => lightv.negate().reflect(normalv).dot(eyev),
+            SpecularModel::BlinnPhong => lightv.add(&eyev.normalize()).normalize().dot(normalv),
+        };
         if reflect_dot_eye <= 0.0 {
             specular = Color::new(0.0, 0.0, 0.0);
         } else {
             // Compute the specular contribution
             let factor = reflect_dot_eye.powf(material.shininess);
-            specular = light.intensity.multiply(material.specular).multiply(factor);
+            specular = intensity.multiply(material.specular_at(object_id, point)).multiply(factor);
         }
     }
+    // Leave out the contributions the light is not meant to add; the ambient light goes with the diffuse one
+    let black = Color::new(0.0, 0.0, 0.0);
+    let (ambient, diffuse) = if light.diffuse { (ambient, diffuse) } else { (black, black) };
+    let specular = if light.specular { specular } else { black };
     // Add the three contributions together to get the final shading
-    // include the shadow factor
-    let diffuse_specular = diffuse.add(&specular).multiply(1.0 - in_shadow);
+    // include the light that gets through to the point
+    let diffuse_specular = diffuse.add(&specular).product(transmission);
     ambient.add(&diffuse_specular)
 }
 
+/// Computes how much the Oren–Nayar model changes the Lambertian diffuse light for one light direction.
+///
+/// This is the usual qualitative approximation of the model, in which the facets of a rough surface both
+/// shadow each other and reflect light back towards the light source.
+///
+/// # Arguments
+///
+/// * `sigma` - The roughness, the standard deviation of the slope of the facets in radians.
+/// * `lightv` - The normalized vector from the point to the light.
+/// * `eyev` - The vector from the point to the viewer's eye.
+/// * `normalv` - The normal vector at the point.
+///
+/// # Returns
+///
+/// The factor the Lambertian diffuse light is multiplied by; 1 when `sigma` is 0.
+fn oren_nayar(sigma: f64, lightv: &Tuple, eyev: &Tuple, normalv: &Tuple) -> f64 {
+    let sigma2 = sigma * sigma;
+    let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let eyev = eyev.normalize();
+    let cos_i = normalv.dot(lightv).clamp(-1.0, 1.0);
+    let cos_r = normalv.dot(&eyev).clamp(-1.0, 1.0);
+    // The cosine of the angle between the light and the eye around the normal
+    let light_across = lightv.subtract(&normalv.multiply(cos_i));
+    let eye_across = eyev.subtract(&normalv.multiply(cos_r));
+    let cos_phi = if light_across.magnitude() < EPSILON || eye_across.magnitude() < EPSILON {
+        0.0
+    } else {
+        light_across.normalize().dot(&eye_across.normalize())
+    };
+
+    let (theta_i, theta_r) = (cos_i.acos(), cos_r.acos());
+    let (alpha, beta) = (theta_i.max(theta_r), theta_i.min(theta_r));
+    a + b * cos_phi.max(0.0) * alpha.sin() * beta.tan()
+}
+
+/// The smallest roughness used by the physically based model, to keep the highlight of polished surfaces finite.
+const MIN_ROUGHNESS: f64 = 0.02;
+
+/// Evaluates the physically based metallic/roughness reflection model for one light direction.
+///
+/// The diffuse part is Lambertian, the specular part is the Cook–Torrance microfacet model with the GGX
+/// distribution, Smith's geometry term and Schlick's approximation of the Fresnel factor. Metals have no
+/// diffuse part and tint their reflection with the base color. The result is scaled by π, so that a white,
+/// rough, non-metallic surface is as bright as a Phong material with a diffuse coefficient of 1.
+///
+/// # Arguments
+///
+/// * `material` - The material of the surface.
+/// * `base_color` - The color of the surface at the point.
+/// * `lightv` - The normalized vector from the point to the light.
+/// * `eyev` - The vector from the point to the viewer's eye.
+/// * `normalv` - The normal vector at the point.
+///
+/// # Returns
+///
+/// The diffuse and the specular fractions of the light reflected towards the eye, per color channel, before the
+/// cosine of the light angle.
+fn cook_torrance(material: &Material, base_color: &Color, lightv: &Tuple, eyev: &Tuple, normalv: &Tuple) -> (Color, Color) {
+    let eyev = eyev.normalize();
+    let halfv = lightv.add(&eyev).normalize();
+    let n_dot_l = normalv.dot(lightv).max(EPSILON);
+    let n_dot_v = normalv.dot(&eyev).max(EPSILON);
+    let n_dot_h = normalv.dot(&halfv).max(0.0);
+    let v_dot_h = eyev.dot(&halfv).max(0.0);
+
+    let roughness = material.roughness.clamp(MIN_ROUGHNESS, 1.0);
+    let alpha2 = roughness.powi(4);
+    let d = alpha2 / (PI * (n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0).powi(2));
+    let k = (roughness + 1.0).powi(2) / 8.0;
+    let g = n_dot_l / (n_dot_l * (1.0 - k) + k) * n_dot_v / (n_dot_v * (1.0 - k) + k);
+
+    let metallic = material.metallic.clamp(0.0, 1.0);
+    let f0 = Color::new(material.f0, material.f0, material.f0).multiply(1.0 - metallic).add(&base_color.multiply(metallic));
+    let white = Color::new(1.0, 1.0, 1.0);
+    let fresnel = f0.add(&white.subtract(&f0).multiply((1.0 - v_dot_h).powi(5)));
+
+    let specular = fresnel.multiply(d * g / (4.0 * n_dot_l * n_dot_v));
+    let diffuse = white.subtract(&fresnel).multiply(1.0 - metallic).product(base_color).multiply(1.0 / PI);
+    (diffuse.multiply(PI), specular.multiply(PI))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
     use crate::color::Color;
     use crate::tuple::Tuple;
-    use super::Light;
+    use super::{AreaLight, Light};
     use super::lighting;
-    use crate::matrix::Matrix;
-    use crate::raytracer::material::Material;
+    use crate::matrix4::Matrix4;
+    use crate::raytracer::material::{DiffuseModel, Material, ShadingModel, SpecularModel};
     use crate::raytracer::material::pattern::Pattern;
     use crate::raytracer::object::sphere::Sphere;
     use crate::raytracer::scene::Scene;
@@ -161,6 +389,26 @@ mod tests {
         assert_eq!(light.position, position);
     }
 
+    #[test]
+    fn finding_a_single_point_on_an_area_light() {
+        let area = AreaLight {
+            corner: Tuple::point(0.0, 0.0, 0.0),
+            uvec: Tuple::vector(2.0, 0.0, 0.0),
+            vvec: Tuple::vector(0.0, 0.0, 1.0),
+            usteps: 4,
+            vsteps: 2,
+            jitter: false,
+            adaptive: false,
+        };
+        let light = Light::from_area(area, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(light.position, Tuple::point(1.0, 0.0, 0.5));
+        assert_eq!(light.point_on_light(0, 0), Tuple::point(0.25, 0.0, 0.25));
+        assert_eq!(light.point_on_light(1, 0), Tuple::point(0.75, 0.0, 0.25));
+        assert_eq!(light.point_on_light(0, 1), Tuple::point(0.25, 0.0, 0.75));
+        assert_eq!(light.point_on_light(2, 0), Tuple::point(1.25, 0.0, 0.25));
+        assert_eq!(light.point_on_light(3, 1), Tuple::point(1.75, 0.0, 0.75));
+    }
+
     #[test]
     fn lighting_with_the_eye_between_the_light_and_the_surface() {
         let mut w = Scene::new();
@@ -255,9 +503,9 @@ mod tests {
         m.ambient = 1.0;
         m.diffuse = 0.0;
         m.specular = 0.0;
-        m.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix::identity(4)),
-                                    Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix::identity(4)),
-                                    Matrix::identity(4));
+        m.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix4::identity()),
+                                    Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix4::identity()),
+                                    Matrix4::identity());
         let mut object = Sphere::new();
         object.material = m;
         w.add_object(Arc::new(object));
@@ -270,4 +518,116 @@ mod tests {
         assert_eq!(c1, Color::new(1.0, 1.0, 1.0));
         assert_eq!(c2, Color::new(0.0, 0.0, 0.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn lighting_a_rough_plastic_with_the_physically_based_model() {
+        let mut w = Scene::new();
+        let mut shape = Sphere::new();
+        shape.material.model = ShadingModel::Pbr;
+        w.add_object(Arc::new(shape));
+        let id = w.ids[0];
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let result = lighting(id, &light, &Tuple::point(0.0, 0.0, 0.0), &eyev, &normalv, 0.0);
+        // 0.1 ambient, 0.96 diffuse and a highlight of 0.04 / (4 * 0.5^4) = 0.16
+        assert_eq!(result, Color::new(1.22, 1.22, 1.22));
+        let behind = Light::new_point_light(Tuple::point(0.0, 0.0, 10.0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(lighting(id, &behind, &Tuple::point(0.0, 0.0, 0.0), &eyev, &normalv, 0.0), Color::new(0.1, 0.1, 0.1));
+    }
+
+    #[test]
+    fn metals_tint_their_highlight_and_have_no_diffuse_light() {
+        let mut w = Scene::new();
+        let mut shape = Sphere::new();
+        shape.material.model = ShadingModel::Pbr;
+        shape.material.metallic = 1.0;
+        shape.material.ambient = 0.0;
+        shape.material.pattern = Pattern::solid(Color::new(1.0, 0.5, 0.0), Matrix4::identity());
+        w.add_object(Arc::new(shape));
+        let id = w.ids[0];
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let facing = lighting(id, &light, &Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, -1.0), &normalv, 0.0);
+        assert_eq!(facing, Color::new(4.0, 2.0, 0.0));
+        // Away from the highlight much less is reflected
+        let eyev = Tuple::vector(0.0, 2_f64.sqrt() / 2.0, -2_f64.sqrt() / 2.0);
+        let off = lighting(id, &light, &Tuple::point(0.0, 0.0, 0.0), &eyev, &normalv, 0.0);
+        assert!(off.r < facing.r / 4.0 && off.b < 0.01);
+    }
+
+    #[test]
+    fn rough_surfaces_reflect_light_back_with_the_oren_nayar_model() {
+        let mut w = Scene::new();
+        let mut shape = Sphere::new();
+        shape.material.diffuse_model = DiffuseModel::OrenNayar(0.5);
+        shape.material.specular = 0.0;
+        shape.material.ambient = 0.0;
+        w.add_object(Arc::new(shape));
+        let id = w.ids[0];
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        // Lit and seen head on, the surface is darker than a Lambertian one
+        let result = lighting(id, &light, &position, &normalv, &normalv, 0.0);
+        assert_eq!(result, Color::new(0.70603, 0.70603, 0.70603));
+        // Lit and seen from the same grazing angle, it is brighter
+        let eyev = Tuple::vector(0.0, 3_f64.sqrt() / 2.0, -0.5);
+        let grazing = Light::new_point_light(eyev.multiply(10.0).add(&position), Color::new(1.0, 1.0, 1.0));
+        let result = lighting(id, &grazing, &position, &eyev, &normalv, 0.0);
+        assert!(result.r > 0.9 * 0.5);
+    }
+
+    #[test]
+    fn blinn_phong_highlights_use_the_half_vector() {
+        let mut w = Scene::new();
+        let mut shape = Sphere::new();
+        shape.material.specular_model = SpecularModel::BlinnPhong;
+        shape.material.shininess = 10.0;
+        w.add_object(Arc::new(shape));
+        let id = w.ids[0];
+        let eyev = Tuple::vector(0.0, 2_f64.sqrt() / 2.0, -2_f64.sqrt() / 2.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        let result = lighting(id, &light, &Tuple::point(0.0, 0.0, 0.0), &eyev, &normalv, 0.0);
+        // The half vector is 22.5 degrees from the normal: 0.1 + 0.9 + 0.9 * cos(22.5)^10
+        assert_eq!(result, Color::new(1.40775, 1.40775, 1.40775));
+    }
+
+    #[test]
+    fn lights_can_add_only_diffuse_or_only_specular_light() {
+        let mut w = Scene::new();
+        w.add_object(Arc::new(Sphere::new()));
+        let id = w.ids[0];
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let mut light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        light.specular = false;
+        assert_eq!(lighting(id, &light, &position, &eyev, &normalv, 0.0), Color::new(1.0, 1.0, 1.0));
+        light.specular = true;
+        light.diffuse = false;
+        assert_eq!(lighting(id, &light, &position, &eyev, &normalv, 0.0), Color::new(0.9, 0.9, 0.9));
+    }
+
+    #[test]
+    fn lights_only_illuminate_objects_in_their_groups() {
+        let mut light = Light::new_point_light(Tuple::point(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        let product = vec!["product".to_string()];
+        assert!(light.illuminates(&[]));
+        assert!(!light.illuminates(&product));
+        light.groups = vec!["product".to_string(), "floor".to_string()];
+        assert!(light.illuminates(&product));
+        assert!(!light.illuminates(&[]));
+        assert!(!light.illuminates(&["backdrop".to_string()]));
+    }
+
+    #[test]
+    fn physical_lights_fall_off_with_the_square_of_the_distance() {
+        let mut light = Light::new_point_light(Tuple::point(0.0, 0.0, 0.0), Color::new(8.0, 8.0, 8.0));
+        let point = Tuple::point(0.0, 2.0, 0.0);
+        assert_eq!(light.intensity_at(&point), Color::new(8.0, 8.0, 8.0));
+        light.inverse_square = true;
+        assert_eq!(light.intensity_at(&point), Color::new(2.0, 2.0, 2.0));
+    }
+}
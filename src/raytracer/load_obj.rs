@@ -1,160 +1,1623 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
 use std::sync::Arc;
-use tobj::Mesh;
+use std::time::UNIX_EPOCH;
+use crate::color::Color;
 use crate::raytracer::material::Material;
+use crate::raytracer::object::Object;
+use crate::raytracer::object::db::get_object;
 use crate::raytracer::object::group::Group;
 use crate::raytracer::object::smooth_triangle::SmoothTriangle;
 use crate::raytracer::object::triangle::Triangle;
+use crate::raytracer::profile::profile_span;
+use crate::raytracer::progress::LoadProgress;
 use crate::tuple::Tuple;
+use crate::EPSILON;
 
-fn get_normals(mesh: &Mesh) -> Vec<Vec<Tuple>> {
-    let mut normals: Vec<Vec<Tuple>> = vec![];
-    let mut next_normal = 0;
-    for normal_index in 0..mesh.face_arities.len() {
-        let end = next_normal + mesh.face_arities[normal_index] as usize;
-        let normal_indices = &mesh.normal_indices[next_normal..end];
-        let mut normal: Vec<Tuple> = vec![];
-        //let mut vertex = 0;
-        for n in normal_indices {
-            let x: f64 = mesh.normals[3 * *n as usize] as f64;
-            let y: f64 = mesh.normals[3 * *n as usize + 1] as f64;
-            let z: f64 = mesh.normals[3 * *n as usize + 2] as f64;
-            //println!("normal: {}, vertex: {},  x: {}, y: {}, z: {}", normals.len(), vertex, x, y, z);
-            normal.push(Tuple::vector(x, y, z));
-            //vertex += 1;
+/// The triangles of one model of an OBJ file, as they are read from the file and stored in the mesh cache.
+///
+/// # Fields
+///
+/// * `name` - The name of the group or object of the file the triangles are in, empty when they are in neither.
+/// * `points` - The three points of each triangle.
+/// * `normals` - The normals at the three points of each triangle, or empty when the model has no normals.
+/// * `colors` - The colors at the three points of each triangle, or empty when the model has no vertex colors.
+/// * `material_names` - The names of the material slots of the triangles, given by `usemtl` in OBJ files. The first
+///   is empty, for the triangles that have the material of the object.
+/// * `materials` - The material slot of each triangle, or empty when they all have the material of the object.
+/// * `face_normals` - The normal of the face of each triangle of a model without normals when it was read from the
+///   mesh cache, or empty to compute them from the points.
+struct ModelTriangles {
+    name: String,
+    points: Vec<[[f32; 3]; 3]>,
+    normals: Vec<[[f32; 3]; 3]>,
+    colors: Vec<[[f32; 3]; 3]>,
+    material_names: Vec<String>,
+    materials: Vec<u32>,
+    face_normals: Vec<[f64; 3]>,
+}
+
+impl ModelTriangles {
+    /// Creates a model without any triangles.
+    fn new(name: &str) -> ModelTriangles {
+        ModelTriangles { name: name.to_string(), points: vec![], normals: vec![], colors: vec![], material_names: vec![], materials: vec![], face_normals: vec![] }
+    }
+
+    /// Returns the name of the material slot of a triangle, empty when it has the material of the object.
+    fn material_name(&self, triangle: usize) -> &str {
+        self.materials.get(triangle).map_or("", |slot| &self.material_names[*slot as usize])
+    }
+
+    /// Adds a triangle to the model as it is read.
+    ///
+    /// Once some triangles of the model have normals the others get the normal of their face, once some corners
+    /// have colors the others are white, and once some triangles have a material slot the others have the material
+    /// of the object, so the lists that are not empty have an entry for every triangle.
+    ///
+    /// # Arguments
+    ///
+    /// * `points` - The corners of the triangle.
+    /// * `normals` - The normals at the corners, if the face has them.
+    /// * `colors` - The colors at the corners, for the vertices that have them.
+    /// * `material` - The name of the material slot of the triangle, empty for the material of the object.
+    fn add_triangle(&mut self, points: [[f32; 3]; 3], normals: Option<[[f32; 3]; 3]>, colors: [Option<[f32; 3]>; 3], material: &str) {
+        if normals.is_some() && self.normals.is_empty() {
+            self.normals = self.points.iter().map(face_normals).collect();
+        }
+        if normals.is_some() || !self.normals.is_empty() {
+            self.normals.push(normals.unwrap_or_else(|| face_normals(&points)));
+        }
+        let colored = colors.iter().any(Option::is_some);
+        if colored && self.colors.is_empty() {
+            self.colors = vec![[[1.0; 3]; 3]; self.points.len()];
+        }
+        if colored || !self.colors.is_empty() {
+            self.colors.push(colors.map(|c| c.unwrap_or([1.0; 3])));
+        }
+        if !material.is_empty() && self.materials.is_empty() {
+            self.material_names = vec![String::new()];
+            self.materials = vec![0; self.points.len()];
         }
-        normals.push(normal);
+        if !material.is_empty() || !self.materials.is_empty() {
+            let slot = self.material_names.iter().position(|name| name == material).unwrap_or_else(|| {
+                self.material_names.push(material.to_string());
+                self.material_names.len() - 1
+            });
+            self.materials.push(slot as u32);
+        }
+        self.points.push(points);
+    }
+}
 
-        next_normal = end;
+/// Returns the normal of the face of a triangle as the normals at its corners.
+fn face_normals([p1, p2, p3]: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let edge = |a: [f32; 3], b: [f32; 3]| Tuple::vector((b[0] - a[0]) as f64, (b[1] - a[1]) as f64, (b[2] - a[2]) as f64);
+    let n = edge(*p1, *p2).cross(&edge(*p1, *p3)).normalize();
+    [[n.x as f32, n.y as f32, n.z as f32]; 3]
+}
+
+/// Reads the numbers after the keyword of a statement into `values`, ignoring any more than fit.
+///
+/// # Returns
+///
+/// The number of values read, or an error if one of the words is not a number or there are fewer than `minimum`.
+fn parse_numbers<'a>(words: impl Iterator<Item = &'a str>, values: &mut [f32], minimum: usize, line: usize, file: &str) -> Result<usize, String> {
+    let mut count = 0;
+    for (value, word) in values.iter_mut().zip(words) {
+        *value = word.parse().map_err(|_| format!("{}:{}: cannot read the number {}", file, line, word))?;
+        count += 1;
+    }
+    if count < minimum {
+        return Err(format!("{}:{}: expected {} numbers, found {}", file, line, minimum, count));
     }
+    Ok(count)
+}
 
-    normals
+/// Turns an index of a face into a position in a list, counting from 1 at the start of the list, or from -1
+/// at the end of it for negative indices.
+fn resolve_index(word: &str, count: usize, (kind, kinds): (&str, &str), line: usize, file: &str) -> Result<usize, String> {
+    let index: i64 = word.parse().map_err(|_| format!("{}:{}: cannot read the {} index {}", file, line, kind, word))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{}:{}: {} index {} is out of range, {} {} are defined so far", file, line, kind, index, count, kinds));
+    }
+    Ok(resolved as usize)
 }
 
-fn get_faces(mesh: &Mesh) -> Vec<Vec<Tuple>> {
-    let mut faces: Vec<Vec<Tuple>> = vec![];
-    let mut next_face = 0;
-    for face in 0..mesh.face_arities.len() {
-        let end = next_face + mesh.face_arities[face] as usize;
-        let face_indices = &mesh.indices[next_face..end];
-        let mut face: Vec<Tuple> = vec![];
-        //let mut vertex = 0;
-        for f in face_indices {
-            let x: f64 = mesh.positions[3 * *f as usize] as f64;
-            let y: f64 = mesh.positions[3 * *f as usize + 1] as f64;
-            let z: f64 = mesh.positions[3 * *f as usize + 2] as f64;
-            //println!("face: {}, vertex: {},  x: {}, y: {}, z: {}", faces.len(), vertex, x, y, z);
-            face.push(Tuple::point(x, y, z));
-            //vertex += 1;
+/// Returns whether a polygon is convex, so that a fan of triangles from its first corner covers it exactly.
+fn is_convex(corners: &[[f32; 3]]) -> bool {
+    let vector = |p: [f32; 3]| Tuple::vector(p[0] as f64, p[1] as f64, p[2] as f64);
+    let n = corners.len();
+    // Newell's normal, which is sound for polygons that are not quite flat
+    let normal = (0..n).fold(Tuple::vector(0.0, 0.0, 0.0), |sum, i| sum.add(&vector(corners[i]).cross(&vector(corners[(i + 1) % n]))));
+    (0..n).all(|i| {
+        let (a, b, c) = (vector(corners[i]), vector(corners[(i + 1) % n]), vector(corners[(i + 2) % n]));
+        b.subtract(&a).cross(&c.subtract(&b)).dot(&normal) >= 0.0
+    })
+}
+
+/// Reads the triangles of an OBJ file, with a model for each run of faces in the same group or object.
+///
+/// Vertices, normals, faces, groups (`g`), objects (`o`) and the names of the materials of faces (`usemtl`) are
+/// read, and other statements, like texture coordinates and material libraries, are skipped. Indices may be negative, counting back from the last vertex or normal
+/// read so far. Faces with more than three corners are split into fans of triangles from their first corner,
+/// which fills polygons that are not convex wrongly, so a warning is printed when there are any. Vertex colors
+/// are the OBJ extension that follows the position of a vertex with its red, green and blue. A line that ends
+/// with a backslash continues on the next line.
+///
+/// The file is read a line at a time into the same buffer, and the triangles of each face are added as soon as
+/// it is read, so only the vertices, normals and triangles are held in memory, never the text of the file.
+///
+/// # Arguments
+///
+/// * `reader` - The contents of the file.
+/// * `file` - The name of the file, for the messages.
+/// * `progress` - Called with the number of bytes read so far, after about every megabyte.
+///
+/// # Returns
+///
+/// The models of the file, or an error naming the line that cannot be read.
+fn parse_obj(mut reader: impl BufRead, file: &str, mut progress: impl FnMut(u64)) -> Result<Vec<ModelTriangles>, String> {
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut vertex_colors: Vec<Option<[f32; 3]>> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+    let mut models = vec![];
+    let mut model = ModelTriangles::new("");
+    let mut corners: Vec<(usize, Option<usize>)> = vec![];
+    let mut material = String::new();
+    let mut concave = (0, 0);
+    let mut statement = String::new();
+    let (mut line, mut first_line) = (0, 0);
+    let (mut bytes, mut reported) = (0, 0);
+    loop {
+        if statement.is_empty() {
+            first_line = line + 1;
+        }
+        let start = statement.len();
+        let read = reader.read_line(&mut statement).map_err(|e| format!("{}:{}: {}", file, line + 1, e))?;
+        if read == 0 && statement.is_empty() {
+            break;
+        }
+        line += 1;
+        bytes += read as u64;
+        if bytes - reported >= 1 << 20 {
+            reported = bytes;
+            progress(bytes);
+        }
+        let text = statement[start..].trim_end_matches(['\n', '\r']);
+        if read > 0 && text.ends_with('\\') {
+            // Replace the backslash and line break with a space, and go on with the next line
+            let length = start + text.len() - 1;
+            statement.truncate(length);
+            statement.push(' ');
+            continue;
+        }
+        let content = statement.split('#').next().unwrap_or_default();
+        let mut words = content.split_whitespace();
+        let mut values = [0.0; 6];
+        match words.next() {
+            Some("v") => {
+                let count = parse_numbers(words, &mut values, 3, first_line, file)?;
+                positions.push([values[0], values[1], values[2]]);
+                vertex_colors.push((count == 6).then(|| [values[3], values[4], values[5]]));
+            }
+            Some("vn") => {
+                parse_numbers(words, &mut values[..3], 3, first_line, file)?;
+                normals.push([values[0], values[1], values[2]]);
+            }
+            Some("f") => {
+                corners.clear();
+                for word in words {
+                    // A corner is v, v/vt, v//vn or v/vt/vn
+                    let mut indices = word.split('/');
+                    let position = resolve_index(indices.next().unwrap_or_default(), positions.len(), ("vertex", "vertices"), first_line, file)?;
+                    let normal = match indices.nth(1) {
+                        Some(index) if !index.is_empty() => Some(resolve_index(index, normals.len(), ("normal", "normals"), first_line, file)?),
+                        _ => None,
+                    };
+                    corners.push((position, normal));
+                }
+                if corners.len() < 3 {
+                    return Err(format!("{}:{}: a face needs at least 3 corners, found {}", file, first_line, corners.len()));
+                }
+                if corners.len() > 3 && !is_convex(&corners.iter().map(|(p, _)| positions[*p]).collect::<Vec<_>>()) {
+                    if concave.0 == 0 {
+                        concave.1 = first_line;
+                    }
+                    concave.0 += 1;
+                }
+                let has_normals = corners.iter().all(|(_, n)| n.is_some());
+                for i in 1..corners.len() - 1 {
+                    let triangle = [corners[0], corners[i], corners[i + 1]];
+                    model.add_triangle(
+                        triangle.map(|(p, _)| positions[p]),
+                        has_normals.then(|| triangle.map(|(_, n)| normals[n.unwrap()])),
+                        triangle.map(|(p, _)| vertex_colors[p]),
+                        &material,
+                    );
+                }
+            }
+            Some("usemtl") => {
+                material.clear();
+                material.push_str(content.trim_start()["usemtl".len()..].trim());
+            }
+            Some("g") | Some("o") => {
+                let name = content.trim_start()[1..].trim();
+                if name != model.name {
+                    let finished = std::mem::replace(&mut model, ModelTriangles::new(name));
+                    if !finished.points.is_empty() {
+                        models.push(finished);
+                    }
+                }
+            }
+            _ => {}
+        }
+        statement.clear();
+    }
+    progress(bytes);
+    if !model.points.is_empty() {
+        models.push(model);
+    }
+    if concave.0 > 0 {
+        eprintln!("Warning: {}: {} faces are not convex, the first on line {}, and may be filled wrongly by their triangles",
+                  file, concave.0, concave.1);
+    }
+    Ok(models)
+}
+
+/// Turns the triangles of a model so that they are all wound the same way, with their fronts facing outward.
+///
+/// Two triangles that share an edge are wound the same way when they run along it in opposite directions, so
+/// the winding of a triangle is passed on across its edges, flipping the neighbours that run the wrong way.
+/// Corners are the same when they have the same position, and edges shared by more than two triangles are not
+/// followed. Each connected part of the model is then turned as a whole: a closed part so that its volume is
+/// positive, which means the fronts face outward, and an open part so that most of its triangles keep the
+/// winding of the file. Last, the normals at the corners of a triangle are flipped if they point to its back.
+///
+/// A triangle is flipped by swapping its second and third corners. Its front is the side that
+/// `(p2 - p1) x (p3 - p1)` points to, as in OBJ files.
+///
+/// # Arguments
+///
+/// * `model` - The triangles of the model, changed in place.
+///
+/// # Returns
+///
+/// The number of triangles whose winding was flipped.
+fn orient_triangles(model: &mut ModelTriangles) -> usize {
+    let _span = profile_span!(INFO, "orient_triangles");
+    let vector = |p: [f32; 3]| Tuple::vector(p[0] as f64, p[1] as f64, p[2] as f64);
+    let count = model.points.len();
+    // Number the distinct positions, with -0 the same as 0
+    let mut vertex_ids = HashMap::new();
+    let corners: Vec<[usize; 3]> = model.points.iter()
+        .map(|triangle| triangle.map(|p| {
+            let next_id = vertex_ids.len();
+            *vertex_ids.entry(p.map(|v| (v + 0.0).to_bits())).or_insert(next_id)
+        }))
+        .collect();
+    // The triangles along each edge, and whether they run along it from the lower to the higher vertex
+    let mut edges: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+    for (t, [a, b, c]) in corners.iter().enumerate() {
+        for (from, to) in [(*a, *b), (*b, *c), (*c, *a)] {
+            if from != to {
+                edges.entry((from.min(to), from.max(to))).or_default().push((t, from < to));
+            }
+        }
+    }
+
+    let mut flipped = vec![false; count];
+    let mut visited = vec![false; count];
+    let mut flipped_count = 0;
+    for start in 0..count {
+        if visited[start] {
+            continue;
+        }
+        // Spread the winding of the first triangle over the part it belongs to
+        visited[start] = true;
+        let mut part = vec![start];
+        let mut closed = true;
+        let mut next = 0;
+        while next < part.len() {
+            let t = part[next];
+            next += 1;
+            let [a, b, c] = corners[t];
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                if from == to {
+                    continue;
+                }
+                let sharing = &edges[&(from.min(to), from.max(to))];
+                if sharing.len() != 2 {
+                    closed = false;
+                    continue;
+                }
+                let forward = (from < to) != flipped[t];
+                for (u, u_forward) in sharing.iter().copied().filter(|(u, _)| *u != t) {
+                    if !visited[u] {
+                        visited[u] = true;
+                        flipped[u] = u_forward == forward;
+                        part.push(u);
+                    }
+                }
+            }
         }
-        faces.push(face);
 
-        next_face = end;
+        let turn_part = if closed {
+            let center = part.iter()
+                .flat_map(|t| model.points[*t])
+                .fold(Tuple::vector(0.0, 0.0, 0.0), |sum, p| sum.add(&vector(p)))
+                .multiply(1.0 / (3 * part.len()) as f64);
+            // Six times the volume of the part, as the sum of the volumes of the tetrahedra from its center
+            let volume: f64 = part.iter()
+                .map(|t| {
+                    let [p1, p2, p3] = model.points[*t].map(|p| vector(p).subtract(&center));
+                    let volume = p1.dot(&p2.cross(&p3));
+                    if flipped[*t] { -volume } else { volume }
+                })
+                .sum();
+            volume < 0.0
+        } else {
+            2 * part.iter().filter(|t| flipped[**t]).count() > part.len()
+        };
+        for t in part {
+            if flipped[t] != turn_part {
+                flipped_count += 1;
+                model.points[t].swap(1, 2);
+                if let Some(normals) = model.normals.get_mut(t) {
+                    normals.swap(1, 2);
+                }
+                if let Some(colors) = model.colors.get_mut(t) {
+                    colors.swap(1, 2);
+                }
+            }
+        }
+    }
+
+    for (points, normals) in model.points.iter().zip(model.normals.iter_mut()) {
+        let [p1, p2, p3] = points.map(vector);
+        let front = p2.subtract(&p1).cross(&p3.subtract(&p1));
+        let normal = normals.iter().fold(Tuple::vector(0.0, 0.0, 0.0), |sum, n| sum.add(&vector(*n)));
+        if front.dot(&normal) < 0.0 {
+            *normals = normals.map(|n| n.map(|v| -v));
+        }
     }
+    flipped_count
+}
+
+/// A quadric, the weighted sum of the squared distances of a point from a set of planes, as the ten distinct
+/// entries of its symmetric 4x4 matrix.
+type Quadric = [f64; 10];
+
+/// Returns the quadric of the plane through `point` with the unit `normal`, weighted by `weight`.
+fn plane_quadric(normal: Tuple, point: Tuple, weight: f64) -> Quadric {
+    let (a, b, c, d) = (normal.x, normal.y, normal.z, -normal.dot(&point));
+    [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|v| v * weight)
+}
+
+/// Returns the sum of two quadrics, which measures the distances from the planes of both.
+fn add_quadrics(q1: &Quadric, q2: &Quadric) -> Quadric {
+    std::array::from_fn(|i| q1[i] + q2[i])
+}
 
-    faces
+/// Returns the error of a point for a quadric: the weighted sum of its squared distances from the planes.
+fn quadric_error(q: &Quadric, p: Tuple) -> f64 {
+    let (x, y, z) = (p.x, p.y, p.z);
+    q[0] * x * x + q[4] * y * y + q[7] * z * z + q[9]
+        + 2.0 * (q[1] * x * y + q[2] * x * z + q[3] * x + q[5] * y * z + q[6] * y + q[8] * z)
 }
 
-fn convert_face_to_triangles(vertexes: &Vec<Tuple>) -> Vec<Triangle> {
-    let mut triangles: Vec<Triangle> = vec![];
+/// The weight of the planes that keep the outline of an open mesh, relative to the planes of its triangles.
+const OUTLINE_WEIGHT: f64 = 10.0;
 
-    for i in 1..vertexes.len() - 1 {
-        triangles.push(Triangle::new(vertexes[0], vertexes[i], vertexes[i + 1]));
+/// The triangles of a model with the corners at the same position joined into vertices.
+///
+/// # Fields
+///
+/// * `positions` - The position of each vertex.
+/// * `normal_sums` - The sum of the normals of the corners of each vertex, zero when the model has no normals.
+/// * `color_sums` - The sum of the colors of the corners of each vertex, zero when the model has no colors.
+/// * `corner_counts` - The number of corners joined into each vertex.
+/// * `faces` - The vertices of each triangle.
+struct WeldedMesh {
+    positions: Vec<Tuple>,
+    normal_sums: Vec<Tuple>,
+    color_sums: Vec<Tuple>,
+    corner_counts: Vec<f64>,
+    faces: Vec<[usize; 3]>,
+}
+
+impl WeldedMesh {
+    /// Joins the corners of a model at the same position, with -0 the same as 0.
+    fn new(model: &ModelTriangles) -> WeldedMesh {
+        let vector = |p: [f32; 3]| Tuple::vector(p[0] as f64, p[1] as f64, p[2] as f64);
+        let zero = Tuple::vector(0.0, 0.0, 0.0);
+        let mut vertex_ids = HashMap::new();
+        let mut mesh = WeldedMesh { positions: vec![], normal_sums: vec![], color_sums: vec![], corner_counts: vec![], faces: vec![] };
+        for (t, triangle) in model.points.iter().enumerate() {
+            let face = std::array::from_fn(|corner| {
+                let id = *vertex_ids.entry(triangle[corner].map(|v| (v + 0.0).to_bits())).or_insert_with(|| {
+                    mesh.positions.push(vector(triangle[corner]));
+                    mesh.normal_sums.push(zero);
+                    mesh.color_sums.push(zero);
+                    mesh.corner_counts.push(0.0);
+                    mesh.positions.len() - 1
+                });
+                if let Some(normals) = model.normals.get(t) {
+                    mesh.normal_sums[id] = mesh.normal_sums[id].add(&vector(normals[corner]));
+                }
+                if let Some(colors) = model.colors.get(t) {
+                    mesh.color_sums[id] = mesh.color_sums[id].add(&vector(colors[corner]));
+                }
+                mesh.corner_counts[id] += 1.0;
+                id
+            });
+            mesh.faces.push(face);
+        }
+        mesh
     }
 
-    triangles
+    /// Returns the mean of the colors of the corners of each vertex.
+    fn mean_colors(&self) -> Vec<Tuple> {
+        self.color_sums.iter().zip(self.corner_counts.iter()).map(|(sum, count)| sum.multiply(1.0 / count)).collect()
+    }
 }
 
-fn convert_face_to_triangles_with_normals(vertexes: &Vec<Tuple>, normals: &Vec<Tuple>) -> Vec<SmoothTriangle> {
-    let mut triangles: Vec<SmoothTriangle> = vec![];
+/// Simplifies a model to about `max_triangles` triangles by collapsing its edges, cheapest first.
+///
+/// This is the quadric error metric of Garland and Heckbert: each vertex keeps the planes of the triangles around
+/// it, and an edge is collapsed by moving both its ends to whichever of them, or their midpoint, is nearest to
+/// the planes of both. Each edge on the outline of an open mesh also adds a plane across it, so that outlines keep
+/// their shape. Collapses that would turn a triangle over, or join parts of the mesh that only touch, are skipped,
+/// so a model may keep more triangles than asked for.
+///
+/// Corners at the same position are joined into one vertex first, with the mean of their normals and colors, so
+/// the normals of hard edges become smooth.
+///
+/// # Arguments
+///
+/// * `model` - The triangles of the model, replaced by the simplified ones.
+/// * `max_triangles` - The number of triangles to keep.
+///
+/// # Returns
+///
+/// The number of triangles removed.
+fn decimate(model: &mut ModelTriangles, max_triangles: usize) -> usize {
+    let _span = profile_span!(INFO, "decimate");
+    let count = model.points.len();
+    if count <= max_triangles {
+        return 0;
+    }
+    let WeldedMesh { mut positions, mut normal_sums, mut color_sums, mut corner_counts, mut faces } = WeldedMesh::new(model);
 
-    for i in 1..vertexes.len() - 1 {
-        triangles.push(SmoothTriangle::new(vertexes[0], vertexes[i], vertexes[i + 1],
-                                            normals[0], normals[i], normals[i + 1]));
+    // The planes of the triangles around each vertex, weighted by their areas, and the planes along the outline
+    let face_normal = |[a, b, c]: [Tuple; 3]| b.subtract(&a).cross(&c.subtract(&a));
+    let mut alive: Vec<bool> = faces.iter().map(|[a, b, c]| a != b && b != c && c != a).collect();
+    let mut quadrics = vec![[0.0; 10]; positions.len()];
+    let mut vertex_faces = vec![vec![]; positions.len()];
+    let mut edges: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    for (f, face) in faces.iter().enumerate().filter(|(f, _)| alive[*f]) {
+        let normal = face_normal(face.map(|v| positions[v]));
+        let area = normal.magnitude() / 2.0;
+        for v in face {
+            if area > 0.0 {
+                quadrics[*v] = add_quadrics(&quadrics[*v], &plane_quadric(normal.normalize(), positions[face[0]], area));
+            }
+            vertex_faces[*v].push(f);
+        }
+        for (a, b) in [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+            edges.entry((a.min(b), a.max(b))).or_insert((0, f)).0 += 1;
+        }
+    }
+    for (&(a, b), &(_, f)) in edges.iter().filter(|(_, (sharing, _))| *sharing == 1) {
+        let along = positions[b].subtract(&positions[a]);
+        let across = along.cross(&face_normal(faces[f].map(|v| positions[v])));
+        if across.magnitude() > 0.0 {
+            let quadric = plane_quadric(across.normalize(), positions[a], OUTLINE_WEIGHT * along.length_squared());
+            quadrics[a] = add_quadrics(&quadrics[a], &quadric);
+            quadrics[b] = add_quadrics(&quadrics[b], &quadric);
+        }
     }
 
-    triangles
+    // The cheapest place to collapse an edge to, and its cost, which is never negative, so its bits sort like it
+    let collapse = |quadrics: &[Quadric], positions: &[Tuple], u: usize, v: usize| {
+        let quadric = add_quadrics(&quadrics[u], &quadrics[v]);
+        let middle = positions[u].add(&positions[v]).multiply(0.5);
+        [positions[u], positions[v], middle].into_iter()
+            .map(|p| (quadric_error(&quadric, p).max(0.0), p))
+            .fold((f64::INFINITY, middle), |best, candidate| if candidate.0 < best.0 { candidate } else { best })
+    };
+    let neighbours = |faces: &[[usize; 3]], vertex_faces: &[Vec<usize>], v: usize| {
+        let mut neighbours: Vec<usize> = vertex_faces[v].iter().flat_map(|f| faces[*f]).filter(|w| *w != v).collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    };
+    let mut versions = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    for &(u, v) in edges.keys() {
+        heap.push(Reverse((collapse(&quadrics, &positions, u, v).0.to_bits(), u, v, 0, 0)));
+    }
+
+    let mut live = alive.iter().filter(|alive| **alive).count();
+    let mut removed = vec![false; positions.len()];
+    while live > max_triangles {
+        let Some(Reverse((_, u, v, version_u, version_v))) = heap.pop() else {
+            break;
+        };
+        if removed[u] || removed[v] || versions[u] != version_u || versions[v] != version_v {
+            continue;
+        }
+        // The ends may only share the neighbours of the triangles along the edge, or the mesh would fold
+        let (neighbours_u, neighbours_v) = (neighbours(&faces, &vertex_faces, u), neighbours(&faces, &vertex_faces, v));
+        let shared_faces = vertex_faces[u].iter().filter(|f| faces[**f].contains(&v)).count();
+        if neighbours_u.iter().filter(|w| neighbours_v.binary_search(w).is_ok()).count() != shared_faces {
+            continue;
+        }
+        let (_, target) = collapse(&quadrics, &positions, u, v);
+        let turns_over = [u, v].iter().any(|w| {
+            vertex_faces[*w].iter().filter(|f| !faces[**f].contains(&u) || !faces[**f].contains(&v)).any(|f| {
+                let before = face_normal(faces[*f].map(|x| positions[x]));
+                let after = face_normal(faces[*f].map(|x| if x == *w { target } else { positions[x] }));
+                before.magnitude() > 0.0 && after.dot(&before) <= 0.0
+            })
+        });
+        if turns_over {
+            continue;
+        }
+
+        // Move u to the target and give it the triangles of v, dropping those along the edge
+        positions[u] = target;
+        quadrics[u] = add_quadrics(&quadrics[u], &quadrics[v]);
+        normal_sums[u] = normal_sums[u].add(&normal_sums[v]);
+        color_sums[u] = color_sums[u].add(&color_sums[v]);
+        corner_counts[u] += corner_counts[v];
+        for f in std::mem::take(&mut vertex_faces[v]) {
+            if faces[f].contains(&u) {
+                alive[f] = false;
+                live -= 1;
+                for x in faces[f].into_iter().filter(|x| *x != v) {
+                    vertex_faces[x].retain(|g| *g != f);
+                }
+            } else {
+                faces[f] = faces[f].map(|x| if x == v { u } else { x });
+                vertex_faces[u].push(f);
+            }
+        }
+        removed[v] = true;
+        versions[u] += 1;
+        for w in neighbours(&faces, &vertex_faces, u) {
+            let (cost, _) = collapse(&quadrics, &positions, u, w);
+            heap.push(Reverse((cost.to_bits(), u, w, versions[u], versions[w])));
+        }
+    }
+
+    let to_f32 = |t: Tuple| [t.x as f32, t.y as f32, t.z as f32];
+    let mut simplified = ModelTriangles::new(&model.name);
+    for (f, face) in faces.iter().enumerate().filter(|(f, _)| alive[*f]) {
+        let front = face_normal(face.map(|v| positions[v]));
+        let normal = |v: usize| if normal_sums[v].magnitude() > 0.0 { normal_sums[v].normalize() } else { front.normalize() };
+        simplified.add_triangle(
+            face.map(|v| to_f32(positions[v])),
+            (!model.normals.is_empty()).then(|| face.map(|v| to_f32(normal(v)))),
+            face.map(|v| (!model.colors.is_empty()).then(|| to_f32(color_sums[v].multiply(1.0 / corner_counts[v])))),
+            model.material_name(f),
+        );
+    }
+    *model = simplified;
+    count - model.points.len()
 }
 
-fn create_group(mesh: &Mesh, material: Material) -> Group {
-    let mut group = Group::new();
-    let faces: Vec<Vec<Tuple>> = get_faces(mesh);
-    if mesh.normal_indices.is_empty() {
-        for f in faces.iter() {
-            let triangles = convert_face_to_triangles(f);
-            for mut t in triangles {
-                t.material = material.clone();
-                group.add_child(Arc::new(t));
+/// Splits each triangle of a mesh into four with one step of Loop subdivision.
+///
+/// A new vertex is put on each edge, at 3/8 of each of its ends and 1/8 of the corners opposite it, and each old
+/// vertex is moved towards its neighbours by the weights of Loop. Edges on the outline of the mesh, or shared by
+/// more than two triangles, are creases: their new vertices are at their middles, and the vertices along them only
+/// move along them. Corners where more than two creases meet stay where they are.
+///
+/// # Arguments
+///
+/// * `positions` - The positions of the vertices.
+/// * `colors` - The colors of the vertices, if they have any, which are blended like the positions.
+/// * `faces` - The vertices of each triangle, with the new vertices of their edges after the old ones.
+fn loop_subdivide(positions: &[Tuple], colors: Option<&[Tuple]>, faces: &[[usize; 3]]) -> (Vec<Tuple>, Option<Vec<Tuple>>, Vec<[usize; 3]>) {
+    // Number the edges in the order they are found, with the corners opposite them
+    let mut edges: HashMap<(usize, usize), (usize, Vec<usize>)> = HashMap::new();
+    let mut neighbours = vec![vec![]; positions.len()];
+    for &[a, b, c] in faces {
+        for (from, to, opposite) in [(a, b, c), (b, c, a), (c, a, b)] {
+            let next_index = positions.len() + edges.len();
+            edges.entry((from.min(to), from.max(to))).or_insert((next_index, vec![])).1.push(opposite);
+            neighbours[from].push(to);
+            neighbours[to].push(from);
+        }
+    }
+    let crease = |a: usize, b: usize| edges[&(a.min(b), a.max(b))].1.len() != 2;
+
+    // The weights of the old vertices in each new one
+    let mut weights: Vec<Vec<(usize, f64)>> = vec![vec![]; positions.len() + edges.len()];
+    for (v, neighbours) in neighbours.iter_mut().enumerate() {
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        let creases: Vec<usize> = neighbours.iter().copied().filter(|w| crease(v, *w)).collect();
+        weights[v] = match creases.as_slice() {
+            [] => {
+                let n = neighbours.len() as f64;
+                let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+                std::iter::once((v, 1.0 - n * beta)).chain(neighbours.iter().map(|w| (*w, beta))).collect()
             }
+            [a, b] => vec![(v, 0.75), (*a, 0.125), (*b, 0.125)],
+            _ => vec![(v, 1.0)],
+        };
+    }
+    for (&(a, b), (index, opposites)) in edges.iter() {
+        weights[*index] = match opposites.as_slice() {
+            [c, d] => vec![(a, 0.375), (b, 0.375), (*c, 0.125), (*d, 0.125)],
+            _ => vec![(a, 0.5), (b, 0.5)],
+        };
+    }
+    let blend = |values: &[Tuple]| -> Vec<Tuple> {
+        weights.iter()
+            .map(|weights| weights.iter().fold(Tuple::vector(0.0, 0.0, 0.0), |sum, (v, weight)| sum.add(&values[*v].multiply(*weight))))
+            .collect()
+    };
+
+    let edge_vertex = |a: usize, b: usize| edges[&(a.min(b), a.max(b))].0;
+    let faces = faces.iter()
+        .flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (edge_vertex(a, b), edge_vertex(b, c), edge_vertex(c, a));
+            [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+        })
+        .collect();
+    (blend(positions), colors.map(blend), faces)
+}
+
+/// Smooths a model with a number of steps of Loop subdivision, see `loop_subdivide`.
+///
+/// Each step splits every triangle into four. The model gets smooth normals from its new surface, see
+/// `recompute_normals`; any normals it had are dropped.
+///
+/// # Arguments
+///
+/// * `model` - The triangles of the model, replaced by the subdivided ones.
+/// * `steps` - The number of steps.
+fn subdivide(model: &mut ModelTriangles, steps: usize) {
+    if steps == 0 || model.points.is_empty() {
+        return;
+    }
+    let _span = profile_span!(INFO, "subdivide");
+    let welded = WeldedMesh::new(model);
+    let mut colors = (!model.colors.is_empty()).then(|| welded.mean_colors());
+    let WeldedMesh { mut positions, faces, .. } = welded;
+    // The triangle of the model each face comes from, for its material
+    let (mut faces, triangles): (Vec<[usize; 3]>, Vec<usize>) = faces.into_iter().enumerate()
+        .filter(|(_, [a, b, c])| a != b && b != c && c != a)
+        .map(|(t, face)| (face, t))
+        .unzip();
+    for _ in 0..steps {
+        (positions, colors, faces) = loop_subdivide(&positions, colors.as_deref(), &faces);
+    }
+
+    let to_f32 = |t: Tuple| [t.x as f32, t.y as f32, t.z as f32];
+    let mut subdivided = ModelTriangles::new(&model.name);
+    for (f, face) in faces.into_iter().enumerate() {
+        // Each step splits a face into four that follow each other
+        let material = model.material_name(triangles[f >> (2 * steps)]);
+        subdivided.add_triangle(face.map(|v| to_f32(positions[v])), None, face.map(|v| colors.as_ref().map(|colors| to_f32(colors[v]))), material);
+    }
+    recompute_normals(&mut subdivided, 180.0);
+    *model = subdivided;
+}
+
+/// Replaces the normals of a model with ones computed from its surface, so that it is shaded smoothly except
+/// across hard edges.
+///
+/// The normal at a corner of a triangle is the mean of the normals of the triangles around its vertex, weighted by
+/// their angles at it, leaving out those that meet the triangle at a hard edge: those whose normals are more than
+/// `hard_edge_angle` degrees from its own. Corners at the same position are the same vertex.
+///
+/// # Arguments
+///
+/// * `model` - The triangles of the model, whose normals are replaced.
+/// * `hard_edge_angle` - The angle in degrees between triangles above which the edge between them stays sharp,
+///   180 for none.
+fn recompute_normals(model: &mut ModelTriangles, hard_edge_angle: f64) {
+    let _span = profile_span!(INFO, "recompute_normals");
+    let WeldedMesh { positions, faces, .. } = WeldedMesh::new(model);
+    let fronts: Vec<Tuple> = faces.iter()
+        .map(|face| {
+            let [p1, p2, p3] = face.map(|v| positions[v]);
+            let front = p2.subtract(&p1).cross(&p3.subtract(&p1));
+            if front.magnitude() > 0.0 { front.normalize() } else { front }
+        })
+        .collect();
+    // The triangles around each vertex, with their angles at it
+    let mut vertex_faces = vec![vec![]; positions.len()];
+    for (f, face) in faces.iter().enumerate().filter(|(f, _)| fronts[*f].magnitude() > 0.0) {
+        for corner in 0..3 {
+            let [from, to1, to2] = [0, 1, 2].map(|i| positions[face[(corner + i) % 3]]);
+            let angle = to1.subtract(&from).normalize().dot(&to2.subtract(&from).normalize()).clamp(-1.0, 1.0).acos();
+            vertex_faces[face[corner]].push((f, angle));
         }
-    } else {
-        let normals: Vec<Vec<Tuple>> = get_normals(mesh);
-        for (f, n) in faces.iter().zip(normals.iter()) {
-            let triangles = convert_face_to_triangles_with_normals(f,n);
-            for mut t in triangles {
-                t.material = material.clone();
-                group.add_child(Arc::new(t));
+    }
+
+    let smooth = hard_edge_angle.to_radians().cos() - EPSILON;
+    model.normals = faces.iter().enumerate()
+        .map(|(f, face)| face.map(|v| {
+            let normal = vertex_faces[v].iter()
+                .filter(|(g, _)| fronts[*g].dot(&fronts[f]) >= smooth)
+                .fold(Tuple::vector(0.0, 0.0, 0.0), |sum, (g, angle)| sum.add(&fronts[*g].multiply(*angle)));
+            let normal = if normal.magnitude() > 0.0 { normal.normalize() } else { fronts[f] };
+            [normal.x as f32, normal.y as f32, normal.z as f32]
+        }))
+        .collect();
+}
+
+/// Computes smooth normals for the corners of triangles from their surface, like `recompute_normals` does for
+/// the triangles of an OBJ file.
+///
+/// # Arguments
+///
+/// * `triangles` - The corners of the triangles. Corners at the same position are the same vertex.
+/// * `hard_edge_angle` - The angle in degrees between triangles above which the edge between them stays sharp,
+///   180 for none.
+///
+/// # Returns
+///
+/// The normals at the corners of each triangle.
+pub(crate) fn surface_normals(triangles: &[[Tuple; 3]], hard_edge_angle: f64) -> Vec<[Tuple; 3]> {
+    let mut model = ModelTriangles::new("");
+    model.points = triangles.iter().map(|corners| corners.map(|p| [p.x as f32, p.y as f32, p.z as f32])).collect();
+    recompute_normals(&mut model, hard_edge_angle);
+    model.normals.iter().map(|corners| corners.map(|n| Tuple::vector(n[0] as f64, n[1] as f64, n[2] as f64))).collect()
+}
+
+/// How the triangles of an OBJ file are changed after it is loaded, and the materials of their slots.
+///
+/// # Fields
+///
+/// * `subdivisions` - The number of steps of Loop subdivision of the model, see `subdivide`.
+/// * `part_subdivisions` - The number of steps for groups and objects of the file, by name, instead of `subdivisions`.
+/// * `max_triangles` - When set, the model is then simplified to about this many triangles, see `decimate`.
+/// * `recompute_normals` - When set, the normals of the model are then computed again from its surface, with
+///   edges between triangles at more than this many degrees kept sharp, see `recompute_normals`.
+/// * `materials` - The materials of the material slots of the file, by name. Triangles of other slots, or of none,
+///   have the material of the object.
+/// * `orient` - Whether the triangles are first turned to face outward consistently, see `orient_triangles`.
+///   Defaults to true.
+/// * `divide` - When set, the triangles of each group and object of the file, or of the whole file when it has
+///   none, are sorted into a bounding volume hierarchy with this threshold, see `Group::divide`.
+#[derive(Debug, Clone)]
+pub struct MeshOptions {
+    pub subdivisions: usize,
+    pub part_subdivisions: HashMap<String, usize>,
+    pub max_triangles: Option<usize>,
+    pub recompute_normals: Option<f64>,
+    pub materials: HashMap<String, Material>,
+    pub orient: bool,
+    pub divide: Option<usize>,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        MeshOptions {
+            subdivisions: 0,
+            part_subdivisions: HashMap::new(),
+            max_triangles: None,
+            recompute_normals: None,
+            materials: HashMap::new(),
+            orient: true,
+            divide: None,
+        }
+    }
+}
+
+/// Orients, subdivides and simplifies the models of a file, and then computes their normals again, as the options
+/// ask. When simplified, each model keeps its share of the triangles of the file. When triangles are flipped to
+/// face outward, a warning with their number is printed.
+fn apply_mesh_options(file: &str, models: &mut [ModelTriangles], options: &MeshOptions) {
+    if options.orient {
+        let flipped: usize = models.iter_mut().map(orient_triangles).sum();
+        if flipped > 0 {
+            let total: usize = models.iter().map(|model| model.points.len()).sum();
+            eprintln!("Warning: {}: {} of {} triangles were flipped to face outward like their neighbours, set orient: false to keep the winding of the file",
+                      file, flipped, total);
+        }
+    }
+    for model in models.iter_mut() {
+        subdivide(model, options.part_subdivisions.get(&model.name).copied().unwrap_or(options.subdivisions));
+    }
+    let total: usize = models.iter().map(|model| model.points.len()).sum();
+    match options.max_triangles {
+        Some(max_triangles) if total > max_triangles => {
+            for model in models.iter_mut() {
+                let share = (max_triangles * model.points.len() / total).max(1);
+                decimate(model, share);
             }
         }
+        _ => {}
+    }
+    if let Some(hard_edge_angle) = options.recompute_normals {
+        for model in models.iter_mut() {
+            recompute_normals(model, hard_edge_angle);
+        }
     }
+}
 
-    group
+/// Makes the triangles of a model, as smooth triangles when the model has normals.
+///
+/// Each triangle gets the material of its slot from `materials`, or `material` when the slot has none. The
+/// triangles share one list of the materials of the slots of the model, with `material` first for the triangles
+/// without a slot, and keep only the number of their slot in it.
+fn model_triangles(model: &ModelTriangles, material: &Material, materials: &HashMap<String, Material>) -> Vec<Arc<dyn Object + Send>> {
+    let slots: Arc<[Material]> = std::iter::once(material)
+        .chain(model.material_names.iter().map(|name| materials.get(name).unwrap_or(material)))
+        .cloned()
+        .collect();
+    let slot = |i: usize| model.materials.get(i).map_or(0, |slot| slot + 1);
+    let vector = |n: [f32; 3]| Tuple::vector(n[0] as f64, n[1] as f64, n[2] as f64);
+    let colors = |i: usize| model.colors.get(i).map(|c| c.map(|c| Color::new(c[0] as f64, c[1] as f64, c[2] as f64)));
+    let mut triangles: Vec<Arc<dyn Object + Send>> = Vec::with_capacity(model.points.len());
+    if model.normals.is_empty() {
+        for (i, [p1, p2, p3]) in model.points.iter().enumerate() {
+            let mut t = match model.face_normals.get(i) {
+                Some([x, y, z]) => Triangle::with_normal(point(*p1), point(*p2), point(*p3), Tuple::vector(*x, *y, *z)),
+                None => Triangle::new(point(*p1), point(*p2), point(*p3)),
+            };
+            t.materials = slots.clone();
+            t.slot = slot(i);
+            t.colors = colors(i);
+            triangles.push(Arc::new(t));
+        }
+    } else {
+        for (i, ([p1, p2, p3], [n1, n2, n3])) in model.points.iter().zip(model.normals.iter()).enumerate() {
+            let mut t = SmoothTriangle::new(point(*p1), point(*p2), point(*p3), vector(*n1), vector(*n2), vector(*n3));
+            t.materials = slots.clone();
+            t.slot = slot(i);
+            t.colors = colors(i);
+            triangles.push(Arc::new(t));
+        }
+    }
+    triangles
+}
+
+/// Converts a point of a model to a `Tuple`.
+fn point(p: [f32; 3]) -> Tuple {
+    Tuple::point(p[0] as f64, p[1] as f64, p[2] as f64)
+}
+
+/// A group of the bounding volume hierarchy of a part of a model, as it is kept in the mesh cache.
+///
+/// # Fields
+///
+/// * `triangles` - The triangles directly in the group, by their number in the part.
+/// * `groups` - The sub-groups of the group.
+#[derive(Debug, Clone, PartialEq)]
+struct HierarchyNode {
+    triangles: Vec<u32>,
+    groups: Vec<HierarchyNode>,
+}
+
+/// Puts the models of a file together by name, in the order the names first appear, since a file can go back
+/// to a group it left.
+fn model_parts(models: &[ModelTriangles]) -> Vec<(&str, Vec<&ModelTriangles>)> {
+    let mut parts: Vec<(&str, Vec<&ModelTriangles>)> = vec![];
+    for model in models {
+        match parts.iter_mut().find(|(name, _)| *name == model.name) {
+            Some((_, part)) => part.push(model),
+            None => parts.push((&model.name, vec![model])),
+        }
+    }
+    parts
+}
+
+/// Adds the triangles of the models of a part to its group and sorts them into a bounding volume hierarchy: the
+/// one read from the mesh cache when there is one, or else a new one made by `Group::divide` when `divide` is set.
+/// The group is then marked as divided, so dividing the model again leaves it as it is.
+///
+/// # Arguments
+///
+/// * `group` - The group of the part.
+/// * `part` - The models of the part.
+/// * `material` - The material of the triangles without a material slot in `materials`.
+/// * `materials` - The materials of the material slots, by name.
+/// * `divide` - The threshold of `Group::divide`, or `None` to leave the triangles in the group.
+/// * `hierarchy` - The hierarchy read from the mesh cache, or `None`.
+///
+/// # Returns
+///
+/// The hierarchy made with `divide`, to be kept in the mesh cache, or `None` when none was made.
+fn fill_part(group: &mut Group, part: &[&ModelTriangles], material: &Material, materials: &HashMap<String, Material>,
+             divide: Option<usize>, hierarchy: Option<&HierarchyNode>) -> Option<HierarchyNode> {
+    let triangles = part.iter().flat_map(|model| model_triangles(model, material, materials));
+    if let Some(hierarchy) = hierarchy {
+        let mut triangles: Vec<Option<Arc<dyn Object + Send>>> = triangles.map(Some).collect();
+        build_hierarchy(group, hierarchy, &mut triangles);
+        group.divided = true;
+        return None;
+    }
+    let numbers: HashMap<usize, u32> = triangles.enumerate().map(|(i, t)| (group.add_child(t), i as u32)).collect();
+    let threshold = divide?;
+    let _span = profile_span!(INFO, "divide");
+    group.divide(threshold);
+    group.divided = true;
+    Some(record_hierarchy(&group.child_ids, &numbers))
+}
+
+/// Adds triangles to a group, and to new sub-groups of it, as a hierarchy read from the mesh cache arranges them.
+fn build_hierarchy(group: &mut Group, node: &HierarchyNode, triangles: &mut [Option<Arc<dyn Object + Send>>]) {
+    for t in &node.triangles {
+        group.add_child(triangles[*t as usize].take().expect("the mesh cache was checked to hold each triangle once"));
+    }
+    for sub_node in &node.groups {
+        let mut subgroup = Group::new();
+        build_hierarchy(&mut subgroup, sub_node, triangles);
+        subgroup.divided = true;
+        group.add_child(Arc::new(subgroup));
+    }
+}
+
+/// Records the bounding volume hierarchy of a divided group, given the ids of its children and the numbers of
+/// its triangles in their part.
+fn record_hierarchy(child_ids: &[usize], numbers: &HashMap<usize, u32>) -> HierarchyNode {
+    let mut node = HierarchyNode { triangles: vec![], groups: vec![] };
+    for id in child_ids {
+        match numbers.get(id) {
+            Some(number) => node.triangles.push(*number),
+            None => node.groups.push(record_hierarchy(&get_object(*id).child_ids(), numbers)),
+        }
+    }
+    node
+}
+
+/// Reads the triangles of every model in an OBJ file.
+fn read_obj_models(file: &str) -> Result<Vec<ModelTriangles>, String> {
+    let _span = profile_span!(INFO, "load_obj", file);
+    let reader = File::open(file).map_err(|e| format!("cannot open OBJ file {}: {}", file, e))?;
+    let size = reader.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut progress = LoadProgress::new(file, size);
+    let models = parse_obj(BufReader::with_capacity(1 << 20, reader), file, |bytes| progress.set(bytes));
+    progress.finish();
+    models
+}
+
+/// Builds the group of an OBJ model, with a named sub-group for each group or object of the file.
+///
+/// The models read for the same name, which appear again when a file goes back to a group, are put together. A
+/// file without names is loaded into a single group of triangles. Triangles get the material of their slot from
+/// `materials`, and the others `material`. The triangles of each part are sorted into a bounding volume hierarchy
+/// as `fill_part` describes, using `hierarchies`, one for each part, when they were read from the mesh cache.
+///
+/// # Returns
+///
+/// The group of the model and the hierarchies made for its parts, or an error if the file has no faces.
+fn create_model_group(file: &str, models: &[ModelTriangles], material: Material, materials: &HashMap<String, Material>,
+                      divide: Option<usize>, hierarchies: Option<&[HierarchyNode]>) -> Result<(Group, Vec<HierarchyNode>), String> {
+    if models.is_empty() {
+        return Err(format!("no faces found in OBJ file {}", file));
+    }
+    let parts = model_parts(models);
+    let hierarchy = |i: usize| hierarchies.map(|hierarchies| &hierarchies[i]);
+    let mut made = vec![];
+    let mut master_group = Group::new();
+    if let [("", part)] = parts.as_slice() {
+        made.extend(fill_part(&mut master_group, part, &material, materials, divide, hierarchy(0)));
+    } else {
+        for (i, (name, part)) in parts.iter().enumerate() {
+            let mut group = Group::new();
+            group.name = (!name.is_empty()).then(|| name.to_string());
+            made.extend(fill_part(&mut group, part, &material, materials, divide, hierarchy(i)));
+            master_group.add_child(Arc::new(group));
+        }
+    }
+    Ok((master_group, made))
 }
 
 /// Loads a 3D model from an OBJ file and returns a group containing the model's geometry.
 ///
 /// This function reads a 3D model from an OBJ file specified by the `file` parameter, applying
 /// the provided `material` to all the geometry within the model. The function supports loading
-/// models that consist of multiple meshes by creating a group for each mesh and then adding these
-/// groups to a master group, which is returned. If the model file contains no meshes, the function
-/// panics with an error message indicating that no models were found in the file.
+/// models that consist of multiple parts by creating a group, named after the `g` or `o` statement,
+/// for each part and then adding these groups to a master group, which is returned. See `parse_obj` for
+/// the statements that are read.
 ///
 /// # Arguments
 ///
 /// * `file` - A string slice that holds the path to the OBJ file to be loaded.
 /// * `material` - A `Material` instance to be applied to all the geometry within the loaded model.
+/// * `options` - How the triangles are changed and divided after they are loaded.
 ///
 /// # Returns
 ///
 /// Returns a `Group` instance that contains all the geometry of the loaded model, with the specified
-/// material applied to each piece of geometry.
+/// material applied to each piece of geometry, or an error if the file cannot be read, has a line that
+/// cannot be parsed, with its line number, or has no faces.
+pub fn load_obj_file(file: &str, material: Material, options: &MeshOptions) -> Result<Group, String> {
+    let mut models = read_obj_models(file)?;
+    apply_mesh_options(file, &mut models, options);
+    create_model_group(file, &models, material, &options.materials, options.divide, None).map(|(group, _)| group)
+}
+
+/// Loads a 3D model from an OBJ file like `load_obj_file`, using a binary cache of its triangles.
 ///
-/// # Panics
+/// The triangles of the model, as they are after the mesh options are applied, are kept in a sidecar file next
+/// to the OBJ file, named after it with `.rraycache` added, together with their normals, the normals of their faces
+/// and the bounding volume hierarchy of each part. When the cache was written for an OBJ file of the same size and
+/// modification time, and with the same mesh options, it is read instead of parsing the OBJ file and building the
+/// model again, which is much faster for large meshes. Otherwise the OBJ file is parsed and the cache written
+/// again; if that fails, a warning is printed and the model is still loaded.
+///
+/// # Arguments
 ///
-/// Panics if no models are found in the specified file or if the file cannot be loaded.
-pub fn load_obj_file(file: &str, material: Material) -> Group {
-    let (models, _materials) = tobj::load_obj(file, &tobj::LoadOptions::default())
-       .expect(&format!("Failed to OBJ load file: {}", file));
+/// * `file` - A string slice that holds the path to the OBJ file to be loaded.
+/// * `material` - A `Material` instance to be applied to all the geometry within the loaded model.
+/// * `options` - How the triangles are changed and divided after they are loaded. The cache is written again when
+///   they change, except for the materials of the slots, which are not kept in it.
+///
+/// # Returns
+///
+/// Returns a `Group` instance that contains all the geometry of the loaded model, or an error like
+/// `load_obj_file`.
+pub fn load_cached_obj_file(file: &str, material: Material, options: &MeshOptions) -> Result<Group, String> {
+    let cache_file = mesh_cache_file(file);
+    let stamp = obj_file_stamp(file);
+    let key = mesh_cache_key(options);
+    if let Some((models, hierarchies)) = stamp.and_then(|stamp| read_mesh_cache(&cache_file, stamp, &key)) {
+        let hierarchies = (!hierarchies.is_empty()).then_some(hierarchies.as_slice());
+        return create_model_group(file, &models, material, &options.materials, options.divide, hierarchies).map(|(group, _)| group);
+    }
+    let mut models = read_obj_models(file)?;
+    apply_mesh_options(file, &mut models, options);
+    let (group, hierarchies) = create_model_group(file, &models, material, &options.materials, options.divide, None)?;
+    if let Some(stamp) = stamp {
+        if let Err(e) = fs::write(&cache_file, encode_mesh_cache(&models, &hierarchies, stamp, &key)) {
+            eprintln!("Warning: cannot write mesh cache {}: {}", cache_file, e);
+        }
+    }
+    Ok(group)
+}
+
+/// Identifies the mesh cache format, followed by its version.
+const MESH_CACHE_MAGIC: &[u8; 8] = b"RRAYMESH";
+const MESH_CACHE_VERSION: u32 = 8;
+
+/// The deepest bounding volume hierarchy read from a mesh cache, so that a damaged cache cannot nest groups
+/// without end.
+const MAX_HIERARCHY_DEPTH: usize = 1024;
+
+/// Returns the path of the mesh cache of an OBJ file.
+pub fn mesh_cache_file(file: &str) -> String {
+    format!("{}.rraycache", file)
+}
+
+/// Returns the size and modification time of an OBJ file, which a cache written for it must match.
+fn obj_file_stamp(file: &str) -> Option<(u64, u64, u32)> {
+    let metadata = fs::metadata(file).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+/// Returns the settings of the mesh options that change the triangles of a model or their hierarchy, which a
+/// cache written with them must match.
+fn mesh_cache_key(options: &MeshOptions) -> Vec<u8> {
+    let mut key = vec![u8::from(options.orient)];
+    key.extend((options.subdivisions as u64).to_le_bytes());
+    let mut part_subdivisions: Vec<(&String, &usize)> = options.part_subdivisions.iter().collect();
+    part_subdivisions.sort();
+    key.extend((part_subdivisions.len() as u64).to_le_bytes());
+    for (name, steps) in part_subdivisions {
+        key.extend((name.len() as u64).to_le_bytes());
+        key.extend(name.as_bytes());
+        key.extend((*steps as u64).to_le_bytes());
+    }
+    let settings = [options.max_triangles.map(|max| max as u64), options.recompute_normals.map(f64::to_bits), options.divide.map(|threshold| threshold as u64)];
+    for setting in settings {
+        key.push(u8::from(setting.is_some()));
+        key.extend(setting.unwrap_or(0).to_le_bytes());
+    }
+    key
+}
+
+/// Writes a bounding volume hierarchy to the mesh cache format: the number of triangles directly in the group and
+/// their numbers, then the number of sub-groups and each of them.
+fn encode_hierarchy(node: &HierarchyNode, bytes: &mut Vec<u8>) {
+    bytes.extend((node.triangles.len() as u32).to_le_bytes());
+    for t in &node.triangles {
+        bytes.extend(t.to_le_bytes());
+    }
+    bytes.extend((node.groups.len() as u32).to_le_bytes());
+    for group in &node.groups {
+        encode_hierarchy(group, bytes);
+    }
+}
+
+/// Writes models to the mesh cache format: the magic bytes, the version, the stamp of the OBJ file, the length of
+/// the key of the mesh options and the key, and the number of models. Then for each model the length of its name
+/// and the name, whether it has normals and vertex colors, its number of triangles, their points, their normals
+/// and their colors, its material slots, and the normals of its faces when it has no normals. Last come the number
+/// of hierarchies, one for each part or none, and the hierarchies. Numbers are little endian.
+fn encode_mesh_cache(models: &[ModelTriangles], hierarchies: &[HierarchyNode], (size, seconds, nanos): (u64, u64, u32), key: &[u8]) -> Vec<u8> {
+    let mut bytes = MESH_CACHE_MAGIC.to_vec();
+    bytes.extend(MESH_CACHE_VERSION.to_le_bytes());
+    bytes.extend(size.to_le_bytes());
+    bytes.extend(seconds.to_le_bytes());
+    bytes.extend(nanos.to_le_bytes());
+    bytes.extend((key.len() as u32).to_le_bytes());
+    bytes.extend(key);
+    bytes.extend((models.len() as u32).to_le_bytes());
+    for model in models {
+        bytes.extend((model.name.len() as u32).to_le_bytes());
+        bytes.extend(model.name.as_bytes());
+        bytes.push(u8::from(!model.normals.is_empty()));
+        bytes.push(u8::from(!model.colors.is_empty()));
+        bytes.extend((model.points.len() as u32).to_le_bytes());
+        for values in model.points.iter().chain(model.normals.iter()).chain(model.colors.iter()).flatten().flatten() {
+            bytes.extend(values.to_le_bytes());
+        }
+        bytes.extend((model.material_names.len() as u32).to_le_bytes());
+        for name in model.material_names.iter() {
+            bytes.extend((name.len() as u32).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+        if !model.material_names.is_empty() {
+            for slot in model.materials.iter() {
+                bytes.extend(slot.to_le_bytes());
+            }
+        }
+        if model.normals.is_empty() {
+            for [p1, p2, p3] in model.points.iter() {
+                let (p1, p2, p3) = (point(*p1), point(*p2), point(*p3));
+                let normal = p3.subtract(&p1).cross(&p2.subtract(&p1)).normalize();
+                for value in [normal.x, normal.y, normal.z] {
+                    bytes.extend(value.to_le_bytes());
+                }
+            }
+        }
+    }
+    bytes.extend((hierarchies.len() as u32).to_le_bytes());
+    for hierarchy in hierarchies {
+        encode_hierarchy(hierarchy, &mut bytes);
+    }
+    bytes
+}
 
-    match models.len() {
-        0 => panic!("No models found in file: {}", file),
-        1 => create_group(&models[0].mesh, material),
-        _ => {
-            let mut master_group = Group::new();
-            for m in models {
-                master_group.add_child(Arc::new(create_group(&m.mesh, material.clone())));
+/// Reads models and the hierarchies of their parts from a mesh cache, or returns `None` if the cache is missing,
+/// damaged, of another version or written for a different OBJ file or other mesh options.
+fn read_mesh_cache(cache_file: &str, stamp: (u64, u64, u32), key: &[u8]) -> Option<(Vec<ModelTriangles>, Vec<HierarchyNode>)> {
+    let bytes = fs::read(cache_file).ok()?;
+    let mut reader = CacheReader { bytes: &bytes };
+    if reader.take(8)? != MESH_CACHE_MAGIC || reader.u32()? != MESH_CACHE_VERSION {
+        return None;
+    }
+    if (reader.u64()?, reader.u64()?, reader.u32()?) != stamp {
+        return None;
+    }
+    let key_length = reader.u32()? as usize;
+    if reader.take(key_length)? != key {
+        return None;
+    }
+    let mut models = vec![];
+    for _ in 0..reader.u32()? {
+        let name = reader.string()?;
+        let has_normals = reader.take(1)?[0] == 1;
+        let has_colors = reader.take(1)?[0] == 1;
+        let count = reader.u32()? as usize;
+        let points = reader.triangles(count)?;
+        let normals = if has_normals { reader.triangles(count)? } else { vec![] };
+        let colors = if has_colors { reader.triangles(count)? } else { vec![] };
+        let material_names = (0..reader.u32()?).map(|_| reader.string()).collect::<Option<Vec<String>>>()?;
+        let materials = if material_names.is_empty() { vec![] } else { (0..count).map(|_| reader.u32()).collect::<Option<Vec<u32>>>()? };
+        if materials.iter().any(|slot| *slot as usize >= material_names.len()) {
+            return None;
+        }
+        let face_normals = if has_normals { vec![] } else { (0..count).map(|_| Some([reader.f64()?, reader.f64()?, reader.f64()?])).collect::<Option<Vec<[f64; 3]>>>()? };
+        models.push(ModelTriangles { name, points, normals, colors, material_names, materials, face_normals });
+    }
+    let hierarchies = (0..reader.u32()?).map(|_| reader.hierarchy(0)).collect::<Option<Vec<HierarchyNode>>>()?;
+    let parts = model_parts(&models);
+    if !hierarchies.is_empty() && hierarchies.len() != parts.len() {
+        return None;
+    }
+    // Each hierarchy must hold every triangle of its part once
+    for (hierarchy, (_, part)) in hierarchies.iter().zip(parts.iter()) {
+        let mut held = vec![false; part.iter().map(|model| model.points.len()).sum()];
+        let mut nodes = vec![hierarchy];
+        while let Some(node) = nodes.pop() {
+            for t in &node.triangles {
+                match held.get_mut(*t as usize) {
+                    Some(held) if !*held => *held = true,
+                    _ => return None,
+                }
             }
-            master_group
+            nodes.extend(node.groups.iter());
+        }
+        if held.contains(&false) {
+            return None;
         }
     }
+    reader.bytes.is_empty().then_some((models, hierarchies))
+}
+
+/// Reads little endian numbers from the bytes of a mesh cache.
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl CacheReader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn hierarchy(&mut self, depth: usize) -> Option<HierarchyNode> {
+        if depth > MAX_HIERARCHY_DEPTH {
+            return None;
+        }
+        let triangles = (0..self.u32()?).map(|_| self.u32()).collect::<Option<Vec<u32>>>()?;
+        let groups = (0..self.u32()?).map(|_| self.hierarchy(depth + 1)).collect::<Option<Vec<HierarchyNode>>>()?;
+        Some(HierarchyNode { triangles, groups })
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).ok()
+    }
+
+    fn triangles(&mut self, count: usize) -> Option<Vec<[[f32; 3]; 3]>> {
+        let bytes = self.take(count.checked_mul(36)?)?;
+        let values: Vec<f32> = bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Some(values.chunks_exact(9).map(|v| [[v[0], v[1], v[2]], [v[3], v[4], v[5]], [v[6], v[7], v[8]]]).collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    use crate::matrix::Matrix;
+    use crate::color::Color;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::camera::Camera;
     use crate::raytracer::light::Light;
     use crate::raytracer::material::Material;
     use crate::raytracer::material::pattern::Pattern;
+    use crate::raytracer::object::Geometry;
+    use crate::raytracer::object::db::get_object;
+    use crate::raytracer::object::group::Group;
     use crate::raytracer::object::plane::Plane;
+    use super::MeshOptions;
     use crate::raytracer::scene::Scene;
     use crate::tuple::Tuple;
 
     #[test]
     fn test_load_obj_file() {
         let obj_file = "examples/teapot-low.obj";
-        let group = super::load_obj_file(obj_file, Material::default());
-        assert_eq!(group.child_ids.len(), 240);
+        let group = super::load_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap();
+        // The triangles are in the group of the file
+        assert_eq!(group.child_ids.len(), 1);
+        let teapot = get_object(group.child_ids[0]);
+        assert_eq!(teapot.name(), Some("Teapot001"));
+        assert_eq!(teapot.child_ids().len(), 240);
+    }
+
+    #[test]
+    fn groups_of_obj_files_are_loaded_as_named_groups() {
+        let group = super::load_obj_file("examples/triangles.obj", Material::default(), &MeshOptions::default()).unwrap();
+        let names: Vec<_> = group.child_ids.iter().map(|id| get_object(*id).name().map(str::to_string)).collect();
+        assert_eq!(names, vec![Some("FirstGroup".to_string()), Some("SecondGroup".to_string())]);
+        assert!(group.child_ids.iter().all(|id| get_object(*id).child_ids().len() == 1));
+
+        // A file without groups or objects is loaded as one group of triangles
+        let dir = std::env::temp_dir().join("rray_obj_groups_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("plain.obj");
+        std::fs::write(&obj_file, "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 3\nf 2 4 3\n").unwrap();
+        let group = super::load_obj_file(obj_file.to_str().unwrap(), Material::default(), &MeshOptions::default()).unwrap();
+        assert_eq!(group.child_ids.len(), 2);
+        assert!(group.child_ids.iter().all(|id| get_object(*id).name().is_none()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn faces_with_negative_indices_and_many_corners_are_read() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\n# a quad, from the end of the lists\nf -4//-1 -3//-1 \\\n  -2//-1 -1//-1\n";
+        let models = super::parse_obj(obj.as_bytes(), "quad.obj", |_| {}).unwrap();
+        assert_eq!(models.len(), 1);
+        let (a, b, c, d) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]);
+        assert_eq!(models[0].points, vec![[a, b, c], [a, c, d]]);
+        assert_eq!(models[0].normals, vec![[[0.0, 0.0, 1.0]; 3]; 2]);
+    }
+
+    #[test]
+    fn faces_are_added_as_they_are_read_with_progress() {
+        // The first face has no normals, so it gets the normal of its face once the second one has some
+        let mut obj = String::from("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 -1\nf 1 2 3\nf 1//1 2//1 3//1\n");
+        while obj.len() < 3 << 20 {
+            obj.push_str("# padding to read more than a megabyte\n");
+        }
+        let mut reports = vec![];
+        let models = super::parse_obj(obj.as_bytes(), "faces.obj", |bytes| reports.push(bytes)).unwrap();
+        assert_eq!(models[0].normals, vec![[[0.0, 0.0, 1.0]; 3], [[0.0, 0.0, -1.0]; 3]]);
+        assert!(models[0].colors.is_empty());
+        assert!(reports.len() >= 3);
+        assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(reports.last(), Some(&(obj.len() as u64)));
+    }
+
+    #[test]
+    fn decimation_keeps_the_shape_of_a_flat_grid() {
+        // A 10 x 10 grid of squares, each split into two triangles
+        let mut model = super::ModelTriangles::new("grid");
+        for y in 0..10 {
+            for x in 0..10 {
+                let (x, y) = (x as f32, y as f32);
+                let (a, b, c, d) = ([x, y, 0.0], [x + 1.0, y, 0.0], [x + 1.0, y + 1.0, 0.0], [x, y + 1.0, 0.0]);
+                model.add_triangle([a, b, c], None, [None; 3], "");
+                model.add_triangle([a, c, d], None, [None; 3], "");
+            }
+        }
+        assert_eq!(super::decimate(&mut model, 20), 200 - model.points.len());
+        assert!(model.points.len() <= 20);
+        // The triangles stay in the plane, facing the same way, and still cover the whole grid
+        let area: f32 = model.points.iter()
+            .map(|[a, b, c]| {
+                assert!([a, b, c].iter().all(|p| p[2] == 0.0 && (0.0..=10.0).contains(&p[0]) && (0.0..=10.0).contains(&p[1])));
+                ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) / 2.0
+            })
+            .inspect(|area| assert!(*area > 0.0))
+            .sum();
+        assert!((area - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn subdivision_smooths_a_cube_into_a_rounded_shape() {
+        let obj = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                   g box\nf 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\ng lid\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+        let mut models = super::parse_obj(obj.as_bytes(), "cube.obj", |_| {}).unwrap();
+        let options = MeshOptions { subdivisions: 2, part_subdivisions: [("lid".to_string(), 1)].into(), max_triangles: None, ..Default::default() };
+        super::apply_mesh_options("cube.obj", &mut models, &options);
+        assert_eq!((models[0].points.len(), models[1].points.len()), (6 * 16, 6 * 4));
+
+        let mut cube = super::parse_obj(obj.replace('g', "#").as_bytes(), "cube.obj", |_| {}).unwrap().remove(0);
+        super::subdivide(&mut cube, 3);
+        assert_eq!(cube.points.len(), 12 * 64);
+        for (points, normals) in cube.points.iter().zip(cube.normals.iter()) {
+            for (p, n) in points.iter().zip(normals) {
+                // The corners are pulled inside the cube, and the normals point away from its center
+                let p = Tuple::vector(p[0] as f64, p[1] as f64, p[2] as f64);
+                assert!(p.magnitude() < 1.5 && p.x.abs() < 1.0 && p.y.abs() < 1.0 && p.z.abs() < 1.0);
+                assert!(p.normalize().dot(&Tuple::vector(n[0] as f64, n[1] as f64, n[2] as f64)) > 0.8);
+            }
+        }
+    }
+
+    #[test]
+    fn recomputed_normals_keep_hard_edges_sharp() {
+        // A cube whose normals in the file all point the same way
+        let obj = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\nvn 0 1 0\n\
+                   f 1//1 4//1 3//1 2//1\nf 5//1 6//1 7//1 8//1\nf 1//1 2//1 6//1 5//1\nf 2//1 3//1 7//1 6//1\nf 3//1 4//1 8//1 7//1\nf 4//1 1//1 5//1 8//1\n";
+        let normal = |n: [f32; 3]| Tuple::vector(n[0] as f64, n[1] as f64, n[2] as f64);
+        let mut cube = super::parse_obj(obj.as_bytes(), "cube.obj", |_| {}).unwrap().remove(0);
+        super::recompute_normals(&mut cube, 30.0);
+        for (points, normals) in cube.points.iter().zip(cube.normals.iter()) {
+            let face = super::face_normals(points)[0];
+            assert!(normals.iter().all(|n| normal(*n).dot(&normal(face)) > 0.9999));
+        }
+
+        super::recompute_normals(&mut cube, 180.0);
+        for (points, normals) in cube.points.iter().zip(cube.normals.iter()) {
+            for (p, n) in points.iter().zip(normals) {
+                // Every corner of the cube is shared by three faces, so its normal points along the diagonal
+                assert!(normal(*p).normalize().dot(&normal(*n)) > 0.9999);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_get_the_material_of_their_slot() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nusemtl paint\nf 1 3 4\nusemtl glass\nf 1 2 4\nusemtl\nf 2 3 4\n";
+        let mut models = super::parse_obj(obj.as_bytes(), "slots.obj", |_| {}).unwrap();
+        assert_eq!(models[0].material_names, vec!["", "paint", "glass"]);
+        assert_eq!(models[0].materials, vec![0, 1, 2, 0]);
+
+        let mut paint = Material::default();
+        paint.reflective = 0.5;
+        let materials = [("paint".to_string(), paint)].into();
+        let group = super::create_model_group("slots.obj", &models, Material::default(), &materials, None, None).unwrap().0;
+        let reflective: Vec<f64> = group.child_ids.iter().map(|id| get_object(*id).get_material().reflective).collect();
+        assert_eq!(reflective, vec![0.0, 0.5, 0.0, 0.0]);
+        // The triangles share the materials of the slots instead of each keeping a copy
+        let first = get_object(group.child_ids[0]);
+        assert!(group.child_ids.iter().all(|id| Arc::ptr_eq(get_object(*id).material_slots().unwrap(), first.material_slots().unwrap())));
+
+        // The faces split from a face keep its slot
+        super::subdivide(&mut models[0], 1);
+        assert_eq!(models[0].materials, [0, 1, 2, 0].iter().flat_map(|slot| [*slot; 4]).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn obj_errors_name_the_line() {
+        let error = |obj: &str| super::parse_obj(obj.as_bytes(), "bad.obj", |_| {}).err().unwrap();
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n"), "bad.obj:5: vertex index 4 is out of range, 3 vertices are defined so far");
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 -3 2\n"), "bad.obj:3: vertex index -3 is out of range, 2 vertices are defined so far");
+        assert_eq!(error("v 0 zero 0\n"), "bad.obj:1: cannot read the number zero");
+        assert_eq!(error("v 0 0 0\nf 1 1\n"), "bad.obj:2: a face needs at least 3 corners, found 2");
+    }
+
+    #[test]
+    fn cached_obj_files_load_the_same_triangles() {
+        let dir = std::env::temp_dir().join("rray_mesh_cache_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("teapot.obj");
+        std::fs::copy("examples/teapot-low.obj", &obj_file).unwrap();
+        let obj_file = obj_file.to_str().unwrap();
+        let cache_file = super::mesh_cache_file(obj_file);
+        let _ = std::fs::remove_file(&cache_file);
+        let triangles = |group: &Group| group.child_ids.iter()
+            .flat_map(|id| get_object(*id).child_ids())
+            .map(|id| get_object(id).geometry())
+            .collect::<Vec<_>>();
+        let expected = triangles(&super::load_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap());
+
+        let written = super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap();
+        let cache = std::fs::read(&cache_file).unwrap();
+        assert_eq!(triangles(&written), expected);
+        // Read back from the cache
+        assert_eq!(triangles(&super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap()), expected);
+
+        // A damaged cache is ignored and written again
+        std::fs::write(&cache_file, &cache[..cache.len() / 2]).unwrap();
+        assert_eq!(triangles(&super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap()), expected);
+        assert_eq!(std::fs::read(&cache_file).unwrap(), cache);
+
+        // A cache of an older version of the OBJ file is not used
+        let mut contents = std::fs::read_to_string(obj_file).unwrap();
+        contents.push_str("f 1 2 3\n");
+        std::fs::write(obj_file, contents).unwrap();
+        assert_eq!(triangles(&super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap()).len(), 241);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cached_obj_files_keep_their_bounding_volume_hierarchy() {
+        let dir = std::env::temp_dir().join("rray_mesh_hierarchy_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("grids.obj");
+        // Two flat grids of 40 triangles without normals, in named groups
+        let mut contents = String::new();
+        for (name, offset) in [("left", 0), ("right", 10)] {
+            contents.push_str(&format!("g {}\n", name));
+            for z in 0..5 {
+                for x in 0..6 {
+                    contents.push_str(&format!("v {} 0 {}\n", x + offset, z));
+                }
+            }
+            let first = if offset == 0 { 1 } else { 31 };
+            for z in 0..4 {
+                for x in 0..5 {
+                    let corner = first + z * 6 + x;
+                    contents.push_str(&format!("f {} {} {}\nf {} {} {}\n", corner, corner + 6, corner + 1, corner + 1, corner + 6, corner + 7));
+                }
+            }
+        }
+        std::fs::write(&obj_file, contents).unwrap();
+        let obj_file = obj_file.to_str().unwrap();
+        let cache_file = super::mesh_cache_file(obj_file);
+        let _ = std::fs::remove_file(&cache_file);
+
+        #[derive(Debug, PartialEq)]
+        enum Node {
+            Triangle(Option<Geometry>),
+            Group(Vec<Node>),
+        }
+        fn node(id: usize) -> Node {
+            match get_object(id).geometry() {
+                Some(Geometry::Group(child_ids)) => Node::Group(child_ids.into_iter().map(node).collect()),
+                geometry => Node::Triangle(geometry),
+            }
+        }
+        let structure = |group: &Group| group.child_ids.iter().map(|id| node(*id)).collect::<Vec<_>>();
+        let options = MeshOptions { divide: Some(8), ..MeshOptions::default() };
+        let expected = structure(&super::load_obj_file(obj_file, Material::default(), &options).unwrap());
+        assert!(matches!(&expected[0], Node::Group(children) if children.iter().any(|child| matches!(child, Node::Group(_)))));
+
+        // Written to the cache, then read back with the same hierarchy
+        assert_eq!(structure(&super::load_cached_obj_file(obj_file, Material::default(), &options).unwrap()), expected);
+        let cached = super::load_cached_obj_file(obj_file, Material::default(), &options).unwrap();
+        assert_eq!(structure(&cached), expected);
+
+        // The hierarchy comes from the cache: when it keeps every triangle of a part in one group, so does the model
+        let stamp = super::obj_file_stamp(obj_file).unwrap();
+        let key = super::mesh_cache_key(&options);
+        let (models, hierarchies) = super::read_mesh_cache(&cache_file, stamp, &key).unwrap();
+        let flat: Vec<super::HierarchyNode> = hierarchies.iter().map(|_| super::HierarchyNode { triangles: (0..40).collect(), groups: vec![] }).collect();
+        std::fs::write(&cache_file, super::encode_mesh_cache(&models, &flat, stamp, &key)).unwrap();
+        let flat_model = super::load_cached_obj_file(obj_file, Material::default(), &options).unwrap();
+        assert!(structure(&flat_model).iter().all(|part| matches!(part, Node::Group(children) if children.len() == 40 && children.iter().all(|child| matches!(child, Node::Triangle(_))))));
+
+        // Other mesh options write the cache again
+        let options = MeshOptions { divide: Some(4), ..MeshOptions::default() };
+        let expected = structure(&super::load_obj_file(obj_file, Material::default(), &options).unwrap());
+        assert_eq!(structure(&super::load_cached_obj_file(obj_file, Material::default(), &options).unwrap()), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn vertex_colors_are_read_from_obj_files() {
+        let dir = std::env::temp_dir().join("rray_vertex_color_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("colored.obj");
+        std::fs::write(&obj_file, "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 1\nv 1 1 0 1 1 1\nf 1 2 3\nf 2 4 3\n").unwrap();
+        let obj_file = obj_file.to_str().unwrap();
+        let _ = std::fs::remove_file(super::mesh_cache_file(obj_file));
+        let colors = |group: &Group| group.child_ids.iter().map(|id| get_object(*id).vertex_colors().copied()).collect::<Vec<_>>();
+
+        let group = super::load_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap();
+        let (red, green, blue, white) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0), Color::new(1.0, 1.0, 1.0));
+        let expected = vec![Some([red, green, blue]), Some([green, white, blue])];
+        assert_eq!(colors(&group), expected);
+        // Written to the cache and read back
+        super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap();
+        assert_eq!(colors(&super::load_cached_obj_file(obj_file, Material::default(), &MeshOptions::default()).unwrap()), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn triangles_are_turned_to_face_outward() {
+        let (a, b, c, d) = ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]);
+        let outward = vec![[a, c, b], [a, b, d], [a, d, c], [b, c, d]];
+        let model = |points: Vec<[[f32; 3]; 3]>| super::ModelTriangles { points, ..super::ModelTriangles::new("") };
+
+        // One triangle wound the other way
+        let mut tetrahedron = model(vec![[a, c, b], [a, d, b], [a, d, c], [b, c, d]]);
+        assert_eq!(super::orient_triangles(&mut tetrahedron), 1);
+        assert_eq!(tetrahedron.points, outward);
+        // All triangles facing inward
+        let mut tetrahedron = model(outward.iter().map(|[p1, p2, p3]| [*p1, *p3, *p2]).collect());
+        assert_eq!(super::orient_triangles(&mut tetrahedron), 4);
+        assert_eq!(tetrahedron.points, outward);
+
+        // An open surface keeps its winding, and normals that point to its back are flipped
+        let mut triangle = model(vec![[a, b, c]]);
+        triangle.normals = vec![[[0.0, 0.0, -1.0]; 3]];
+        assert_eq!(super::orient_triangles(&mut triangle), 0);
+        assert_eq!(triangle.points, vec![[a, b, c]]);
+        assert_eq!(triangle.normals, vec![[[0.0, 0.0, 1.0]; 3]]);
+    }
+
+    #[test]
+    fn orienting_the_triangles_of_a_file_can_be_turned_off() {
+        let dir = std::env::temp_dir().join("rray_orient_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("tetrahedron.obj");
+        // The second face is wound the other way from its neighbours
+        std::fs::write(&obj_file, "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 4 2\nf 1 4 3\nf 2 3 4\n").unwrap();
+        let obj_file = obj_file.to_str().unwrap();
+        let triangles = |options: &MeshOptions| super::load_obj_file(obj_file, Material::default(), options).unwrap()
+            .child_ids.iter()
+            .map(|id| get_object(*id).geometry())
+            .collect::<Vec<_>>();
+        let oriented = triangles(&MeshOptions::default());
+        let kept = triangles(&MeshOptions { orient: false, ..Default::default() });
+        let changed: Vec<usize> = (0..4).filter(|i| oriented[*i] != kept[*i]).collect();
+        assert_eq!(changed, vec![1]);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
@@ -284,57 +1747,55 @@ mod tests {
     #[test]
     #[ignore]
     fn test_render_model() {
-        use crate::color::Color;
-
         let mut c = Camera::new(800, 400, std::f64::consts::PI / 3.0);
         let from = Tuple::point(0.0, 1.5, -5.0);
         let to = Tuple::point(0.0, 1.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut floor = Plane::new();
-        floor.transform = Matrix::translate(0.0, 0.0, 0.0);
-        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                 Matrix::scale(0.1, 0.1, 0.1).multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0)));
+        floor.transform = Matrix4::translate(0.0, 0.0, 0.0);
+        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                 Matrix4::scale(0.1, 0.1, 0.1).multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0)));
         floor.material.specular = 0.0;
         w.add_object(Arc::new(floor));
 
         let mut left_wall = Plane::new();
-        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                       Matrix::identity(4)
-                                                           .multiply(&Matrix::translate(124.0, 124.0, 124.0)
-                                                               .multiply(&Matrix::scale(7.0, 7.0, 7.0))
+        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                       Matrix4::identity()
+                                                           .multiply(&Matrix4::translate(124.0, 124.0, 124.0)
+                                                               .multiply(&Matrix4::scale(7.0, 7.0, 7.0))
                                                            ));
-        left_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / -4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        left_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / -4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
         left_wall.material.specular = 0.0;
         w.add_object(Arc::new(left_wall));
 
         let mut right_wall = Plane::new();
-        right_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        right_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
-        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         right_wall.material.specular = 0.0;
         w.add_object(Arc::new(right_wall));
 
         let mut material = Material::default();
-        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix::identity(4));
-        let mut group = super::load_obj_file("examples/teapot-low.obj", material);
-        group.transform = Matrix::identity(4)
-            //.multiply(&Matrix::rotate_y(std::f64::consts::PI))
-            .multiply(&Matrix::scale(0.10, 0.10, 0.10))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / -2.0))
+        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix4::identity());
+        let mut group = super::load_obj_file("examples/teapot-low.obj", material, &MeshOptions::default()).unwrap();
+        group.transform = Matrix4::identity()
+            //.multiply(&Matrix4::rotate_y(std::f64::consts::PI))
+            .multiply(&Matrix4::scale(0.10, 0.10, 0.10))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / -2.0))
         ;
         w.add_object(Arc::new(group));
 
@@ -1,13 +1,19 @@
+use std::sync::Arc;
 use crate::color::Color;
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
+use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::pattern::Pattern;
+use crate::raytracer::material::texture::Texture;
+use crate::raytracer::ray::Footprint;
 use crate::tuple::Tuple;
 use crate::raytracer::object::db::get_object;
-use crate::raytracer::object::world_to_object;
+use crate::raytracer::object::{vector_to_world, world_to_object};
+use crate::EPSILON;
 
 pub(crate) mod pattern;
-mod noise;
-mod texture;
+pub(crate) mod noise;
+pub(crate) mod texture;
+pub(crate) mod texture_cache;
 
 /// Represents the material properties of a surface in a ray tracing scene.
 ///
@@ -31,6 +37,29 @@ mod texture;
 ///   while 1 means it is completely transparent.
 /// * `refractive_index` - The refractive index of the material, used in calculating refraction through
 ///   transparent materials.
+/// * `refraction_roughness` - How much refracted rays are scattered, from 0 for clear glass to about 1 for
+///   heavily frosted glass.
+/// * `refraction_samples` - The number of refracted rays averaged at each hit when `refraction_roughness` is set.
+/// * `normal_map` - An optional tangent space normal map that adds surface detail to the shading normal. It is
+///   shared, since the materials of a mesh's slots are cloned when a part or an override changes them.
+/// * `bump` - An optional pattern used as a height field that adds bumps to the shading normal.
+/// * `diffuse_model` - The model used for the diffuse light of the `Phong` shading model.
+/// * `specular_model` - The model used for the highlights of the `Phong` shading model.
+/// * `model` - The model used to shade the light reflected by the surface.
+/// * `metallic` - For the `Pbr` model, how metallic the surface is, from 0 for plastic, stone or wood to 1 for bare metal.
+/// * `roughness` - For the `Pbr` model, how rough the surface is, from 0 for polished to 1 for completely matte.
+/// * `f0` - For the `Pbr` model, the fraction of light reflected head on by the non-metallic part of the surface.
+/// * `channel_maps` - Optional patterns that vary `reflective`, `transparency`, `specular` and
+///   `refraction_roughness` over the surface.
+/// * `double_sided` - Whether both sides of triangles with this material are seen. When `false` the backs of
+///   triangles, the side that `(p2 - p1) x (p3 - p1)` points away from, are not hit at all. That is the inside
+///   of a closed mesh wound like those of OBJ and STL files, so it is faster to render. Other shapes always
+///   have two sides, as rays have to leave them.
+/// * `cast_shadows` - Whether surfaces with this material block light. When `false` the surface is
+///   ignored by shadow rays, which is useful for helper geometry.
+/// * `visible_to_camera` - Whether surfaces with this material are seen directly by the camera.
+/// * `visible_to_reflections` - Whether surfaces with this material are seen in reflections and refractions.
+/// * `light_groups` - The light groups of surfaces with this material, which decide the lights they are lit by.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Material {
     pub pattern: Pattern,
@@ -41,12 +70,114 @@ pub struct Material {
     pub reflective: f64,
     pub transparency: f64,
     pub refractive_index: f64,
+    pub refraction_roughness: f64,
+    pub refraction_samples: usize,
+    pub normal_map: Option<Arc<Texture>>,
+    pub bump: Option<Bump>,
+    pub diffuse_model: DiffuseModel,
+    pub specular_model: SpecularModel,
+    pub model: ShadingModel,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub f0: f64,
+    pub channel_maps: ChannelMaps,
+    pub double_sided: bool,
+    pub cast_shadows: bool,
+    pub visible_to_camera: bool,
+    pub visible_to_reflections: bool,
+    pub light_groups: Vec<String>,
+}
+
+/// The model used for the diffuse light of a surface.
+///
+/// # Variants
+///
+/// * `Lambert` - Ideal matte surface, equally bright from every direction.
+/// * `OrenNayar` - Rough matte surface made of tiny facets, which looks flatter and reflects more light back
+///   towards the light source, like clay, concrete or the moon. It holds the roughness `sigma`, the standard
+///   deviation of the slope of the facets in radians; 0 is the same as `Lambert`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffuseModel {
+    Lambert,
+    OrenNayar(f64),
+}
+
+/// The model used for the specular highlights of a surface.
+///
+/// # Variants
+///
+/// * `Phong` - The highlight depends on the angle between the eye and the reflected light.
+/// * `BlinnPhong` - The highlight depends on the angle between the normal and the half vector, halfway between
+///   the eye and the light. It stays round at grazing angles, and is wider than a `Phong` one of the same shininess.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecularModel {
+    Phong,
+    BlinnPhong,
+}
+
+/// The model used to shade the light that a surface reflects from the light sources.
+///
+/// # Variants
+///
+/// * `Phong` - The Phong reflection model, set by `diffuse`, `specular` and `shininess`.
+/// * `Pbr` - A physically based metallic/roughness model, with a Lambertian diffuse term and a Cook–Torrance
+///   specular term, set by `metallic`, `roughness` and `f0` as in glTF materials.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShadingModel {
+    Phong,
+    Pbr,
+}
+
+/// A pattern used as a height field to make a surface look bumpy.
+///
+/// The brightness of the pattern is the height of the surface. Only the shading normal changes, the
+/// surface itself stays where it is, so the silhouette of the object is not affected.
+///
+/// # Fields
+///
+/// * `pattern` - The pattern giving the height, evaluated in object space like the pattern of the material.
+/// * `strength` - How strongly the slopes of the height field tilt the normal; negative values invert the bumps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bump {
+    pub pattern: Pattern,
+    pub strength: f64,
+}
+
+/// Patterns that vary the scalar channels of a material over its surface.
+///
+/// The brightness of a pattern at a point, the average of its color channels, multiplies the scalar
+/// value of the channel there, so a checker of white and black squares on a material with `reflective`
+/// 1 alternates mirror and matte tiles. Channels without a pattern keep their scalar value everywhere.
+///
+/// # Fields
+///
+/// * `reflective` - The pattern varying the reflectivity.
+/// * `transparency` - The pattern varying the transparency.
+/// * `specular` - The pattern varying the strength of the specular highlight.
+/// * `refraction_roughness` - The pattern varying the refraction roughness.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChannelMaps {
+    pub reflective: Option<Pattern>,
+    pub transparency: Option<Pattern>,
+    pub specular: Option<Pattern>,
+    pub refraction_roughness: Option<Pattern>,
+}
+
+/// Returns the value of a scalar channel at a point, scaled by the brightness of its pattern if it has one.
+fn channel_at(value: f64, map: &Option<Pattern>, shape: usize, world_point: &Tuple) -> f64 {
+    match map {
+        Some(pattern) => {
+            let c = pattern.pattern_at(&world_to_object(shape, world_point), shape);
+            value * (c.r + c.g + c.b) / 3.0
+        }
+        None => value,
+    }
 }
 
 impl Material {
     pub fn default() -> Material {
         Material {
-            pattern: Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix::identity(4)),
+            pattern: Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix4::identity()),
             ambient: 0.1,
             diffuse: 0.9,
             specular: 0.9,
@@ -54,8 +185,50 @@ impl Material {
             reflective: 0.0,
             transparency: 0.0,
             refractive_index: 1.0,
+            refraction_roughness: 0.0,
+            refraction_samples: 1,
+            normal_map: None,
+            bump: None,
+            diffuse_model: DiffuseModel::Lambert,
+            specular_model: SpecularModel::Phong,
+            model: ShadingModel::Phong,
+            metallic: 0.0,
+            roughness: 0.5,
+            f0: 0.04,
+            channel_maps: ChannelMaps::default(),
+            double_sided: true,
+            cast_shadows: true,
+            visible_to_camera: true,
+            visible_to_reflections: true,
+            light_groups: vec![],
         }
     }
+
+    /// Returns the reflectivity at a point on an object with this material, in world space.
+    pub fn reflective_at(&self, shape: usize, world_point: &Tuple) -> f64 {
+        channel_at(self.reflective, &self.channel_maps.reflective, shape, world_point)
+    }
+
+    /// Returns the transparency at a point on an object with this material, in world space.
+    pub fn transparency_at(&self, shape: usize, world_point: &Tuple) -> f64 {
+        channel_at(self.transparency, &self.channel_maps.transparency, shape, world_point)
+    }
+
+    /// Returns whether light can get through an object with this material anywhere, because it is
+    /// transparent or has a transparency map.
+    pub fn may_be_transparent(&self) -> bool {
+        self.transparency > 0.0 || self.channel_maps.transparency.is_some()
+    }
+
+    /// Returns the strength of the specular highlight at a point on an object with this material, in world space.
+    pub fn specular_at(&self, shape: usize, world_point: &Tuple) -> f64 {
+        channel_at(self.specular, &self.channel_maps.specular, shape, world_point)
+    }
+
+    /// Returns the refraction roughness at a point on an object with this material, in world space.
+    pub fn refraction_roughness_at(&self, shape: usize, world_point: &Tuple) -> f64 {
+        channel_at(self.refraction_roughness, &self.channel_maps.refraction_roughness, shape, world_point)
+    }
 }
 
 /// Calculates the color of a pattern at a given point in world space for a specific object.
@@ -75,8 +248,100 @@ impl Material {
 ///
 /// Returns a `Color` representing the color of the pattern at the specified point on the object.
 pub fn pattern_at_object(shape: usize, world_point: &Tuple) -> Color {
+    pattern_at_object_filtered(shape, world_point, None)
+}
+
+/// Calculates the color of a pattern at a point in world space, averaged over the footprint of a pixel.
+///
+/// This is `pattern_at_object` for camera rays and their reflections, see `Pattern::filtered_pattern_at`.
+///
+/// # Arguments
+///
+/// * `shape` - The unique identifier of the object within the scene.
+/// * `world_point` - The point in world space where the color is to be calculated.
+/// * `footprint` - The area of the surface covered by the pixel, in world space, or `None` to sample the point.
+///
+/// # Returns
+///
+/// Returns the color of the pattern around the point on the object.
+pub fn pattern_at_object_filtered(shape: usize, world_point: &Tuple, footprint: Option<&Footprint>) -> Color {
     let object_point = world_to_object(shape, world_point);
-    get_object(shape).get_material().pattern.pattern_at(&object_point, shape)
+    let footprint = footprint.map(|f| Footprint { dpdx: world_to_object(shape, &f.dpdx), dpdy: world_to_object(shape, &f.dpdy) });
+    get_object(shape).get_material().pattern.filtered_pattern_at(&object_point, footprint.as_ref(), shape)
+}
+
+/// Perturbs a shading normal with the normal map of an object's material.
+///
+/// The normal map stores a direction in tangent space in each pixel: red along the direction in which the
+/// texture coordinate `u` grows, green along `v`, and blue along the surface normal, each mapped from
+/// [-1, 1] to [0, 1]. The tangents come from the object's `local_tangents_at`, so the map follows the same
+/// texture coordinates as image patterns.
+///
+/// # Arguments
+///
+/// * `shape` - The unique identifier of the hit object.
+/// * `world_point` - The hit point in world space.
+/// * `hit` - The intersection, for objects whose tangents depend on it.
+/// * `normalv` - The normalized shading normal to perturb.
+/// * `normal_map` - The normal map texture.
+///
+/// # Returns
+///
+/// The perturbed, normalized normal, or `normalv` when the object has no usable texture coordinates at the point.
+pub fn apply_normal_map(shape: usize, world_point: &Tuple, hit: &Intersection, normalv: &Tuple, normal_map: &Texture) -> Tuple {
+    let object = get_object(shape);
+    let local_point = world_to_object(shape, world_point);
+    let (u, v) = object.uv_mapping(&local_point);
+    let (dpdu, dpdv) = object.local_tangents_at(&local_point, hit);
+
+    // Make the tangents perpendicular to the shading normal
+    let tangent = vector_to_world(shape, &dpdu);
+    let tangent = tangent.subtract(&normalv.multiply(tangent.dot(normalv)));
+    let bitangent = vector_to_world(shape, &dpdv);
+    let bitangent = bitangent.subtract(&normalv.multiply(bitangent.dot(normalv)));
+    if tangent.magnitude() < EPSILON || bitangent.magnitude() < EPSILON {
+        return *normalv;
+    }
+
+    let c = normal_map.sample_texture(u, v);
+    tangent.normalize().multiply(2.0 * c.r - 1.0)
+        .add(&bitangent.normalize().multiply(2.0 * c.g - 1.0))
+        .add(&normalv.multiply(2.0 * c.b - 1.0))
+        .normalize()
+}
+
+/// The distance between the points used to estimate the slope of a bump pattern.
+const BUMP_STEP: f64 = 1e-3;
+
+/// Tilts a shading normal according to the slope of a bump pattern at a point.
+///
+/// The height is the average of the color channels of the pattern. Its slope along two directions in
+/// the surface is estimated from nearby points, and the normal is tilted away from the uphill direction.
+///
+/// # Arguments
+///
+/// * `shape` - The unique identifier of the hit object.
+/// * `world_point` - The hit point in world space.
+/// * `normalv` - The normalized shading normal to tilt.
+/// * `bump` - The bump pattern and its strength.
+///
+/// # Returns
+///
+/// The tilted, normalized normal.
+pub fn apply_bump(shape: usize, world_point: &Tuple, normalv: &Tuple, bump: &Bump) -> Tuple {
+    let height = |p: &Tuple| {
+        let c = bump.pattern.pattern_at(&world_to_object(shape, p), shape);
+        (c.r + c.g + c.b) / 3.0
+    };
+    let helper = if normalv.x.abs() > 0.9 { Tuple::vector(0.0, 1.0, 0.0) } else { Tuple::vector(1.0, 0.0, 0.0) };
+    let t1 = helper.cross(normalv).normalize();
+    let t2 = normalv.cross(&t1);
+    let h = height(world_point);
+    let slope1 = (height(&world_point.add(&t1.multiply(BUMP_STEP))) - h) / BUMP_STEP;
+    let slope2 = (height(&world_point.add(&t2.multiply(BUMP_STEP))) - h) / BUMP_STEP;
+    normalv.subtract(&t1.multiply(bump.strength * slope1))
+        .subtract(&t2.multiply(bump.strength * slope2))
+        .normalize()
 }
 
 #[cfg(test)]
@@ -84,13 +349,20 @@ mod tests {
     use std::sync::Arc;
     use crate::color::Color;
     use crate::tuple::Tuple;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::light::{Light, lighting};
-    use crate::raytracer::material::Material;
+    use crate::raytracer::material::{Bump, Material};
     use crate::raytracer::material::pattern_at_object;
     use crate::raytracer::material::pattern::Pattern;
     use crate::raytracer::object::sphere::Sphere;
     use crate::raytracer::scene::Scene;
+    use image::{Rgba, RgbaImage};
+    use crate::raytracer::intersection::Intersection;
+    use crate::raytracer::material::texture::{Texture, TextureFilter};
+    use crate::raytracer::object::Object;
+    use crate::raytracer::object::db::{add_object, get_object};
+    use crate::raytracer::object::plane::Plane;
+    use crate::raytracer::ray::Ray;
 
     #[test]
     fn surface_in_shadow() {
@@ -115,14 +387,61 @@ mod tests {
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
         let mut shape = Sphere::new();
-        shape.transform = Matrix::scale(2.0, 2.0, 2.0);
+        shape.transform = Matrix4::scale(2.0, 2.0, 2.0);
         let mut m = Material::default();
         m.pattern = Pattern::test();
-        m.pattern.transform = Matrix::translate(0.5, 1.0, 1.5);
+        m.pattern.transform = Matrix4::translate(0.5, 1.0, 1.5);
         shape.material = m;
         w.add_object(Arc::new(shape));
         let id = w.ids[0];
         let c = pattern_at_object(id, &Tuple::point(2.5, 3.0, 3.5));
         assert_eq!(c, Color::new(0.75, 0.5, 0.25));
     }
-}
\ No newline at end of file
+
+    fn flat_color_map(rgb: [u8; 3]) -> Arc<Texture> {
+        let image = RgbaImage::from_pixel(1, 1, Rgba([rgb[0], rgb[1], rgb[2], 255]));
+        Arc::new(Texture::from_image(image, TextureFilter::Nearest))
+    }
+
+    #[test]
+    fn normal_maps_tilt_the_shading_normal() {
+        let mut plane = Plane::new();
+        // Pointing fully along u, which grows along x on a plane
+        plane.material.normal_map = Some(flat_color_map([255, 128, 128]));
+        let id = plane.get_id();
+        add_object(Arc::new(plane));
+        let r = Ray::new(Tuple::point(0.25, 1.0, 0.25), Tuple::vector(0.0, -1.0, 0.0));
+        let xs = vec![Intersection { t: 1.0, object: id, u: 0.0, v: 0.0 }];
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert!(comps.normalv.x > 0.99);
+        assert_eq!(comps.over_point.y, crate::EPSILON);
+
+        // A flat normal map keeps the normal of a sphere, using the estimated tangents
+        let mut sphere = Sphere::new();
+        sphere.material.normal_map = Some(flat_color_map([128, 128, 255]));
+        let id = sphere.get_id();
+        add_object(Arc::new(sphere));
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.3, 0.4, 5.0).normalize());
+        let xs = get_object(id).intersect(&r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        let geometric = get_object(id).normal_at(&comps.point, &xs[0]);
+        assert!(comps.normalv.dot(&geometric) > 0.999);
+    }
+
+    #[test]
+    fn bumps_tilt_the_normal_away_from_rising_ground() {
+        // A gradient pattern rises along x, from 0 at x = 0 to 1 at x = 1
+        let ramp = Pattern::gradient(Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix4::identity()),
+                                     Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix4::identity()),
+                                     Matrix4::identity());
+        let mut plane = Plane::new();
+        plane.material.bump = Some(Bump { pattern: ramp, strength: 1.0 });
+        let id = plane.get_id();
+        add_object(Arc::new(plane));
+        let r = Ray::new(Tuple::point(0.5, 1.0, 0.5), Tuple::vector(0.0, -1.0, 0.0));
+        let xs = vec![Intersection { t: 1.0, object: id, u: 0.0, v: 0.0 }];
+        let comps = xs[0].prepare_computations(&r, &xs);
+        let expected = Tuple::vector(-1.0, 1.0, 0.0).normalize();
+        assert!(comps.normalv.dot(&expected) > 0.999);
+    }
+}
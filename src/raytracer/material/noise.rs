@@ -1,18 +1,96 @@
-
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicI32, Ordering};
 use fastnoise_lite::{FastNoiseLite, NoiseType};
-use lazy_static::lazy_static;
 
-fn init_noise() -> FastNoiseLite {
-    let mut noise = FastNoiseLite::new();
-    noise.set_noise_type(Some(NoiseType::Perlin));
-    noise
+/// The seed used by the noise generator when no other seed has been set.
+const DEFAULT_SEED: i32 = 1337;
+
+// The seed shared by all threads; each thread keeps its own generator in sync with it.
+static NOISE_SEED: AtomicI32 = AtomicI32::new(DEFAULT_SEED);
+
+/// The frequency FastNoise Lite works at by default, which noise patterns keep unless they set their own.
+pub const DEFAULT_FREQUENCY: f64 = 0.01;
+
+/// Distance along the noise coordinates between two slices of 4D noise, far enough for them to be unrelated.
+const SLICE_OFFSET: (f64, f64, f64) = (131.7, 71.3, 97.1);
+
+/// The noise algorithms available to noise patterns.
+///
+/// # Variants
+///
+/// * `Perlin` - Classic gradient noise (default).
+/// * `OpenSimplex2` - Simplex style gradient noise, with fewer directional artifacts than Perlin.
+/// * `Value` - Interpolated random values on a grid, blockier than gradient noise.
+/// * `Cubic` - Value noise with cubic interpolation, smoother than `Value`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseAlgorithm {
+    Perlin,
+    OpenSimplex2,
+    Value,
+    Cubic,
+}
+
+impl NoiseAlgorithm {
+    const ALL: [NoiseAlgorithm; 4] = [NoiseAlgorithm::Perlin, NoiseAlgorithm::OpenSimplex2, NoiseAlgorithm::Value, NoiseAlgorithm::Cubic];
+
+    fn noise_type(&self) -> NoiseType {
+        match self {
+            NoiseAlgorithm::Perlin => NoiseType::Perlin,
+            NoiseAlgorithm::OpenSimplex2 => NoiseType::OpenSimplex2,
+            NoiseAlgorithm::Value => NoiseType::Value,
+            NoiseAlgorithm::Cubic => NoiseType::ValueCubic,
+        }
+    }
+}
+
+/// The settings of the noise behind a noise or perturbed pattern.
+///
+/// # Fields
+///
+/// * `algorithm` - The noise algorithm.
+/// * `frequency` - The number of noise features per unit, before the pattern transformation.
+/// * `w` - The fourth coordinate of the noise, which can be changed from frame to frame to make the noise evolve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseSettings {
+    pub algorithm: NoiseAlgorithm,
+    pub frequency: f64,
+    pub w: f64,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        NoiseSettings { algorithm: NoiseAlgorithm::Perlin, frequency: DEFAULT_FREQUENCY, w: 0.0 }
+    }
+}
+
+thread_local! {
+    static NOISE_GENERATORS: RefCell<[FastNoiseLite; 4]> = RefCell::new(init_noise(DEFAULT_SEED));
+}
+
+fn init_noise(seed: i32) -> [FastNoiseLite; 4] {
+    NoiseAlgorithm::ALL.map(|algorithm| {
+        let mut noise = FastNoiseLite::with_seed(seed);
+        noise.set_noise_type(Some(algorithm.noise_type()));
+        noise.set_frequency(Some(1.0));
+        noise
+    })
+}
+
+/// Sets the seed of the noise used by noise and perturbed patterns.
+///
+/// # Arguments
+///
+/// * `seed` - The new seed, or `None` to restore the default seed.
+pub fn set_noise_seed(seed: Option<u64>) {
+    let seed = seed.map(|s| s as i32).unwrap_or(DEFAULT_SEED);
+    NOISE_SEED.store(seed, Ordering::Relaxed);
 }
 
 /// Generates a 3D noise value using a pre-initialized noise generator.
 ///
-/// This function computes a 3D Perlin noise value for the given coordinates. The noise value
-/// is generated using a globally available `NOISE_GENERATOR` which is initialized with Perlin
-/// noise settings. The function returns a raw noise value as a `f64`.
+/// This function computes a 3D Perlin noise value for the given coordinates at the default frequency.
+/// The noise value is generated using a per-thread generator which is initialized with Perlin noise
+/// settings and the seed set by `set_noise_seed`. The function returns a raw noise value as a `f64`.
 ///
 /// # Arguments
 ///
@@ -23,9 +101,50 @@ fn init_noise() -> FastNoiseLite {
 /// # Returns
 ///
 /// Returns a `f64` representing the raw noise value at the given 3D coordinates.
+#[allow(dead_code)]
 pub fn get_noise_3d(x: f64, y: f64, z: f64) -> f64 {
-    let raw_noise = NOISE_GENERATOR.get_noise_3d(x, y, z) as f64;
-    raw_noise
+    get_noise_4d(x, y, z, 0.0, &NoiseSettings::default())
+}
+
+/// Samples one 3D noise generator, with the coordinates already scaled to noise features.
+fn sample(algorithm: NoiseAlgorithm, x: f64, y: f64, z: f64) -> f64 {
+    NOISE_GENERATORS.with(|generators| {
+        let mut generators = generators.borrow_mut();
+        let seed = NOISE_SEED.load(Ordering::Relaxed);
+        if generators[0].seed != seed {
+            *generators = init_noise(seed);
+        }
+        generators[algorithm as usize].get_noise_3d(x, y, z) as f64
+    })
+}
+
+/// Generates a 4D noise value with the given settings.
+///
+/// FastNoise Lite only generates 3D noise, so the fourth dimension blends between unrelated 3D slices
+/// of the noise, one noise feature apart along `w`. At `w` = 0 this is plain 3D noise.
+///
+/// # Arguments
+///
+/// * `x` - The x-coordinate.
+/// * `y` - The y-coordinate.
+/// * `z` - The z-coordinate.
+/// * `w` - The fourth coordinate.
+/// * `settings` - The noise algorithm and frequency; its `w` is not used.
+///
+/// # Returns
+///
+/// Returns the noise value, roughly in the range -1.0 to 1.0.
+pub fn get_noise_4d(x: f64, y: f64, z: f64, w: f64, settings: &NoiseSettings) -> f64 {
+    let f = settings.frequency;
+    let (x, y, z, w) = (x * f, y * f, z * f, w * f);
+    let slice = |k: f64| sample(settings.algorithm, x + k * SLICE_OFFSET.0, y + k * SLICE_OFFSET.1, z + k * SLICE_OFFSET.2);
+    let k = w.floor();
+    let t = w - k;
+    if t == 0.0 {
+        return slice(k);
+    }
+    let t = t * t * (3.0 - 2.0 * t);
+    slice(k) * (1.0 - t) + slice(k + 1.0) * t
 }
 
 /// Generates a fractal noise value using the Perlin noise algorithm with octaves.
@@ -48,12 +167,30 @@ pub fn get_noise_3d(x: f64, y: f64, z: f64) -> f64 {
 ///
 /// Returns a `f64` representing the normalized fractal noise value at the given 3D coordinates.
 pub fn octave_perlin(x: f64, y: f64, z: f64, octaves: usize, persistence: f64) -> f64 {
+    octave_noise(x, y, z, octaves, persistence, &NoiseSettings::default())
+}
+
+/// Generates a fractal noise value like `octave_perlin`, with the given noise settings.
+///
+/// # Arguments
+///
+/// * `x` - The x-coordinate in 3D space.
+/// * `y` - The y-coordinate in 3D space.
+/// * `z` - The z-coordinate in 3D space.
+/// * `octaves` - The number of layers of noise to combine.
+/// * `persistence` - The rate at which the amplitude of each successive layer decreases.
+/// * `settings` - The noise algorithm, frequency and fourth coordinate.
+///
+/// # Returns
+///
+/// Returns the fractal noise value at the given coordinates.
+pub fn octave_noise(x: f64, y: f64, z: f64, octaves: usize, persistence: f64, settings: &NoiseSettings) -> f64 {
     let mut total: f64 = 0.0;
     let mut frequency = 1.0;
     let mut amplitude = 1.0;
     let mut max_value = 0.0; // Used for normalizing result to 0.0 - 1.0
     for _ in 0..octaves {
-        total += get_noise_3d(x * frequency, y * frequency, z * frequency) * amplitude;
+        total += get_noise_4d(x * frequency, y * frequency, z * frequency, settings.w * frequency, settings) * amplitude;
         max_value += amplitude;
         amplitude *= persistence;
         frequency *= 2.0;
@@ -62,7 +199,95 @@ pub fn octave_perlin(x: f64, y: f64, z: f64, octaves: usize, persistence: f64) -
     total / max_value
 }
 
-lazy_static! {
-    pub static ref NOISE_GENERATOR: FastNoiseLite = init_noise();
+
+/// Hashes the coordinates of a cell and the seed to a well mixed 64 bit value.
+fn hash_cell(i: i64, j: i64, k: i64, seed: i32) -> u64 {
+    let mut h = (seed as u64) ^ 0x9E37_79B9_7F4A_7C15;
+    for c in [i, j, k] {
+        h ^= c as u64;
+        h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h ^= h >> 31;
+    }
+    h ^= h >> 29;
+    h = h.wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 32)
 }
 
+/// Computes cellular (Worley) noise at a point.
+///
+/// Space is divided into unit cells, each holding one feature point at a random position that depends
+/// on the seed set by `set_noise_seed`. The result is the distance from the point to the closest feature
+/// point (F1) and to the second closest one (F2).
+///
+/// # Arguments
+///
+/// * `x` - The x-coordinate in 3D space.
+/// * `y` - The y-coordinate in 3D space.
+/// * `z` - The z-coordinate in 3D space.
+///
+/// # Returns
+///
+/// Returns the distances `(f1, f2)`, with `f1 <= f2`.
+pub fn worley(x: f64, y: f64, z: f64) -> (f64, f64) {
+    let seed = NOISE_SEED.load(Ordering::Relaxed);
+    let (ci, cj, ck) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+    let mut f1 = f64::INFINITY;
+    let mut f2 = f64::INFINITY;
+    for i in ci - 1..=ci + 1 {
+        for j in cj - 1..=cj + 1 {
+            for k in ck - 1..=ck + 1 {
+                let h = hash_cell(i, j, k, seed);
+                // Three 21 bit fractions of the hash place the feature point inside the cell
+                let fraction = |shift: u32| ((h >> shift) & 0x1F_FFFF) as f64 / 0x20_0000 as f64;
+                let dx = i as f64 + fraction(0) - x;
+                let dy = j as f64 + fraction(21) - y;
+                let dz = k as f64 + fraction(42) - z;
+                let d = (dx * dx + dy * dy + dz * dz).sqrt();
+                if d < f1 {
+                    f2 = f1;
+                    f1 = d;
+                } else if d < f2 {
+                    f2 = d;
+                }
+            }
+        }
+    }
+    (f1, f2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_noise_3d, get_noise_4d, worley, NoiseAlgorithm, NoiseSettings};
+
+    #[test]
+    fn noise_settings_select_the_algorithm_and_frequency() {
+        let perlin = NoiseSettings::default();
+        assert_eq!(get_noise_4d(3.3, 4.4, 5.5, 0.0, &perlin), get_noise_3d(3.3, 4.4, 5.5));
+        let value = NoiseSettings { algorithm: NoiseAlgorithm::Value, ..perlin };
+        assert_ne!(get_noise_4d(333.0, 44.0, 555.0, 0.0, &value), get_noise_4d(333.0, 44.0, 555.0, 0.0, &perlin));
+        let doubled = NoiseSettings { frequency: 0.02, ..perlin };
+        assert!((get_noise_4d(150.0, 25.0, 75.0, 0.0, &doubled) - get_noise_4d(300.0, 50.0, 150.0, 0.0, &perlin)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn noise_changes_smoothly_along_w() {
+        let settings = NoiseSettings::default();
+        let (x, y, z) = (123.0, 45.0, 678.0);
+        let a = get_noise_4d(x, y, z, 0.0, &settings);
+        let b = get_noise_4d(x, y, z, 1.0, &settings);
+        let c = get_noise_4d(x, y, z, 200.0, &settings);
+        assert!((a - b).abs() < 0.05);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn worley_noise_finds_the_two_closest_feature_points() {
+        for p in [(0.1, 0.2, 0.3), (5.5, -3.25, 7.75), (-10.0, 0.0, 0.5)] {
+            let (f1, f2) = worley(p.0, p.1, p.2);
+            assert!(f1 <= f2);
+            // There is a feature point in the cell of the point itself
+            assert!(f1 < 3.0_f64.sqrt());
+            assert_eq!(worley(p.0, p.1, p.2), (f1, f2));
+        }
+    }
+}
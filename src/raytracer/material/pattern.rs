@@ -1,8 +1,12 @@
 use crate::color::Color;
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::material::noise;
-use crate::raytracer::material::texture::Texture;
+use crate::raytracer::material::noise::NoiseSettings;
+use crate::raytracer::material::texture::{Texture, UvProjection};
+use crate::raytracer::object::cube::Cube;
+use crate::raytracer::ray::Footprint;
 use crate::tuple::Tuple;
+use crate::EPSILON;
 
 /// Represents the type of pattern to be applied to a surface in a ray tracing context.
 ///
@@ -19,8 +23,17 @@ use crate::tuple::Tuple;
 /// * `Ring(Box<Pattern>, Box<Pattern>)` - A ring pattern alternating between two patterns in a radial fashion.
 /// * `Checker(Box<Pattern>, Box<Pattern>)` - A checkerboard pattern alternating between two patterns.
 /// * `Blend(Box<Pattern>, Box<Pattern>, f64)` - A blend of two patterns, with the blend ratio specified by a floating point value.
-/// * `Perturbed(Box<Pattern>, f64, usize, f64)` - A pattern perturbed by noise, with parameters for scale, octaves, and persistence.
-/// * `Noise(Box<Pattern>, Box<Pattern>, f64, usize, f64)` - A noise-based pattern, with parameters for scale, octaves, and persistence.
+/// * `Perturbed(Box<Pattern>, f64, usize, f64, NoiseSettings)` - A pattern perturbed by noise, with parameters for scale, octaves, persistence and the noise itself.
+/// * `Noise(Box<Pattern>, Box<Pattern>, f64, usize, f64, NoiseSettings)` - A noise-based pattern, with parameters for scale, octaves, persistence and the noise itself.
+/// * `Texture(Texture)` - An image, mapped onto the object with its texture coordinates.
+/// * `CubeMap(Box<[Texture; 6]>, bool)` - Six images, one per face of a cube around the origin, in the order of `CubeFace`, and whether they are seen from inside the cube, like a skybox, rather than from outside.
+/// * `Wood(Box<Pattern>, Box<Pattern>, Grain)` - Concentric rings around the y axis, shading from the first pattern to the second across each ring, bent by noise.
+/// * `Marble(Box<Pattern>, Box<Pattern>, Grain)` - Veins running across the x axis, shading from the first pattern to the second, swirled by noise.
+/// * `Brick(Box<Pattern>, Box<Pattern>, BrickLayout)` - Bricks of the first pattern laid in rows along the y axis, joined by mortar of the second pattern.
+/// * `Worley(Box<Pattern>, Box<Pattern>, WorleyDistance)` - Cellular noise, shading from the first pattern at the feature points to the second pattern away from them.
+/// * `Composite(CompositeOp, Box<Pattern>, Box<Pattern>, Option<Box<Pattern>>)` - Two patterns combined with an operator, optionally only where a third pattern, the mask, is bright.
+/// * `UvCheckers(Box<Pattern>, Box<Pattern>, f64, f64, Option<UvProjection>)` - A checkerboard in texture space, with the number of squares along u and v.
+/// * `UvAlignCheck(Box<[Color; 5]>, Option<UvProjection>)` - A texture space test pattern with a main color and one color per corner, in the order main, upper left, upper right, bottom left, bottom right.
 #[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum PatternType {
@@ -31,9 +44,118 @@ pub enum PatternType {
     Ring(Box<Pattern>, Box<Pattern>),
     Checker(Box<Pattern>, Box<Pattern>),
     Blend(Box<Pattern>, Box<Pattern>, f64),
-    Perturbed(Box<Pattern>, f64, usize, f64),
-    Noise(Box<Pattern>, Box<Pattern>, f64, usize, f64),
-    Texture(Texture)
+    Perturbed(Box<Pattern>, f64, usize, f64, NoiseSettings),
+    Noise(Box<Pattern>, Box<Pattern>, f64, usize, f64, NoiseSettings),
+    Texture(Texture),
+    CubeMap(Box<[Texture; 6]>, bool),
+    Wood(Box<Pattern>, Box<Pattern>, Grain),
+    Marble(Box<Pattern>, Box<Pattern>, Grain),
+    Brick(Box<Pattern>, Box<Pattern>, BrickLayout),
+    Worley(Box<Pattern>, Box<Pattern>, WorleyDistance),
+    Composite(CompositeOp, Box<Pattern>, Box<Pattern>, Option<Box<Pattern>>),
+    UvCheckers(Box<Pattern>, Box<Pattern>, f64, f64, Option<UvProjection>),
+    UvAlignCheck(Box<[Color; 5]>, Option<UvProjection>),
+}
+
+/// The grain of a wood or marble pattern.
+///
+/// # Fields
+///
+/// * `frequency` - The number of rings or veins per unit.
+/// * `turbulence` - How far the rings or veins are pushed around by noise, in units.
+/// * `octaves` - The number of layers of noise, more giving finer detail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grain {
+    pub frequency: f64,
+    pub turbulence: f64,
+    pub octaves: usize,
+}
+
+/// The noise generator works at a frequency of 0.01, so points are scaled up to get about one noise feature per unit.
+const GRAIN_NOISE_SCALE: f64 = 100.0;
+
+impl Grain {
+    /// Returns the noise displacement of the grain at a point.
+    fn displacement(&self, p: &Tuple) -> f64 {
+        if self.turbulence == 0.0 {
+            return 0.0;
+        }
+        let (x, y, z) = (p.x * GRAIN_NOISE_SCALE, p.y * GRAIN_NOISE_SCALE, p.z * GRAIN_NOISE_SCALE);
+        noise::octave_perlin(x, y, z, self.octaves.max(1), 0.5) * self.turbulence
+    }
+}
+
+/// The layout of a brick pattern.
+///
+/// Bricks are laid in rows stacked along the y axis, and every other row is shifted by `offset` along
+/// both x and z, so the bond shows on every face of a cube. Each brick has mortar on its low side along
+/// every axis.
+///
+/// # Fields
+///
+/// * `size` - The size of a brick along x, y and z, including its mortar.
+/// * `mortar` - The width of the mortar joints.
+/// * `offset` - How far every other row is shifted, as a fraction of a brick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrickLayout {
+    pub size: (f64, f64, f64),
+    pub mortar: f64,
+    pub offset: f64,
+}
+
+impl BrickLayout {
+    /// Returns whether a point lies in the mortar between bricks.
+    fn is_mortar(&self, p: &Tuple) -> bool {
+        let (width, height, depth) = self.size;
+        let row = (p.y / height).floor();
+        let shift = if row.rem_euclid(2.0) == 1.0 { self.offset } else { 0.0 };
+        let x = (p.x / width + shift).rem_euclid(1.0) * width;
+        let y = p.y.rem_euclid(height);
+        let z = (p.z / depth + shift).rem_euclid(1.0) * depth;
+        x < self.mortar || y < self.mortar || z < self.mortar
+    }
+}
+
+/// Which distance of cellular noise a Worley pattern shows.
+///
+/// # Variants
+///
+/// * `F1` - The distance to the closest feature point, giving round cells like scales or stones.
+/// * `F2` - The distance to the second closest feature point.
+/// * `F2MinusF1` - The difference between the two, which is zero on the borders between cells, like cracked earth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorleyDistance {
+    F1,
+    F2,
+    F2MinusF1,
+}
+
+/// How a composite pattern combines its two patterns.
+///
+/// # Variants
+///
+/// * `Add` - The sum of the colors, which brightens.
+/// * `Multiply` - The product of the colors, which darkens.
+/// * `Screen` - The inverse of the product of the inverted colors, which brightens without going over 1.
+/// * `Mask` - The second color, so with a mask the second pattern shows where the mask is bright and the first elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompositeOp {
+    Add,
+    Multiply,
+    Screen,
+    Mask,
+}
+
+impl CompositeOp {
+    /// Combines two colors.
+    fn apply(&self, a: &Color, b: &Color) -> Color {
+        match self {
+            CompositeOp::Add => a.add(b),
+            CompositeOp::Multiply => a.product(b),
+            CompositeOp::Screen => a.add(b).subtract(&a.product(b)),
+            CompositeOp::Mask => *b,
+        }
+    }
 }
 
 /// Represents a pattern with a specific type and transformation.
@@ -50,7 +172,7 @@ pub enum PatternType {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Pattern {
     pub pattern_type: PatternType,
-    pub transform: Matrix,
+    pub transform: Matrix4,
 }
 
 impl Pattern {
@@ -58,69 +180,125 @@ impl Pattern {
     pub fn test() -> Pattern {
         Pattern {
             pattern_type: PatternType::Test,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
         }
     }
 
-    pub fn solid(color: Color, transform: Matrix) -> Pattern {
+    pub fn solid(color: Color, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Solid(color),
             transform,
         }
     }
 
-    pub fn stripe(a: Pattern, b: Pattern, transform: Matrix) -> Pattern {
+    pub fn stripe(a: Pattern, b: Pattern, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Stripe(Box::new(a), Box::new(b)),
             transform,
         }
     }
 
-    pub fn gradient(a: Pattern, b: Pattern, transform: Matrix) -> Pattern {
+    pub fn gradient(a: Pattern, b: Pattern, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Gradient(Box::new(a), Box::new(b)),
             transform,
         }
     }
 
-    pub fn ring(a: Pattern, b: Pattern, transform: Matrix) -> Pattern {
+    pub fn ring(a: Pattern, b: Pattern, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Ring(Box::new(a), Box::new(b)),
             transform,
         }
     }
 
-    pub fn checker(a: Pattern, b: Pattern, transform: Matrix) -> Pattern {
+    pub fn checker(a: Pattern, b: Pattern, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Checker(Box::new(a), Box::new(b)),
             transform,
         }
     }
 
-    pub fn blend(a: Pattern, b: Pattern, scale: f64, transform: Matrix) -> Pattern {
+    pub fn blend(a: Pattern, b: Pattern, scale: f64, transform: Matrix4) -> Pattern {
         Pattern {
             pattern_type: PatternType::Blend(Box::new(a), Box::new(b), scale),
             transform,
         }
     }
 
-    pub fn perturbed(a: Pattern, scale: f64, octaves: usize, persistence: f64, transform: Matrix) -> Pattern {
+    pub fn perturbed(a: Pattern, scale: f64, octaves: usize, persistence: f64, noise: NoiseSettings, transform: Matrix4) -> Pattern {
         Pattern {
-            pattern_type: PatternType::Perturbed(Box::new(a), scale, octaves, persistence),
+            pattern_type: PatternType::Perturbed(Box::new(a), scale, octaves, persistence, noise),
             transform,
         }
     }
 
-    pub fn noise(a: Pattern, b: Pattern, scale: f64, octaves: usize, persistence: f64, transform: Matrix) -> Pattern {
+    pub fn noise(a: Pattern, b: Pattern, scale: f64, octaves: usize, persistence: f64, noise: NoiseSettings, transform: Matrix4) -> Pattern {
         Pattern {
-            pattern_type: PatternType::Noise(Box::new(a), Box::new(b), scale, octaves, persistence),
+            pattern_type: PatternType::Noise(Box::new(a), Box::new(b), scale, octaves, persistence, noise),
             transform,
         }
     }
 
-    pub fn texture(file_name: &str, transform: Matrix) -> Pattern {
+    pub fn cube_map(faces: [Texture; 6], inside: bool, transform: Matrix4) -> Pattern {
         Pattern {
-            pattern_type: PatternType::Texture(Texture::new(file_name)),
+            pattern_type: PatternType::CubeMap(Box::new(faces), inside),
+            transform,
+        }
+    }
+
+    pub fn texture(texture: Texture, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Texture(texture),
+            transform,
+        }
+    }
+
+    pub fn wood(a: Pattern, b: Pattern, grain: Grain, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Wood(Box::new(a), Box::new(b), grain),
+            transform,
+        }
+    }
+
+    pub fn marble(a: Pattern, b: Pattern, grain: Grain, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Marble(Box::new(a), Box::new(b), grain),
+            transform,
+        }
+    }
+
+    pub fn brick(brick: Pattern, mortar: Pattern, layout: BrickLayout, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Brick(Box::new(brick), Box::new(mortar), layout),
+            transform,
+        }
+    }
+
+    pub fn worley(a: Pattern, b: Pattern, distance: WorleyDistance, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Worley(Box::new(a), Box::new(b), distance),
+            transform,
+        }
+    }
+
+    pub fn composite(op: CompositeOp, a: Pattern, b: Pattern, mask: Option<Pattern>, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::Composite(op, Box::new(a), Box::new(b), mask.map(Box::new)),
+            transform,
+        }
+    }
+
+    pub fn uv_checkers(a: Pattern, b: Pattern, width: f64, height: f64, projection: Option<UvProjection>, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::UvCheckers(Box::new(a), Box::new(b), width, height, projection),
+            transform,
+        }
+    }
+
+    pub fn uv_align_check(colors: [Color; 5], projection: Option<UvProjection>, transform: Matrix4) -> Pattern {
+        Pattern {
+            pattern_type: PatternType::UvAlignCheck(Box::new(colors), projection),
             transform,
         }
     }
@@ -143,7 +321,30 @@ impl Pattern {
     ///
     /// Returns a `Color` representing the color of the pattern at the given point on the object.
     pub fn pattern_at(&self, object_point: &Tuple, shape: usize) -> Color {
-        let pattern_point = self.transform.inverse().multiply_tuple(object_point);
+        self.filtered_pattern_at(object_point, None, shape)
+    }
+
+    /// Calculates the color of the pattern around a point on an object, averaged over the footprint of a pixel.
+    ///
+    /// Stripes and checkers are blended by how much of the footprint each of their two patterns covers, and
+    /// textures are sampled from a smaller copy of the image when a pixel covers several of its pixels. This
+    /// keeps fine patterns from flickering in the distance. The other patterns pass the footprint on to the
+    /// patterns they are made of. Without a footprint this is the same as `pattern_at`.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_point` - The point on the object in object space.
+    /// * `footprint` - The area of the surface covered by the pixel, in object space, or `None` to sample the point.
+    /// * `shape` - The ID of the object, for patterns that use its texture coordinates.
+    ///
+    /// # Returns
+    ///
+    /// Returns the average color of the pattern over the footprint.
+    pub fn filtered_pattern_at(&self, object_point: &Tuple, footprint: Option<&Footprint>, shape: usize) -> Color {
+        let inverse = self.transform.inverse();
+        let pattern_point = inverse.multiply_tuple(object_point);
+        let footprint = footprint.map(|f| f.transform(&inverse));
+        let footprint = footprint.as_ref();
         match &self.pattern_type {
             PatternType::Test => {
                 Color::new(pattern_point.x, pattern_point.y, pattern_point.z)
@@ -152,88 +353,229 @@ impl Pattern {
                 color.clone()
             },
             PatternType::Stripe(a, b) => {
-                if (pattern_point.x.floor() as i32) % 2 == 0 {
-                    a.pattern_at(&pattern_point, shape)
-                } else {
-                    b.pattern_at(&pattern_point, shape)
-                }
+                let width = footprint.map_or(0.0, |f| f.extent().x);
+                let weight = (1.0 + filtered_square_wave(pattern_point.x, width)) / 2.0;
+                mix(a, b, weight, &pattern_point, footprint, shape)
             },
             PatternType::Gradient(a, b) => {
-                let a = a.pattern_at(&pattern_point, shape);
-                let b = b.pattern_at(&pattern_point, shape);
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let b = b.filtered_pattern_at(&pattern_point, footprint, shape);
                 let distance = b.subtract(&a);
                 let fraction = pattern_point.x - pattern_point.x.floor();
                 a.add(&distance.multiply(fraction))
             },
             PatternType::Ring(a, b) => {
                 if (pattern_point.x.powi(2) + pattern_point.z.powi(2)).sqrt().floor() as i32 % 2 == 0 {
-                    a.pattern_at(&pattern_point, shape)
+                    a.filtered_pattern_at(&pattern_point, footprint, shape)
                 } else {
-                    b.pattern_at(&pattern_point, shape)
+                    b.filtered_pattern_at(&pattern_point, footprint, shape)
                 }
             },
             PatternType::Checker(a, b) => {
-                if (pattern_point.x.floor() + pattern_point.y.floor() + pattern_point.z.floor()) as i32 % 2 == 0 {
-                    a.pattern_at(&pattern_point, shape)
-                } else {
-                    b.pattern_at(&pattern_point, shape)
-                }
+                // The checker is the product of a square wave along each axis, so it is filtered one axis at a time
+                let width = footprint.map_or(Tuple::vector(0.0, 0.0, 0.0), |f| f.extent());
+                let wave = filtered_square_wave(pattern_point.x, width.x)
+                    * filtered_square_wave(pattern_point.y, width.y)
+                    * filtered_square_wave(pattern_point.z, width.z);
+                mix(a, b, (1.0 + wave) / 2.0, &pattern_point, footprint, shape)
             },
             PatternType::Blend(a, b, scale) => {
-                let a = a.pattern_at(&pattern_point, shape);
-                let b = b.pattern_at(&pattern_point, shape);
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let b = b.filtered_pattern_at(&pattern_point, footprint, shape);
                 a.multiply(1.0-scale).add(&b.multiply(*scale))
             },
-            PatternType::Perturbed(a, scale, octaves, persistence) => {
+            PatternType::Perturbed(a, scale, octaves, persistence, settings) => {
                 let x = pattern_point.x;
                 let y = pattern_point.y;
                 let z = pattern_point.z;
-                let noise_x = noise::octave_perlin(x, y, z, *octaves, *persistence) * scale;
-                let noise_y = noise::octave_perlin(x, y, z + 1.0, *octaves, *persistence) * scale;
-                let noise_z = noise::octave_perlin(x, y, z + 2.0, *octaves, *persistence) * scale;
+                let noise_x = noise::octave_noise(x, y, z, *octaves, *persistence, settings) * scale;
+                let noise_y = noise::octave_noise(x, y, z + 1.0, *octaves, *persistence, settings) * scale;
+                let noise_z = noise::octave_noise(x, y, z + 2.0, *octaves, *persistence, settings) * scale;
                 let new_x = pattern_point.x + noise_x;
                 let new_y = pattern_point.y + noise_y;
                 let new_z = pattern_point.z + noise_z;
                 let new_point = Tuple::new(new_x, new_y, new_z, pattern_point.w);
-                a.pattern_at(&new_point, shape)
+                a.filtered_pattern_at(&new_point, footprint, shape)
             },
-            PatternType::Noise(a, b, scale, octaves, persistence) => {
-                let noise = noise::octave_perlin(pattern_point.x, pattern_point.y, pattern_point.z, *octaves, *persistence);
+            PatternType::Noise(a, b, scale, octaves, persistence, settings) => {
+                let noise = noise::octave_noise(pattern_point.x, pattern_point.y, pattern_point.z, *octaves, *persistence, settings);
                 let noise = noise * scale;
                 if noise <= 0.0 {
-                    a.pattern_at(&pattern_point, shape).multiply(-noise)
+                    a.filtered_pattern_at(&pattern_point, footprint, shape).multiply(-noise)
                 } else {
-                    b.pattern_at(&pattern_point, shape).multiply(noise)
+                    b.filtered_pattern_at(&pattern_point, footprint, shape).multiply(noise)
                 }
             },
             PatternType::Texture(texture) => {
-                let object = crate::raytracer::object::db::get_object(shape);
-                let (u,v) = object.uv_mapping(&pattern_point);
-                texture.sample_texture(u, v)
+                let uv = |point: &Tuple| uv_at(texture.projection, point, shape);
+                let (u, v) = uv(&pattern_point);
+                match footprint.and_then(|f| uv_footprint(&uv, &pattern_point, f)) {
+                    Some((dx, dy)) => texture.sample_texture_footprint(u, v, dx, dy),
+                    None => texture.sample_texture(u, v),
+                }
+            }
+            PatternType::CubeMap(faces, inside) => {
+                // The point is projected onto the cube from the origin
+                let face_uv = |point: &Tuple| if *inside { Cube::face_uv(point) } else { Cube::outside_face_uv(point) };
+                let (face, u, v) = face_uv(&pattern_point);
+                // Footprints that reach across an edge of the cube are not filtered
+                let uv = |point: &Tuple| match face_uv(point) {
+                    (other, u, v) if other == face => (u, v),
+                    _ => (f64::NAN, f64::NAN),
+                };
+                match footprint.and_then(|f| uv_footprint(&uv, &pattern_point, f)) {
+                    Some((dx, dy)) => faces[face as usize].sample_texture_footprint(u, v, dx, dy),
+                    None => faces[face as usize].sample_texture(u, v),
+                }
+            }
+            PatternType::Wood(a, b, grain) => {
+                let radius = (pattern_point.x.powi(2) + pattern_point.z.powi(2)).sqrt() + grain.displacement(&pattern_point);
+                let fraction = (radius * grain.frequency).rem_euclid(1.0);
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let b = b.filtered_pattern_at(&pattern_point, footprint, shape);
+                a.add(&b.subtract(&a).multiply(fraction))
+            }
+            PatternType::Marble(a, b, grain) => {
+                let x = pattern_point.x + grain.displacement(&pattern_point);
+                let fraction = ((x * grain.frequency * std::f64::consts::PI).sin() + 1.0) / 2.0;
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let b = b.filtered_pattern_at(&pattern_point, footprint, shape);
+                a.add(&b.subtract(&a).multiply(fraction))
+            }
+            PatternType::Brick(brick, mortar, layout) => {
+                if layout.is_mortar(&pattern_point) {
+                    mortar.filtered_pattern_at(&pattern_point, footprint, shape)
+                } else {
+                    brick.filtered_pattern_at(&pattern_point, footprint, shape)
+                }
+            }
+            PatternType::Worley(a, b, distance) => {
+                let (f1, f2) = noise::worley(pattern_point.x, pattern_point.y, pattern_point.z);
+                let fraction = match distance {
+                    WorleyDistance::F1 => f1,
+                    WorleyDistance::F2 => f2,
+                    WorleyDistance::F2MinusF1 => f2 - f1,
+                }.clamp(0.0, 1.0);
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let b = b.filtered_pattern_at(&pattern_point, footprint, shape);
+                a.add(&b.subtract(&a).multiply(fraction))
+            }
+            PatternType::Composite(op, a, b, mask) => {
+                let a = a.filtered_pattern_at(&pattern_point, footprint, shape);
+                let combined = op.apply(&a, &b.filtered_pattern_at(&pattern_point, footprint, shape));
+                match mask {
+                    // Each channel of the mask blends the matching channel from the first pattern to the result
+                    Some(mask) => {
+                        let m = mask.filtered_pattern_at(&pattern_point, footprint, shape);
+                        a.add(&combined.subtract(&a).product(&m))
+                    }
+                    None => combined,
+                }
+            }
+            PatternType::UvCheckers(a, b, width, height, projection) => {
+                let uv = |point: &Tuple| uv_at(*projection, point, shape);
+                let (u, v) = uv(&pattern_point);
+                let (du, dv) = match footprint.and_then(|f| uv_footprint(&uv, &pattern_point, f)) {
+                    Some((dx, dy)) => (dx.0.abs().max(dy.0.abs()), dx.1.abs().max(dy.1.abs())),
+                    None => (0.0, 0.0),
+                };
+                let wave = filtered_square_wave(u * width, du * width) * filtered_square_wave(v * height, dv * height);
+                mix(a, b, (1.0 + wave) / 2.0, &pattern_point, footprint, shape)
+            }
+            PatternType::UvAlignCheck(colors, projection) => {
+                let (u, v) = uv_at(*projection, &pattern_point, shape);
+                let [main, ul, ur, bl, br] = **colors;
+                match (u, v) {
+                    (u, v) if v > 0.8 && u < 0.2 => ul,
+                    (u, v) if v > 0.8 && u > 0.8 => ur,
+                    (u, v) if v < 0.2 && u < 0.2 => bl,
+                    (u, v) if v < 0.2 && u > 0.8 => br,
+                    _ => main,
+                }
             }
         }
     }
 }
 
+/// Blends two patterns, evaluating only the one that is needed when the weight is 0 or 1.
+fn mix(a: &Pattern, b: &Pattern, weight: f64, pattern_point: &Tuple, footprint: Option<&Footprint>, shape: usize) -> Color {
+    if weight >= 1.0 {
+        a.filtered_pattern_at(pattern_point, footprint, shape)
+    } else if weight <= 0.0 {
+        b.filtered_pattern_at(pattern_point, footprint, shape)
+    } else {
+        let a = a.filtered_pattern_at(pattern_point, footprint, shape);
+        let b = b.filtered_pattern_at(pattern_point, footprint, shape);
+        a.multiply(weight).add(&b.multiply(1.0 - weight))
+    }
+}
+
+/// Averages the square wave that is 1 where `floor(x)` is even and -1 where it is odd over a box of the
+/// given width around `x`.
+///
+/// Boxes narrower than `EPSILON` are not averaged, so that points on a flat floor with rounding errors in
+/// their height do not turn gray.
+fn filtered_square_wave(x: f64, width: f64) -> f64 {
+    if width.is_nan() || width < EPSILON {
+        return if x.floor().rem_euclid(2.0) == 1.0 { -1.0 } else { 1.0 };
+    }
+    // The integral of the square wave from 0 to x is a triangle wave
+    let integral = |x: f64| {
+        let x = x.rem_euclid(2.0);
+        if x < 1.0 { x } else { 2.0 - x }
+    };
+    (integral(x + width / 2.0) - integral(x - width / 2.0)) / width
+}
+
+/// Finds how far the texture coordinates move across a footprint in pattern space.
+///
+/// The coordinates are wrapped, so a footprint across the seam of a sphere moves them a little instead of
+/// almost all the way around.
+///
+/// # Returns
+///
+/// The change of `(u, v)` to the next pixel along the row and down the column, or `None` when `uv` does
+/// not give numbers at the corners of the footprint.
+fn uv_footprint(uv: &dyn Fn(&Tuple) -> (f64, f64), pattern_point: &Tuple, footprint: &Footprint) -> Option<((f64, f64), (f64, f64))> {
+    let (u, v) = uv(pattern_point);
+    let delta = |offset: &Tuple| {
+        let (u1, v1) = uv(&pattern_point.add(offset));
+        let (du, dv) = (u1 - u, v1 - v);
+        (du - du.round(), dv - dv.round())
+    };
+    let (dx, dy) = (delta(&footprint.dpdx), delta(&footprint.dpdy));
+    [dx.0, dx.1, dy.0, dy.1].iter().all(|d| d.is_finite()).then_some((dx, dy))
+}
+
+/// Finds the texture coordinates of a point in pattern space, with the given projection or else the mapping of the shape.
+fn uv_at(projection: Option<UvProjection>, pattern_point: &Tuple, shape: usize) -> (f64, f64) {
+    match projection {
+        Some(projection) => projection.uv(pattern_point),
+        None => crate::raytracer::object::db::get_object(shape).uv_mapping(pattern_point),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
     use crate::color::Color;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::intersection::Intersection;
     use crate::raytracer::light::Light;
     use crate::tuple::Tuple;
     use crate::raytracer::material::noise::get_noise_3d;
-    use crate::raytracer::material::pattern::Pattern;
+    use crate::raytracer::material::pattern::{BrickLayout, CompositeOp, Grain, Pattern};
+    use crate::raytracer::material::texture::{Texture, TextureFilter, UvProjection};
+    use image::{Rgba, RgbaImage};
     use crate::raytracer::object::sphere::Sphere;
-    use crate::raytracer::ray::Ray;
+    use crate::raytracer::ray::{Footprint, Ray};
     use crate::raytracer::scene::Scene;
 
     #[test]
     fn stripe_pattern_is_constant_in_y() {
-        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                Matrix::identity(4));
+        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 1.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 2.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
@@ -241,9 +583,9 @@ mod tests {
 
     #[test]
     fn stripe_pattern_is_constant_in_z() {
-        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                Matrix::identity(4));
+        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 1.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 2.0), 0), Color::new(1.0, 1.0, 1.0));
@@ -251,9 +593,9 @@ mod tests {
 
     #[test]
     fn stripe_pattern_alternates_in_x() {
-        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                Matrix::identity(4));
+        let p = Pattern::stripe(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.9, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(1.0, 0.0, 0.0), 0), Color::new(0.0, 0.0, 0.0));
@@ -264,9 +606,9 @@ mod tests {
 
     #[test]
     fn gradient_pattern_linearly_interpolates_between_colors() {
-        let p = Pattern::gradient(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                  Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                  Matrix::identity(4));
+        let p = Pattern::gradient(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                  Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                  Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.25, 0.0, 0.0), 0), Color::new(0.75, 0.75, 0.75));
         assert_eq!(p.pattern_at(&Tuple::point(0.5, 0.0, 0.0), 0), Color::new(0.5, 0.5, 0.5));
@@ -275,9 +617,9 @@ mod tests {
 
     #[test]
     fn ring_should_extend_in_both_x_and_z() {
-        let p = Pattern::ring(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                              Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                              Matrix::identity(4));
+        let p = Pattern::ring(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                              Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                              Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(1.0, 0.0, 0.0), 0), Color::new(0.0, 0.0, 0.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 1.0), 0), Color::new(0.0, 0.0, 0.0));
@@ -286,9 +628,9 @@ mod tests {
 
     #[test]
     fn checkers_repeat_in_x() {
-        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                 Matrix::identity(4));
+        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                 Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.99, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(1.01, 0.0, 0.0), 0), Color::new(0.0, 0.0, 0.0));
@@ -296,9 +638,9 @@ mod tests {
 
     #[test]
     fn checkers_repeat_in_y() {
-        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                 Matrix::identity(4));
+        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                 Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.99, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 1.01, 0.0), 0), Color::new(0.0, 0.0, 0.0));
@@ -306,14 +648,108 @@ mod tests {
 
     #[test]
     fn checkers_repeat_in_z() {
-        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix::identity(4)),
-                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix::identity(4)),
-                                 Matrix::identity(4));
+        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                 Matrix4::identity());
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 0.99), 0), Color::new(1.0, 1.0, 1.0));
         assert_eq!(p.pattern_at(&Tuple::point(0.0, 0.0, 1.01), 0), Color::new(0.0, 0.0, 0.0));
     }
 
+    #[test]
+    fn checkers_are_averaged_over_the_footprint() {
+        let p = Pattern::checker(Pattern::solid(Color::new(1.0,1.0,1.0), Matrix4::identity()),
+                                 Pattern::solid(Color::new(0.0,0.0,0.0), Matrix4::identity()),
+                                 Matrix4::identity());
+        let footprint = |dx: f64| Footprint { dpdx: Tuple::vector(dx, 0.0, 0.0), dpdy: Tuple::vector(0.0, 0.0, 0.0) };
+        // A footprint inside one square sees only that square
+        assert_eq!(p.filtered_pattern_at(&Tuple::point(0.25, 0.0, 0.5), Some(&footprint(0.5)), 0), Color::new(1.0, 1.0, 1.0));
+        // A footprint across an edge sees both squares
+        assert_eq!(p.filtered_pattern_at(&Tuple::point(1.0, 0.0, 0.5), Some(&footprint(1.0)), 0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(p.filtered_pattern_at(&Tuple::point(1.0, 0.0, 0.5), Some(&footprint(0.5)), 0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(p.filtered_pattern_at(&Tuple::point(0.75, 0.0, 0.5), Some(&footprint(1.0)), 0), Color::new(0.75, 0.75, 0.75));
+        // A tiny footprint is the same as no footprint
+        assert_eq!(p.filtered_pattern_at(&Tuple::point(0.99, 0.0, 0.0), Some(&footprint(1e-9)), 0), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn cube_map_picks_the_image_of_each_face() {
+        let colors = [[255, 255, 0], [0, 255, 255], [255, 0, 0], [0, 255, 0], [255, 0, 255], [255, 255, 255]];
+        let faces = colors.map(|c| Texture::from_image(RgbaImage::from_pixel(1, 1, Rgba([c[0], c[1], c[2], 255])), TextureFilter::Nearest));
+        let p = Pattern::cube_map(faces, true, Matrix4::identity());
+        assert_eq!(p.pattern_at(&Tuple::point(-1.0, 0.5, -0.5), 0), Color::new(1.0, 1.0, 0.0));
+        assert_eq!(p.pattern_at(&Tuple::point(-0.5, 0.5, 1.0), 0), Color::new(0.0, 1.0, 1.0));
+        assert_eq!(p.pattern_at(&Tuple::point(1.0, 0.5, 0.5), 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(p.pattern_at(&Tuple::point(0.5, 0.5, -1.0), 0), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(p.pattern_at(&Tuple::point(-0.5, 1.0, -0.5), 0), Color::new(1.0, 0.0, 1.0));
+        assert_eq!(p.pattern_at(&Tuple::point(-0.5, -1.0, 0.5), 0), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn wood_and_marble_shade_between_their_patterns() {
+        let white = Pattern::solid(Color::new(1.0, 1.0, 1.0), Matrix4::identity());
+        let black = Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix4::identity());
+        let grain = Grain { frequency: 2.0, turbulence: 0.0, octaves: 1 };
+        let wood = Pattern::wood(black.clone(), white.clone(), grain, Matrix4::identity());
+        assert_eq!(wood.pattern_at(&Tuple::point(0.0, 5.0, 0.0), 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(wood.pattern_at(&Tuple::point(0.0, 0.0, 0.25), 0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(wood.pattern_at(&Tuple::point(0.3, 0.0, 0.4), 0), Color::new(0.0, 0.0, 0.0));
+        let marble = Pattern::marble(black, white, grain, Matrix4::identity());
+        assert_eq!(marble.pattern_at(&Tuple::point(0.0, 1.0, 2.0), 0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(marble.pattern_at(&Tuple::point(0.25, 0.0, 0.0), 0), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn brick_pattern_has_staggered_rows_of_bricks() {
+        let red = Color::new(0.8, 0.2, 0.1);
+        let grey = Color::new(0.5, 0.5, 0.5);
+        let layout = BrickLayout { size: (1.0, 0.5, 1.0), mortar: 0.1, offset: 0.5 };
+        let p = Pattern::brick(Pattern::solid(red, Matrix4::identity()), Pattern::solid(grey, Matrix4::identity()), layout, Matrix4::identity());
+        assert_eq!(p.pattern_at(&Tuple::point(0.5, 0.25, 0.5), 0), red);
+        assert_eq!(p.pattern_at(&Tuple::point(0.05, 0.25, 0.5), 0), grey);
+        assert_eq!(p.pattern_at(&Tuple::point(0.5, 0.55, 0.5), 0), grey);
+        // The joints of the next row are shifted by half a brick
+        assert_eq!(p.pattern_at(&Tuple::point(0.55, 0.75, 0.25), 0), grey);
+        assert_eq!(p.pattern_at(&Tuple::point(0.05, 0.75, 0.25), 0), red);
+    }
+
+    #[test]
+    fn compositing_two_patterns() {
+        let a = Pattern::solid(Color::new(0.5, 0.25, 0.0), Matrix4::identity());
+        let b = Pattern::solid(Color::new(0.5, 0.5, 1.0), Matrix4::identity());
+        let origin = Tuple::point(0.0, 0.0, 0.0);
+        let add = Pattern::composite(CompositeOp::Add, a.clone(), b.clone(), None, Matrix4::identity());
+        assert_eq!(add.pattern_at(&origin, 0), Color::new(1.0, 0.75, 1.0));
+        let multiply = Pattern::composite(CompositeOp::Multiply, a.clone(), b.clone(), None, Matrix4::identity());
+        assert_eq!(multiply.pattern_at(&origin, 0), Color::new(0.25, 0.125, 0.0));
+        let screen = Pattern::composite(CompositeOp::Screen, a.clone(), b.clone(), None, Matrix4::identity());
+        assert_eq!(screen.pattern_at(&origin, 0), Color::new(0.75, 0.625, 1.0));
+        let stripes = Pattern::stripe(Pattern::solid(Color::white(), Matrix4::identity()),
+                                      Pattern::solid(Color::new(0.0, 0.0, 0.0), Matrix4::identity()),
+                                      Matrix4::identity());
+        let mask = Pattern::composite(CompositeOp::Mask, a, b, Some(stripes), Matrix4::identity());
+        assert_eq!(mask.pattern_at(&origin, 0), Color::new(0.5, 0.5, 1.0));
+        assert_eq!(mask.pattern_at(&Tuple::point(1.5, 0.0, 0.0), 0), Color::new(0.5, 0.25, 0.0));
+    }
+
+    #[test]
+    fn uv_patterns_are_evaluated_in_texture_space() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let black = Color::new(0.0, 0.0, 0.0);
+        let p = Pattern::uv_checkers(Pattern::solid(black, Matrix4::identity()), Pattern::solid(white, Matrix4::identity()),
+                                     2.0, 2.0, Some(UvProjection::Planar), Matrix4::identity());
+        assert_eq!(p.pattern_at(&Tuple::point(0.25, 0.0, 0.25), 0), black);
+        assert_eq!(p.pattern_at(&Tuple::point(0.75, 0.0, 0.25), 0), white);
+        assert_eq!(p.pattern_at(&Tuple::point(0.75, 0.0, 0.75), 0), black);
+        let colors = [white, Color::new(1.0, 0.0, 0.0), Color::new(1.0, 1.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 1.0, 1.0)];
+        let p = Pattern::uv_align_check(colors, Some(UvProjection::Planar), Matrix4::identity());
+        assert_eq!(p.pattern_at(&Tuple::point(0.5, 0.0, 0.5), 0), colors[0]);
+        assert_eq!(p.pattern_at(&Tuple::point(0.1, 0.0, 0.9), 0), colors[1]);
+        assert_eq!(p.pattern_at(&Tuple::point(0.9, 0.0, 0.9), 0), colors[2]);
+        assert_eq!(p.pattern_at(&Tuple::point(0.1, 0.0, 0.1), 0), colors[3]);
+        assert_eq!(p.pattern_at(&Tuple::point(0.9, 0.0, 0.1), 0), colors[4]);
+    }
+
     #[test]
     #[ignore]
     fn test_fastnoise() {
@@ -1,22 +1,158 @@
 
-use image::ImageReader;
+use std::sync::Arc;
 use image::RgbaImage;
+use image::imageops::crop_imm;
 use crate::color::Color;
+use crate::raytracer::material::texture_cache::{cached_image, SharedImage};
+use crate::raytracer::object::cube::Cube;
+use crate::tuple::Tuple;
+
+/// How a texture is sampled between the centers of its pixels.
+///
+/// # Variants
+///
+/// * `Nearest` - The color of the closest pixel, which looks blocky when the texture is magnified.
+/// * `Bilinear` - A blend of the four closest pixels, weighted by distance, which looks smooth.
+/// * `Trilinear` - A bilinear blend that is also blended between the two halved copies of the image closest
+///   in size to a pixel, so distant textures change smoothly with distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+/// How texture coordinates outside of the range [0, 1] are treated.
+///
+/// # Variants
+///
+/// * `Clamp` - The edge pixels of the image are stretched out.
+/// * `Repeat` - The image is tiled.
+/// * `Mirror` - The image is tiled, with every other tile mirrored so that the tiles join seamlessly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureWrap {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+/// A way of turning a point into texture coordinates that does not depend on the shape of the object.
+///
+/// # Variants
+///
+/// * `Spherical` - Longitude and latitude around the origin, like the mapping of a sphere.
+/// * `Planar` - The x and z coordinates, repeating every unit, like the mapping of a plane.
+/// * `Cylindrical` - The angle around the y axis and the y coordinate, repeating every unit, like the sides of a cylinder.
+/// * `Cubic` - The point projected onto the cube around the origin, with each face covering the whole texture as seen from outside.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UvProjection {
+    Spherical,
+    Planar,
+    Cylindrical,
+    Cubic,
+}
+
+impl UvProjection {
+    /// Finds the texture coordinates of a point.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - The point, in pattern space.
+    ///
+    /// # Returns
+    ///
+    /// The texture coordinates `u` and `v`, both in the range [0, 1].
+    pub fn uv(&self, point: &Tuple) -> (f64, f64) {
+        match self {
+            UvProjection::Spherical => {
+                let theta = point.z.atan2(point.x);
+                let phi = (point.y / point.length_squared().sqrt()).acos();
+                let u = (theta + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
+                (u, 1.0 - phi / std::f64::consts::PI)
+            }
+            UvProjection::Planar => (point.x.rem_euclid(1.0), point.z.rem_euclid(1.0)),
+            UvProjection::Cylindrical => {
+                let theta = point.z.atan2(point.x);
+                let u = (theta + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
+                (u, point.y.rem_euclid(1.0))
+            }
+            UvProjection::Cubic => {
+                let (_, u, v) = Cube::outside_face_uv(point);
+                (u, v)
+            }
+        }
+    }
+}
 
 /// Represents a texture for use in texturing 3D objects.
+///
+/// Texture coordinates come from `projection`, or from the object the texture is on when it is `None`.
+/// They are then multiplied by `scale` and moved by `offset`, and brought back into the image according
+/// to `wrap`, so a scale of 4 with `Repeat` tiles the image 4 times. The image is shared with the other
+/// textures made from the same file, see `cached_image`.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Texture {
-    pub width: u32,
-    pub height: u32,
-    pub image: RgbaImage
+    pub image: Arc<SharedImage>,
+    pub filter: TextureFilter,
+    pub wrap: TextureWrap,
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+    pub projection: Option<UvProjection>,
 }
 
 impl Texture {
     /// Creates a new `Texture` instance from an image file.
-    pub fn new(path: &str) -> Texture {
-        let image = ImageReader::open(path).unwrap().decode().unwrap().to_rgba8();
-        let (width, height) = image.dimensions();
-        Texture { width, height, image }
+    ///
+    /// The texture is clamped at its edges and not scaled or moved. The file is read the first time the
+    /// texture is sampled, and only once for all textures made from it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the image file.
+    /// * `filter` - How the texture is sampled between pixel centers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file does not exist.
+    pub fn new(path: &str, filter: TextureFilter) -> Texture {
+        Texture::from_shared_image(cached_image(path), filter)
+    }
+
+    /// Creates a new `Texture` instance from an image in memory, clamped at its edges and not scaled or moved.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image.
+    /// * `filter` - How the texture is sampled between pixel centers.
+    pub fn from_image(image: RgbaImage, filter: TextureFilter) -> Texture {
+        Texture::from_shared_image(Arc::new(SharedImage::from_image(image)), filter)
+    }
+
+    fn from_shared_image(image: Arc<SharedImage>, filter: TextureFilter) -> Texture {
+        Texture { image, filter, wrap: TextureWrap::Clamp, scale: (1.0, 1.0), offset: (0.0, 0.0), projection: None }
+    }
+
+    /// Returns the width of the image in pixels, reading the image if it has not been read yet.
+    pub fn width(&self) -> u32 {
+        self.image.get().width()
+    }
+
+    /// Returns the height of the image in pixels, reading the image if it has not been read yet.
+    pub fn height(&self) -> u32 {
+        self.image.get().height()
+    }
+
+    /// Applies the scale, offset and wrap mode to a texture coordinate.
+    fn wrap_coordinate(&self, t: f64, scale: f64, offset: f64) -> f64 {
+        let t = t * scale + offset;
+        match self.wrap {
+            TextureWrap::Clamp => t.clamp(0.0, 1.0),
+            TextureWrap::Repeat => t.rem_euclid(1.0),
+            TextureWrap::Mirror => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 { 2.0 - t } else { t }
+            }
+        }
     }
 
     /// Returns the color of the texture at the specified coordinates.
@@ -29,38 +165,158 @@ impl Texture {
     /// # Returns
     ///
     /// Returns the color of the texture at the specified coordinates.
+    #[allow(dead_code)]
     pub fn get_color(&self, u: f64, v: f64) -> [u8; 4] {
-        // Clamp input texture coordinates to [0, 1]
-        let u = u.clamp(0.0, 1.0);
-        let v = v.clamp(0.0, 1.0);
+        nearest(self.image.get(), u, v)
+    }
 
-        // Convert u,v to pixel coordinates
-        let x = ((u * self.width as f64) as u32).min(self.width - 1);
-        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+    /// Returns the color of the texture at the specified coordinates, using the texture's filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The u-coordinate of the texture.
+    /// * `v` - The v-coordinate of the texture.
+    ///
+    /// # Returns
+    ///
+    /// Returns the color of the texture, with each channel between 0 and 1.
+    pub fn sample_texture(&self, u: f64, v: f64) -> Color {
+        let u = self.wrap_coordinate(u, self.scale.0, self.offset.0);
+        let v = self.wrap_coordinate(v, self.scale.1, self.offset.1);
+        self.sample_image(self.image.get(), u, v)
+    }
 
-        // Flip y for correct orientation, since v=0 is at the bottom
-        let y = self.height - y - 1;
+    /// Returns the average color of the texture over the footprint of a pixel.
+    ///
+    /// When the footprint covers more than one pixel of the texture, the texture is sampled from the halved
+    /// copy of the image whose pixels are closest in size to it, see `SharedImage::level`. With the
+    /// `Trilinear` filter it is sampled from the two closest copies and the two colors are blended.
+    /// Otherwise this is the same as `sample_texture`.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - The u-coordinate of the texture.
+    /// * `v` - The v-coordinate of the texture.
+    /// * `dx` - The change of the coordinates to the next pixel along the row.
+    /// * `dy` - The change of the coordinates to the next pixel down the column.
+    ///
+    /// # Returns
+    ///
+    /// Returns the color of the texture, with each channel between 0 and 1.
+    pub fn sample_texture_footprint(&self, u: f64, v: f64, dx: (f64, f64), dy: (f64, f64)) -> Color {
+        let width = self.width() as f64 * self.scale.0.abs();
+        let height = self.height() as f64 * self.scale.1.abs();
+        let texels = (dx.0 * width).hypot(dx.1 * height).max((dy.0 * width).hypot(dy.1 * height));
+        if texels.is_nan() || texels <= 1.0 {
+            return self.sample_texture(u, v);
+        }
+        let u = self.wrap_coordinate(u, self.scale.0, self.offset.0);
+        let v = self.wrap_coordinate(v, self.scale.1, self.offset.1);
+        let level = texels.log2();
+        if self.filter != TextureFilter::Trilinear {
+            return self.sample_image(self.image.level(level.round() as usize), u, v);
+        }
+        let lower = level.floor();
+        let fraction = level - lower;
+        let a = self.sample_image(self.image.level(lower as usize), u, v);
+        let b = self.sample_image(self.image.level(lower as usize + 1), u, v);
+        a.multiply(1.0 - fraction).add(&b.multiply(fraction))
+    }
 
-        // Get pixel color
-        let pixel = self.image.get_pixel(x, y);
-        [pixel[0], pixel[1], pixel[2], pixel[3]]
+    /// Samples an image, the texture's own or a smaller copy of it, with the texture's filter.
+    fn sample_image(&self, image: &RgbaImage, u: f64, v: f64) -> Color {
+        match self.filter {
+            TextureFilter::Nearest => to_color(nearest(image, u, v)),
+            TextureFilter::Bilinear | TextureFilter::Trilinear => self.sample_bilinear(image, u, v),
+        }
     }
 
-    pub fn sample_texture(&self, u: f64, v: f64) -> Color {
-        let color = self.get_color(u, v);
-        Color::new(color[0] as f64 / 255.0,
-                     color[1] as f64 / 255.0,
-                     color[2] as f64 / 255.0)
+    fn sample_bilinear(&self, image: &RgbaImage, u: f64, v: f64) -> Color {
+        // Pixel centers are half a pixel in from the pixel edges
+        let (width, height) = (image.width(), image.height());
+        let fx = u.clamp(0.0, 1.0) * width as f64 - 0.5;
+        let fy = v.clamp(0.0, 1.0) * height as f64 - 0.5;
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        // Repeated textures blend with the other side of the image at the edges
+        let index = |i: f64, size: u32| match self.wrap {
+            TextureWrap::Repeat => i.rem_euclid(size as f64) as u32,
+            _ => (i.max(0.0) as u32).min(size - 1),
+        };
+        let texel = |x: f64, y: f64| to_color(pixel(image, index(x, width), index(y, height)));
+        let bottom = texel(x0, y0).multiply(1.0 - tx).add(&texel(x0 + 1.0, y0).multiply(tx));
+        let top = texel(x0, y0 + 1.0).multiply(1.0 - tx).add(&texel(x0 + 1.0, y0 + 1.0).multiply(tx));
+        bottom.multiply(1.0 - ty).add(&top.multiply(ty))
     }
 }
 
+/// Returns the color of the pixel of an image that contains the specified texture coordinates.
+fn nearest(image: &RgbaImage, u: f64, v: f64) -> [u8; 4] {
+    // Clamp input texture coordinates to [0, 1]
+    let u = u.clamp(0.0, 1.0);
+    let v = v.clamp(0.0, 1.0);
+
+    // Convert u,v to pixel coordinates
+    let (width, height) = (image.width(), image.height());
+    let x = ((u * width as f64) as u32).min(width - 1);
+    let y = ((v * height as f64) as u32).min(height - 1);
+
+    pixel(image, x, y)
+}
+
+/// Returns the color of a pixel of an image, counting rows from the bottom of the image.
+fn pixel(image: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+    // Flip y for correct orientation, since v=0 is at the bottom
+    let y = image.height() - y - 1;
+
+    // Get pixel color
+    let pixel = image.get_pixel(x, y);
+    [pixel[0], pixel[1], pixel[2], pixel[3]]
+}
+
+/// Loads the six faces of a cube map from a single image in the horizontal cross layout.
+///
+/// The image is 4 faces wide and 3 faces high. The middle row holds the left, front, right and back
+/// faces, and the up and down faces are above and below the front face. The faces are cut out of the
+/// image right away, which is read through the texture cache:
+///
+/// ```text
+///       [up]
+/// [left][front][right][back]
+///       [down]
+/// ```
+///
+/// # Arguments
+///
+/// * `path` - The path of the image file.
+/// * `filter` - How the faces are sampled between pixel centers.
+///
+/// # Returns
+///
+/// The faces in the order of `CubeFace`: left, front, right, back, up and down.
+pub fn load_cube_cross(path: &str, filter: TextureFilter) -> [Texture; 6] {
+    let shared = cached_image(path);
+    let image = shared.get();
+    let (width, height) = (image.width() / 4, image.height() / 3);
+    let face = |column: u32, row: u32| {
+        Texture::from_image(crop_imm(image, column * width, row * height, width, height).to_image(), filter)
+    };
+    [face(0, 1), face(1, 1), face(2, 1), face(3, 1), face(1, 0), face(1, 2)]
+}
+
+fn to_color(color: [u8; 4]) -> Color {
+    Color::new(color[0] as f64 / 255.0,
+               color[1] as f64 / 255.0,
+               color[2] as f64 / 255.0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_texture() {
-        let texture = Texture::new("examples/test_texture.png");
+        let texture = Texture::new("examples/test_texture.png", TextureFilter::Nearest);
         let color = texture.get_color(0.0, 0.0);
         assert_eq!(color, [0, 0, 0, 255]);
         let color = texture.get_color(1.0, 1.0);
@@ -80,4 +336,66 @@ mod tests {
         let color = texture.get_color(0.8, 0.2 - crate::EPSILON);
         assert_eq!(color, [19, 73, 151, 255]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bilinear_filtering_blends_neighboring_pixels() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+        let mut texture = Texture::from_image(image, TextureFilter::Bilinear);
+        assert_eq!(texture.sample_texture(0.25, 0.5), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(texture.sample_texture(0.5, 0.5), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(texture.sample_texture(0.75, 0.5), Color::new(1.0, 1.0, 1.0));
+        texture.filter = TextureFilter::Nearest;
+        assert_eq!(texture.sample_texture(0.5, 0.5), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn distant_textures_are_sampled_from_halved_images() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 1, image::Rgba([255, 255, 255, 255]));
+        image.put_pixel(1, 1, image::Rgba([0, 0, 0, 255]));
+        let mut texture = Texture::from_image(image, TextureFilter::Nearest);
+        // A footprint smaller than a pixel samples the image itself
+        assert_eq!(texture.sample_texture_footprint(0.25, 0.25, (0.1, 0.0), (0.0, 0.1)), texture.sample_texture(0.25, 0.25));
+        // A footprint as wide as the image samples its single pixel average
+        let gray = 128.0 / 255.0;
+        assert_eq!(texture.sample_texture_footprint(0.25, 0.25, (1.0, 0.0), (0.0, 1.0)), Color::new(gray, gray, gray));
+        // In between, the closest copy is sampled, or with trilinear filtering the image and its average are blended
+        assert_eq!(texture.sample_texture_footprint(0.25, 0.25, (0.75, 0.0), (0.0, 0.75)), Color::new(gray, gray, gray));
+        texture.filter = TextureFilter::Trilinear;
+        let color = texture.sample_texture_footprint(0.25, 0.25, (0.75, 0.0), (0.0, 0.75));
+        assert!(color.r > gray && color.r < 1.0);
+    }
+
+    #[test]
+    fn wrapping_texture_coordinates() {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+        let mut texture = Texture::from_image(image, TextureFilter::Nearest);
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        assert_eq!(texture.sample_texture(1.25, 0.5), white);
+        texture.wrap = TextureWrap::Repeat;
+        assert_eq!(texture.sample_texture(1.25, 0.5), black);
+        texture.wrap = TextureWrap::Mirror;
+        assert_eq!(texture.sample_texture(1.25, 0.5), white);
+        texture.scale = (2.0, 1.0);
+        texture.offset = (0.5, 0.0);
+        texture.wrap = TextureWrap::Repeat;
+        assert_eq!(texture.sample_texture(0.5, 0.5), white);
+        assert_eq!(texture.sample_texture(0.125, 0.5), white);
+    }
+
+    #[test]
+    fn projecting_points_to_texture_coordinates() {
+        let p = Tuple::point(0.0, 0.0, -1.0);
+        assert_eq!(UvProjection::Spherical.uv(&p), (0.25, 0.5));
+        assert_eq!(UvProjection::Cylindrical.uv(&Tuple::point(0.0, 1.75, -1.0)), (0.25, 0.75));
+        assert_eq!(UvProjection::Planar.uv(&Tuple::point(-0.25, 3.0, 1.5)), (0.75, 0.5));
+        assert_eq!(UvProjection::Cubic.uv(&Tuple::point(0.5, 0.5, -1.0)), (0.75, 0.75));
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use image::{ImageReader, RgbaImage};
+use lazy_static::lazy_static;
+
+/// An image used by textures, shared by every texture made from the same file.
+///
+/// Images read from a file are only decoded the first time a texture needs them, so textures on
+/// objects that are never seen cost no memory.
+///
+/// # Fields
+///
+/// * `path` - The file the image is read from, or `None` for an image created in memory.
+/// * `image` - The decoded image, once it has been needed.
+/// * `levels` - Copies of the image, each half the size of the one before, once they have been needed.
+#[derive(Debug)]
+pub struct SharedImage {
+    path: Option<String>,
+    image: OnceLock<RgbaImage>,
+    levels: OnceLock<Vec<RgbaImage>>,
+}
+
+impl SharedImage {
+    /// Creates a shared image that is read from a file when it is first needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the image file.
+    fn from_file(path: &str) -> SharedImage {
+        SharedImage { path: Some(path.to_string()), image: OnceLock::new(), levels: OnceLock::new() }
+    }
+
+    /// Creates a shared image from an image in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image.
+    pub fn from_image(image: RgbaImage) -> SharedImage {
+        SharedImage { path: None, image: OnceLock::from(image), levels: OnceLock::new() }
+    }
+
+    /// Returns the image, decoding it from its file if this is the first time it is needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be read or is not an image.
+    pub fn get(&self) -> &RgbaImage {
+        self.image.get_or_init(|| {
+            let path = self.path.as_deref().unwrap_or_default();
+            ImageReader::open(path)
+                .unwrap_or_else(|e| panic!("cannot open texture {}: {}", path, e))
+                .decode()
+                .unwrap_or_else(|e| panic!("cannot decode texture {}: {}", path, e))
+                .to_rgba8()
+        })
+    }
+
+    /// Returns a copy of the image that is halved in size `level` times, for textures seen from far away.
+    ///
+    /// Level 0 is the image itself. Each pixel of a level is the average of four pixels of the level
+    /// before, down to a single pixel; higher levels give that last level. The copies are made the first
+    /// time one is needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The number of times the image is halved.
+    pub fn level(&self, level: usize) -> &RgbaImage {
+        if level == 0 {
+            return self.get();
+        }
+        let levels = self.levels.get_or_init(|| {
+            let mut levels: Vec<RgbaImage> = Vec::new();
+            loop {
+                let previous = levels.last().unwrap_or(self.get());
+                if previous.width() == 1 && previous.height() == 1 {
+                    return levels;
+                }
+                levels.push(halve(previous));
+            }
+        });
+        if levels.is_empty() {
+            return self.get();
+        }
+        &levels[(level - 1).min(levels.len() - 1)]
+    }
+
+    /// Returns the file the image is read from, or `None` for an image created in memory.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Returns the number of bytes the decoded image and its smaller copies take up, or 0 if it has not been
+    /// decoded yet.
+    pub fn memory_usage(&self) -> usize {
+        let levels = self.levels.get().map_or(0, |levels| levels.iter().map(|level| level.as_raw().len()).sum());
+        self.image.get().map_or(0, |image| image.as_raw().len()) + levels
+    }
+
+    /// Returns the number of bytes the image takes up once it is decoded, reading only the header of
+    /// the file if it has not been decoded yet.
+    pub fn decoded_size(&self) -> usize {
+        match (self.image.get(), &self.path) {
+            (Some(image), _) => image.as_raw().len(),
+            (None, Some(path)) => image::image_dimensions(path).map_or(0, |(width, height)| width as usize * height as usize * 4),
+            (None, None) => 0,
+        }
+    }
+}
+
+impl PartialEq for SharedImage {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.path, &other.path) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => self.get() == other.get(),
+            _ => false,
+        }
+    }
+}
+
+/// Shrinks an image to half its size, rounding down but keeping at least one pixel, by averaging blocks of
+/// four pixels.
+fn halve(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = ((image.width() / 2).max(1), (image.height() / 2).max(1));
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0u32; 4];
+        for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel((x * 2 + sx).min(image.width() - 1), (y * 2 + sy).min(image.height() - 1));
+            for (total, channel) in sum.iter_mut().zip(pixel.0) {
+                *total += channel as u32;
+            }
+        }
+        image::Rgba(sum.map(|total| ((total + 2) / 4) as u8))
+    })
+}
+
+/// A cached image and the modification time of its file when it was cached.
+struct CacheEntry {
+    modified: Option<SystemTime>,
+    image: Arc<SharedImage>,
+}
+
+// All textures read from files, by canonical path, so each file is only loaded once.
+lazy_static! {
+    static ref TEXTURE_CACHE: Mutex<HashMap<String, CacheEntry>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the shared image for a file, adding it to the cache if it is not there yet.
+///
+/// The header of the file is read every time, so files that are not images are reported while the scene is
+/// loaded, but the image is not decoded until a texture first samples it. Different paths to the same file share
+/// an image. A file that has changed since it was cached gets a new image, so re-rendered scenes see
+/// the change.
+///
+/// # Arguments
+///
+/// * `path` - The path of the image file.
+///
+/// # Returns
+///
+/// The image shared by all textures made from the file.
+///
+/// # Panics
+///
+/// Panics if the file does not exist or its header cannot be read as an image.
+pub fn cached_image(path: &str) -> Arc<SharedImage> {
+    let metadata = fs::metadata(path).unwrap_or_else(|e| panic!("cannot open texture {}: {}", path, e));
+    let modified = metadata.modified().ok();
+    // Checked before the cache is locked, so that a bad file does not poison the lock for other textures
+    if let Err(e) = image::image_dimensions(path) {
+        panic!("cannot decode texture {}: {}", path, e);
+    }
+    let key = fs::canonicalize(path).map_or_else(|_| path.to_string(), |p| p.display().to_string());
+    let mut cache = TEXTURE_CACHE.lock().unwrap();
+    match cache.get(&key) {
+        Some(entry) if entry.modified == modified => entry.image.clone(),
+        _ => {
+            let image = Arc::new(SharedImage::from_file(path));
+            cache.insert(key, CacheEntry { modified, image: image.clone() });
+            image
+        }
+    }
+}
+
+/// Removes the images no texture uses any more from the cache, freeing their memory.
+pub fn remove_unused_images() {
+    TEXTURE_CACHE.lock().unwrap().retain(|_, entry| Arc::strong_count(&entry.image) > 1);
+}
+
+/// How much memory the cached textures use.
+///
+/// # Fields
+///
+/// * `files` - The number of image files in the cache.
+/// * `loaded` - How many of them have been decoded.
+/// * `bytes` - The memory taken up by the decoded images.
+/// * `bytes_when_loaded` - The memory all of the images would take up once decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TextureMemory {
+    pub files: usize,
+    pub loaded: usize,
+    pub bytes: usize,
+    pub bytes_when_loaded: usize,
+}
+
+/// Reports how much memory the cached textures use.
+///
+/// # Returns
+///
+/// The number of cached files, how many of them are decoded, the memory the decoded images take up, and
+/// the memory all of them would take up.
+pub fn texture_memory() -> TextureMemory {
+    let cache = TEXTURE_CACHE.lock().unwrap();
+    cache.values().fold(TextureMemory::default(), |memory, entry| {
+        let bytes = entry.image.memory_usage();
+        TextureMemory {
+            files: memory.files + 1,
+            loaded: memory.loaded + usize::from(bytes > 0),
+            bytes: memory.bytes + bytes,
+            bytes_when_loaded: memory.bytes_when_loaded + entry.image.decoded_size(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn images_are_shared_and_loaded_when_first_needed() {
+        let a = cached_image("examples/test_texture.png");
+        let b = cached_image("./examples/../examples/test_texture.png");
+        assert!(Arc::ptr_eq(&a, &b));
+        let size = a.decoded_size();
+        let (width, height) = a.get().dimensions();
+        assert_eq!(size, (width * height * 4) as usize);
+        assert_eq!(a.memory_usage(), (width * height * 4) as usize);
+        assert!(texture_memory().bytes >= a.memory_usage());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot decode texture")]
+    fn corrupt_images_are_reported_when_they_are_added() {
+        let dir = std::env::temp_dir().join("rray_corrupt_texture_test");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("corrupt.png");
+        fs::write(&file, b"\x89PNG\r\n\x1a\nnot really an image").unwrap();
+        cached_image(file.to_str().unwrap());
+    }
+
+    #[test]
+    fn images_in_memory_compare_by_content() {
+        let a = SharedImage::from_image(RgbaImage::new(2, 1));
+        let b = SharedImage::from_image(RgbaImage::new(2, 1));
+        assert_eq!(a, b);
+        assert_eq!(a.memory_usage(), 8);
+    }
+}
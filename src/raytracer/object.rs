@@ -9,13 +9,19 @@ pub(crate) mod triangle;
 pub(crate) mod smooth_triangle;
 pub(crate) mod csg;
 pub(crate) mod torus;
+pub(crate) mod rounded_box;
+pub(crate) mod frozen;
 
 use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 use crate::EPSILON;
-use crate::matrix::Matrix;
+use crate::color::Color;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
+use crate::raytracer::object::csg::CsgOperation;
 use crate::raytracer::object::db::get_object;
+use crate::raytracer::object::frozen::BakedTransforms;
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -30,7 +36,15 @@ use crate::tuple::Tuple;
 /// # Methods
 ///
 /// * `intersect` - Calculates the intersections of a ray with the object, returning a list of intersection points.
+/// * `intersect_into` - Calculates the intersections of a ray with the object, adding them to an existing list.
+/// * `local_intersect_into` - Calculates the intersections of a ray in object space, adding them to an existing list.
+/// * `occludes` - Checks if a ray hits an object that casts shadows within its range, stopping at the first such hit.
+///   The scratch list is used to collect intersections along the way and is left as it was.
+/// * `local_occludes` - Checks the same for a ray in object space.
 /// * `normal_at` - Computes the normal vector at a given point on the object's surface, useful for shading calculations.
+/// * `face_normal_at` - The normal of the surface itself, which decides which side of it a ray hits. It is the same
+///   as `normal_at` except for smooth triangles, whose shading normal is blended from the normals at the corners.
+/// * `local_face_normal_at` - The same in object space.
 /// * `get_transform` - Retrieves the object's transformation matrix.
 /// * `get_material` - Retrieves the material properties of the object.
 /// * `set_transform` - Sets the object's transformation matrix.
@@ -41,13 +55,52 @@ use crate::tuple::Tuple;
 /// * `set_parent_id` - Sets the identifier of the object's parent in a scene graph.
 /// * `get_aabb` - Computes the axis-aligned bounding box (AABB) of the object for spatial partitioning optimizations.
 /// * `includes` - Checks if the object includes another object by ID, useful for CSG operations and scene graph management.
+/// * `uv_mapping` - Maps a point on the object's surface to texture coordinates.
+/// * `local_tangents_at` - The directions in which the texture coordinates `u` and `v` grow at a point, used for normal mapping.
+/// * `vertex_colors` - For triangles, the colors at the three corners, blended across the triangle and multiplied into
+///   the surface color.
+/// * `edge_distance` - For triangles, the barycentric distance from a hit to the nearest edge, used for wireframe rendering.
+/// * `geometry` - The shape of the object in a form other renderers can use, like the GPU backend, if it has one.
+/// * `child_ids` - The ids of the objects inside a group or CSG object.
+/// * `name` - The name of a group, if it has one.
+/// * `baked_transforms` - The transformations baked when the scene was frozen, if it has been.
+/// * `casts_transparent_shadows` - Whether the object, or any object inside it, casts shadows that light can get through.
+/// * `divide` - Splits the children of groups, also those inside it, into sub-groups with tighter bounds, see `Group`.
 pub trait Object: Sync + Send {
+    #[allow(dead_code)]
     fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
+        let mut xs = vec![];
+        self.intersect_into(ray, &mut xs);
+        xs
+    }
+
+    fn intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        let trans_ray = ray.transform(&self.get_transform().inverse());
+        self.local_intersect_into(&trans_ray, xs)
+    }
+
+    #[allow(dead_code)]
+    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+        let mut xs = vec![];
+        self.local_intersect_into(ray, &mut xs);
+        xs
+    }
+
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>);
+
+    fn occludes(&self, ray: &Ray, scratch: &mut Vec<Intersection>) -> bool {
         let trans_ray = ray.transform(&self.get_transform().inverse());
-        self.local_intersect(&trans_ray)
+        self.local_occludes(&trans_ray, scratch)
     }
 
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection>;
+    fn local_occludes(&self, ray: &Ray, scratch: &mut Vec<Intersection>) -> bool {
+        let start = scratch.len();
+        self.local_intersect_into(ray, scratch);
+        let occluded = scratch[start..].iter()
+            .any(|x| get_object(x.object).get_material().cast_shadows);
+        scratch.truncate(start);
+        occluded
+    }
 
     fn normal_at(&self, world_point: &Tuple, hit: &Intersection) -> Tuple {
         let local_point = world_to_object(self.get_id(), world_point);
@@ -57,9 +110,19 @@ pub trait Object: Sync + Send {
 
     fn local_normal_at(&self, local_point: &Tuple, _hit: &Intersection) -> Tuple;
 
-    fn get_transform(&self) -> &Matrix;
+    fn face_normal_at(&self, world_point: &Tuple, hit: &Intersection) -> Tuple {
+        let local_point = world_to_object(self.get_id(), world_point);
+        let local_normal = self.local_face_normal_at(&local_point, hit);
+        normal_to_world(self.get_id(), &local_normal)
+    }
+
+    fn local_face_normal_at(&self, local_point: &Tuple, hit: &Intersection) -> Tuple {
+        self.local_normal_at(local_point, hit)
+    }
+
+    fn get_transform(&self) -> &Matrix4;
     fn get_material(&self) -> &Material;
-    fn set_transform(&mut self, transform: Matrix);
+    fn set_transform(&mut self, transform: Matrix4);
     fn set_material(&mut self, material: Material);
     fn debug_string(&self) -> String;
     fn get_id(&self) -> usize;
@@ -70,6 +133,84 @@ pub trait Object: Sync + Send {
     fn uv_mapping(&self, _point: &Tuple) -> (f64, f64) {
         (0.0, 0.0)
     }
+    fn local_tangents_at(&self, local_point: &Tuple, hit: &Intersection) -> (Tuple, Tuple) {
+        // Estimate the gradients of u and v in the tangent plane from nearby points
+        let normal = self.local_normal_at(local_point, hit).normalize();
+        let helper = if normal.x.abs() > 0.9 { Tuple::vector(0.0, 1.0, 0.0) } else { Tuple::vector(1.0, 0.0, 0.0) };
+        let t1 = helper.cross(&normal).normalize();
+        let t2 = normal.cross(&t1);
+        let h = 1e-4;
+        let (u, v) = self.uv_mapping(local_point);
+        let (u1, v1) = self.uv_mapping(&local_point.add(&t1.multiply(h)));
+        let (u2, v2) = self.uv_mapping(&local_point.add(&t2.multiply(h)));
+        // Texture coordinates wrap around, so a jump across the seam is taken the short way
+        let delta = |d: f64| d - d.round();
+        let dpdu = t1.multiply(delta(u1 - u)).add(&t2.multiply(delta(u2 - u)));
+        let dpdv = t1.multiply(delta(v1 - v)).add(&t2.multiply(delta(v2 - v)));
+        (dpdu, dpdv)
+    }
+    fn vertex_colors(&self) -> Option<&[Color; 3]> {
+        None
+    }
+    /// The materials of the slots of the mesh the object belongs to, shared with its other triangles, or `None`
+    /// for objects that keep a material of their own.
+    fn material_slots(&self) -> Option<&Arc<[Material]>> {
+        None
+    }
+    /// Replaces the shared materials of the slots of a mesh, keeping the slot of the object.
+    fn set_material_slots(&mut self, _materials: Arc<[Material]>) {
+    }
+    fn edge_distance(&self, _hit: &Intersection) -> Option<f64> {
+        None
+    }
+    #[allow(dead_code)]
+    fn geometry(&self) -> Option<Geometry> {
+        None
+    }
+    fn child_ids(&self) -> Vec<usize> {
+        vec![]
+    }
+    fn name(&self) -> Option<&str> {
+        None
+    }
+    fn baked_transforms(&self) -> Option<&BakedTransforms> {
+        None
+    }
+    fn casts_transparent_shadows(&self) -> bool {
+        let material = self.get_material();
+        material.cast_shadows && material.may_be_transparent()
+    }
+    fn divide(&mut self, _threshold: usize) {
+    }
+}
+
+/// The shape of an object, described for renderers that do not call the `Object` methods, like the GPU backend.
+///
+/// # Variants
+///
+/// * `Sphere` - The unit sphere, placed by the object's transformation.
+/// * `Plane` - The xz plane, placed by the object's transformation.
+/// * `Cube` - The cube from -1 to 1 on every axis, placed by the object's transformation.
+/// * `Triangle` - The corners and the normals at the corners, in object space.
+/// * `Group` - The ids of the children, placed by the group's transformation.
+/// * `Cylinder` - The cylinder of radius 1 around the y axis, between `minimum` and `maximum`, capped when `closed`.
+/// * `Cone` - The double cone around the y axis, between `minimum` and `maximum`, capped when `closed`.
+/// * `Torus` - The torus around the z axis with a major radius of 1 and the given minor radius.
+/// * `RoundedBox` - The cube from -1 to 1 on every axis, with its edges and corners rounded by the given radius.
+/// * `Csg` - The operation, and the ids of the left and right objects, placed by the CSG object's transformation.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum Geometry {
+    Sphere,
+    Plane { width: f64, depth: f64 },
+    Cube,
+    Triangle([Tuple; 3], [Tuple; 3]),
+    Group(Vec<usize>),
+    Cylinder { minimum: f64, maximum: f64, closed: bool },
+    Cone { minimum: f64, maximum: f64, closed: bool },
+    Torus(f64),
+    RoundedBox(f64),
+    Csg(CsgOperation, usize, usize),
 }
 
 impl PartialEq for dyn Object {
@@ -90,6 +231,7 @@ impl Debug for dyn Object {
 /// local coordinate system. It accounts for the object's transformations and those of its parent
 /// objects in the scene graph hierarchy, if any. This is particularly useful for ray tracing calculations
 /// where interactions with objects are often computed in the object's local space for simplicity.
+/// Once the scene is frozen, the baked transformation of the object is used instead of walking up its parents.
 ///
 /// # Arguments
 ///
@@ -101,6 +243,9 @@ impl Debug for dyn Object {
 /// Returns the transformed point in the object's local coordinate system as a `Tuple`.
 pub fn world_to_object(object_id: usize, world_point: &Tuple) -> Tuple {
     let object = get_object(object_id);
+    if let Some(baked) = object.baked_transforms() {
+        return baked.world_to_object.multiply_tuple(world_point);
+    }
     let mut point = world_point.clone();
     if let Some(parent_id) = object.get_parent_id() {
         point = world_to_object(parent_id, &point);
@@ -128,6 +273,11 @@ pub fn world_to_object(object_id: usize, world_point: &Tuple) -> Tuple {
 /// Returns the transformed normal vector in the world coordinate system as a `Tuple`.
 pub fn normal_to_world(object_id: usize, object_normal: &Tuple) -> Tuple {
     let object = get_object(object_id);
+    if let Some(baked) = object.baked_transforms() {
+        let mut normal = baked.normal_to_world.multiply_tuple(object_normal);
+        normal.w = 0.0;
+        return normal.normalize();
+    }
     let mut normal = object.get_transform().inverse().transpose().multiply_tuple(&object_normal);
     normal.w = 0.0;
     normal = normal.normalize();
@@ -137,6 +287,34 @@ pub fn normal_to_world(object_id: usize, object_normal: &Tuple) -> Tuple {
     normal
 }
 
+/// Transforms a direction vector, like a surface tangent, from object space to world space.
+///
+/// Unlike normals, tangents follow the surface when it is stretched, so they are transformed by the
+/// object's transformation itself, and then by the transformations of its parents. The result is not normalized.
+///
+/// # Arguments
+///
+/// * `object_id` - The unique identifier of the object whose vector is being transformed.
+/// * `object_vector` - A reference to the `Tuple` representing the vector in the object's local space.
+///
+/// # Returns
+///
+/// Returns the transformed vector in the world coordinate system as a `Tuple`.
+pub fn vector_to_world(object_id: usize, object_vector: &Tuple) -> Tuple {
+    let object = get_object(object_id);
+    if let Some(baked) = object.baked_transforms() {
+        let mut vector = baked.object_to_world.multiply_tuple(object_vector);
+        vector.w = 0.0;
+        return vector;
+    }
+    let mut vector = object.get_transform().multiply_tuple(object_vector);
+    vector.w = 0.0;
+    if let Some(parent_id) = object.get_parent_id() {
+        vector = vector_to_world(parent_id, &vector);
+    }
+    vector
+}
+
 /// Represents an Axis-Aligned Bounding Box (AABB) in a ray tracing scene.
 ///
 /// An AABB is a simple way to describe a volume in 3D space and is used for various optimizations,
@@ -214,7 +392,8 @@ impl AABB {
         let tmin = tmin_x.max(tmin_y.max(tmin_z));
         let tmax = tmax_x.min(tmax_y.min(tmax_z));
 
-        tmin <= tmax
+        // The box also has to overlap the range of distances at which the ray's hits count
+        tmin <= tmax && tmin <= r.t_max && tmax >= r.t_min
     }
 
     fn adjust_min_max(&mut self, x: f64, y:f64, z: f64) {
@@ -256,7 +435,7 @@ impl AABB {
     /// # Returns
     ///
     /// Returns `true` if the ray intersects with the AABB, otherwise `false`.
-    pub fn apply_transform(&self, transform: &Matrix) -> AABB {
+    pub fn apply_transform(&self, transform: &Matrix4) -> AABB {
           let corners = [
             Tuple::point(self.min.x, self.min.y, self.min.z),
             Tuple::point(self.min.x, self.min.y, self.max.z),
@@ -282,7 +461,7 @@ impl AABB {
 mod test {
     use std::sync::Arc;
     use crate::color::Color;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::intersection::Intersection;
     use crate::raytracer::light::Light;
     use crate::raytracer::object::db::get_object;
@@ -335,7 +514,7 @@ mod test {
     fn test_transform() {
         let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
         let mut s = Sphere::new();
-        s.transform = Matrix::scale(2.0, 2.0, 2.0);
+        s.transform = Matrix4::scale(2.0, 2.0, 2.0);
         let s = Arc::new(s);
         let s: Arc<dyn Object> = s;
         let xs = s.intersect(&r);
@@ -345,7 +524,7 @@ mod test {
 
         let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
         let mut s = Sphere::new();
-        s.transform = Matrix::translate(5.0, 0.0, 0.0);
+        s.transform = Matrix4::translate(5.0, 0.0, 0.0);
         let s = Arc::new(s);
         let s: Arc<dyn Object> = s;
         let xs = s.intersect(&r);
@@ -1,11 +1,12 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 use crate::EPSILON;
 use crate::raytracer::object::db::get_next_id;
+use crate::raytracer::object::cylinder::side_v;
 
 /// Represents a cone in a 3D ray tracing context.
 ///
@@ -28,7 +29,7 @@ pub struct Cone {
     pub minimum: f64,
     pub maximum: f64,
     pub closed: bool,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
 }
 
@@ -49,7 +50,7 @@ impl Cone {
         Cone {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
             minimum,
             maximum,
@@ -66,33 +67,30 @@ impl Cone {
         (x * x + z * z) <= y * y
     }
 
-    fn intersect_caps(&self, ray: &Ray) -> Vec<Intersection> {
+    fn intersect_caps(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let minimum = self.minimum;
         let maximum = self.maximum;
         let closed = self.closed;
-        let mut xs: Vec<Intersection> = vec![];
 
         // Caps only matter if the cone is closed, and might be
         // intersected by the ray
         if !closed || ray.direction.y.abs() < EPSILON {
-            return xs;
+            return;
         }
 
         // Check for an intersection with the lower end cap by intersecting
         // the ray with the plane at y = minimum
         let t = (minimum - ray.origin.y) / ray.direction.y;
-        if Cone::check_cap(ray, t) {
+        if ray.in_range(t) && Cone::check_cap(ray, t) {
             xs.push(Intersection::new(t, self.id, 0.0, 0.0));
         }
 
         // Check for an intersection with the upper end cap by intersecting
         // the ray with the plane at y = maximum
         let t = (maximum - ray.origin.y) / ray.direction.y;
-        if Cone::check_cap(ray, t) {
+        if ray.in_range(t) && Cone::check_cap(ray, t) {
             xs.push(Intersection::new(t, self.id, 0.0, 0.0));
         }
-
-        xs
     }
 }
 
@@ -104,7 +102,7 @@ impl Cone {
 ///
 /// # Methods
 ///
-/// - `local_intersect`: Calculates the intersections of a ray with the cone, considering the cone's transformation.
+/// - `local_intersect_into`: Calculates the intersections of a ray with the cone, considering the cone's transformation.
 /// - `local_normal_at`: Computes the normal vector at a given point on the cone, taking into account its transformation.
 /// - `get_transform`: Returns the transformation matrix of the cone.
 /// - `get_material`: Returns the material of the cone.
@@ -117,16 +115,15 @@ impl Cone {
 /// - `get_aabb`: Calculates the axis-aligned bounding box (AABB) of the cone.
 /// - `includes`: Checks if the given object identifier matches the cone's identifier.
 impl Object for Cone {
-    fn local_intersect(&self, trans_ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<Intersection> = vec![];
+    fn local_intersect_into(&self, trans_ray: &Ray, xs: &mut Vec<Intersection>) {
         let minimum = self.minimum;
         let maximum = self.maximum;
         let a = trans_ray.direction.x * trans_ray.direction.x - trans_ray.direction.y * trans_ray.direction.y + trans_ray.direction.z * trans_ray.direction.z;
         let b = 2.0 * trans_ray.origin.x * trans_ray.direction.x - 2.0 * trans_ray.origin.y * trans_ray.direction.y + 2.0 * trans_ray.origin.z * trans_ray.direction.z;
 
         if a.abs() < EPSILON && b.abs() < EPSILON {
-            self.intersect_caps(&trans_ray).iter().for_each(|i| xs.push(i.clone()));
-            return xs;
+            self.intersect_caps(trans_ray, xs);
+            return;
         }
 
         let c = trans_ray.origin.x * trans_ray.origin.x - trans_ray.origin.y * trans_ray.origin.y + trans_ray.origin.z * trans_ray.origin.z;
@@ -135,14 +132,16 @@ impl Object for Cone {
             let t = -c / (2.0 * b);
             let y = trans_ray.origin.y + t * trans_ray.direction.y;
             if minimum < y && y < maximum {
-                xs.push(Intersection::new(t, self.id, 0.0, 0.0));
-                return xs;
+                if trans_ray.in_range(t) {
+                    xs.push(Intersection::new(t, self.id, 0.0, 0.0));
+                }
+                return;
             }
         }
 
         let discriminant = b * b - 4.0 * a * c;
         if discriminant < 0.0 {
-            return vec![];
+            return;
         }
 
         let mut t0 = (-b - discriminant.sqrt()) / (2.0 * a);
@@ -152,17 +151,15 @@ impl Object for Cone {
         }
 
         let y0 = trans_ray.origin.y + t0 * trans_ray.direction.y;
-        if minimum < y0 && y0 < maximum {
+        if minimum < y0 && y0 < maximum && trans_ray.in_range(t0) {
             xs.push(Intersection::new(t0, self.id, 0.0, 0.0));
         }
         let y1 = trans_ray.origin.y + t1 * trans_ray.direction.y;
-        if minimum < y1 && y1 < maximum {
+        if minimum < y1 && y1 < maximum && trans_ray.in_range(t1) {
             xs.push(Intersection::new(t1, self.id, 0.0, 0.0));
         }
 
-        self.intersect_caps(&trans_ray).iter().for_each(|i| xs.push(i.clone()));
-
-        xs
+        self.intersect_caps(trans_ray, xs);
     }
 
     fn local_normal_at(&self, local_point: &Tuple, _hit: &Intersection) -> Tuple {
@@ -186,7 +183,11 @@ impl Object for Cone {
         }
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Cone { minimum: self.minimum, maximum: self.maximum, closed: self.closed })
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -194,7 +195,7 @@ impl Object for Cone {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -230,27 +231,34 @@ impl Object for Cone {
     }
 
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
-        let y_min_dist = (point.y - self.minimum).abs();
-        let y_max_dist = (point.y - self.maximum).abs();
-
-        if self.closed && (y_min_dist <= EPSILON || y_max_dist <= EPSILON) {
-            // Point on the cap
-            let radius = point.y.abs();
+        let radius = point.y.abs();
+        let dist = (point.x * point.x + point.z * point.z).sqrt();
+        let on_cap = point.y >= self.maximum - EPSILON || point.y <= self.minimum + EPSILON;
+        if self.closed && on_cap && dist < radius - EPSILON {
+            // The disk of the cap fills the texture, whatever its radius
             let u = (point.x / radius + 1.0) / 2.0;
             let v = (point.z / radius + 1.0) / 2.0;
             (u, v)
         } else {
-            // Calculate the angle theta around the y-axis
-            let theta = (point.z.atan2(point.x) + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
-
-            // Point on the conical surface
-            let height_range = self.maximum - self.minimum;
-            let normalized_y = (point.y - self.minimum) / height_range;
-
-            // Normalize u for better texture mapping
-            let u = normalized_y;
-
-            (u, theta)
+            let theta = point.z.atan2(point.x);
+            let u = (theta + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
+            (u, side_v(point.y, self.minimum, self.maximum))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raytracer::object::cone::Cone;
+    use crate::raytracer::object::Object;
+    use crate::tuple::Tuple;
+
+    #[test]
+    fn uv_mapping_of_a_closed_cone() {
+        let c = Cone::new(-2.0, 0.0, true);
+        assert_eq!(c.uv_mapping(&Tuple::point(0.0, -1.0, -1.0)), (0.25, 0.5));
+        assert_eq!(c.uv_mapping(&Tuple::point(2.0, -2.0, 0.0)), (0.5, 0.0));
+        assert_eq!(c.uv_mapping(&Tuple::point(1.0, -2.0, 1.0)), (0.75, 0.75));
+        assert_eq!(c.uv_mapping(&Tuple::point(0.0, 0.0, 0.0)), (0.5, 1.0));
+    }
+}
@@ -1,10 +1,10 @@
 use std::str::FromStr;
 use std::sync::{Arc, RwLock, RwLockReadGuard};
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::db::{add_object, get_next_id, get_object};
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::db::{add_object, get_next_id, get_object, update_object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -18,6 +18,7 @@ use crate::tuple::Tuple;
 /// - `Union`: Combines two objects into a single object that encompasses the volume of both.
 /// - `Intersection`: Creates a new object from the overlapping volume of two objects.
 /// - `Difference`: Subtracts the volume of the second object from the first, creating a new object.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CsgOperation {
     Union,
     Intersection,
@@ -82,7 +83,7 @@ impl FromStr for CsgOperation {
 pub struct Csg {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub operation: CsgOperation,
     pub left: usize,
     pub right: usize,
@@ -111,11 +112,12 @@ pub struct Csg {
 /// - `get_aabb_cache`: Retrieves a read lock on the AABB cache.
 /// - `set_aabb_cache`: Updates the AABB cache with a new value.
 /// - `set_left`: Sets the left child object and updates its parent ID to this CSG node's ID.
-/// - `local_intersect`: Performs intersection tests with the child objects, filtering the results based on the CSG operation.
+/// - `local_intersect_into`: Performs intersection tests with the child objects, filtering the results based on the CSG operation.
 /// - `local_normal_at`: CSG nodes do not have a normal vector; calling this method will panic.
 /// - `set_right`: Sets the right child object and updates its parent ID to this CSG node's ID.
 /// - `intersection_allowed`: Determines if an intersection is allowed based on the CSG operation and the hit statuses of child objects.
 /// - `filter_intersections`: Filters a list of intersections, returning only those that are allowed by the CSG operation.
+/// - `filter_intersections_from`: Filters the end of a list of intersections in place, as `filter_intersections` does.
 ///
 /// # Implementations
 ///
@@ -127,7 +129,7 @@ impl Csg {
         Csg {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             operation,
             left: usize::MAX,
             right: usize::MAX,
@@ -174,16 +176,24 @@ impl Csg {
         }
     }
 
-    pub fn filter_intersections(&self, xs: &Vec<Intersection>) -> Vec<Intersection> {
+    #[allow(dead_code)]
+    pub fn filter_intersections(&self, xs: &[Intersection]) -> Vec<Intersection> {
+        let mut result = xs.to_vec();
+        self.filter_intersections_from(&mut result, 0);
+        result
+    }
+
+    pub fn filter_intersections_from(&self, xs: &mut Vec<Intersection>, start: usize) {
+        let left = get_object(self.left);
         let mut inl = false;
         let mut inr = false;
-        let mut result = Vec::new();
+        let mut kept = start;
 
-        for i in xs {
-            let left = get_object(self.left);
-            let lhit = left.includes(i.object);
+        for i in start..xs.len() {
+            let lhit = left.includes(xs[i].object);
             if self.intersection_allowed(lhit, inl, inr) {
-                result.push(i.clone());
+                xs.swap(kept, i);
+                kept += 1;
             }
             if lhit {
                 inl = !inl;
@@ -191,7 +201,7 @@ impl Csg {
                 inr = !inr;
             }
         }
-        result
+        xs.truncate(kept);
     }
 }
 
@@ -206,7 +216,7 @@ impl Csg {
 /// # Methods
 ///
 /// - `intersect`: Overrides the trait method to perform intersection tests by transforming the ray into
-///   the CSG node's local space, then delegating to `local_intersect` which considers the CSG operation.
+///   the CSG node's local space, then delegating to `local_intersect_into` which considers the CSG operation.
 /// - `normal_at`: Calculates the normal at a given point on the surface of the CSG node. This method
 ///   panics because CSG nodes themselves do not have a normal vector; normals are derived from their child objects.
 /// - `get_transform`: Returns a reference to the transformation matrix of the CSG node, allowing it to be
@@ -222,21 +232,36 @@ impl Csg {
 /// - `get_aabb`: Calculates and returns the axis-aligned bounding box (AABB) of the CSG node, considering
 ///   the bounds of its child objects and its own transformation.
 /// - `includes`: Checks if the given object identifier matches either of the CSG node's child objects.
+/// - `child_ids`: Returns the identifiers of the child objects that have been set.
+/// - `casts_transparent_shadows`: Checks the child objects, since CSG nodes do not have a material of their own.
 impl Object for Csg {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
-        let left = get_object(self.left);
-        let right = get_object(self.right);
-        let mut xs = left.intersect(ray);
-        xs.append(&mut right.intersect(ray));
-        xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
-        self.filter_intersections(&xs)
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        // Telling inside from outside takes all the hits of the children, so only the result is limited to the range
+        let start = xs.len();
+        let whole_ray = Ray::new(ray.origin, ray.direction);
+        get_object(self.left).intersect_into(&whole_ray, xs);
+        get_object(self.right).intersect_into(&whole_ray, xs);
+        xs[start..].sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        self.filter_intersections_from(xs, start);
+        let mut kept = start;
+        for i in start..xs.len() {
+            if ray.in_range(xs[i].t) {
+                xs.swap(kept, i);
+                kept += 1;
+            }
+        }
+        xs.truncate(kept);
     }
 
     fn local_normal_at(&self, _vector: &Tuple, _hit: &Intersection) -> Tuple {
         panic!("CSG do not have normals")
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Csg(self.operation, self.left, self.right))
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -244,7 +269,7 @@ impl Object for Csg {
         panic!("CSG do not have materials")
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -295,6 +320,20 @@ impl Object for Csg {
     fn includes(&self, object_id: usize) -> bool {
         object_id == self.left || object_id == self.right
     }
+
+    fn child_ids(&self) -> Vec<usize> {
+        [self.left, self.right].into_iter().filter(|id| *id != usize::MAX).collect()
+    }
+
+    fn divide(&mut self, threshold: usize) {
+        for id in self.child_ids() {
+            update_object(id, |child| child.divide(threshold));
+        }
+    }
+
+    fn casts_transparent_shadows(&self) -> bool {
+        self.child_ids().iter().any(|id| get_object(*id).casts_transparent_shadows())
+    }
 }
 
 
@@ -389,6 +428,38 @@ mod tests {
         assert_eq!(result[1], i1);
     }
 
+    #[test]
+    fn intersecting_into_a_list_keeps_the_intersections_already_in_it() {
+        let mut c = Csg::new(CsgOperation::Union);
+        c.set_left(Arc::new(crate::raytracer::object::sphere::Sphere::new()));
+        let mut s2 = crate::raytracer::object::sphere::Sphere::new();
+        s2.set_transform(Matrix4::translate(0.0, 0.0, 0.5));
+        c.set_right(Arc::new(s2));
+        let other = Intersection::new(10.0, usize::MAX, 0.0, 0.0);
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let mut xs = vec![other.clone()];
+        c.local_intersect_into(&r, &mut xs);
+        assert_eq!(xs.len(), 3);
+        assert_eq!(xs[0], other);
+        assert_eq!(xs[1..], c.local_intersect(&r)[..]);
+        assert_eq!(xs[1].t, 4.0);
+        assert_eq!(xs[2].t, 6.5);
+    }
+
+    #[test]
+    fn a_ray_with_a_range_starting_inside_a_csg_object_only_hits_its_surface() {
+        let mut c = Csg::new(CsgOperation::Union);
+        c.set_left(Arc::new(crate::raytracer::object::sphere::Sphere::new()));
+        let mut s2 = crate::raytracer::object::sphere::Sphere::new();
+        s2.set_transform(Matrix4::translate(0.0, 0.0, 0.5));
+        c.set_right(Arc::new(s2));
+        // The left sphere is left at 6.0 while still inside the right one, which is not a hit of the union
+        let r = Ray::with_range(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0), 5.0, 10.0);
+        let xs = c.local_intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 6.5);
+    }
+
     #[test]
     #[ignore]
     fn test_render_csg() {
@@ -398,61 +469,61 @@ mod tests {
         let from = Tuple::point(0.0, 1.5, -5.0);
         let to = Tuple::point(0.0, 1.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut floor = Plane::new();
-        floor.transform = Matrix::translate(0.0, 0.0, 0.0);
-        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                 Matrix::scale(0.1, 0.1, 0.1).multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0)));
+        floor.transform = Matrix4::translate(0.0, 0.0, 0.0);
+        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                 Matrix4::scale(0.1, 0.1, 0.1).multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0)));
         floor.material.specular = 0.0;
         w.add_object(Arc::new(floor));
 
         let mut left_wall = Plane::new();
-        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                       Matrix::identity(4)
-                                                           .multiply(&Matrix::translate(124.0, 124.0, 124.0)
-                                                               .multiply(&Matrix::scale(7.0, 7.0, 7.0))
+        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                       Matrix4::identity()
+                                                           .multiply(&Matrix4::translate(124.0, 124.0, 124.0)
+                                                               .multiply(&Matrix4::scale(7.0, 7.0, 7.0))
                                                            ));
-        left_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / -4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        left_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / -4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
         left_wall.material.specular = 0.0;
         w.add_object(Arc::new(left_wall));
 
         let mut right_wall = Plane::new();
-        right_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        right_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
-        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         right_wall.material.specular = 0.0;
         w.add_object(Arc::new(right_wall));
 
         let mut csg = Csg::new(CsgOperation::Difference);
 
         let mut material = Material::default();
-        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix::identity(4));
+        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix4::identity());
         let mut sphere = Sphere::new();
         sphere.material = material.clone();
-        sphere.transform = Matrix::identity(4)
-            .multiply(&Matrix::scale(0.6, 0.6, 0.6))
-            .multiply(&Matrix::translate(0.0, 1.0, 0.0))
+        sphere.transform = Matrix4::identity()
+            .multiply(&Matrix4::scale(0.6, 0.6, 0.6))
+            .multiply(&Matrix4::translate(0.0, 1.0, 0.0))
         ;
         csg.set_right(Arc::new(sphere));
 
         let mut cube = Cube::new();
         cube.material = material.clone();
-        cube.transform = Matrix::identity(4)
-            .multiply(&Matrix::scale(0.5, 0.5, 0.5))
-            .multiply(&Matrix::translate(0.0, 1.2, 0.0))
+        cube.transform = Matrix4::identity()
+            .multiply(&Matrix4::scale(0.5, 0.5, 0.5))
+            .multiply(&Matrix4::translate(0.0, 1.2, 0.0))
         ;
         csg.set_left(Arc::new(cube));
 
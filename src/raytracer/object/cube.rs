@@ -1,7 +1,7 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 use crate::EPSILON;
@@ -25,7 +25,7 @@ use crate::raytracer::object::db::get_next_id;
 pub struct Cube {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
 }
 
@@ -40,7 +40,7 @@ impl Cube {
         Cube {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
         }
     }
@@ -61,18 +61,72 @@ impl Cube {
     }
 }
 
+/// The six faces of a cube.
+///
+/// The discriminants are the indices of the faces in a cube map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CubeFace {
+    Left = 0,
+    Front = 1,
+    Right = 2,
+    Back = 3,
+    Up = 4,
+    Down = 5,
+}
+
+impl Cube {
+    /// Finds the face of the cube a point lies on, and the texture coordinates of the point on that face.
+    ///
+    /// Each face is mapped to the whole range [0, 1] of `u` and `v`, with `v` growing upwards on the side
+    /// faces, so the six images of a cube map join up at the edges. The images read correctly from the
+    /// center of the cube, as in a skybox; seen from outside they are mirrored, see `outside_face_uv`.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - A point on the cube, in object space. Other points are projected onto the cube from its center.
+    ///
+    /// # Returns
+    ///
+    /// The face the point lies on and the texture coordinates `u` and `v` on that face.
+    pub fn face_uv(point: &Tuple) -> (CubeFace, f64, f64) {
+        let coord = point.x.abs().max(point.y.abs()).max(point.z.abs());
+        let (x, y, z) = (point.x / coord, point.y / coord, point.z / coord);
+        if coord == point.x {
+            (CubeFace::Right, (1.0 - z) * 0.5, (y + 1.0) * 0.5)
+        } else if coord == -point.x {
+            (CubeFace::Left, (z + 1.0) * 0.5, (y + 1.0) * 0.5)
+        } else if coord == point.y {
+            (CubeFace::Up, (x + 1.0) * 0.5, (1.0 - z) * 0.5)
+        } else if coord == -point.y {
+            (CubeFace::Down, (x + 1.0) * 0.5, (z + 1.0) * 0.5)
+        } else if coord == point.z {
+            (CubeFace::Front, (x + 1.0) * 0.5, (y + 1.0) * 0.5)
+        } else {
+            (CubeFace::Back, (1.0 - x) * 0.5, (y + 1.0) * 0.5)
+        }
+    }
+
+    /// Like `face_uv`, but with `u` flipped so that the images read correctly from outside the cube, as on a crate.
+    pub fn outside_face_uv(point: &Tuple) -> (CubeFace, f64, f64) {
+        let (face, u, v) = Cube::face_uv(point);
+        (face, 1.0 - u, v)
+    }
+}
+
 impl Object for Cube {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let (xtmin, xtmax) = Cube::check_axis(ray.origin.x, ray.direction.x);
         let (ytmin, ytmax) = Cube::check_axis(ray.origin.y, ray.direction.y);
         let (ztmin, ztmax) = Cube::check_axis(ray.origin.z, ray.direction.z);
         let tmin = xtmin.max(ytmin).max(ztmin);
         let tmax = xtmax.min(ytmax).min(ztmax);
         if tmin > tmax {
-            vec![]
-        } else {
-            vec![Intersection::new(tmin, self.id, 0.0, 0.0),
-                 Intersection::new(tmax, self.id, 0.0, 0.0)]
+            return;
+        }
+        for t in [tmin, tmax] {
+            if ray.in_range(t) {
+                xs.push(Intersection::new(t, self.id, 0.0, 0.0));
+            }
         }
     }
 
@@ -87,7 +141,7 @@ impl Object for Cube {
         }
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -95,7 +149,7 @@ impl Object for Cube {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -129,48 +183,13 @@ impl Object for Cube {
         self.id == object_id
     }
 
-    fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
-        let abs_x = point.x.abs();
-        let abs_y = point.y.abs();
-        let abs_z = point.z.abs();
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Cube)
+    }
 
-        if abs_x >= abs_y && abs_x >= abs_z {
-            if point.x > 0.0 {
-                // Right face
-                let u = (point.z + 1.0) * 0.5;
-                let v = (point.y + 1.0) * 0.5;
-                (u, v)
-            } else {
-                // Left face
-                let u = (1.0 - point.z) * 0.5;
-                let v = (point.y + 1.0) * 0.5;
-                (u, v)
-            }
-        } else if abs_y >= abs_x && abs_y >= abs_z {
-            if point.y > 0.0 {
-                // Top face
-                let u = (point.x + 1.0) * 0.5;
-                let v = (1.0 - point.z) * 0.5;
-                (u, v)
-            } else {
-                // Bottom face
-                let u = (point.x + 1.0) * 0.5;
-                let v = (point.z + 1.0) * 0.5;
-                (u, v)
-            }
-        } else {
-            if point.z > 0.0 {
-                // Front face
-                let u = (point.x + 1.0) * 0.5;
-                let v = (point.y + 1.0) * 0.5;
-                (u, v)
-            } else {
-                // Back face
-                let u = (1.0 - point.x) * 0.5;
-                let v = (point.y + 1.0) * 0.5;
-                (u, v)
-            }
-        }
+    fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
+        let (_, u, v) = Cube::outside_face_uv(point);
+        (u, v)
     }
 }
 
@@ -180,7 +199,7 @@ mod tests {
     use crate::raytracer::object::Object;
     use crate::raytracer::ray::Ray;
     use crate::tuple::Tuple;
-    use super::Cube;
+    use super::{Cube, CubeFace};
 
     #[test]
     fn test_check_axis() {
@@ -282,4 +301,24 @@ mod tests {
             assert_eq!(normal, n);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn finding_the_face_and_uv_of_a_point_on_a_cube() {
+        let cases = [
+            (Tuple::point(-1.0, 0.5, -0.25), CubeFace::Left, 0.375, 0.75),
+            (Tuple::point(1.0, -0.75, 0.8), CubeFace::Right, 0.1, 0.125),
+            (Tuple::point(0.1, 0.6, 1.0), CubeFace::Front, 0.55, 0.8),
+            (Tuple::point(-0.7, 0.1, -1.0), CubeFace::Back, 0.85, 0.55),
+            (Tuple::point(0.5, 1.0, 0.1), CubeFace::Up, 0.75, 0.45),
+            (Tuple::point(0.3, -1.0, -0.6), CubeFace::Down, 0.65, 0.2),
+        ];
+        for (point, face, u, v) in cases {
+            let (f, pu, pv) = Cube::face_uv(&point);
+            assert_eq!(f, face);
+            assert!((pu - u).abs() < 1e-9 && (pv - v).abs() < 1e-9, "{:?}", point);
+        }
+        let (f, u, v) = Cube::outside_face_uv(&Tuple::point(0.1, 0.6, 1.0));
+        assert_eq!(f, CubeFace::Front);
+        assert!((u - 0.45).abs() < 1e-9 && (v - 0.8).abs() < 1e-9);
+    }
+}
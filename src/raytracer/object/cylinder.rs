@@ -1,7 +1,7 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 use crate::EPSILON;
@@ -32,10 +32,22 @@ pub struct Cylinder {
     pub minimum: f64,
     pub maximum: f64,
     pub closed: bool,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
 }
 
+/// Finds the `v` texture coordinate of a point on the side of a cylinder or cone.
+///
+/// A finite side is stretched over the whole texture from bottom to top, an infinite one repeats the
+/// texture every unit along the y axis.
+pub(crate) fn side_v(y: f64, minimum: f64, maximum: f64) -> f64 {
+    if minimum.is_finite() && maximum.is_finite() && maximum > minimum {
+        ((y - minimum) / (maximum - minimum)).clamp(0.0, 1.0)
+    } else {
+        y.rem_euclid(1.0)
+    }
+}
+
 /// Implementation of `Cylinder` functionalities.
 ///
 /// This implementation provides the necessary methods to integrate `Cylinder` objects into the ray tracing system,
@@ -47,7 +59,7 @@ impl Cylinder {
         Cylinder {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
             minimum,
             maximum,
@@ -63,43 +75,38 @@ impl Cylinder {
         (x * x + z * z) <= 1.0
     }
 
-    fn intersect_caps(&self, ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<Intersection> = vec![];
-
+    fn intersect_caps(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         // Caps only matter if the cylinder is closed, and might be
         // intersected by the ray
         if !self.closed || ray.direction.y.abs() < EPSILON {
-            return xs;
+            return;
         }
 
         // Check for an intersection with the lower end cap by intersecting
         // the ray with the plane at y = minimum
         let t = (self.minimum - ray.origin.y) / ray.direction.y;
-        if Cylinder::check_cap(ray, t) {
+        if ray.in_range(t) && Cylinder::check_cap(ray, t) {
             xs.push(Intersection::new(t, self.id, 0.0, 0.0));
         }
 
         // Check for an intersection with the upper end cap by intersecting
         // the ray with the plane at y = maximum
         let t = (self.maximum - ray.origin.y) / ray.direction.y;
-        if Cylinder::check_cap(ray, t) {
+        if ray.in_range(t) && Cylinder::check_cap(ray, t) {
             xs.push(Intersection::new(t, self.id, 0.0, 0.0));
         }
-
-        xs
     }
 }
 
 impl Object for Cylinder {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<Intersection> = vec![];
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let a = ray.direction.x * ray.direction.x + ray.direction.z * ray.direction.z;
         if a.abs() > EPSILON {
             let b = 2.0 * ray.origin.x * ray.direction.x + 2.0 * ray.origin.z * ray.direction.z;
             let c = ray.origin.x * ray.origin.x + ray.origin.z * ray.origin.z - 1.0;
             let discriminant = b * b - 4.0 * a * c;
             if discriminant < 0.0 {
-                return vec![];
+                return;
             }
             let mut t0 = (-b - discriminant.sqrt()) / (2.0 * a);
             let mut t1 = (-b + discriminant.sqrt()) / (2.0 * a);
@@ -108,18 +115,16 @@ impl Object for Cylinder {
             }
 
             let y0 = ray.origin.y + t0 * ray.direction.y;
-            if self.minimum < y0 && y0 < self.maximum {
+            if self.minimum < y0 && y0 < self.maximum && ray.in_range(t0) {
                 xs.push(Intersection::new(t0, self.id, 0.0, 0.0));
             }
             let y1 = ray.origin.y + t1 * ray.direction.y;
-            if self.minimum < y1 && y1 < self.maximum {
+            if self.minimum < y1 && y1 < self.maximum && ray.in_range(t1) {
                 xs.push(Intersection::new(t1, self.id, 0.0, 0.0));
             }
         }
 
-        self.intersect_caps(ray).iter().for_each(|i| xs.push(i.clone()));
-
-        xs
+        self.intersect_caps(ray, xs);
     }
 
     fn local_normal_at(&self, local_point: &Tuple, _hit: &Intersection) -> Tuple {
@@ -135,7 +140,11 @@ impl Object for Cylinder {
         }
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Cylinder { minimum: self.minimum, maximum: self.maximum, closed: self.closed })
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -143,7 +152,7 @@ impl Object for Cylinder {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -179,19 +188,34 @@ impl Object for Cylinder {
     }
 
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
-        if self.closed && (point.y <= self.minimum || point.y >= self.maximum) {
+        let dist = point.x * point.x + point.z * point.z;
+        if self.closed && dist < 1.0 && (point.y >= self.maximum - EPSILON || point.y <= self.minimum + EPSILON) {
+            // The caps are mapped from above, the unit disk filling the texture
             let u = (point.x + 1.0) / 2.0;
             let v = (point.z + 1.0) / 2.0;
             (u, v)
         } else {
             let theta = point.z.atan2(point.x);
             let u = (theta + std::f64::consts::PI) / (2.0 * std::f64::consts::PI);
-
-            // Wrap the v coordinate to repeat this texture along the y-axis
-            let v = point.y % 1.0;
-            let v = if v < 0.0 { 1.0 + v } else { v };
-
-            (u, v)
+            (u, side_v(point.y, self.minimum, self.maximum))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raytracer::object::cylinder::Cylinder;
+    use crate::raytracer::object::Object;
+    use crate::tuple::Tuple;
+
+    #[test]
+    fn uv_mapping_of_a_closed_cylinder() {
+        let c = Cylinder::new(0.0, 2.0, true);
+        assert_eq!(c.uv_mapping(&Tuple::point(0.0, 0.5, -1.0)), (0.25, 0.25));
+        assert_eq!(c.uv_mapping(&Tuple::point(1.0, 2.0, 0.0)), (0.5, 1.0));
+        assert_eq!(c.uv_mapping(&Tuple::point(0.5, 2.0, -0.5)), (0.75, 0.25));
+        assert_eq!(c.uv_mapping(&Tuple::point(0.0, 0.0, 0.0)), (0.5, 0.5));
+        let infinite = Cylinder::new(f64::NEG_INFINITY, f64::INFINITY, false);
+        assert_eq!(infinite.uv_mapping(&Tuple::point(0.0, -2.25, -1.0)), (0.25, 0.75));
+    }
+}
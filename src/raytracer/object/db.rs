@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
 use crate::raytracer::object::{AABB, Object};
@@ -39,15 +39,13 @@ pub fn get_object(id: usize) -> Arc<dyn Object + Send> {
 /// # Returns
 ///
 /// The number of objects as `usize`.
-#[allow(dead_code)]
-fn number_of_objects() -> usize {
+pub fn number_of_objects() -> usize {
     let objects = GLOBAL_OBJECTS.lock().unwrap();
     objects.len()
 }
 
 /// Clears all objects from the global storage.
-#[allow(dead_code)]
-fn clear_global_objects() {
+pub fn clear_global_objects() {
     let mut objects = GLOBAL_OBJECTS.lock().unwrap();
     objects.clear();
 }
@@ -76,6 +74,35 @@ pub fn add_object(object: Arc<dyn Object + Send>) {
     objects[id] = object;
 }
 
+/// Takes an object out of the list, leaving a placeholder until it is added again with `add_object`.
+///
+/// This is how objects that are already in the list are changed, like the children a group moves into
+/// sub-groups, since they can only be changed while nothing else holds them.
+///
+/// # Panics
+///
+/// Panics if the object is not found.
+pub fn take_object(id: usize) -> Arc<dyn Object + Send> {
+    let mut objects = GLOBAL_OBJECTS.lock().unwrap();
+    if id >= objects.len() {
+        panic!("Object not found: {}", id)
+    }
+    std::mem::replace(&mut objects[id], Arc::new(Sentinel { id, parent_id: None }))
+}
+
+/// Changes an object that is already in the list.
+///
+/// The object is taken out of the list while `change` runs, so `change` can add and look up other objects.
+///
+/// # Panics
+///
+/// Panics if the object is not found, or is still held elsewhere so it cannot be changed.
+pub fn update_object(id: usize, change: impl FnOnce(&mut (dyn Object + Send))) {
+    let mut object = take_object(id);
+    change(Arc::get_mut(&mut object).unwrap_or_else(|| panic!("Object {} is in use and cannot be changed", id)));
+    add_object(object);
+}
+
 /// A placeholder object used to reserve an ID in the global storage.
 struct Sentinel {
     id: usize,
@@ -83,14 +110,17 @@ struct Sentinel {
 }
 
 impl Object for Sentinel {
-    fn intersect(&self, _ray: &Ray) -> Vec<Intersection> {
-        vec![]
+    fn intersect_into(&self, _ray: &Ray, _xs: &mut Vec<Intersection>) {
     }
 
-    fn local_intersect(&self, _ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, _ray: &Ray, _xs: &mut Vec<Intersection>) {
         panic!("Sentinel should not be intersected")
     }
 
+    fn occludes(&self, _ray: &Ray, _scratch: &mut Vec<Intersection>) -> bool {
+        false
+    }
+
     fn normal_at(&self, _point: &Tuple, _hit: &Intersection) -> Tuple {
         Tuple::vector(0.0, 0.0, 0.0)
     }
@@ -99,7 +129,7 @@ impl Object for Sentinel {
         panic!("Sentinel has no local normal")
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         panic!("Sentinel has no transform")
     }
 
@@ -107,7 +137,7 @@ impl Object for Sentinel {
         panic!("Sentinel has no material")
     }
 
-    fn set_transform(&mut self, _transform: Matrix) {
+    fn set_transform(&mut self, _transform: Matrix4) {
     }
 
     fn set_material(&mut self, _material: Material) {
@@ -136,4 +166,8 @@ impl Object for Sentinel {
     fn includes(&self, _object_id: usize) -> bool {
         false
     }
+
+    fn casts_transparent_shadows(&self) -> bool {
+        false
+    }
 }
\ No newline at end of file
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::matrix4::Matrix4;
+use crate::raytracer::intersection::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::object::db::{add_object, get_object};
+use crate::raytracer::object::{AABB, Geometry, Object};
+use crate::raytracer::ray::Ray;
+use crate::tuple::Tuple;
+
+/// The transformations of an object and its parents, multiplied together once the scene is built.
+///
+/// # Fields
+///
+/// * `inverse` - The inverse of the object's own transformation, which takes rays from its parent's space to its own.
+/// * `world_to_object` - Takes points from world space to the object's space, through all of its parents.
+/// * `normal_to_world` - The transposed `world_to_object`, which takes normals from the object's space to world space.
+/// * `object_to_world` - Takes vectors from the object's space to world space, through all of its parents.
+#[derive(Debug, Clone, Copy)]
+pub struct BakedTransforms {
+    pub inverse: Matrix4,
+    pub world_to_object: Matrix4,
+    pub normal_to_world: Matrix4,
+    pub object_to_world: Matrix4,
+}
+
+impl BakedTransforms {
+    /// Bakes the transformations of an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `transform` - The object's own transformation.
+    /// * `parent` - The baked transformations of the object's parent, or `None` if it has no parent.
+    ///
+    /// # Returns
+    ///
+    /// The baked transformations of the object.
+    pub fn new(transform: &Matrix4, parent: Option<&BakedTransforms>) -> BakedTransforms {
+        let inverse = transform.inverse();
+        let (world_to_object, object_to_world) = match parent {
+            Some(parent) => (inverse.multiply(&parent.world_to_object), parent.object_to_world.multiply(transform)),
+            None => (inverse, *transform),
+        };
+        BakedTransforms { inverse, world_to_object, normal_to_world: world_to_object.transpose(), object_to_world }
+    }
+}
+
+/// An object whose transformations have been baked, so that rendering does not need to invert matrices or
+/// walk up the parents of the object.
+///
+/// Frozen objects take the place of the objects they wrap in the object storage, under the same id, and
+/// forward everything else to them.
+///
+/// # Fields
+///
+/// * `object` - The wrapped object.
+/// * `baked` - The baked transformations of the object.
+pub struct FrozenObject {
+    object: Arc<dyn Object + Send>,
+    baked: BakedTransforms,
+}
+
+impl Object for FrozenObject {
+    fn intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        self.object.local_intersect_into(&ray.transform(&self.baked.inverse), xs)
+    }
+
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        self.object.local_intersect_into(ray, xs)
+    }
+
+    fn occludes(&self, ray: &Ray, scratch: &mut Vec<Intersection>) -> bool {
+        self.object.local_occludes(&ray.transform(&self.baked.inverse), scratch)
+    }
+
+    fn local_occludes(&self, ray: &Ray, scratch: &mut Vec<Intersection>) -> bool {
+        self.object.local_occludes(ray, scratch)
+    }
+
+    fn normal_at(&self, world_point: &Tuple, hit: &Intersection) -> Tuple {
+        let local_point = self.baked.world_to_object.multiply_tuple(world_point);
+        let local_normal = self.object.local_normal_at(&local_point, hit);
+        let mut normal = self.baked.normal_to_world.multiply_tuple(&local_normal);
+        normal.w = 0.0;
+        normal.normalize()
+    }
+
+    fn local_normal_at(&self, local_point: &Tuple, hit: &Intersection) -> Tuple {
+        self.object.local_normal_at(local_point, hit)
+    }
+
+    fn face_normal_at(&self, world_point: &Tuple, hit: &Intersection) -> Tuple {
+        let local_point = self.baked.world_to_object.multiply_tuple(world_point);
+        let local_normal = self.object.local_face_normal_at(&local_point, hit);
+        let mut normal = self.baked.normal_to_world.multiply_tuple(&local_normal);
+        normal.w = 0.0;
+        normal.normalize()
+    }
+
+    fn local_face_normal_at(&self, local_point: &Tuple, hit: &Intersection) -> Tuple {
+        self.object.local_face_normal_at(local_point, hit)
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
+        self.object.get_transform()
+    }
+
+    fn get_material(&self) -> &Material {
+        self.object.get_material()
+    }
+
+    fn set_transform(&mut self, _transform: Matrix4) {
+        panic!("Frozen objects cannot be transformed")
+    }
+
+    fn set_material(&mut self, _material: Material) {
+        panic!("Frozen objects cannot be changed")
+    }
+
+    fn debug_string(&self) -> String {
+        self.object.debug_string()
+    }
+
+    fn get_id(&self) -> usize {
+        self.object.get_id()
+    }
+
+    fn get_parent_id(&self) -> Option<usize> {
+        self.object.get_parent_id()
+    }
+
+    fn set_parent_id(&mut self, _id: usize) {
+        panic!("Frozen objects cannot be moved to another parent")
+    }
+
+    fn get_aabb(&self) -> AABB {
+        self.object.get_aabb()
+    }
+
+    fn includes(&self, object_id: usize) -> bool {
+        self.object.includes(object_id)
+    }
+
+    fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
+        self.object.uv_mapping(point)
+    }
+
+    fn local_tangents_at(&self, local_point: &Tuple, hit: &Intersection) -> (Tuple, Tuple) {
+        self.object.local_tangents_at(local_point, hit)
+    }
+
+    fn vertex_colors(&self) -> Option<&[Color; 3]> {
+        self.object.vertex_colors()
+    }
+
+    fn edge_distance(&self, hit: &Intersection) -> Option<f64> {
+        self.object.edge_distance(hit)
+    }
+
+    fn geometry(&self) -> Option<Geometry> {
+        self.object.geometry()
+    }
+
+    fn child_ids(&self) -> Vec<usize> {
+        self.object.child_ids()
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.object.name()
+    }
+
+    fn baked_transforms(&self) -> Option<&BakedTransforms> {
+        Some(&self.baked)
+    }
+
+    fn casts_transparent_shadows(&self) -> bool {
+        self.object.casts_transparent_shadows()
+    }
+}
+
+/// Freezes objects and all of their children, replacing them in the object storage with `FrozenObject`s.
+///
+/// This is done once the scene is built and before it is rendered; the objects must not change afterwards.
+/// Objects that are already frozen are left as they are.
+///
+/// # Arguments
+///
+/// * `ids` - The ids of the objects without a parent to freeze.
+pub fn freeze_objects(ids: &[usize]) {
+    for id in ids {
+        freeze_object(*id, None);
+    }
+}
+
+fn freeze_object(id: usize, parent: Option<&BakedTransforms>) {
+    let object = get_object(id);
+    let baked = match object.baked_transforms() {
+        Some(baked) => *baked,
+        None => {
+            let baked = BakedTransforms::new(object.get_transform(), parent);
+            add_object(Arc::new(FrozenObject { object: object.clone(), baked }));
+            baked
+        }
+    };
+    for child_id in object.child_ids() {
+        freeze_object(child_id, Some(&baked));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::matrix4::Matrix4;
+    use crate::raytracer::intersection::Intersection;
+    use crate::raytracer::object::csg::{Csg, CsgOperation};
+    use crate::raytracer::object::db::get_object;
+    use crate::raytracer::object::group::Group;
+    use crate::raytracer::object::sphere::Sphere;
+    use crate::raytracer::object::{normal_to_world, vector_to_world, world_to_object, Object};
+    use crate::raytracer::ray::Ray;
+    use crate::tuple::Tuple;
+    use super::freeze_objects;
+
+    #[test]
+    fn frozen_objects_transform_like_the_objects_they_wrap() {
+        let mut g1 = Group::new();
+        g1.set_transform(Matrix4::rotate_y(std::f64::consts::PI / 2.0));
+        let mut g2 = Group::new();
+        g2.set_transform(Matrix4::scale(1.0, 2.0, 3.0));
+        let mut s = Sphere::new();
+        s.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
+        let s_id = s.get_id();
+        g2.add_child(Arc::new(s));
+        let g2_id = g1.add_child(Arc::new(g2));
+        let g1_id = g1.get_id();
+        crate::raytracer::object::db::add_object(Arc::new(g1));
+
+        let point = Tuple::point(1.7321, 1.1547, -5.5774);
+        let normal = Tuple::vector(0.5, -0.5, 0.7);
+        let ray = Ray::new(Tuple::point(-10.0, 0.0, -10.0), Tuple::vector(1.0, 0.0, 1.0).normalize());
+        let hit = Intersection::new(0.0, s_id, 0.0, 0.0);
+        let before = (
+            world_to_object(s_id, &point),
+            normal_to_world(s_id, &normal),
+            vector_to_world(s_id, &normal),
+            get_object(s_id).normal_at(&point, &hit),
+            get_object(g1_id).intersect(&ray).iter().map(|i| i.t).collect::<Vec<f64>>(),
+        );
+
+        freeze_objects(&[g1_id]);
+        for id in [g1_id, g2_id, s_id] {
+            assert!(get_object(id).baked_transforms().is_some());
+        }
+        let after = (
+            world_to_object(s_id, &point),
+            normal_to_world(s_id, &normal),
+            vector_to_world(s_id, &normal),
+            get_object(s_id).normal_at(&point, &hit),
+            get_object(g1_id).intersect(&ray).iter().map(|i| i.t).collect::<Vec<f64>>(),
+        );
+        assert_eq!(before.0, after.0);
+        assert_eq!(before.1, after.1);
+        assert_eq!(before.2, after.2);
+        assert_eq!(before.3, after.3);
+        assert_eq!(before.4.len(), after.4.len());
+        for (a, b) in before.4.iter().zip(after.4.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn freezing_reaches_the_children_of_csg_objects() {
+        let mut csg = Csg::new(CsgOperation::Union);
+        csg.set_transform(Matrix4::translate(0.0, 1.0, 0.0));
+        let left = csg.set_left(Arc::new(Sphere::new()));
+        let mut right = Sphere::new();
+        right.set_transform(Matrix4::translate(0.5, 0.0, 0.0));
+        let right = csg.set_right(Arc::new(right));
+        let csg_id = csg.get_id();
+        crate::raytracer::object::db::add_object(Arc::new(csg));
+
+        freeze_objects(&[csg_id]);
+        freeze_objects(&[csg_id]);
+        assert!(get_object(left).baked_transforms().is_some());
+        let p = world_to_object(right, &Tuple::point(0.5, 1.0, 0.0));
+        assert_eq!(p, Tuple::point(0.0, 0.0, 0.0));
+    }
+}
@@ -1,10 +1,11 @@
+use std::collections::HashSet;
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::{AABB, Object};
-use crate::raytracer::object::db::{add_object, get_next_id, get_object};
+use crate::raytracer::object::{AABB, Geometry, Object};
+use crate::raytracer::object::db::{add_object, get_next_id, get_object, take_object, update_object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -23,13 +24,18 @@ use crate::tuple::Tuple;
 ///   to the entire group, positioning it within the 3D scene.
 /// * `child_ids` - A vector of unique identifiers for the child objects contained within the group.
 ///   These children can be other groups or individual objects.
+/// * `name` - The name of the group, like that of a group or object of an OBJ file, or `None`.
+/// * `divided` - Whether the children are already sorted into a bounding volume hierarchy, like one read from a
+///   mesh cache, so that `divide` leaves them as they are.
 /// * `aabb_cache` - A cache for the axis-aligned bounding box (AABB) of the group, wrapped in `RwLock` and `Arc`
 ///   for thread safety. This cache is used to optimize intersection tests by avoiding redundant calculations.
 pub struct Group {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub child_ids: Vec<usize>,
+    pub name: Option<String>,
+    pub divided: bool,
     aabb_cache: Arc<RwLock<Option<AABB>>>,  // Cache for the AABB wrapped in RwLock and Arc for thread safety
 }
 
@@ -45,8 +51,10 @@ impl Group {
         Group {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             child_ids: Vec::new(),
+            name: None,
+            divided: false,
             aabb_cache: Arc::new(RwLock::new(None)),  // Initialize the cache as None
         }
     }
@@ -74,27 +82,79 @@ impl Group {
         self.child_ids.push(child_id);
         child_id
     }
+
+    /// Sorts the children into the two halves of the group's bounding box, split across its longest side.
+    ///
+    /// # Returns
+    ///
+    /// The ids of the children that fit entirely into the first half, and those that fit into the second.
+    /// Children that cross the middle, or have no bounds, are in neither.
+    fn partition_children(&self) -> (Vec<usize>, Vec<usize>) {
+        let bounds = self.get_aabb();
+        let size = bounds.max.subtract(&bounds.min);
+        if !(size.x.is_finite() && size.y.is_finite() && size.z.is_finite()) {
+            return (vec![], vec![]);
+        }
+        let (mut left_max, mut right_min) = (bounds.max, bounds.min);
+        if size.x >= size.y && size.x >= size.z {
+            left_max.x = bounds.min.x + size.x / 2.0;
+            right_min.x = left_max.x;
+        } else if size.y >= size.z {
+            left_max.y = bounds.min.y + size.y / 2.0;
+            right_min.y = left_max.y;
+        } else {
+            left_max.z = bounds.min.z + size.z / 2.0;
+            right_min.z = left_max.z;
+        }
+        let contains = |min: &Tuple, max: &Tuple, aabb: &AABB| {
+            aabb.min.x >= min.x && aabb.min.y >= min.y && aabb.min.z >= min.z
+                && aabb.max.x <= max.x && aabb.max.y <= max.y && aabb.max.z <= max.z
+        };
+        let (mut left, mut right) = (vec![], vec![]);
+        for id in &self.child_ids {
+            let child = get_object(*id);
+            let aabb = child.get_aabb().apply_transform(child.get_transform());
+            if contains(&bounds.min, &left_max, &aabb) {
+                left.push(*id);
+            } else if contains(&right_min, &bounds.max, &aabb) {
+                right.push(*id);
+            }
+        }
+        (left, right)
+    }
+
+    /// Moves some of the children into a new group, which becomes a child in their place.
+    fn make_subgroup(&mut self, ids: Vec<usize>) {
+        let moved: HashSet<usize> = ids.iter().copied().collect();
+        self.child_ids.retain(|id| !moved.contains(id));
+        let mut subgroup = Group::new();
+        for id in ids {
+            subgroup.add_child(take_object(id));
+        }
+        self.add_child(Arc::new(subgroup));
+    }
 }
 
 impl Object for Group {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<Intersection> = Vec::new();
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         if self.get_aabb().intersect(ray) {
+            let start = xs.len();
             for child_id in &self.child_ids {
-                let child = get_object(*child_id);
-                let mut child_xs = child.intersect(ray);
-                xs.append(&mut child_xs);
+                get_object(*child_id).intersect_into(ray, xs);
             }
-            xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+            xs[start..].sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
         }
-        xs
+    }
+
+    fn local_occludes(&self, ray: &Ray, scratch: &mut Vec<Intersection>) -> bool {
+        self.get_aabb().intersect(ray) && self.child_ids.iter().any(|id| get_object(*id).occludes(ray, scratch))
     }
 
     fn local_normal_at(&self, _vector: &Tuple, _hit: &Intersection) -> Tuple {
         panic!("Groups do not have normals")
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -102,7 +162,7 @@ impl Object for Group {
         panic!("Groups do not have materials")
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -157,6 +217,48 @@ impl Object for Group {
         }
         false
     }
+
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Group(self.child_ids.clone()))
+    }
+
+    fn child_ids(&self) -> Vec<usize> {
+        self.child_ids.clone()
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn casts_transparent_shadows(&self) -> bool {
+        self.child_ids.iter().any(|id| get_object(*id).casts_transparent_shadows())
+    }
+
+    /// Builds a bounding volume hierarchy: when the group has at least `threshold` children, the ones in
+    /// either half of its bounding box are moved into a sub-group for that half. Then every group and CSG
+    /// object among the children, including the new sub-groups, is divided in the same way, until the
+    /// groups are small. Rays that miss the bounds of a sub-group skip all of its children.
+    fn divide(&mut self, threshold: usize) {
+        if self.divided {
+            return;
+        }
+        if threshold <= self.child_ids.len() {
+            let (left, right) = self.partition_children();
+            // When all children fit into one half their bounds are flat, and splitting again would not end
+            if left.len() < self.child_ids.len() && right.len() < self.child_ids.len() {
+                for ids in [left, right] {
+                    if !ids.is_empty() {
+                        self.make_subgroup(ids);
+                    }
+                }
+            }
+        }
+        for id in self.child_ids.clone() {
+            if !get_object(id).child_ids().is_empty() {
+                update_object(id, |child| child.divide(threshold));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -164,7 +266,7 @@ mod tests {
     use std::sync::Arc;
 
     use crate::color::Color;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::camera::Camera;
     use crate::raytracer::intersection::Intersection;
     use crate::raytracer::light::Light;
@@ -192,9 +294,9 @@ mod tests {
         g.id = 100;
         let s1 = Sphere::new();
         let mut s2 = Sphere::new();
-        s2.set_transform(Matrix::translate(0.0, 0.0, -3.0));
+        s2.set_transform(Matrix4::translate(0.0, 0.0, -3.0));
         let mut s3 = Sphere::new();
-        s3.set_transform(Matrix::translate(5.0, 0.0, 0.0));
+        s3.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
         let s1_id = g.add_child(Arc::new(s1));
         let s2_id = g.add_child(Arc::new(s2));
         g.add_child(Arc::new(s3));
@@ -211,9 +313,9 @@ mod tests {
     fn intersecting_a_transformed_group() {
         let mut g = Group::new();
         g.id = 100;
-        g.set_transform(Matrix::scale(2.0, 2.0, 2.0));
+        g.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
         let mut s = Sphere::new();
-        s.set_transform(Matrix::translate(5.0, 0.0, 0.0));
+        s.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
         g.add_child(Arc::new(s));
         let r = Ray::new(Tuple::point(10.0, 0.0, -10.0), Tuple::vector(0.0, 0.0, 1.0));
         let xs = g.intersect(&r);
@@ -226,14 +328,14 @@ mod tests {
         scene.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut g1 = Group::new();
-        g1.set_transform(Matrix::rotate_y(std::f64::consts::PI / 2.0));
+        g1.set_transform(Matrix4::rotate_y(std::f64::consts::PI / 2.0));
 
         let mut g2 = Group::new();
-        g2.set_transform(Matrix::scale(2.0, 2.0, 2.0));
+        g2.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
 
         let mut s = Sphere::new();
         let s_id = s.get_id();
-        s.set_transform(Matrix::translate(5.0, 0.0, 0.0));
+        s.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
         let s: Arc<dyn Object + Send> = Arc::new(s);
         g2.add_child(s);
 
@@ -251,14 +353,14 @@ mod tests {
         scene.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut g1 = Group::new();
-        g1.set_transform(Matrix::rotate_y(std::f64::consts::PI / 2.0));
+        g1.set_transform(Matrix4::rotate_y(std::f64::consts::PI / 2.0));
 
         let mut g2 = Group::new();
-        g2.set_transform(Matrix::scale(1.0, 2.0, 3.0));
+        g2.set_transform(Matrix4::scale(1.0, 2.0, 3.0));
 
         let mut s = Sphere::new();
         let s_id = s.get_id();
-        s.set_transform(Matrix::translate(5.0, 0.0, 0.0));
+        s.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
         let s: Arc<dyn Object + Send> = Arc::new(s);
         g2.add_child(s);
 
@@ -276,11 +378,11 @@ mod tests {
         scene.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut g1 = Group::new();
-        g1.set_transform(Matrix::rotate_y(std::f64::consts::PI / 2.0));
+        g1.set_transform(Matrix4::rotate_y(std::f64::consts::PI / 2.0));
         let mut g2 = Group::new();
-        g2.set_transform(Matrix::scale(1.0, 2.0, 3.0));
+        g2.set_transform(Matrix4::scale(1.0, 2.0, 3.0));
         let mut s = Sphere::new();
-        s.set_transform(Matrix::translate(5.0, 0.0, 0.0));
+        s.set_transform(Matrix4::translate(5.0, 0.0, 0.0));
         let s_id = s.get_id();
         let s: Arc<dyn Object + Send> = Arc::new(s);
         g2.add_child(s);
@@ -294,16 +396,16 @@ mod tests {
 
     fn hexagon_corner() -> Sphere {
         let mut corner = Sphere::new();
-        corner.set_transform(Matrix::translate(0.0, 0.0, -1.0) * Matrix::scale(0.25, 0.25, 0.25));
+        corner.set_transform(Matrix4::translate(0.0, 0.0, -1.0) * Matrix4::scale(0.25, 0.25, 0.25));
         corner
     }
 
     fn hexagon_edge() -> Cylinder {
         let mut edge = Cylinder::new(0.0, 1.0, true);
-        edge.set_transform(Matrix::translate(0.0, 0.0, -1.0)
-            * Matrix::rotate_y(-std::f64::consts::PI / 6.0)
-            * Matrix::rotate_z(-std::f64::consts::PI / 2.0)
-            * Matrix::scale(0.25, 1.0, 0.25));
+        edge.set_transform(Matrix4::translate(0.0, 0.0, -1.0)
+            * Matrix4::rotate_y(-std::f64::consts::PI / 6.0)
+            * Matrix4::rotate_z(-std::f64::consts::PI / 2.0)
+            * Matrix4::scale(0.25, 1.0, 0.25));
         edge
     }
 
@@ -318,7 +420,7 @@ mod tests {
         let mut hex = Group::new();
         for n in 0..6 {
             let mut side = hexagon_side();
-            side.set_transform(Matrix::rotate_y(n as f64 * std::f64::consts::PI / 3.0));
+            side.set_transform(Matrix4::rotate_y(n as f64 * std::f64::consts::PI / 3.0));
             hex.add_child(Arc::new(side));
         }
         hex
@@ -337,17 +439,65 @@ mod tests {
         let from = Tuple::point(0.0, 2.0, -5.0);
         let to = Tuple::point(0.0, 0.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
 
         let mut g = hexagon();
-        g.set_transform(Matrix::rotate_x(degrees_to_radians(-20.0)));
+        g.set_transform(Matrix4::rotate_x(degrees_to_radians(-20.0)));
         w.add_object(Arc::new(g));
 
         let image = c.render(&w);
 
         image.write_to_file("canvas.png", 1);
     }
+
+    fn sphere_at(x: f64, y: f64, z: f64) -> Arc<Sphere> {
+        let mut s = Sphere::new();
+        s.transform = Matrix4::translate(x, y, z);
+        Arc::new(s)
+    }
+
+    #[test]
+    fn partitioning_a_groups_children() {
+        let mut g = Group::new();
+        let s1 = g.add_child(sphere_at(-2.0, 0.0, 0.0));
+        let s2 = g.add_child(sphere_at(2.0, 0.0, 0.0));
+        let s3 = g.add_child(Arc::new(Sphere::new()));
+        let (left, right) = g.partition_children();
+        assert_eq!(left, vec![s1]);
+        assert_eq!(right, vec![s2]);
+        g.make_subgroup(left);
+        assert_eq!(g.child_ids.len(), 3);
+        assert_eq!(g.child_ids[..2], [s2, s3]);
+        assert_eq!(get_object(g.child_ids[2]).child_ids(), vec![s1]);
+        assert_eq!(get_object(s1).get_parent_id(), Some(g.child_ids[2]));
+    }
+
+    #[test]
+    fn subdividing_a_group_partitions_its_children() {
+        let mut g = Group::new();
+        let s1 = g.add_child(sphere_at(-2.0, -2.0, 0.0));
+        let s2 = g.add_child(sphere_at(-2.0, 2.0, 0.0));
+        let mut big = Sphere::new();
+        big.transform = Matrix4::scale(4.0, 4.0, 4.0);
+        let s3 = g.add_child(Arc::new(big));
+        g.divide(1);
+        assert_eq!(g.child_ids[0], s3);
+        let subgroup = get_object(g.child_ids[1]).child_ids();
+        assert_eq!(subgroup.len(), 2);
+        assert_eq!(get_object(subgroup[0]).child_ids(), vec![s1]);
+        assert_eq!(get_object(subgroup[1]).child_ids(), vec![s2]);
+    }
+
+    #[test]
+    fn groups_with_fewer_children_than_the_threshold_are_not_divided() {
+        let mut g = Group::new();
+        g.add_child(sphere_at(-2.0, 0.0, 0.0));
+        g.add_child(sphere_at(2.0, 0.0, 0.0));
+        let children = g.child_ids.clone();
+        g.divide(3);
+        assert_eq!(g.child_ids, children);
+    }
 }
@@ -1,15 +1,17 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::EPSILON;
 use crate::raytracer::object::db::get_next_id;
 use crate::tuple::Tuple;
 
-/// Represents an infinite plane in a 3D scene.
+/// Represents a plane in a 3D scene, the xz plane in object space.
 ///
-/// This struct is part of a ray tracing system, defining a plane that extends infinitely in two dimensions.
+/// This struct is part of a ray tracing system, defining a plane that extends infinitely in two dimensions,
+/// unless it is given a width along x or a depth along z, centered on its origin. A finite plane has finite
+/// bounds, so the groups it is in can still skip the rays that miss it.
 /// It is characterized by its position, orientation (through a transformation matrix), and material properties.
 ///
 /// # Fields
@@ -21,11 +23,15 @@ use crate::tuple::Tuple;
 ///   positioning it within the 3D scene.
 /// * `material` - The material properties of the plane, defining how it interacts with light and shadows
 ///   within the scene.
+/// * `width` - The extent of the plane along x, infinite unless it is limited.
+/// * `depth` - The extent of the plane along z, infinite unless it is limited.
 pub struct Plane {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
+    pub width: f64,
+    pub depth: f64,
 }
 
 /// Implementation of `Plane` functionalities.
@@ -40,27 +46,50 @@ impl Plane {
         Plane {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
+            width: f64::INFINITY,
+            depth: f64::INFINITY,
         }
     }
 
+    /// Creates a plane limited to `width` along x and `depth` along z, centered on its origin.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The size of the plane along x, greater than 0, or infinity for no limit.
+    /// * `depth` - The size of the plane along z, greater than 0, or infinity for no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or depth is not greater than 0.
+    pub fn bounded(width: f64, depth: f64) -> Plane {
+        if width.is_nan() || width <= 0.0 {
+            panic!("The width of a plane must be greater than 0, got {}", width);
+        }
+        if depth.is_nan() || depth <= 0.0 {
+            panic!("The depth of a plane must be greater than 0, got {}", depth);
+        }
+        Plane { width, depth, ..Plane::new() }
+    }
 }
 
 impl Object for Plane {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         if ray.direction.y.abs() < EPSILON {
-            vec![]
-        } else {
-            let t = -ray.origin.y / ray.direction.y;
-            vec![Intersection { t, object: self.id, u: 0.0, v: 0.0}]
+            return;
+        }
+        let t = -ray.origin.y / ray.direction.y;
+        let (x, z) = (ray.origin.x + t * ray.direction.x, ray.origin.z + t * ray.direction.z);
+        if ray.in_range(t) && x.abs() <= self.width / 2.0 && z.abs() <= self.depth / 2.0 {
+            xs.push(Intersection { t, object: self.id, u: 0.0, v: 0.0});
         }
     }
     fn local_normal_at(&self, _local_point: &Tuple, _hit: &Intersection) -> Tuple {
         Tuple::vector(0.0, 1.0, 0.0)
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -68,7 +97,7 @@ impl Object for Plane {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -93,8 +122,8 @@ impl Object for Plane {
     }
 
     fn get_aabb(&self) -> AABB {
-        let min = Tuple::point(f64::NEG_INFINITY, 0.0, f64::NEG_INFINITY);
-        let max = Tuple::point(f64::INFINITY, 0.0, f64::INFINITY);
+        let min = Tuple::point(-self.width / 2.0, 0.0, -self.depth / 2.0);
+        let max = Tuple::point(self.width / 2.0, 0.0, self.depth / 2.0);
         AABB::new(min, max)
     }
 
@@ -102,6 +131,10 @@ impl Object for Plane {
         self.id == object_id
     }
 
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Plane { width: self.width, depth: self.depth })
+    }
+
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
         let u = point.x % 1.0;
         let v = point.z % 1.0;
@@ -111,4 +144,42 @@ impl Object for Plane {
 
         (u, v)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raytracer::object::group::Group;
+    use crate::raytracer::object::Object;
+    use crate::raytracer::ray::Ray;
+    use crate::tuple::Tuple;
+    use std::sync::Arc;
+    use super::Plane;
+
+    #[test]
+    fn a_bounded_plane_is_only_hit_within_its_extents() {
+        let p = Plane::bounded(4.0, 2.0);
+        let down = Tuple::vector(0.0, -1.0, 0.0);
+        let xs = p.local_intersect(&Ray::new(Tuple::point(1.9, 1.0, 0.9), down));
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0].t, 1.0);
+        assert!(p.local_intersect(&Ray::new(Tuple::point(2.1, 1.0, 0.0), down)).is_empty());
+        assert!(p.local_intersect(&Ray::new(Tuple::point(0.0, 1.0, -1.1), down)).is_empty());
+        // An unbounded plane is hit anywhere
+        assert_eq!(Plane::new().local_intersect(&Ray::new(Tuple::point(1e6, 1.0, 1e6), down)).len(), 1);
+    }
+
+    #[test]
+    fn a_group_with_a_bounded_plane_has_finite_bounds() {
+        let mut g = Group::new();
+        g.add_child(Arc::new(Plane::bounded(4.0, 2.0)));
+        let aabb = g.get_aabb();
+        assert_eq!(aabb.min, Tuple::point(-2.0, 0.0, -1.0));
+        assert_eq!(aabb.max, Tuple::point(2.0, 0.0, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "The depth of a plane must be greater than 0, got -2")]
+    fn the_size_of_a_bounded_plane_is_positive() {
+        Plane::bounded(4.0, -2.0);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::matrix4::Matrix4;
+use crate::raytracer::intersection::Intersection;
+use crate::raytracer::material::Material;
+use crate::raytracer::object::cube::Cube;
+use crate::raytracer::object::db::get_next_id;
+use crate::raytracer::object::{AABB, Geometry, Object};
+use crate::raytracer::ray::Ray;
+use crate::tuple::Tuple;
+
+/// How far a hit may lie outside the face, edge or corner it was found on and still count, so that rays
+/// through the seams between them do not slip through.
+const SEAM_EPSILON: f64 = 1e-9;
+
+/// A rounded box is the cube from -1 to 1 on every axis with its edges and corners rounded off.
+/// The flat faces shrink by the corner radius on every side, the edges are quarter cylinders and the
+/// corners are eighths of spheres, so the box still touches the planes at -1 and 1 on every axis.
+/// A radius of 1 gives the unit sphere.
+pub struct RoundedBox {
+    pub id: usize,
+    pub parent_id: Option<usize>,
+    pub radius: f64,
+    pub transform: Matrix4,
+    pub material: Material,
+}
+
+impl RoundedBox {
+    /// Creates a rounded box.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The radius of the rounded edges and corners, greater than 0 and at most 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the radius is out of range.
+    pub fn new(radius: f64) -> Self {
+        if !(radius > 0.0 && radius <= 1.0) {
+            panic!("The radius of a rounded box must be greater than 0 and at most 1, got {}", radius);
+        }
+        RoundedBox {
+            id: get_next_id(),
+            parent_id: None,
+            radius,
+            transform: Matrix4::identity(),
+            material: Material::default(),
+        }
+    }
+
+    /// The half size of the inner box whose edges and corners are the centers of the rounding.
+    fn inner(&self) -> f64 {
+        1.0 - self.radius
+    }
+}
+
+/// Finds the distances along a ray where it meets a sphere or an infinite cylinder, given the parts of the
+/// origin and direction across the sphere or cylinder, measured from its center.
+///
+/// # Arguments
+///
+/// * `o` - The origin of the ray relative to the center, with the part along a cylinder's axis left out.
+/// * `d` - The direction of the ray, with the part along a cylinder's axis left out.
+/// * `radius` - The radius of the sphere or cylinder.
+///
+/// # Returns
+///
+/// The two distances, or `None` if the ray misses.
+fn round_hits(o: &[f64], d: &[f64], radius: f64) -> Option<[f64; 2]> {
+    let a: f64 = d.iter().map(|d| d * d).sum();
+    if a == 0.0 {
+        return None;
+    }
+    let b: f64 = 2.0 * o.iter().zip(d).map(|(o, d)| o * d).sum::<f64>();
+    let c: f64 = o.iter().map(|o| o * o).sum::<f64>() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some([(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)])
+}
+
+impl Object for RoundedBox {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        let k = self.inner();
+        let r = self.radius;
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let at = |t: f64| [origin[0] + t * direction[0], origin[1] + t * direction[1], origin[2] + t * direction[2]];
+
+        // The box is convex, so the ray enters at the nearest hit on any part of the surface and leaves at
+        // the farthest one
+        let mut tmin = f64::INFINITY;
+        let mut tmax = -f64::INFINITY;
+        let mut found = |t: f64| {
+            tmin = tmin.min(t);
+            tmax = tmax.max(t);
+        };
+
+        for axis in 0..3 {
+            let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+            for sign in [-1.0, 1.0] {
+                // The flat face at -1 or 1 on this axis
+                if direction[axis] != 0.0 {
+                    let t = (sign - origin[axis]) / direction[axis];
+                    let p = at(t);
+                    if p[b].abs() <= k + SEAM_EPSILON && p[c].abs() <= k + SEAM_EPSILON {
+                        found(t);
+                    }
+                }
+                // The two quarter cylinders along this axis on the side of the face
+                for other in [-1.0, 1.0] {
+                    let (cb, cc) = (sign * k, other * k);
+                    let o = [origin[b] - cb, origin[c] - cc];
+                    let d = [direction[b], direction[c]];
+                    for t in round_hits(&o, &d, r).into_iter().flatten() {
+                        let p = at(t);
+                        if p[axis].abs() <= k + SEAM_EPSILON
+                            && (p[b] - cb) * sign >= -SEAM_EPSILON
+                            && (p[c] - cc) * other >= -SEAM_EPSILON {
+                            found(t);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The corner spheres
+        for sx in [-1.0, 1.0] {
+            for sy in [-1.0, 1.0] {
+                for sz in [-1.0, 1.0] {
+                    let center = [sx * k, sy * k, sz * k];
+                    let o = [origin[0] - center[0], origin[1] - center[1], origin[2] - center[2]];
+                    for t in round_hits(&o, &direction, r).into_iter().flatten() {
+                        let p = at(t);
+                        if (p[0] - center[0]) * sx >= -SEAM_EPSILON
+                            && (p[1] - center[1]) * sy >= -SEAM_EPSILON
+                            && (p[2] - center[2]) * sz >= -SEAM_EPSILON {
+                            found(t);
+                        }
+                    }
+                }
+            }
+        }
+
+        if tmin > tmax {
+            return;
+        }
+        for t in [tmin, tmax] {
+            if ray.in_range(t) {
+                xs.push(Intersection::new(t, self.id, 0.0, 0.0));
+            }
+        }
+    }
+
+    fn local_normal_at(&self, local_point: &Tuple, _hit: &Intersection) -> Tuple {
+        // The normal points away from the nearest point of the inner box
+        let k = self.inner();
+        let normal = Tuple::vector(
+            local_point.x - local_point.x.clamp(-k, k),
+            local_point.y - local_point.y.clamp(-k, k),
+            local_point.z - local_point.z.clamp(-k, k),
+        );
+        if normal.magnitude() > 0.0 {
+            normal.normalize()
+        } else {
+            // A point inside the inner box, which only rounding can produce, gets the normal of the cube
+            let maxc = local_point.x.abs().max(local_point.y.abs()).max(local_point.z.abs());
+            if maxc == local_point.x.abs() {
+                Tuple::vector(local_point.x.signum(), 0.0, 0.0)
+            } else if maxc == local_point.y.abs() {
+                Tuple::vector(0.0, local_point.y.signum(), 0.0)
+            } else {
+                Tuple::vector(0.0, 0.0, local_point.z.signum())
+            }
+        }
+    }
+
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::RoundedBox(self.radius))
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
+        &self.transform
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn set_transform(&mut self, transform: Matrix4) {
+        self.transform = transform;
+    }
+
+    fn set_material(&mut self, material: Material) {
+        self.material = material;
+    }
+
+    fn debug_string(&self) -> String {
+        format!("RoundedBox: radius: {}, transform: {:?}, material: {:?}", self.radius, self.transform, self.material)
+    }
+
+    fn get_id(&self) -> usize {
+        self.id
+    }
+
+    fn get_parent_id(&self) -> Option<usize> {
+        self.parent_id
+    }
+
+    fn set_parent_id(&mut self, id: usize) {
+        self.parent_id = Some(id);
+    }
+
+    fn get_aabb(&self) -> AABB {
+        let min = Tuple::point(-1.0, -1.0, -1.0);
+        let max = Tuple::point(1.0, 1.0, 1.0);
+        AABB { min, max }
+    }
+
+    fn includes(&self, object_id: usize) -> bool {
+        self.id == object_id
+    }
+
+    fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
+        let (_, u, v) = Cube::outside_face_uv(point);
+        (u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raytracer::intersection::Intersection;
+    use crate::raytracer::object::Object;
+    use crate::raytracer::object::rounded_box::RoundedBox;
+    use crate::raytracer::ray::Ray;
+    use crate::tuple::Tuple;
+
+    fn intersect(b: &RoundedBox, origin: Tuple, direction: Tuple) -> Vec<f64> {
+        let mut xs = vec![];
+        b.local_intersect_into(&Ray::new(origin, direction), &mut xs);
+        xs.iter().map(|x| x.t).collect()
+    }
+
+    #[test]
+    fn a_ray_through_the_faces_hits_them_like_a_cube() {
+        let b = RoundedBox::new(0.25);
+        let xs = intersect(&b, Tuple::point(-5.0, 0.5, 0.0), Tuple::vector(1.0, 0.0, 0.0));
+        assert_eq!(xs, vec![4.0, 6.0]);
+        let xs = intersect(&b, Tuple::point(0.0, 0.0, 0.0), Tuple::vector(0.0, 0.0, 1.0));
+        assert_eq!(xs, vec![-1.0, 1.0]);
+    }
+
+    #[test]
+    fn a_ray_towards_a_corner_hits_the_rounding() {
+        let b = RoundedBox::new(0.25);
+        let direction = Tuple::vector(1.0, 1.0, 1.0).normalize();
+        let xs = intersect(&b, Tuple::point(0.0, 0.0, 0.0), direction);
+        assert_eq!(xs.len(), 2);
+        // The corner spheres are centred at 0.75 on every axis
+        let corner = 0.75 * 3.0_f64.sqrt() + 0.25;
+        assert!((xs[0] + corner).abs() < 1e-9);
+        assert!((xs[1] - corner).abs() < 1e-9);
+
+        // A cube would be hit by this ray, but the rounded corner is missed
+        assert!(intersect(&b, Tuple::point(0.95, 0.95, -5.0), Tuple::vector(0.0, 0.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn normals_of_a_rounded_box() {
+        let b = RoundedBox::new(0.5);
+        let hit = Intersection::new(0.0, b.id, 0.0, 0.0);
+        assert_eq!(b.local_normal_at(&Tuple::point(1.0, 0.2, -0.3), &hit), Tuple::vector(1.0, 0.0, 0.0));
+        let edge = Tuple::point(0.5 + 0.5 / 2.0_f64.sqrt(), 0.5 + 0.5 / 2.0_f64.sqrt(), 0.0);
+        assert_eq!(b.local_normal_at(&edge, &hit), Tuple::vector(1.0, 1.0, 0.0).normalize());
+        let s = 1.0 / 3.0_f64.sqrt();
+        let corner = Tuple::point(-0.5 - 0.5 * s, 0.5 + 0.5 * s, -0.5 - 0.5 * s);
+        assert_eq!(b.local_normal_at(&corner, &hit), Tuple::vector(-s, s, -s));
+    }
+
+    #[test]
+    #[should_panic]
+    fn the_radius_of_a_rounded_box_is_at_most_one() {
+        RoundedBox::new(1.5);
+    }
+}
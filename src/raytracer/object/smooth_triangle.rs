@@ -1,8 +1,12 @@
-use crate::matrix::Matrix;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
 use crate::raytracer::object::db::get_next_id;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::triangle::default_material_slots;
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -19,18 +23,21 @@ use crate::tuple::Tuple;
 ///   This can be `None` if the smooth triangle does not have a parent.
 /// * `transform` - A transformation matrix that applies translation, rotation, and scaling to the smooth triangle,
 ///   positioning it within the 3D scene.
-/// * `material` - The material properties of the smooth triangle, defining how it interacts with light and shadows
-///   within the scene.
+/// * `materials` - The materials of the slots of the mesh the smooth triangle belongs to, shared by all its
+///   triangles so that each does not keep a copy of its material, or a single material for a triangle of its own.
+/// * `slot` - The slot of the triangle in `materials`, whose material is the material of the triangle.
 /// * `p1`, `p2`, `p3` - The vertices of the triangle, represented as points in space.
 /// * `n1`, `n2`, `n3` - The normal vectors at each of the triangle's vertices, used for smooth shading.
 /// * `e1`, `e2` - Edge vectors of the triangle, calculated as `p2 - p1` and `p3 - p1` respectively.
 /// * `normal` - The normal vector of the triangle's plane, calculated from the cross product of `e2` and `e1`.
+/// * `colors` - The colors at `p1`, `p2` and `p3`, like those read from an OBJ file, or `None`.
 #[derive(Debug, PartialEq)]
 pub struct SmoothTriangle {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
-    pub material: Material,
+    pub transform: Matrix4,
+    pub materials: Arc<[Material]>,
+    pub slot: u32,
     pub p1: Tuple,
     pub p2: Tuple,
     pub p3: Tuple,
@@ -40,6 +47,7 @@ pub struct SmoothTriangle {
     pub e1: Tuple,
     pub e2: Tuple,
     pub normal: Tuple,
+    pub colors: Option<[Color; 3]>,
 }
 
 /// Implementation of `SmoothTriangle` functionalities.
@@ -56,8 +64,9 @@ impl SmoothTriangle {
         SmoothTriangle {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
-            material: Material::default(),
+            transform: Matrix4::identity(),
+            materials: default_material_slots(),
+            slot: 0,
             p1,
             p2,
             p3,
@@ -67,57 +76,75 @@ impl SmoothTriangle {
             e1,
             e2,
             normal,
+            colors: None,
         }
     }
 }
 
 impl Object for SmoothTriangle {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let dir_cross_e2 = ray.direction.cross(&self.e2);
         let det = self.e1.dot(&dir_cross_e2);
-        if det.abs() < crate::EPSILON {
-            return vec![];
+        // A negative determinant means the ray hits the back of the triangle, the side that `e1 x e2` points away from
+        if det.abs() < crate::EPSILON || (det < 0.0 && !self.get_material().double_sided) {
+            return;
         }
 
         let f = 1.0 / det;
         let p1_to_origin = ray.origin.subtract(&self.p1);
         let u = f * p1_to_origin.dot(&dir_cross_e2);
         if u < 0.0 || u > 1.0 {
-            return vec![];
+            return;
         }
 
         let origin_cross_e1 = p1_to_origin.cross(&self.e1);
         let v = f * ray.direction.dot(&origin_cross_e1);
         if v < 0.0 || (u + v) > 1.0 {
-            return vec![];
+            return;
         }
 
         let t = f * self.e2.dot(&origin_cross_e1);
-        vec![Intersection { t, object: self.id, u, v}]
+        if ray.in_range(t) {
+            xs.push(Intersection { t, object: self.id, u, v});
+        }
     }
 
     fn local_normal_at(&self, _local_point: &Tuple, hit: &Intersection) -> Tuple {
         self.n2 * hit.u + self.n3 * hit.v + self.n1 * (1.0 - hit.u - hit.v)
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn local_face_normal_at(&self, _local_point: &Tuple, _hit: &Intersection) -> Tuple {
+        // The face normal points to the same side as the normals at the corners
+        if self.normal.dot(&(self.n1 + self.n2 + self.n3)) < 0.0 { self.normal.negate() } else { self.normal }
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
     fn get_material(&self) -> &Material {
-        &self.material
+        &self.materials[self.slot as usize]
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
     fn set_material(&mut self, material: Material) {
-        self.material = material;
+        self.materials = Arc::from([material]);
+        self.slot = 0;
     }
 
     fn debug_string(&self) -> String {
-        format!("Triangle: transform: {:?}, material: {:?}", self.transform, self.material)
+        format!("Triangle: transform: {:?}, material: {:?}", self.transform, self.get_material())
+    }
+
+    fn material_slots(&self) -> Option<&Arc<[Material]>> {
+        Some(&self.materials)
+    }
+
+    fn set_material_slots(&mut self, materials: Arc<[Material]>) {
+        self.materials = materials;
     }
 
     fn get_id(&self) -> usize {
@@ -148,6 +175,19 @@ impl Object for SmoothTriangle {
         self.id == object_id
     }
 
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Triangle([self.p1, self.p2, self.p3], [self.n1, self.n2, self.n3]))
+    }
+
+    fn vertex_colors(&self) -> Option<&[Color; 3]> {
+        self.colors.as_ref()
+    }
+
+    fn edge_distance(&self, hit: &Intersection) -> Option<f64> {
+        // u and v are barycentric coordinates, the third one is 1 - u - v
+        Some(hit.u.min(hit.v).min(1.0 - hit.u - hit.v))
+    }
+
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
         let v0 = self.p2.subtract(&self.p1);
         let v1 = self.p3.subtract(&self.p1);
@@ -171,6 +211,11 @@ impl Object for SmoothTriangle {
 
         (u, v)
     }
+
+    fn local_tangents_at(&self, _local_point: &Tuple, _hit: &Intersection) -> (Tuple, Tuple) {
+        // With the implicit UV mapping, u grows along the first edge and v along the second
+        (self.p2.subtract(&self.p1), self.p3.subtract(&self.p1))
+    }
 }
 
 
@@ -338,4 +383,56 @@ mod tests {
         let comps = xs[0].prepare_computations(&r, &xs);
         assert_eq!(comps.normalv, Tuple::vector(-0.5547, 0.83205, 0.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn vertex_colors_are_blended_with_u_v() {
+        let mut scene = crate::raytracer::scene::Scene::new();
+        let mut tri = SmoothTriangle::new(
+            Tuple::point(0.0, 1.0, 0.0),
+            Tuple::point(-1.0, 0.0, 0.0),
+            Tuple::point(1.0, 0.0, 0.0),
+            Tuple::vector(0.0, 1.0, 0.0),
+            Tuple::vector(-1.0, 0.0, 0.0),
+            Tuple::vector(1.0, 0.0, 0.0),
+        );
+        tri.colors = Some([Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0)]);
+        let tri_id = scene.add_object(Arc::new(tri));
+        let r = Ray::new(Tuple::point(-0.2, 0.3, -2.0), Tuple::vector(0.0, 0.0, 1.0));
+        let xs = vec![Intersection { t: 2.0, object: tri_id, u: 0.45, v: 0.25 }];
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_eq!(comps.vertex_color, Color::new(0.3, 0.45, 0.25));
+
+        // The diffuse color of the material is multiplied by the vertex color
+        let light = Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0));
+        scene.add_light(light);
+        let plain = crate::raytracer::light::lighting(tri_id, &scene.light[0], &comps.over_point, &comps.eyev, &comps.normalv, 0.0);
+        let shaded = scene.shade_hit(&comps, 0);
+        assert!(shaded.g < plain.g && shaded.r < shaded.g);
+    }
+
+    #[test]
+    fn the_normal_is_flipped_with_the_face_of_a_smooth_triangle() {
+        let mut scene = crate::raytracer::scene::Scene::new();
+        let n = Tuple::vector(0.8, 0.0, -0.6);
+        let tri = SmoothTriangle::new(
+            Tuple::point(0.0, 1.0, 0.0),
+            Tuple::point(-1.0, 0.0, 0.0),
+            Tuple::point(1.0, 0.0, 0.0),
+            n, n, n,
+        );
+        let tri_id = scene.add_object(Arc::new(tri));
+        // Seen from the front at a grazing angle, the blended normal faces away from the eye, but is kept
+        let direction = Tuple::vector(0.8, 0.0, 0.6);
+        let r = Ray::new(Tuple::point(0.0, 0.5, 0.0).subtract(&direction.multiply(2.0)), direction);
+        let xs = get_object(tri_id).intersect(&r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert!(!comps.inside);
+        assert_eq!(comps.normalv, n);
+        // Seen from the back, the normal is flipped along with the face
+        let r = Ray::new(Tuple::point(0.0, 0.5, 2.0), Tuple::vector(0.0, 0.0, -1.0));
+        let xs = get_object(tri_id).intersect(&r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert!(comps.inside);
+        assert_eq!(comps.normalv, n.negate());
+    }
+}
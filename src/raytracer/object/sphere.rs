@@ -1,8 +1,8 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
 use crate::raytracer::object::db::get_next_id;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -25,7 +25,7 @@ use crate::tuple::Tuple;
 pub struct Sphere {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
 }
 
@@ -40,7 +40,7 @@ impl Sphere {
         Sphere {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
         }
     }
@@ -52,7 +52,7 @@ impl Sphere {
         Sphere {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: m,
         }
     }
@@ -61,19 +61,21 @@ impl Sphere {
 const ORIGIN: Tuple = Tuple { x: 0.0, y: 0.0, z: 0.0, w: 1.0 };
 
 impl Object for Sphere {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let sphere_to_ray = ray.origin.subtract(&ORIGIN);
         let a = ray.direction.dot(&ray.direction);
         let b = 2.0 * ray.direction.dot(&sphere_to_ray);
         let c = sphere_to_ray.dot(&sphere_to_ray) - 1.0;
         let discriminant: f64 = b * b - 4.0 * a * c;
         if discriminant < 0.0 {
-            vec![]
-        } else {
-            let t1: f64 = (-b - discriminant.sqrt()) / (2.0 * a);
-            let t2: f64 = (-b + discriminant.sqrt()) / (2.0 * a);
-            vec![Intersection { t: t1, object: self.id, u: 0.0, v: 0.0},
-                 Intersection { t: t2, object: self.id, u: 0.0, v: 0.0}]
+            return;
+        }
+        let t1: f64 = (-b - discriminant.sqrt()) / (2.0 * a);
+        let t2: f64 = (-b + discriminant.sqrt()) / (2.0 * a);
+        for t in [t1, t2] {
+            if ray.in_range(t) {
+                xs.push(Intersection { t, object: self.id, u: 0.0, v: 0.0});
+            }
         }
     }
 
@@ -81,7 +83,7 @@ impl Object for Sphere {
         local_point.subtract(&Tuple::point(0.0, 0.0, 0.0))
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -89,7 +91,7 @@ impl Object for Sphere {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -123,6 +125,10 @@ impl Object for Sphere {
         self.id == object_id
     }
 
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Sphere)
+    }
+
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
         let theta = point.z.atan2(point.x);
         let phi = (point.y / point.length_squared().sqrt()).acos();
@@ -1,11 +1,21 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
 use crate::raytracer::object::db::get_next_id;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
-use roots::{find_roots_quartic, Roots};
+use roots::find_roots_cubic;
+
+/// How far the bounding sphere used to limit the root search reaches past the torus.
+const BOUND_MARGIN: f64 = 1e-3;
+
+/// The largest value of the torus quartic at a turning point that still counts as the ray touching the
+/// surface. Grazing rays produce a double root there, which a plain sign test would miss.
+const GRAZING_EPSILON: f64 = 1e-9;
+
+/// The most steps taken when narrowing down a root or a turning point of the quartic.
+const MAX_SOLVER_STEPS: usize = 64;
 
 /// A torus is a donut shape. It is defined by two radii: the major radius and the minor radius.
 /// The major radius is the distance from the center of the torus to the center of the tube.
@@ -16,7 +26,7 @@ pub struct Torus {
     pub id: usize,
     pub parent_id: Option<usize>,
     pub minor_radius: f64,
-    pub transform: Matrix,
+    pub transform: Matrix4,
     pub material: Material,
 }
 
@@ -26,70 +36,54 @@ impl Torus {
             id: get_next_id(),
             parent_id: None,
             minor_radius,
-            transform: Matrix::identity(4),
+            transform: Matrix4::identity(),
             material: Material::default(),
         }
     }
 }
 
 impl Object for Torus {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
-        let ox = ray.origin.x;
-        let oy = ray.origin.y;
-        let oz = ray.origin.z;
-        let dx = ray.direction.x;
-        let dy = ray.direction.y;
-        let dz = ray.direction.z;
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
+        // Solve along a unit direction, so the quartic is monic and its values are distances in object space
+        let length = ray.direction.magnitude();
+        if length == 0.0 {
+            return;
+        }
+        let d = ray.direction.multiply(1.0 / length);
 
+        // Only the part of the ray inside the bounding sphere can hit the torus. Starting the search where the
+        // ray enters it keeps the coefficients small for rays that start far away.
         let r = self.minor_radius;
-        let r_sq = r * r;
-
-        let sum_d_sq = dx * dx + dy * dy + dz * dz;
-        let e = ox * ox + oy * oy + oz * oz - r_sq + 1.0;  // R^2 is 1^2 = 1
-        let f = ray.origin.dot(&ray.direction);
-        let four = 4.0;  // 4 * 1^2 = 4
-
-        let a4 = sum_d_sq * sum_d_sq;
-        let a3 = 4.0 * sum_d_sq * f;
-        let a2 = 2.0 * sum_d_sq * e + 4.0 * f * f - four * (dx * dx + dy * dy);
-        let a1 = 4.0 * e * f - 2.0 * four * (ox * dx + oy * dy);
-        let a0 = e * e - four * (ox * ox + oy * oy);
-
-        // Find the roots of the quartic equation
-        let roots = find_roots_quartic(a4, a3, a2, a1, a0);
-
-        let mut intersections = vec![];
-        match roots {
-            Roots::No(_) => {},
-            Roots::One([t]) => {
-                if t > 0.0 {
-                    intersections.push(Intersection { t, object: self.id, u: 0.0, v: 0.0 });
-                }
-            }
-            Roots::Two(ts) => {
-                for t in ts {
-                    if t > 0.0 {
-                        intersections.push(Intersection { t, object: self.id, u: 0.0, v: 0.0 });
-                    }
-                }
-            }
-            Roots::Three(ts) => {
-                for t in ts {
-                    if t > 0.0 {
-                        intersections.push(Intersection { t, object: self.id, u: 0.0, v: 0.0 });
-                    }
-                }
-            }
-            Roots::Four(ts) => {
-                for t in ts {
-                    if t > 0.0 {
-                        intersections.push(Intersection { t, object: self.id, u: 0.0, v: 0.0 });
-                    }
-                }
+        let bound = 1.0 + r + BOUND_MARGIN; // Major radius is 1.0
+        let b = ray.origin.x * d.x + ray.origin.y * d.y + ray.origin.z * d.z;
+        let c = ray.origin.x * ray.origin.x + ray.origin.y * ray.origin.y + ray.origin.z * ray.origin.z - bound * bound;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return;
+        }
+        // The range of the ray narrows the search further, measured along the unit direction
+        let exit = (-b + discriminant.sqrt()).min(ray.t_max * length);
+        let enter = (-b - discriminant.sqrt()).max(0.0).max(ray.t_min * length);
+        if exit <= enter {
+            return;
+        }
+        let o = ray.origin.add(&d.multiply(enter));
+
+        let e = o.x * o.x + o.y * o.y + o.z * o.z - r * r + 1.0; // R^2 is 1^2 = 1
+        let f = o.x * d.x + o.y * d.y + o.z * d.z;
+        let coefficients = [
+            4.0 * f,
+            2.0 * e + 4.0 * f * f - 4.0 * (d.x * d.x + d.y * d.y),
+            4.0 * e * f - 8.0 * (o.x * d.x + o.y * d.y),
+            e * e - 4.0 * (o.x * o.x + o.y * o.y),
+        ];
+
+        for s in quartic_roots_between(&coefficients, exit - enter) {
+            let t = (enter + s) / length;
+            if ray.in_range(t) {
+                xs.push(Intersection { t, object: self.id, u: 0.0, v: 0.0 });
             }
         }
-
-        intersections
     }
 
     fn local_normal_at(&self, local_point: &Tuple, _hit: &Intersection) -> Tuple {
@@ -104,7 +98,11 @@ impl Object for Torus {
         normal.normalize()
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Torus(self.minor_radius))
+    }
+
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
@@ -112,7 +110,7 @@ impl Object for Torus {
         &self.material
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
@@ -159,21 +157,206 @@ impl Object for Torus {
 
         (u, v)
     }
+
+    fn local_tangents_at(&self, local_point: &Tuple, _hit: &Intersection) -> (Tuple, Tuple) {
+        // The derivatives of the point on the surface with respect to the angles behind u and v
+        let dist = (local_point.x * local_point.x + local_point.y * local_point.y).sqrt();
+        let dpdu = Tuple::vector(-local_point.y, local_point.x, 0.0).multiply(2.0 * std::f64::consts::PI);
+        let dpdv = Tuple::vector(
+            -local_point.z * local_point.x / dist,
+            -local_point.z * local_point.y / dist,
+            dist - 1.0,
+        ).multiply(2.0 * std::f64::consts::PI);
+        (dpdu, dpdv)
+    }
+}
+
+/// Evaluates the monic quartic `s^4 + c[0] s^3 + c[1] s^2 + c[2] s + c[3]` and its derivative.
+fn quartic(c: &[f64; 4], s: f64) -> (f64, f64) {
+    let value = (((s + c[0]) * s + c[1]) * s + c[2]) * s + c[3];
+    let slope = ((4.0 * s + 3.0 * c[0]) * s + 2.0 * c[1]) * s + c[2];
+    (value, slope)
+}
+
+/// Finds the roots of a monic quartic between 0 (exclusive) and `end`, in increasing order.
+///
+/// The turning points of the quartic split the range into pieces on which it only rises or falls, so each
+/// piece holds at most one root, which is narrowed down by Newton steps kept inside its bracket. A turning
+/// point where the quartic is within `GRAZING_EPSILON` of zero is a grazing hit, reported twice as the double
+/// root it is so that inside and outside still alternate along the ray.
+///
+/// # Arguments
+///
+/// * `c` - The coefficients of `s^3`, `s^2`, `s` and the constant term.
+/// * `end` - The end of the range to search.
+///
+/// # Returns
+///
+/// The roots found in the range.
+fn quartic_roots_between(c: &[f64; 4], end: f64) -> Vec<f64> {
+    let mut points = vec![0.0];
+    for &s in find_roots_cubic(4.0, 3.0 * c[0], 2.0 * c[1], c[2]).as_ref() {
+        let s = polish_turning_point(c, s);
+        if s > 0.0 && s < end {
+            points.push(s);
+        }
+    }
+    points.push(end);
+    points.sort_by(f64::total_cmp);
+    let values: Vec<f64> = points.iter().map(|&s| quartic(c, s).0).collect();
+
+    let mut roots = vec![];
+    for i in 0..points.len() - 1 {
+        if i > 0 && values[i].abs() < GRAZING_EPSILON {
+            roots.push(points[i]);
+            roots.push(points[i]);
+        }
+        let (low, high) = (values[i], values[i + 1]);
+        if low.abs() >= GRAZING_EPSILON && high.abs() >= GRAZING_EPSILON && (low < 0.0) != (high < 0.0) {
+            roots.push(bracketed_root(c, points[i], points[i + 1], low));
+        }
+    }
+    roots
+}
+
+/// Narrows down the root of a quartic between two points where it has opposite signs.
+///
+/// Newton steps are used while they stay inside the bracket, and bisection otherwise.
+///
+/// # Arguments
+///
+/// * `c` - The coefficients of the monic quartic.
+/// * `low` - The start of the bracket.
+/// * `high` - The end of the bracket.
+/// * `low_value` - The value of the quartic at `low`.
+///
+/// # Returns
+///
+/// The root.
+fn bracketed_root(c: &[f64; 4], mut low: f64, mut high: f64, low_value: f64) -> f64 {
+    let mut s = 0.5 * (low + high);
+    for _ in 0..MAX_SOLVER_STEPS {
+        let (value, slope) = quartic(c, s);
+        if value == 0.0 {
+            return s;
+        }
+        if (value < 0.0) == (low_value < 0.0) {
+            low = s;
+        } else {
+            high = s;
+        }
+        let newton = s - value / slope;
+        let next = if newton > low && newton < high { newton } else { 0.5 * (low + high) };
+        if (next - s).abs() <= 1e-12 * (1.0 + s.abs()) {
+            return next;
+        }
+        s = next;
+    }
+    s
+}
+
+/// Refines a turning point of a quartic with Newton steps on its derivative.
+fn polish_turning_point(c: &[f64; 4], start: f64) -> f64 {
+    let mut s = start;
+    for _ in 0..MAX_SOLVER_STEPS {
+        let slope = quartic(c, s).1;
+        let curvature = (12.0 * s + 6.0 * c[0]) * s + 2.0 * c[1];
+        if curvature == 0.0 {
+            break;
+        }
+        let step = slope / curvature;
+        s -= step;
+        if step.abs() <= 1e-12 * (1.0 + s.abs()) {
+            break;
+        }
+    }
+    if s.is_finite() { s } else { start }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    use crate::matrix::Matrix;
+    use crate::matrix4::Matrix4;
     use crate::raytracer::camera::Camera;
     use crate::raytracer::light::Light;
     use crate::raytracer::material::Material;
     use crate::raytracer::material::pattern::Pattern;
     use crate::raytracer::object::plane::Plane;
+    use crate::raytracer::intersection::Intersection;
     use crate::raytracer::object::torus::Torus;
+    use crate::raytracer::ray::Ray;
     use crate::raytracer::scene::Scene;
     use crate::tuple::Tuple;
 
+    fn intersect(torus: &Torus, origin: Tuple, direction: Tuple) -> Vec<f64> {
+        use crate::raytracer::object::Object;
+        let mut xs = vec![];
+        torus.local_intersect_into(&Ray::new(origin, direction), &mut xs);
+        xs.iter().map(|x| x.t).collect()
+    }
+
+    #[test]
+    fn a_ray_through_the_hole_hits_the_tube_four_times() {
+        let t = Torus::new(0.25);
+        let xs = intersect(&t, Tuple::point(-3.0, 0.0, 0.0), Tuple::vector(2.0, 0.0, 0.0));
+        assert_eq!(xs.len(), 4);
+        for (x, expected) in xs.iter().zip([0.875, 1.125, 1.875, 2.125]) {
+            assert!((x - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn a_ray_grazing_the_top_of_the_tube_touches_it_twice() {
+        let t = Torus::new(0.25);
+        let xs = intersect(&t, Tuple::point(-3.0, 0.0, 0.25), Tuple::vector(1.0, 0.0, 0.0));
+        assert_eq!(xs.len(), 4);
+        for (x, expected) in xs.iter().zip([2.0, 2.0, 4.0, 4.0]) {
+            assert!((x - expected).abs() < 1e-4);
+        }
+        let xs = intersect(&t, Tuple::point(-3.0, 0.0, 0.25 + 1e-6), Tuple::vector(1.0, 0.0, 0.0));
+        assert!(xs.is_empty());
+        let xs = intersect(&t, Tuple::point(-3.0, 0.0, 0.25 - 1e-6), Tuple::vector(1.0, 0.0, 0.0));
+        assert_eq!(xs.len(), 4);
+    }
+
+    #[test]
+    fn a_ray_from_inside_the_tube_hits_it_on_the_way_out() {
+        let t = Torus::new(0.25);
+        let xs = intersect(&t, Tuple::point(0.0, 1.0, 0.0), Tuple::vector(0.0, 0.0, 1.0));
+        assert_eq!(xs.len(), 1);
+        assert!((xs[0] - 0.25).abs() < 1e-9);
+        assert!(intersect(&t, Tuple::point(0.0, 0.0, 3.0), Tuple::vector(0.0, 0.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn a_ray_with_nan_components_misses_instead_of_panicking() {
+        let t = Torus::new(0.25);
+        assert!(intersect(&t, Tuple::point(f64::NAN, 0.0, 0.0), Tuple::vector(1.0, 0.0, 0.0)).is_empty());
+        assert!(intersect(&t, Tuple::point(-3.0, 0.0, 0.0), Tuple::vector(1.0, f64::NAN, 0.0)).is_empty());
+    }
+
+    #[test]
+    fn tangents_of_a_torus_follow_the_texture_coordinates() {
+        use crate::raytracer::object::Object;
+        let t = Torus::new(0.25);
+        let hit = Intersection { t: 0.0, object: t.id, u: 0.0, v: 0.0 };
+        let (dpdu, dpdv) = t.local_tangents_at(&Tuple::point(1.25, 0.0, 0.0), &hit);
+        assert_eq!(dpdu.normalize(), Tuple::vector(0.0, 1.0, 0.0));
+        assert_eq!(dpdv.normalize(), Tuple::vector(0.0, 0.0, 1.0));
+        let (dpdu, dpdv) = t.local_tangents_at(&Tuple::point(0.0, 1.0, 0.25), &hit);
+        assert_eq!(dpdu.normalize(), Tuple::vector(-1.0, 0.0, 0.0));
+        assert_eq!(dpdv.normalize(), Tuple::vector(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn uv_mapping_of_a_torus() {
+        use crate::raytracer::object::Object;
+        let t = Torus::new(0.25);
+        assert_eq!(t.uv_mapping(&Tuple::point(1.25, 0.0, 0.0)), (0.5, 0.5));
+        assert_eq!(t.uv_mapping(&Tuple::point(0.0, 1.0, 0.25)), (0.75, 0.75));
+        assert_eq!(t.uv_mapping(&Tuple::point(-0.75, 0.0, 0.0)), (1.0, 1.0));
+    }
+
     #[test]
     #[ignore]
     fn test_render_torus() {
@@ -190,53 +373,53 @@ mod tests {
         let from = Tuple::point(0.0, 2.0, -5.0);
         let to = Tuple::point(0.0, 0.0, 0.0);
         let up = Tuple::vector(0.0, 1.0, 0.0);
-        c.transform = Matrix::view_transform(from, to, up);
+        c.transform = Matrix4::view_transform(from, to, up);
 
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(-10.0, 10.0, -10.0), Color::new(1.0, 1.0, 1.0)));
         //w.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(0.5, 0.5, 0.5) * 2.0));
 
         let mut floor = Plane::new();
-        floor.transform = Matrix::translate(0.0, 0.0, 0.0);
-        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                 Matrix::scale(0.1, 0.1, 0.1).multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0)));
+        floor.transform = Matrix4::translate(0.0, 0.0, 0.0);
+        floor.material.pattern = Pattern::stripe(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                 Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                 Matrix4::scale(0.1, 0.1, 0.1).multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0)));
         floor.material.specular = 0.0;
         //w.add_object(Arc::new(floor));
 
         let mut left_wall = Plane::new();
-        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix::identity(4)),
-                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix::identity(4)),
-                                                       Matrix::identity(4)
-                                                           .multiply(&Matrix::translate(124.0, 124.0, 124.0)
-                                                               .multiply(&Matrix::scale(7.0, 7.0, 7.0))
+        left_wall.material.pattern = Pattern::gradient(Pattern::solid(Color::new(1.0, 0.5, 0.5), Matrix4::identity()),
+                                                       Pattern::solid(Color::new(0.5, 1.0, 0.5), Matrix4::identity()),
+                                                       Matrix4::identity()
+                                                           .multiply(&Matrix4::translate(124.0, 124.0, 124.0)
+                                                               .multiply(&Matrix4::scale(7.0, 7.0, 7.0))
                                                            ));
-        left_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / -4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        left_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / -4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
         left_wall.material.specular = 0.0;
         w.add_object(Arc::new(left_wall));
 
         let mut right_wall = Plane::new();
-        right_wall.transform = Matrix::identity(4)
-            .multiply(&Matrix::rotate_y(std::f64::consts::PI / 4.0))
-            .multiply(&Matrix::translate(0.0, 0.0, 5.0))
-            .multiply(&Matrix::rotate_x(std::f64::consts::PI / 2.0))
+        right_wall.transform = Matrix4::identity()
+            .multiply(&Matrix4::rotate_y(std::f64::consts::PI / 4.0))
+            .multiply(&Matrix4::translate(0.0, 0.0, 5.0))
+            .multiply(&Matrix4::rotate_x(std::f64::consts::PI / 2.0))
         ;
-        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix::identity(4));
+        right_wall.material.pattern = Pattern::solid(Color::new(1.0, 0.9, 0.9), Matrix4::identity());
         right_wall.material.specular = 0.0;
         w.add_object(Arc::new(right_wall));
 
         let mut material = Material::default();
-        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix::identity(4));
+        material.pattern = Pattern::solid(Color::new(0.302, 0.71, 0.98), Matrix4::identity());
         let mut torus = Torus::new(0.25 * 1.0);
         torus.material = material.clone();
-        torus.transform = Matrix::identity(4)
-            .multiply(&Matrix::translate(0.0, 0.0, 0.0))
-            //.multiply(&Matrix::scale(0.75, 0.75, 0.75))
-            .multiply(&Matrix::rotate_x(angle))
+        torus.transform = Matrix4::identity()
+            .multiply(&Matrix4::translate(0.0, 0.0, 0.0))
+            //.multiply(&Matrix4::scale(0.75, 0.75, 0.75))
+            .multiply(&Matrix4::rotate_x(angle))
         ;
         w.add_object(Arc::new(torus));
 
@@ -1,8 +1,11 @@
-use crate::matrix::Matrix;
+use std::sync::{Arc, OnceLock};
+
+use crate::color::Color;
+use crate::matrix4::Matrix4;
 use crate::raytracer::intersection::Intersection;
 use crate::raytracer::material::Material;
 use crate::raytracer::object::db::get_next_id;
-use crate::raytracer::object::{AABB, Object};
+use crate::raytracer::object::{AABB, Geometry, Object};
 use crate::raytracer::ray::Ray;
 use crate::tuple::Tuple;
 
@@ -22,23 +25,27 @@ use crate::tuple::Tuple;
 ///   This can be `None` if the triangle does not have a parent.
 /// * `transform`: A transformation matrix that applies translation, rotation, and scaling to the triangle,
 ///   positioning it within the 3D scene.
-/// * `material`: The material properties of the triangle, defining how it interacts with light and shadows
-///   within the scene.
+/// * `materials`: The materials of the slots of the mesh the triangle belongs to, shared by all its triangles so
+///   that each does not keep a copy of its material, or a single material for a triangle of its own.
+/// * `slot`: The slot of the triangle in `materials`, whose material is the material of the triangle.
 /// * `p1`, `p2`, `p3`: The vertices of the triangle, represented as points in space.
 /// * `e1`, `e2`: Edge vectors of the triangle, calculated from the vertices.
 /// * `normal`: The normal vector of the triangle's plane, calculated from the cross product of `e2` and `e1`.
+/// * `colors`: The colors at `p1`, `p2` and `p3`, like those read from an OBJ file, or `None`.
 #[derive(Debug, PartialEq)]
 pub struct Triangle {
     pub id: usize,
     pub parent_id: Option<usize>,
-    pub transform: Matrix,
-    pub material: Material,
+    pub transform: Matrix4,
+    pub materials: Arc<[Material]>,
+    pub slot: u32,
     pub p1: Tuple,
     pub p2: Tuple,
     pub p3: Tuple,
     pub e1: Tuple,
     pub e2: Tuple,
     pub normal: Tuple,
+    pub colors: Option<[Color; 3]>,
 }
 
 /// Implementation of `Triangle` functionalities.
@@ -50,71 +57,102 @@ pub struct Triangle {
 /// allowing them to interact with rays, lights, and other objects in a consistent manner.
 impl Triangle {
     pub fn new(p1: Tuple, p2: Tuple, p3: Tuple) -> Triangle {
+        let normal = p3.subtract(&p1).cross(&p2.subtract(&p1)).normalize();
+        Triangle::with_normal(p1, p2, p3, normal)
+    }
+
+    /// Creates a triangle like `new` whose normal is already known, like one read from a mesh cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `p1`, `p2`, `p3` - The vertices of the triangle.
+    /// * `normal` - The normal of the triangle's plane, as `new` computes it.
+    pub fn with_normal(p1: Tuple, p2: Tuple, p3: Tuple, normal: Tuple) -> Triangle {
         let e1 = p2.subtract(&p1);
         let e2 = p3.subtract(&p1);
-        let normal = e2.cross(&e1).normalize();
         Triangle {
             id: get_next_id(),
             parent_id: None,
-            transform: Matrix::identity(4),
-            material: Material::default(),
+            transform: Matrix4::identity(),
+            materials: default_material_slots(),
+            slot: 0,
             p1,
             p2,
             p3,
             e1,
             e2,
             normal,
+            colors: None,
         }
     }
 }
 
+/// Returns the material slots of triangles that have not been given a material, a single default material
+/// shared by all of them.
+pub(crate) fn default_material_slots() -> Arc<[Material]> {
+    static DEFAULT_MATERIAL_SLOTS: OnceLock<Arc<[Material]>> = OnceLock::new();
+    DEFAULT_MATERIAL_SLOTS.get_or_init(|| Arc::from([Material::default()])).clone()
+}
+
 impl Object for Triangle {
-    fn local_intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    fn local_intersect_into(&self, ray: &Ray, xs: &mut Vec<Intersection>) {
         let dir_cross_e2 = ray.direction.cross(&self.e2);
         let det = self.e1.dot(&dir_cross_e2);
-        if det.abs() < crate::EPSILON {
-            return vec![];
+        // A negative determinant means the ray hits the back of the triangle, the side that `e1 x e2` points away from
+        if det.abs() < crate::EPSILON || (det < 0.0 && !self.get_material().double_sided) {
+            return;
         }
 
         let f = 1.0 / det;
         let p1_to_origin = ray.origin.subtract(&self.p1);
         let u = f * p1_to_origin.dot(&dir_cross_e2);
         if u < 0.0 || u > 1.0 {
-            return vec![];
+            return;
         }
 
         let origin_cross_e1 = p1_to_origin.cross(&self.e1);
         let v = f * ray.direction.dot(&origin_cross_e1);
         if v < 0.0 || (u + v) > 1.0 {
-            return vec![];
+            return;
         }
 
         let t = f * self.e2.dot(&origin_cross_e1);
-        vec![Intersection { t, object: self.id, u, v}]
+        if ray.in_range(t) {
+            xs.push(Intersection { t, object: self.id, u, v});
+        }
     }
 
     fn local_normal_at(&self, _local_point: &Tuple, _hit: &Intersection) -> Tuple {
         self.normal
     }
 
-    fn get_transform(&self) -> &Matrix {
+    fn get_transform(&self) -> &Matrix4 {
         &self.transform
     }
 
     fn get_material(&self) -> &Material {
-        &self.material
+        &self.materials[self.slot as usize]
     }
 
-    fn set_transform(&mut self, transform: Matrix) {
+    fn set_transform(&mut self, transform: Matrix4) {
         self.transform = transform;
     }
 
     fn set_material(&mut self, material: Material) {
-        self.material = material;
+        self.materials = Arc::from([material]);
+        self.slot = 0;
     }
 
     fn debug_string(&self) -> String {
-        format!("Triangle: transform: {:?}, material: {:?}", self.transform, self.material)
+        format!("Triangle: transform: {:?}, material: {:?}", self.transform, self.get_material())
+    }
+
+    fn material_slots(&self) -> Option<&Arc<[Material]>> {
+        Some(&self.materials)
+    }
+
+    fn set_material_slots(&mut self, materials: Arc<[Material]>) {
+        self.materials = materials;
     }
 
     fn get_id(&self) -> usize {
@@ -145,6 +183,19 @@ impl Object for Triangle {
         self.id == object_id
     }
 
+    fn geometry(&self) -> Option<Geometry> {
+        Some(Geometry::Triangle([self.p1, self.p2, self.p3], [self.normal, self.normal, self.normal]))
+    }
+
+    fn vertex_colors(&self) -> Option<&[Color; 3]> {
+        self.colors.as_ref()
+    }
+
+    fn edge_distance(&self, hit: &Intersection) -> Option<f64> {
+        // u and v are barycentric coordinates, the third one is 1 - u - v
+        Some(hit.u.min(hit.v).min(1.0 - hit.u - hit.v))
+    }
+
     fn uv_mapping(&self, point: &Tuple) -> (f64, f64) {
         let v0 = self.p2.subtract(&self.p1);
         let v1 = self.p3.subtract(&self.p1);
@@ -168,11 +219,17 @@ impl Object for Triangle {
 
         (u, v)
     }
+
+    fn local_tangents_at(&self, _local_point: &Tuple, _hit: &Intersection) -> (Tuple, Tuple) {
+        // With the implicit UV mapping, u grows along the first edge and v along the second
+        (self.p2.subtract(&self.p1), self.p3.subtract(&self.p1))
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use crate::raytracer::material::Material;
     use crate::raytracer::object::Object;
     use crate::raytracer::object::triangle::Triangle;
     use crate::raytracer::ray::Ray;
@@ -253,4 +310,19 @@ mod tests {
         assert_eq!(xs.len(), 1);
         assert_eq!(xs[0].t, 2.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_single_sided_triangle_is_only_hit_from_the_front() {
+        let mut t = Triangle::new(
+            Tuple::point(0.0, 1.0, 0.0),
+            Tuple::point(-1.0, 0.0, 0.0),
+            Tuple::point(1.0, 0.0, 0.0),
+        );
+        t.set_material(Material { double_sided: false, ..Material::default() });
+        // (p2 - p1) x (p3 - p1) points along +z, so the front faces +z
+        let back = Ray::new(Tuple::point(0.0, 0.5, -2.0), Tuple::vector(0.0, 0.0, 1.0));
+        let front = Ray::new(Tuple::point(0.0, 0.5, 2.0), Tuple::vector(0.0, 0.0, -1.0));
+        assert_eq!(t.local_intersect(&back).len(), 0);
+        assert_eq!(t.local_intersect(&front).len(), 1);
+    }
+}
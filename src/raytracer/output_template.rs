@@ -0,0 +1,162 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The values that can be put into output file names.
+///
+/// # Fields
+///
+/// * `scene` - The name of the scene file, without its directory and extension.
+/// * `camera` - The name of the camera the scene is rendered with, or `default`.
+/// * `width` - The width of the image in pixels.
+/// * `height` - The height of the image in pixels.
+/// * `frame` - The number of the frame being rendered.
+/// * `seed` - The seed of the render, or `None` if it has none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTokens {
+    pub scene: String,
+    pub camera: String,
+    pub width: usize,
+    pub height: usize,
+    pub frame: usize,
+    pub seed: Option<u64>,
+}
+
+/// Expands the tokens in an output file name, using the current date and time.
+///
+/// See `expand_output_name_at`.
+///
+/// # Arguments
+///
+/// * `template` - The output file name, with tokens in braces.
+/// * `tokens` - The values of the tokens.
+///
+/// # Returns
+///
+/// The file name with every token replaced by its value.
+///
+/// # Panics
+///
+/// Panics if a token is unknown, not closed or has an invalid format.
+pub fn expand_output_name(template: &str, tokens: &OutputTokens) -> String {
+    expand_output_name_at(template, tokens, SystemTime::now())
+}
+
+/// Expands the tokens in an output file name.
+///
+/// Tokens are written in braces: `{scene}`, `{camera}`, `{width}`, `{height}`, `{frame}`, `{seed}`
+/// (empty without a seed), `{date}` as `YYYY-MM-DD` and `{time}` as `HH-MM-SS`, both in UTC. Numbers can
+/// be padded with zeros to a width, as in `{frame:04}`. `{{` and `}}` give literal braces.
+///
+/// # Arguments
+///
+/// * `template` - The output file name, with tokens in braces.
+/// * `tokens` - The values of the tokens.
+/// * `now` - The time used for `{date}` and `{time}`.
+///
+/// # Returns
+///
+/// The file name with every token replaced by its value.
+///
+/// # Panics
+///
+/// Panics if a token is unknown, not closed or has an invalid format.
+pub fn expand_output_name_at(template: &str, tokens: &OutputTokens, now: SystemTime) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        name.push_str(&rest[..start]);
+        if rest[start..].starts_with("{{") || rest[start..].starts_with("}}") {
+            name.push_str(&rest[start..start + 1]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        if rest[start..].starts_with('}') {
+            panic!("unmatched }} in output file name {}", template);
+        }
+        let end = rest[start..].find('}')
+            .unwrap_or_else(|| panic!("unclosed {{ in output file name {}", template)) + start;
+        name.push_str(&expand_token(&rest[start + 1..end], tokens, now));
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+    name
+}
+
+/// Returns the value of a single token, given without its braces.
+fn expand_token(token: &str, tokens: &OutputTokens, now: SystemTime) -> String {
+    let (key, format) = match token.split_once(':') {
+        Some((key, format)) => (key, Some(format)),
+        None => (token, None),
+    };
+    let number = match key {
+        "width" => Some(tokens.width as u64),
+        "height" => Some(tokens.height as u64),
+        "frame" => Some(tokens.frame as u64),
+        "seed" => tokens.seed,
+        _ => None,
+    };
+    let value = match key {
+        "scene" => tokens.scene.clone(),
+        "camera" => tokens.camera.clone(),
+        "date" | "time" => {
+            let (date, time) = utc_date_time(now);
+            if key == "date" { date } else { time }
+        }
+        "width" | "height" | "frame" | "seed" => number.map_or_else(String::new, |n| n.to_string()),
+        _ => panic!("unknown token {{{}}} in output file name", key),
+    };
+    match format {
+        None => value,
+        Some(format) => {
+            let width: usize = format.parse()
+                .unwrap_or_else(|_| panic!("invalid format {{{}}} in output file name, expected a width like {{{}:04}}", token, key));
+            match number {
+                Some(n) => format!("{:0width$}", n, width = width),
+                None => panic!("only numbers can be padded in output file names, not {{{}}}", key),
+            }
+        }
+    }
+}
+
+/// Splits a time into its UTC date, as `YYYY-MM-DD`, and time of day, as `HH-MM-SS`.
+fn utc_date_time(now: SystemTime) -> (String, String) {
+    let seconds = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    // Converts days since 1970-01-01 to a date in the proleptic Gregorian calendar
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (format!("{:04}-{:02}-{:02}", year, month, day),
+     format!("{:02}-{:02}-{:02}", time / 3600, time % 3600 / 60, time % 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{expand_output_name_at, OutputTokens};
+
+    fn tokens() -> OutputTokens {
+        OutputTokens { scene: "room".to_string(), camera: "default".to_string(), width: 800, height: 600, frame: 7, seed: None }
+    }
+
+    #[test]
+    fn tokens_in_output_names_are_expanded() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(expand_output_name_at("renders/{scene}_{width}x{height}_{frame:04}.png", &tokens(), now),
+                   "renders/room_800x600_0007.png");
+        assert_eq!(expand_output_name_at("{date}_{time}{seed}.png", &tokens(), now), "2024-02-29_12-34-56.png");
+        assert_eq!(expand_output_name_at("{{{camera}}}.png", &tokens(), now), "{default}.png");
+        assert_eq!(expand_output_name_at("output.png", &tokens(), now), "output.png");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown token {name}")]
+    fn unknown_tokens_are_rejected() {
+        expand_output_name_at("{name}.png", &tokens(), UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::raytracer::camera::Camera;
+use crate::raytracer::canvas::Canvas;
+use crate::raytracer::scene::Scene;
+
+/// Renders a scene while showing the image in a window as the tiles finish.
+///
+/// The render runs on a background thread while the window is updated a few times per second.
+/// Pressing Esc, or closing the window, aborts the render; the tiles finished so far are kept,
+/// so the partial image can still be saved. The window closes when the render is complete.
+/// If the window cannot be opened the render simply continues without it.
+///
+/// # Arguments
+///
+/// * `camera` - The camera to render the scene with.
+/// * `scene` - A reference to the `Scene` that will be rendered.
+/// * `aa` - The anti-aliasing factor, used to size the window to the final image size.
+///
+/// # Returns
+///
+/// A `Canvas` instance representing the rendered (or partially rendered) image.
+#[cfg(feature = "preview")]
+pub fn render_in_window(camera: &Camera, scene: &Scene, aa: usize) -> Canvas {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::raytracer::progress::CancelToken;
+    use std::thread;
+    use minifb::{Key, ScaleMode, Window, WindowOptions};
+
+    let image = Mutex::new(Canvas::new(camera.hsize, camera.vsize));
+    let cancel = CancelToken::new();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            camera.render_into(scene, &image, None, &cancel);
+            done.store(true, Ordering::Relaxed);
+        });
+
+        let options = WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::AspectRatioStretch,
+            ..WindowOptions::default()
+        };
+        let mut window = match Window::new("rray - press Esc to stop", camera.hsize / aa, camera.vsize / aa, options) {
+            Ok(window) => window,
+            Err(e) => {
+                eprintln!("Unable to open preview window: {}", e);
+                return;
+            }
+        };
+        window.set_target_fps(10);
+
+        while !done.load(Ordering::Relaxed) {
+            if !window.is_open() || window.is_key_down(Key::Escape) {
+                cancel.cancel();
+                break;
+            }
+            let buffer = to_window_buffer(&image.lock().unwrap());
+            window.update_with_buffer(&buffer, camera.hsize, camera.vsize)
+                .expect("Failed to update preview window");
+        }
+    });
+
+    image.into_inner().unwrap()
+}
+
+/// Renders a scene without a window, because rray was built without the `preview` feature.
+#[cfg(not(feature = "preview"))]
+pub fn render_in_window(camera: &Camera, scene: &Scene, _aa: usize) -> Canvas {
+    eprintln!("rray was built without the preview feature, rendering without a window");
+    camera.render(scene)
+}
+
+/// Converts the canvas to the 0RGB pixel format used by the window.
+#[cfg(feature = "preview")]
+fn to_window_buffer(canvas: &Canvas) -> Vec<u32> {
+    canvas.pixels.iter()
+        .map(|c| {
+            let r = (c.r.clamp(0.0, 1.0) * 255.0) as u32;
+            let g = (c.g.clamp(0.0, 1.0) * 255.0) as u32;
+            let b = (c.b.clamp(0.0, 1.0) * 255.0) as u32;
+            (r << 16) | (g << 8) | b
+        })
+        .collect()
+}
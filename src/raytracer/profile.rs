@@ -0,0 +1,75 @@
+/// Opens a span that records how long the rest of the enclosing block takes in the profile.
+///
+/// The span is given a level (`INFO` for the phases of a render, `TRACE` for the shading of single
+/// rays), a name and optionally fields, as with `tracing::span!`. The returned guard closes the span
+/// when it is dropped, so it must be bound to a variable such as `_span`. Without the `profile`
+/// feature this does nothing.
+#[cfg(feature = "profile")]
+macro_rules! profile_span {
+    ($level:ident, $name:literal $(, $($fields:tt)*)?) => {
+        tracing::span!(tracing::Level::$level, $name $(, $($fields)*)?).entered()
+    };
+}
+
+/// Opens a span that records how long the rest of the enclosing block takes in the profile.
+///
+/// This does nothing, because rray was built without the `profile` feature.
+#[cfg(not(feature = "profile"))]
+macro_rules! profile_span {
+    ($level:ident, $name:literal $(, $($fields:tt)*)?) => {
+        $crate::raytracer::profile::NoSpan
+    };
+}
+
+pub(crate) use profile_span;
+
+/// Stands in for a span guard when rray is built without the `profile` feature.
+#[cfg(not(feature = "profile"))]
+pub struct NoSpan;
+
+/// Writes the profile when it is dropped.
+///
+/// # Fields
+///
+/// * `guard` - Flushes the chrome trace to its file when dropped.
+pub struct Profiler {
+    #[cfg(feature = "profile")]
+    #[allow(dead_code)]
+    guard: tracing_chrome::FlushGuard,
+}
+
+/// Starts recording the time spent in the profiled spans to a chrome trace file.
+///
+/// The file can be opened in `chrome://tracing` or <https://ui.perfetto.dev>. It shows the phases of
+/// loading and building the scene, and every rendered tile on the thread that rendered it. With
+/// `shading` set it also shows the intersection and shading phases of every ray, which makes the file
+/// much larger, so it is best used with small images or regions.
+///
+/// # Arguments
+///
+/// * `file` - The path of the trace file.
+/// * `shading` - Whether to also record the shading of every ray.
+///
+/// # Returns
+///
+/// The `Profiler`, which writes the trace when it is dropped.
+#[cfg(feature = "profile")]
+pub fn start_profiling(file: &str, shading: bool) -> Profiler {
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::prelude::*;
+
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .file(file)
+        .include_args(true)
+        .build();
+    let level = if shading { LevelFilter::TRACE } else { LevelFilter::INFO };
+    tracing_subscriber::registry().with(layer.with_filter(level)).init();
+    Profiler { guard }
+}
+
+/// Does not record a profile, because rray was built without the `profile` feature.
+#[cfg(not(feature = "profile"))]
+pub fn start_profiling(_file: &str, _shading: bool) -> Profiler {
+    eprintln!("rray was built without the profile feature, no profile is written");
+    Profiler {}
+}
@@ -0,0 +1,234 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+use indicatif::{ProgressBar, ProgressStyle};
+use crate::raytracer::json::json_string;
+
+/// How rendering progress is reported.
+///
+/// # Variants
+///
+/// * `Bar` - An interactive progress bar on the terminal.
+/// * `Quiet` - No progress output at all.
+/// * `Json` - Newline-delimited JSON events on standard output, for use by other programs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressMode {
+    Bar,
+    Quiet,
+    Json,
+}
+
+/// Tracks the number of rendered pixels and reports it according to a `ProgressMode`.
+///
+/// In JSON mode a `start` event is written when the render begins, a `progress` event each time
+/// another percent of the pixels is done, and a `finish` event at the end, for example:
+///
+/// ```text
+/// {"event":"start","total":480000}
+/// {"event":"progress","done":4800,"total":480000,"percent":1}
+/// {"event":"finish","done":480000,"total":480000,"elapsed_ms":1234}
+/// ```
+pub struct Progress {
+    mode: ProgressMode,
+    total: u64,
+    done: AtomicU64,
+    last_percent: AtomicU64,
+    bar: Option<ProgressBar>,
+    start: Instant,
+}
+
+impl Progress {
+    /// Starts reporting progress for a render of `total` pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - How progress is reported.
+    /// * `total` - The number of pixels that will be rendered.
+    ///
+    /// # Returns
+    ///
+    /// A new `Progress` instance.
+    pub fn new(mode: ProgressMode, total: u64) -> Progress {
+        let bar = match mode {
+            ProgressMode::Bar => Some(ProgressBar::new(total)),
+            _ => None,
+        };
+        if mode == ProgressMode::Json {
+            println!("{{\"event\":\"start\",\"total\":{}}}", total);
+        }
+        Progress { mode, total, done: AtomicU64::new(0), last_percent: AtomicU64::new(0), bar, start: Instant::now() }
+    }
+
+    /// Records that `amount` more pixels have been rendered.
+    ///
+    /// # Returns
+    ///
+    /// The number of pixels rendered so far.
+    pub fn inc(&self, amount: u64) -> u64 {
+        let done = self.done.fetch_add(amount, Ordering::Relaxed) + amount;
+        if let Some(bar) = &self.bar {
+            bar.inc(amount);
+        }
+        if self.mode == ProgressMode::Json && self.total > 0 {
+            let percent = done * 100 / self.total;
+            // Only the thread that moves the percentage forward reports it
+            if self.last_percent.fetch_max(percent, Ordering::Relaxed) < percent {
+                println!("{{\"event\":\"progress\",\"done\":{},\"total\":{},\"percent\":{}}}", done, self.total, percent);
+            }
+        }
+        done
+    }
+
+    /// Finishes reporting, once rendering has stopped.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+        if self.mode == ProgressMode::Json {
+            println!("{{\"event\":\"finish\",\"done\":{},\"total\":{},\"elapsed_ms\":{}}}",
+                     self.done.load(Ordering::Relaxed), self.total, self.start.elapsed().as_millis());
+        }
+    }
+}
+
+// How the loading of large files is reported while a scene is built. Scenes are built on one thread, so each
+// thread keeps its own; it is quiet unless a render sets it.
+thread_local! {
+    static LOAD_PROGRESS_MODE: Cell<ProgressMode> = const { Cell::new(ProgressMode::Quiet) };
+}
+
+/// Sets how the loading of large files is reported on this thread, see `LoadProgress`.
+pub fn set_load_progress_mode(mode: ProgressMode) {
+    LOAD_PROGRESS_MODE.with(|m| m.set(mode));
+}
+
+/// Files smaller than this many bytes load quickly, so their progress is not reported.
+const LOAD_PROGRESS_MIN_BYTES: u64 = 16 << 20;
+
+/// Reports how many bytes of a large file have been read, in the mode set by `set_load_progress_mode`.
+///
+/// In JSON mode a `load` event is written each time another percent of the file is read, for example:
+///
+/// ```text
+/// {"event":"load","file":"city.obj","done":5242880,"total":524288000,"percent":1}
+/// ```
+pub struct LoadProgress {
+    mode: ProgressMode,
+    file: String,
+    total: u64,
+    last_percent: u64,
+    bar: Option<ProgressBar>,
+}
+
+impl LoadProgress {
+    /// Starts reporting the loading of a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The name of the file.
+    /// * `total` - The size of the file in bytes. Nothing is reported for small files.
+    pub fn new(file: &str, total: u64) -> LoadProgress {
+        let mode = if total < LOAD_PROGRESS_MIN_BYTES { ProgressMode::Quiet } else { LOAD_PROGRESS_MODE.with(Cell::get) };
+        let bar = (mode == ProgressMode::Bar).then(|| {
+            let bar = ProgressBar::new(total).with_message(format!("Loading {}", file));
+            if let Ok(style) = ProgressStyle::with_template("{msg} {wide_bar} {bytes}/{total_bytes}") {
+                bar.set_style(style);
+            }
+            bar
+        });
+        LoadProgress { mode, file: file.to_string(), total, last_percent: 0, bar }
+    }
+
+    /// Records that the first `done` bytes of the file have been read.
+    pub fn set(&mut self, done: u64) {
+        if let Some(bar) = &self.bar {
+            bar.set_position(done);
+        }
+        if self.mode == ProgressMode::Json && self.total > 0 {
+            let percent = done * 100 / self.total;
+            if percent > self.last_percent {
+                self.last_percent = percent;
+                println!("{{\"event\":\"load\",\"file\":{},\"done\":{},\"total\":{},\"percent\":{}}}", json_string(&self.file), done, self.total, percent);
+            }
+        }
+    }
+
+    /// Finishes reporting, once the file has been read.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// A tile that has just been rendered, as reported to the callback of `Camera::render_with`.
+///
+/// # Fields
+///
+/// * `x0`, `y0` - The first pixel of the tile.
+/// * `x1`, `y1` - The pixel after the last one of the tile, in each direction.
+/// * `done` - The number of pixels rendered so far, including this tile.
+/// * `total` - The number of pixels the render is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileProgress {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl TileProgress {
+    /// Returns how much of the render is done, from 0.0 to 100.0.
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            100.0
+        } else {
+            self.done as f64 * 100.0 / self.total as f64
+        }
+    }
+}
+
+/// Stops a render from another thread, for example when the user presses a cancel button.
+///
+/// Clones of a token share its state, so one clone can be handed to the render while another is
+/// kept to cancel it. Once cancelled, tiles that have not been started are skipped; the tiles being
+/// rendered are finished.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancels the render the token was given to.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use super::{Progress, ProgressMode};
+
+    #[test]
+    fn progress_counts_rendered_pixels() {
+        let progress = Progress::new(ProgressMode::Quiet, 10);
+        progress.inc(4);
+        progress.inc(6);
+        progress.finish();
+        assert_eq!(progress.done.load(Ordering::Relaxed), 10);
+    }
+}
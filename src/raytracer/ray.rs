@@ -1,14 +1,118 @@
-use crate::matrix::Matrix;
+use crate::matrix4::Matrix4;
 use crate::tuple::Tuple;
 
 /// Represents a ray in 3D space.
 ///
 /// A ray is defined by an origin point and a direction vector. It can be used
 /// to trace paths through a scene, such as for ray tracing algorithms.
+///
+/// Only hits at distances `t` from `t_min` up to, but not including, `t_max` are reported by the
+/// objects it is intersected with. Shadow rays use this to ignore everything beyond the light
+/// without collecting it first. The range is measured in units of the direction, so it stays the
+/// same when the ray is transformed into the space of an object.
+///
+/// Camera rays and their reflections also carry `differentials`, the rays through the neighbouring
+/// pixels, so the area of a surface one pixel covers can be found where they hit it.
 #[derive(Debug, Clone)]
 pub struct Ray {
     pub origin: Tuple,
     pub direction: Tuple,
+    pub t_min: f64,
+    pub t_max: f64,
+    pub differentials: Option<RayDifferentials>,
+}
+
+/// The rays through the pixels next to the pixel of a ray, one to the right and one below.
+///
+/// # Fields
+///
+/// * `dx_origin` - The origin of the ray through the next pixel along the row.
+/// * `dx_direction` - The direction of the ray through the next pixel along the row.
+/// * `dy_origin` - The origin of the ray through the next pixel down the column.
+/// * `dy_direction` - The direction of the ray through the next pixel down the column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RayDifferentials {
+    pub dx_origin: Tuple,
+    pub dx_direction: Tuple,
+    pub dy_origin: Tuple,
+    pub dy_direction: Tuple,
+}
+
+impl RayDifferentials {
+    /// Finds how far a hit point moves from one pixel to the next.
+    ///
+    /// The neighbouring rays are intersected with the plane that touches the surface at the hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - The hit point.
+    /// * `normal` - The normal of the surface at the hit.
+    ///
+    /// # Returns
+    ///
+    /// The footprint of the pixel on the surface, or `None` when a neighbouring ray runs along the plane.
+    pub fn footprint(&self, point: &Tuple, normal: &Tuple) -> Option<Footprint> {
+        let offset = |origin: &Tuple, direction: &Tuple| {
+            let denominator = normal.dot(direction);
+            if denominator.abs() < crate::EPSILON {
+                return None;
+            }
+            let t = normal.dot(&point.subtract(origin)) / denominator;
+            Some(origin.add(&direction.multiply(t)).subtract(point))
+        };
+        Some(Footprint {
+            dpdx: offset(&self.dx_origin, &self.dx_direction)?,
+            dpdy: offset(&self.dy_origin, &self.dy_direction)?,
+        })
+    }
+
+    /// Finds the differentials of a ray reflected at a hit, treating the surface as flat around the hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `footprint` - The footprint of the pixel at the hit, from `footprint`.
+    /// * `point` - The hit point.
+    /// * `normal` - The normal the ray is reflected about.
+    ///
+    /// # Returns
+    ///
+    /// The differentials of the reflected ray.
+    pub fn reflect(&self, footprint: &Footprint, point: &Tuple, normal: &Tuple) -> RayDifferentials {
+        RayDifferentials {
+            dx_origin: point.add(&footprint.dpdx),
+            dx_direction: self.dx_direction.reflect(normal),
+            dy_origin: point.add(&footprint.dpdy),
+            dy_direction: self.dy_direction.reflect(normal),
+        }
+    }
+}
+
+/// The area of a surface covered by one pixel, as the change of the hit point between neighbouring pixels.
+///
+/// Patterns average their colors over it, so fine details far away blend together instead of flickering.
+///
+/// # Fields
+///
+/// * `dpdx` - How far the hit point moves to the next pixel along the row.
+/// * `dpdy` - How far the hit point moves to the next pixel down the column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    pub dpdx: Tuple,
+    pub dpdy: Tuple,
+}
+
+impl Footprint {
+    /// Transforms the footprint by a given matrix, such as into the space of an object or a pattern.
+    pub fn transform(&self, matrix: &Matrix4) -> Footprint {
+        Footprint { dpdx: matrix.multiply_tuple(&self.dpdx), dpdy: matrix.multiply_tuple(&self.dpdy) }
+    }
+
+    /// Returns the largest extent of the footprint along each axis, the width of a box that filters it.
+    pub fn extent(&self) -> Tuple {
+        Tuple::vector(self.dpdx.x.abs().max(self.dpdy.x.abs()),
+                      self.dpdx.y.abs().max(self.dpdy.y.abs()),
+                      self.dpdx.z.abs().max(self.dpdy.z.abs()))
+    }
 }
 
 impl Ray {
@@ -21,9 +125,35 @@ impl Ray {
     ///
     /// # Returns
     ///
-    /// A new instance of `Ray`.
+    /// A new instance of `Ray`, which reports hits at any distance, also behind its origin.
     pub fn new(origin: Tuple, direction: Tuple) -> Ray {
-        Ray { origin, direction }
+        Ray::with_range(origin, direction, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    /// Constructs a new `Ray` that only reports hits at some distances along it.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - A `Tuple` representing the origin point of the ray.
+    /// * `direction` - A `Tuple` representing the direction vector of the ray.
+    /// * `t_min` - The smallest distance at which hits count.
+    /// * `t_max` - The distance from which hits no longer count.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Ray`.
+    pub fn with_range(origin: Tuple, direction: Tuple, t_min: f64, t_max: f64) -> Ray {
+        Ray { origin, direction, t_min, t_max, differentials: None }
+    }
+
+    /// Returns the ray with the given differentials, see `RayDifferentials`.
+    pub fn with_differentials(self, differentials: Option<RayDifferentials>) -> Ray {
+        Ray { differentials, ..self }
+    }
+
+    /// Checks if a hit at distance `t` along the ray counts, see `Ray`.
+    pub fn in_range(&self, t: f64) -> bool {
+        t >= self.t_min && t < self.t_max
     }
 
     /// Calculates the position of a point along the ray at a given distance.
@@ -42,19 +172,24 @@ impl Ray {
     /// Transforms the ray by a given matrix.
     ///
     /// This method applies a transformation to the ray's origin and direction,
-    /// effectively moving, scaling, or rotating the ray in 3D space.
+    /// effectively moving, scaling, or rotating the ray in 3D space. The range of
+    /// distances at which hits count is kept. The differentials are only needed in
+    /// world space and are dropped.
     ///
     /// # Arguments
     ///
-    /// * `matrix` - A `Matrix` representing the transformation to apply.
+    /// * `matrix` - A `Matrix4` representing the transformation to apply.
     ///
     /// # Returns
     ///
     /// A new `Ray` instance representing the transformed ray.
-    pub fn transform(&self, matrix: &Matrix) -> Ray {
+    pub fn transform(&self, matrix: &Matrix4) -> Ray {
         Ray {
             origin: matrix.multiply_tuple(&self.origin),
             direction: matrix.multiply_tuple(&self.direction),
+            t_min: self.t_min,
+            t_max: self.t_max,
+            differentials: None,
         }
     }
 }
@@ -62,8 +197,8 @@ impl Ray {
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    use crate::matrix::Matrix;
-    use super::Ray;
+    use crate::matrix4::Matrix4;
+    use super::{Ray, RayDifferentials};
     use crate::tuple::Tuple;
     use crate::color::Color;
     use crate::raytracer::canvas::Canvas;
@@ -93,16 +228,31 @@ mod tests {
         assert_eq!(r.position(2.5), Tuple::point(4.5, 3.0, 4.0));
     }
 
+    #[test]
+    fn a_ray_only_reports_hits_in_its_range() {
+        use crate::raytracer::object::Object;
+        let mut s = Sphere::new();
+        s.transform = Matrix4::scale(2.0, 2.0, 2.0);
+        let origin = Tuple::point(0.0, 0.0, -5.0);
+        let direction = Tuple::vector(0.0, 0.0, 1.0);
+        let ts = |r: &Ray| s.intersect(r).iter().map(|x| x.t).collect::<Vec<_>>();
+        assert_eq!(ts(&Ray::new(origin, direction)), vec![3.0, 7.0]);
+        assert_eq!(ts(&Ray::with_range(origin, direction, 0.0, 5.0)), vec![3.0]);
+        assert_eq!(ts(&Ray::with_range(origin, direction, 3.5, 7.0)), Vec::<f64>::new());
+        let moved = Ray::with_range(origin, direction, 1.0, 2.0).transform(&Matrix4::scale(3.0, 3.0, 3.0));
+        assert_eq!((moved.t_min, moved.t_max), (1.0, 2.0));
+    }
+
     #[test]
     fn test_transform() {
         let r = Ray::new(Tuple::point(1.0, 2.0, 3.0), Tuple::vector(0.0, 1.0, 0.0));
-        let m = Matrix::translate(3.0, 4.0, 5.0);
+        let m = Matrix4::translate(3.0, 4.0, 5.0);
         let r2 = r.transform(&m);
         assert_eq!(r2.origin, Tuple::point(4.0, 6.0, 8.0));
         assert_eq!(r2.direction, Tuple::vector(0.0, 1.0, 0.0));
 
         let r = Ray::new(Tuple::point(1.0, 2.0, 3.0), Tuple::vector(0.0, 1.0, 0.0));
-        let m = Matrix::scale(2.0, 3.0, 4.0);
+        let m = Matrix4::scale(2.0, 3.0, 4.0);
         let r2 = r.transform(&m);
         assert_eq!(r2.origin, Tuple::point(2.0, 6.0, 12.0));
         assert_eq!(r2.direction, Tuple::vector(0.0, 3.0, 0.0));
@@ -114,7 +264,7 @@ mod tests {
         w.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
         let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
         let mut s = Sphere::new();
-        s.transform = Matrix::translate(0.0, 0.0, 1.0);
+        s.transform = Matrix4::translate(0.0, 0.0, 1.0);
         w.add_object(Arc::new(s));
         let id = w.ids[0];
         let i = Intersection { t: 5.0, object: id, u: 0.0, v: 0.0};
@@ -138,7 +288,7 @@ mod tests {
         let mut canvas = Canvas::new(canvas_pixels, canvas_pixels);
         let color = Color::new(1.0, 0.0, 0.0);
         let mut s = Sphere::new();
-        s.transform = Matrix::scale(1.0, 0.5, 1.0);
+        s.transform = Matrix4::scale(1.0, 0.5, 1.0);
         w.add_object(Arc::new(s));
         let id = w.ids[0];
         let object = get_object(id);
@@ -172,8 +322,8 @@ mod tests {
         let half = wall_size / 2.0;
         let mut canvas = Canvas::new(canvas_pixels, canvas_pixels);
         let mut s = Sphere::new();
-        //s.transform = Matrix::scale(1.0, 0.5, 1.0);
-        s.material.pattern = Pattern::solid(Color::new(1.0, 0.2, 1.0), Matrix::identity(4));
+        //s.transform = Matrix4::scale(1.0, 0.5, 1.0);
+        s.material.pattern = Pattern::solid(Color::new(1.0, 0.2, 1.0), Matrix4::identity());
         w.add_object(Arc::new(s));
         let id = w.ids[0];
         let object = get_object(id);
@@ -258,19 +408,19 @@ mod tests {
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
         let mut a = Sphere::glass_sphere();
-        a.transform = Matrix::scale(2.0, 2.0, 2.0);
+        a.transform = Matrix4::scale(2.0, 2.0, 2.0);
         a.material.refractive_index = 1.5;
         w.add_object(Arc::new(a));
         let aid = w.ids[0];
 
         let mut b = Sphere::glass_sphere();
-        b.transform = Matrix::translate(0.0, 0.0, -0.25);
+        b.transform = Matrix4::translate(0.0, 0.0, -0.25);
         b.material.refractive_index = 2.0;
         w.add_object(Arc::new(b));
         let bid = w.ids[1];
 
         let mut c = Sphere::glass_sphere();
-        c.transform = Matrix::translate(0.0, 0.0, 0.25);
+        c.transform = Matrix4::translate(0.0, 0.0, 0.25);
         c.material.refractive_index = 2.5;
         w.add_object(Arc::new(c));
         let cid = w.ids[2];
@@ -295,13 +445,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn differentials_give_the_footprint_of_a_pixel_and_are_reflected() {
+        let mut w = Scene::new();
+        w.add_object(Arc::new(Plane::new()));
+        let id = w.ids[0];
+        let r = Ray::new(Tuple::point(0.0, 1.0, -1.0), Tuple::vector(0.0, -1.0, 1.0).normalize())
+            .with_differentials(Some(RayDifferentials {
+                dx_origin: Tuple::point(0.0, 1.0, -1.0),
+                dx_direction: Tuple::vector(0.1, -1.0, 1.0).normalize(),
+                dy_origin: Tuple::point(0.0, 1.0, -1.0),
+                dy_direction: Tuple::vector(0.0, -1.0, 1.1).normalize(),
+            }));
+        let xs = vec![Intersection::new(2f64.sqrt(), id, 0.0, 0.0)];
+        let comps = xs[0].prepare_computations(&r, &xs);
+        let footprint = comps.footprint.unwrap();
+        assert_eq!(footprint.dpdx, Tuple::vector(0.1, 0.0, 0.0));
+        assert_eq!(footprint.dpdy, Tuple::vector(0.0, 0.0, 0.1));
+        let reflected = comps.reflected_differentials.unwrap();
+        assert_eq!(reflected.dx_origin, Tuple::point(0.1, 0.0, 0.0));
+        assert_eq!(reflected.dx_direction, Tuple::vector(0.1, 1.0, 1.0).normalize());
+        // Rays without differentials have no footprint
+        let comps = xs[0].prepare_computations(&Ray::new(r.origin, r.direction), &xs);
+        assert!(comps.footprint.is_none());
+    }
+
     #[test]
     fn underpoint_is_offset_below_the_surface() {
         let mut w = Scene::new();
         w.add_light(Light::new_point_light(Tuple::point(0.0, 0.0, -10.0), Color::new(1.0, 1.0, 1.0)));
         let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
         let mut s = Sphere::glass_sphere();
-        s.transform = Matrix::translate(0.0, 0.0, 1.0);
+        s.transform = Matrix4::translate(0.0, 0.0, 1.0);
         w.add_object(Arc::new(s));
         let id = w.ids[0];
         let i = Intersection { t: 5.0, object: id, u: 0.0, v: 0.0};
@@ -0,0 +1,258 @@
+use std::cell::{Cell, RefCell};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::tuple::Tuple;
+
+/// How the 2D samples of area lights, ambient occlusion and environment lighting are placed.
+///
+/// Uniform random samples clump and leave gaps, so the noise they leave fades slowly as more samples are taken.
+/// Low-discrepancy sequences spread their points evenly, so the same number of samples gives less noise. Each pixel
+/// shifts or scrambles the sequence in its own way, so that neighbouring pixels do not repeat the same pattern.
+///
+/// # Variants
+///
+/// * `Random` - Independent uniform random samples.
+/// * `Halton` - The Halton sequence in bases 2 and 3, shifted by a random offset for each pixel.
+/// * `Sobol` - The first two dimensions of the Sobol sequence, scrambled by random bits for each pixel.
+/// * `BlueNoise` - The Sobol sequence, scrambled by bits that change evenly from pixel to pixel, so that the noise
+///   that is left is fine grained, without clumps, and is easily smoothed away by anti-aliasing or the eye.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampler {
+    Random,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+/// The position in the sample sequence of the pixel being rendered.
+///
+/// # Fields
+///
+/// * `sampler` - The sampler of the render.
+/// * `index` - The index of the next point of the sequence.
+/// * `offset` - The shift of the sequence for the pixel, or for Sobol points the bits that are flipped.
+#[derive(Debug, Clone, Copy)]
+struct PixelSequence {
+    sampler: Sampler,
+    index: u32,
+    offset: (f64, f64),
+}
+
+// Each render thread has its own random number generator, so no locking is needed while sampling.
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+    static SEQUENCE: Cell<PixelSequence> = const { Cell::new(PixelSequence { sampler: Sampler::Random, index: 0, offset: (0.0, 0.0) }) };
+}
+
+/// Reseeds the random number generator of the current thread for a specific pixel.
+///
+/// The new state only depends on `seed` and the pixel coordinates, so the random numbers drawn
+/// while tracing a pixel are the same no matter which thread renders it, or in which order the
+/// pixels are rendered. This is what makes seeded renders reproducible.
+///
+/// # Arguments
+///
+/// * `seed` - The render seed.
+/// * `x` - The x-coordinate of the pixel.
+/// * `y` - The y-coordinate of the pixel.
+pub fn seed_pixel(seed: u64, x: usize, y: usize) {
+    let pixel = ((y as u64) << 32) | (x as u64 & 0xffff_ffff);
+    let state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ pixel;
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(state));
+}
+
+/// Starts the sample sequence of the current thread over for a specific pixel.
+///
+/// This is called after `seed_pixel`, so the random offsets of the `Halton` and `Sobol` samplers are
+/// reproducible in seeded renders.
+///
+/// # Arguments
+///
+/// * `sampler` - The sampler of the render.
+/// * `x` - The x-coordinate of the pixel.
+/// * `y` - The y-coordinate of the pixel.
+pub fn start_pixel(sampler: Sampler, x: usize, y: usize) {
+    let offset = match sampler {
+        Sampler::Random => (0.0, 0.0),
+        Sampler::Halton | Sampler::Sobol => (random_range(0.0, 1.0), random_range(0.0, 1.0)),
+        Sampler::BlueNoise => {
+            // Roberts' R2 sequence and Jimenez' interleaved gradient noise, two well spread dither masks
+            let (x, y) = (x as f64, y as f64);
+            ((0.5 + x * 0.754_877_666_246_692_7 + y * 0.569_840_290_998_053_2).fract(),
+             (52.982_918_9 * (0.067_110_56 * x + 0.005_837_15 * y).fract()).fract())
+        }
+    };
+    SEQUENCE.with(|sequence| sequence.set(PixelSequence { sampler, index: 0, offset }));
+}
+
+/// Returns the next point of the sample sequence of the current pixel, see `Sampler`.
+///
+/// With the `Random` sampler, or before `start_pixel` is called on the thread, the point is drawn from the
+/// current thread's random number generator.
+///
+/// # Returns
+///
+/// A point in the unit square `[0, 1) x [0, 1)`.
+pub fn sample_2d() -> (f64, f64) {
+    let mut pixel = SEQUENCE.with(|sequence| sequence.get());
+    let index = pixel.index;
+    pixel.index = pixel.index.wrapping_add(1);
+    SEQUENCE.with(|sequence| sequence.set(pixel));
+    let (u_offset, v_offset) = pixel.offset;
+    match pixel.sampler {
+        Sampler::Random => (random_range(0.0, 1.0), random_range(0.0, 1.0)),
+        Sampler::Halton => ((radical_inverse(2, index) + u_offset).fract(), (radical_inverse(3, index) + v_offset).fract()),
+        // Flipping the same bits of every point keeps the Sobol points evenly spread, where shifting them would not
+        Sampler::Sobol | Sampler::BlueNoise => (
+            to_unit(sobol(index, 0) ^ from_unit(u_offset)),
+            to_unit(sobol(index, 1) ^ from_unit(v_offset)),
+        ),
+    }
+}
+
+/// Converts a number in `[0, 1)` to the 32 bits of its binary fraction.
+fn from_unit(value: f64) -> u32 {
+    (value * 4_294_967_296.0) as u32
+}
+
+/// Converts the 32 bits of a binary fraction to a number in `[0, 1)`.
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / 4_294_967_296.0
+}
+
+/// Mirrors the digits of `index` in `base` around the decimal point, which gives the Halton sequence in that base.
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let mut result = 0.0;
+    let mut scale = 1.0 / base as f64;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+/// Returns the bits of the binary fraction of a point of the Sobol sequence in dimension 0 or 1.
+///
+/// Dimension 0 is the base 2 radical inverse. Dimension 1 uses the direction numbers of the polynomial x + 1,
+/// where each one is the one before xor-ed with itself shifted right by one.
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0u32;
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction = if dimension == 0 { direction >> 1 } else { direction ^ (direction >> 1) };
+    }
+    result
+}
+
+/// Returns a random number in the range `[low, high)` from the current thread's generator.
+///
+/// # Arguments
+///
+/// * `low` - The inclusive lower bound.
+/// * `high` - The exclusive upper bound.
+///
+/// # Returns
+///
+/// A random `f64` between `low` and `high`.
+pub fn random_range(low: f64, high: f64) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(low..high))
+}
+
+/// Returns a random vector inside the unit sphere, with every point equally likely.
+///
+/// # Returns
+///
+/// A vector with a magnitude below 1.
+pub fn random_in_unit_sphere() -> Tuple {
+    loop {
+        let v = Tuple::vector(random_range(-1.0, 1.0), random_range(-1.0, 1.0), random_range(-1.0, 1.0));
+        if v.magnitude() < 1.0 {
+            return v;
+        }
+    }
+}
+
+/// Returns a random direction in the hemisphere around a normal, with a cosine-weighted distribution.
+///
+/// Directions close to the normal are more likely than directions close to the surface, in proportion
+/// to the cosine of the angle to the normal, which matches how much they contribute to diffuse lighting.
+///
+/// # Arguments
+///
+/// * `normal` - The normalized surface normal the hemisphere is centered around.
+///
+/// # Returns
+///
+/// A normalized vector on the same side of the surface as `normal`.
+pub fn cosine_weighted_direction(normal: &Tuple) -> Tuple {
+    // Build an orthonormal basis around the normal
+    let helper = if normal.x.abs() > 0.9 { Tuple::vector(0.0, 1.0, 0.0) } else { Tuple::vector(1.0, 0.0, 0.0) };
+    let tangent = helper.cross(normal).normalize();
+    let bitangent = normal.cross(&tangent);
+
+    // Pick a point on the unit disk and project it up onto the hemisphere
+    let (s, t) = sample_2d();
+    let r = s.sqrt();
+    let theta = t * 2.0 * std::f64::consts::PI;
+    let (x, y) = (r * theta.cos(), r * theta.sin());
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    tangent.multiply(x).add(&bitangent.multiply(y)).add(&normal.multiply(z)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cosine_weighted_direction, random_range, sample_2d, seed_pixel, sobol, start_pixel, to_unit, Sampler};
+    use crate::tuple::Tuple;
+
+    #[test]
+    fn seeding_a_pixel_makes_samples_repeatable() {
+        seed_pixel(42, 3, 7);
+        let first: Vec<f64> = (0..4).map(|_| random_range(0.0, 1.0)).collect();
+        seed_pixel(42, 3, 7);
+        let second: Vec<f64> = (0..4).map(|_| random_range(0.0, 1.0)).collect();
+        assert_eq!(first, second);
+
+        seed_pixel(42, 7, 3);
+        let other: Vec<f64> = (0..4).map(|_| random_range(0.0, 1.0)).collect();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn cosine_weighted_directions_stay_in_the_hemisphere() {
+        let normal = Tuple::vector(0.0, 0.0, -1.0);
+        for _ in 0..100 {
+            let direction = cosine_weighted_direction(&normal);
+            assert!(direction.dot(&normal) >= 0.0);
+            assert!((direction.magnitude() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_fill_the_unit_square_evenly() {
+        assert_eq!((0..4).map(|i| (to_unit(sobol(i, 0)), to_unit(sobol(i, 1)))).collect::<Vec<_>>(),
+                   vec![(0.0, 0.0), (0.5, 0.5), (0.25, 0.75), (0.75, 0.25)]);
+        for sampler in [Sampler::Halton, Sampler::Sobol, Sampler::BlueNoise] {
+            seed_pixel(1, 5, 9);
+            start_pixel(sampler, 5, 9);
+            // Each of the 4 x 4 cells of the square gets one of the first 16 samples
+            let mut cells = [0; 16];
+            for _ in 0..16 {
+                let (u, v) = sample_2d();
+                assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                cells[(u * 4.0) as usize * 4 + (v * 4.0) as usize] += 1;
+            }
+            assert!(cells.iter().all(|&count| count <= 2), "{:?} clumps: {:?}", sampler, cells);
+        }
+        // The random sampler draws from the generator
+        start_pixel(Sampler::Random, 5, 9);
+        seed_pixel(1, 5, 9);
+        let expected = (random_range(0.0, 1.0), random_range(0.0, 1.0));
+        seed_pixel(1, 5, 9);
+        assert_eq!(sample_2d(), expected);
+    }
+}
@@ -1,28 +1,54 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::fs;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use yaml_rust2::{Yaml, YamlLoader};
 use yaml_rust2::yaml::{Array, Hash};
 
 use crate::color::Color;
-use crate::matrix::Matrix;
-use crate::raytracer::camera::Camera;
-use crate::raytracer::light::Light;
-use crate::raytracer::load_obj::load_obj_file;
-use crate::raytracer::material::Material;
-use crate::raytracer::material::pattern::Pattern;
+use crate::matrix4::Matrix4;
+use crate::raytracer::canvas::{output_format, DEFAULT_QUALITY};
+use crate::raytracer::camera::{field_of_view_from_lens, Camera, Exposure, Stereo, StereoOutput};
+use crate::raytracer::film::FilmBuffer;
+use crate::raytracer::environment::Environment;
+use crate::raytracer::generate::{generate_objects, scatter_objects, Layout, Scatter, ScatterRegion};
+use crate::raytracer::light::{AreaLight, Light, LightType};
+use crate::raytracer::load_obj::{load_cached_obj_file, load_obj_file, surface_normals, MeshOptions};
+use crate::raytracer::material::{Bump, DiffuseModel, Material, ShadingModel, SpecularModel};
+use crate::raytracer::material::noise::set_noise_seed;
+use crate::raytracer::material::noise::{NoiseAlgorithm, NoiseSettings, DEFAULT_FREQUENCY};
+use crate::raytracer::material::pattern::{BrickLayout, CompositeOp, Grain, Pattern, PatternType, WorleyDistance};
+use crate::raytracer::material::texture::{load_cube_cross, Texture, TextureFilter, TextureWrap, UvProjection};
+use crate::raytracer::material::texture_cache::{remove_unused_images, texture_memory};
 use crate::raytracer::object::cone::Cone;
 use crate::raytracer::object::csg::{Csg, CsgOperation};
 use crate::raytracer::object::cube::Cube;
 use crate::raytracer::object::cylinder::Cylinder;
 use crate::raytracer::object::group::Group;
-use crate::raytracer::object::Object;
+use crate::raytracer::object::{Geometry, AABB, Object};
+use crate::raytracer::object::db::{clear_global_objects, get_object, number_of_objects, update_object};
+use crate::raytracer::animation::{frame_file_template, scene_fps, scene_frame_rate, scene_time, set_scene_frame, CameraKey, CameraPath, FrameRange, Interpolation, Track};
+use crate::raytracer::output_template::{expand_output_name, OutputTokens};
+use crate::raytracer::profile::profile_span;
 use crate::raytracer::object::plane::Plane;
 use crate::raytracer::object::sphere::Sphere;
 use crate::raytracer::object::torus::Torus;
+use crate::raytracer::object::rounded_box::RoundedBox;
+use crate::raytracer::object::smooth_triangle::SmoothTriangle;
 use crate::raytracer::object::triangle::Triangle;
-use crate::raytracer::scene::Scene;
+use crate::raytracer::preview_window::render_in_window;
+use crate::raytracer::gpu::{render_on_gpu, Backend};
+use crate::raytracer::progress::{set_load_progress_mode, ProgressMode};
+use crate::raytracer::scene_export::export_scene;
+use crate::raytracer::sampling::Sampler;
+use crate::raytracer::sky::Sky;
+use crate::raytracer::sweep::{sweep, Curve};
+use crate::raytracer::scene::{AmbientOcclusion, Background, DebugMode, Fog, FogFalloff, Scene, ShadowMode};
+use crate::raytracer::volume::{Volume, VolumeShape};
 use crate::tuple::Tuple;
 
 fn degrees_to_radians(degrees: f64) -> f64 {
@@ -43,6 +69,19 @@ fn point_from_vec(v: &Array) -> Tuple {
     Tuple::point(x, y, z)
 }
 
+/// Reads the `colors` of a triangle, one color for each of its points, if it has them.
+///
+/// # Panics
+///
+/// Panics if `colors` does not hold three colors.
+fn vertex_colors(shape: &Yaml) -> Option<[Color; 3]> {
+    let colors = shape["colors"].as_vec()?;
+    if colors.len() != 3 {
+        panic!("a triangle needs three colors, one for each point, found {}", colors.len());
+    }
+    Some([0, 1, 2].map(|i| color_from_vec(colors[i].as_vec().unwrap())))
+}
+
 fn vector_from_vec(v: &Array) -> Tuple {
     let x = get_f64(&v[0]);
     let y = get_f64(&v[1]);
@@ -81,15 +120,90 @@ fn get_f64_default(node: &Yaml, default: f64) -> f64 {
     }
 }
 
+/// Reads a count, like a number of samples, which must be a whole number of at least `minimum`.
+///
+/// # Arguments
+///
+/// * `node` - The YAML value of the count.
+/// * `minimum` - The smallest count allowed.
+/// * `name` - What is counted, for the error message.
+///
+/// # Returns
+///
+/// The count, or `None` if it is not set.
+///
+/// # Panics
+///
+/// Panics if the count is not a whole number, or is less than `minimum`.
+fn get_count(node: &Yaml, minimum: usize, name: &str) -> Option<usize> {
+    match node {
+        Yaml::BadValue => None,
+        Yaml::Integer(count) if *count >= 0 && *count as u64 >= minimum as u64 => Some(*count as usize),
+        Yaml::Integer(count) => panic!("{} must be at least {}, got {}", name, minimum, count),
+        _ => panic!("{} must be a whole number, got {:?}", name, node),
+    }
+}
+
+/// Reads a list of names, which can also be given as a single name, or an empty list if the node is missing.
+fn get_string_list(node: &Yaml) -> Vec<String> {
+    match node {
+        Yaml::String(name) => vec![name.clone()],
+        Yaml::Array(names) => names.iter()
+            .map(|name| name.as_str().unwrap_or_else(|| panic!("{:?} is not a name", name)).to_string())
+            .collect(),
+        _ => vec![],
+    }
+}
+
 fn get_f64_hash(hash: &Hash, key: &str) -> f64 {
     let value = &hash[&Yaml::String(key.to_string())];
     get_f64(value)
 }
 
-fn create_camera(doc: &Yaml, width: usize, height: usize) -> Camera {
-    let camera = doc["camera"].as_hash().expect("camera definition not found");
+/// Finds the definition of the camera to render with.
+///
+/// A scene has either a single `camera`, or several named ones in a `cameras` section, or both.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+/// * `name` - The name of the camera in the `cameras` section, or `None` for the default camera: the
+///   `camera` if there is one, and otherwise the first of the `cameras`.
+///
+/// # Returns
+///
+/// The YAML definition of the camera.
+///
+/// # Panics
+///
+/// Panics if there is no camera, or no camera with the given name.
+fn select_camera<'a>(doc: &'a Yaml, name: Option<&str>) -> &'a Yaml {
+    let cameras = doc["cameras"].as_hash();
+    match name {
+        Some(name) => {
+            let camera = &doc["cameras"][name];
+            if camera.is_badvalue() {
+                let names: Vec<&str> = cameras.into_iter().flat_map(|c| c.keys()).filter_map(|k| k.as_str()).collect();
+                panic!("Unknown camera: {} (the scene has: {})", name, names.join(", "));
+            }
+            camera
+        }
+        None if !doc["camera"].is_badvalue() => &doc["camera"],
+        None => cameras.and_then(|c| c.values().next()).expect("camera definition not found"),
+    }
+}
+
+fn create_camera(doc: &Yaml, name: Option<&str>, width: usize, height: usize) -> Camera {
+    let camera = select_camera(doc, name).as_hash().expect("camera definition not found");
     //print_type(camera);
-    let fov = get_f64_hash(camera, "fov");
+    // The field of view is either given directly, or by the lens and the sensor, as in a real camera
+    let fov = match camera.get(&Yaml::String("focal_length".to_string())) {
+        Some(focal_length) => {
+            let sensor_width = camera.get(&Yaml::String("sensor_width".to_string())).map_or(36.0, get_f64);
+            field_of_view_from_lens(get_f64(focal_length), sensor_width, width, height)
+        }
+        None => degrees_to_radians(get_f64_hash(camera, "fov")),
+    };
     let from = camera[&Yaml::String("from".to_string())].as_vec().expect("camera.from not found");
     let to = camera[&Yaml::String("to".to_string())].as_vec().expect("camera.to not found");
     let up = camera[&Yaml::String("up".to_string())].as_vec().expect("camera.up not found");
@@ -97,54 +211,176 @@ fn create_camera(doc: &Yaml, width: usize, height: usize) -> Camera {
     let mut c = Camera::new(
         width,
         height,
-        degrees_to_radians(fov),
+        fov,
     );
 
-    c.transform = Matrix::view_transform(
-        point_from_vec(from),
-        point_from_vec(to),
-        vector_from_vec(up),
-    );
+    let (from, to, up) = (point_from_vec(from), point_from_vec(to), vector_from_vec(up));
+    // An animated camera follows its path instead
+    let (from, to, up) = match &doc["animation"]["camera"] {
+        Yaml::BadValue => (from, to, up),
+        path => create_camera_path(path, scene_frame_rate(), from, to, up).at(scene_time()),
+    };
+    let roll = camera.get(&Yaml::String("roll".to_string())).map_or(0.0, get_f64);
+    c.transform = Matrix4::view_transform_rolled(from, to, up, degrees_to_radians(roll));
+    c.shift_x = camera.get(&Yaml::String("shift_x".to_string())).map_or(0.0, get_f64);
+    c.shift_y = camera.get(&Yaml::String("shift_y".to_string())).map_or(0.0, get_f64);
+    if let Some(stereo) = camera.get(&Yaml::String("stereo".to_string())) {
+        c.stereo = Some(Stereo {
+            interocular: get_f64_default(&stereo["interocular"], 0.065 / scene_unit()),
+            convergence: get_f64(&stereo["convergence"]),
+            output: match stereo["output"].as_str().unwrap_or("anaglyph") {
+                "side_by_side" => StereoOutput::SideBySide,
+                "anaglyph" => StereoOutput::Anaglyph,
+                other => panic!("unknown stereo output {}", other),
+            },
+        });
+    }
+    c.exposure = match camera.get(&Yaml::String("exposure".to_string())) {
+        None => None,
+        Some(Yaml::String(s)) if s == "auto" => Some(Exposure::Auto),
+        Some(ev @ (Yaml::Real(_) | Yaml::Integer(_))) => Some(Exposure::Ev100(get_f64(ev))),
+        Some(_) => panic!("camera exposure must be a number or auto"),
+    };
 
     c
 }
 
+/// Reads the path of the camera from the `camera` entry of the `animation` section.
+///
+/// Each keyframe is given at a `time` in seconds or at a `frame`, and can set `from`, `to` and `up`.
+/// Values a keyframe leaves out are those of the camera.
+///
+/// # Arguments
+///
+/// * `path` - The `camera` entry of the `animation` section.
+/// * `fps` - The number of frames per second the frames of keyframes are counted in, which is that of the
+///   render, set by `--fps` or by the scene.
+/// * `from`, `to`, `up` - The position, the point looked at and the up direction of the camera.
+fn create_camera_path(path: &Yaml, fps: f64, from: Tuple, to: Tuple, up: Tuple) -> CameraPath {
+    let keys = path["keyframes"].as_vec().expect("animation.camera.keyframes not found").iter()
+        .map(|key| CameraKey {
+            time: get_key_time(key, fps),
+            from: key["from"].as_vec().map_or(from, point_from_vec),
+            to: key["to"].as_vec().map_or(to, point_from_vec),
+            up: key["up"].as_vec().map_or(up, vector_from_vec),
+        })
+        .collect();
+    CameraPath::new(keys, create_interpolation(&path["interpolation"]))
+}
+
+fn create_interpolation(interpolation: &Yaml) -> Interpolation {
+    match interpolation.as_str().unwrap_or("catmull_rom") {
+        "catmull_rom" => Interpolation::CatmullRom,
+        "linear" => Interpolation::Linear,
+        other => panic!("unknown interpolation {}", other),
+    }
+}
+
+/// Returns the time in seconds of a keyframe, given by its `time` or by its `frame` counted at `fps` frames
+/// per second.
+fn get_key_time(key: &Yaml, fps: f64) -> f64 {
+    match (&key["time"], &key["frame"]) {
+        (Yaml::BadValue, Yaml::BadValue) => panic!("keyframes need a time or a frame"),
+        (Yaml::BadValue, frame) => get_f64(frame) / fps,
+        (time, _) => get_f64(time),
+    }
+}
+
+// The length of one unit of the scene being built in meters, from its `units` and `scale`. Scenes are
+// built on one thread, so each thread keeps its own.
+thread_local! {
+    static SCENE_UNIT: Cell<f64> = const { Cell::new(1.0) };
+}
+
+/// Returns the length of one unit of the scene being built in meters.
+fn scene_unit() -> f64 {
+    SCENE_UNIT.with(Cell::get)
+}
+
+/// Returns the length of a unit of length in meters.
+///
+/// # Panics
+///
+/// Panics if the unit is not known.
+fn meters_per_unit(units: &Yaml) -> f64 {
+    match units.as_str().expect("units must be a name like meters or inches") {
+        "meters" | "m" => 1.0,
+        "centimeters" | "cm" => 0.01,
+        "millimeters" | "mm" => 0.001,
+        "kilometers" | "km" => 1000.0,
+        "inches" | "in" => 0.0254,
+        "feet" | "ft" => 0.3048,
+        other => panic!("unknown units {}", other),
+    }
+}
+
+/// The luminous efficacy used to convert the power of lights given in watts to lumens, the maximum possible.
+const LUMENS_PER_WATT: f64 = 683.0;
+
 fn create_lights(doc: &Yaml) -> Vec<Light> {
     let mut created_lights: Vec<Light> = vec![];
 
-    let lights = doc["lights"].as_vec().expect("lights not found");
+    // An environment can light the scene on its own
+    let has_environment = !doc["environment"].is_badvalue();
+    let lights = match doc["lights"].as_vec() {
+        Some(lights) => lights.as_slice(),
+        None if has_environment => &[],
+        None => panic!("lights not found"),
+    };
 
-    if lights.is_empty() {
+    if lights.is_empty() && !has_environment {
         panic!("No lights found in scene");
     }
 
     for light in lights {
         let light_type = light["type"].as_str().expect("light.light_type not found");
-        let color = light["color"].as_vec().expect("light.color not found");
+        // A light given in lumens or watts is tinted by its color, and falls off with the distance
+        let power = match (&light["lumens"], &light["watts"]) {
+            (Yaml::BadValue, Yaml::BadValue) => None,
+            (lumens, Yaml::BadValue) => Some(get_f64(lumens)),
+            (Yaml::BadValue, watts) => Some(get_f64(watts) * LUMENS_PER_WATT),
+            _ => panic!("a light can have lumens or watts, not both"),
+        };
+        let color = match power {
+            None => color_from_vec(light["color"].as_vec().expect("light.color not found")),
+            Some(lumens) => {
+                let tint = light["color"].as_vec().map_or(Color::white(), color_from_vec);
+                // The light falls off with the distance in meters
+                tint.multiply(lumens / (4.0 * PI * scene_unit().powi(2)))
+            }
+        };
 
         match light_type {
             "point" => {
                 let position = light["position"].as_vec().expect("light.position not found");
                 created_lights.push(Light::new_point_light(
                     point_from_vec(position),
-                    color_from_vec(color),
+                    color,
                 ));
             }
             "area" => {
                 let corner = point_from_vec(&light["corner"].as_vec().unwrap());
                 let uvec = vector_from_vec(&light["uvec"].as_vec().unwrap());
                 let vvec = vector_from_vec(&light["vvec"].as_vec().unwrap());
-                let level = light["level"].as_i64().unwrap_or(5) as usize;
-                created_lights.push(Light::new_area_light(
+                let level = get_count(&light["level"], 1, "area light level").unwrap_or(5);
+                let area = AreaLight {
                     corner,
                     uvec,
                     vvec,
-                    color_from_vec(color),
-                    level,
-                ));
+                    usteps: get_count(&light["usteps"], 1, "area light usteps").unwrap_or(level),
+                    vsteps: get_count(&light["vsteps"], 1, "area light vsteps").unwrap_or(level),
+                    jitter: light["jitter"].as_bool().unwrap_or(true),
+                    adaptive: light["adaptive"].as_bool().unwrap_or(false),
+                };
+                created_lights.push(Light::from_area(area, color));
             }
             _ => panic!("Unknown light type: {}", light_type),
         }
+        let created = created_lights.last_mut().unwrap();
+        created.diffuse = light["diffuse"].as_bool().unwrap_or(true);
+        created.specular = light["specular"].as_bool().unwrap_or(true);
+        created.groups = get_string_list(&light["groups"]);
+        created.inverse_square = power.is_some();
     }
 
     created_lights
@@ -154,8 +390,8 @@ fn create_csg(shape: &Yaml) -> Arc<dyn Object> {
     let operation: Result<CsgOperation, _> = operation_str.parse();
     let operation = operation.expect(format!("Unknown operation: {}", operation_str).as_str());
     let mut csg = Csg::new(operation);
-    let left = create_shape(&shape["left"]);
-    let right = create_shape(&shape["right"]);
+    let left = create_shape(&inherit_object_properties(shape, &shape["left"]));
+    let right = create_shape(&inherit_object_properties(shape, &shape["right"]));
     csg.set_left(left);
     csg.set_right(right);
     Arc::new(csg)
@@ -167,15 +403,303 @@ fn create_group(shape: &Yaml) -> Arc<dyn Object> {
 
     for child in children {
         let hidden = child["hidden"].as_bool().unwrap_or(false);
-        if !hidden {
-            group.add_child(create_shape(child));
+        if hidden {
+            continue;
+        }
+        if child["generate"].is_badvalue() {
+            group.add_child(create_shape(&inherit_object_properties(shape, child)));
+        } else {
+            let object = inherit_object_properties(shape, &child["object"]);
+            for generated in generate_objects(&create_layout(child), |_, _| create_shape(&object)) {
+                group.add_child(generated);
+            }
+        }
+    }
+    if let Some(threshold) = divide_threshold(shape, None) {
+        group.divide(threshold);
+    }
+
+    Arc::new(group)
+}
+
+/// Creates a group of triangles from a mesh written in the scene file, whose faces share its `vertices`.
+///
+/// Each face lists the indices of its vertices, counted from 0, and faces with more than three vertices are
+/// split into fans of triangles like those of OBJ files. With `normals`, one for each vertex, the triangles are
+/// smooth triangles, and with `colors`, one for each vertex, they have vertex colors. With `recompute_normals`,
+/// the triangles are smooth triangles with normals computed from the surface, like those of OBJ files, instead of
+/// `normals`. The mesh is divided into sub-groups like an OBJ mesh.
+///
+/// # Arguments
+///
+/// * `shape` - The YAML entry of the mesh.
+///
+/// # Returns
+///
+/// The group of triangles, each with the material of the mesh.
+///
+/// # Panics
+///
+/// Panics if a face has fewer than three vertices or one that does not exist or has a negative index, if
+/// there are not as many normals or colors as vertices, or if `recompute_normals` is not valid.
+fn create_mesh(shape: &Yaml) -> Arc<dyn Object> {
+    let vertices: Vec<Tuple> = shape["vertices"].as_vec().expect("vertices not found").iter()
+        .map(|v| point_from_vec(v.as_vec().unwrap()))
+        .collect();
+    let per_vertex = |key: &str| shape[key].as_vec().inspect(|values| {
+        if values.len() != vertices.len() {
+            panic!("a mesh with {} vertices needs as many {}, found {}", vertices.len(), key, values.len());
+        }
+    });
+    let normals: Option<Vec<Tuple>> = per_vertex("normals").map(|normals| normals.iter().map(|n| vector_from_vec(n.as_vec().unwrap())).collect());
+    let colors: Option<Vec<Color>> = per_vertex("colors").map(|colors| colors.iter().map(|c| color_from_vec(c.as_vec().unwrap())).collect());
+    let materials: Arc<[Material]> = Arc::from([create_object_material(shape)]);
+
+    let mut triangles: Vec<[usize; 3]> = vec![];
+    for face in shape["faces"].as_vec().expect("faces not found") {
+        let face: Vec<usize> = face.as_vec().unwrap().iter().map(|i| get_count(i, 0, "mesh vertex index").unwrap()).collect();
+        if face.len() < 3 {
+            panic!("a face of a mesh needs at least three vertices, found {}", face.len());
+        }
+        if let Some(i) = face.iter().find(|i| **i >= vertices.len()) {
+            panic!("a face of a mesh uses vertex {}, but the mesh has {} vertices", i, vertices.len());
+        }
+        triangles.extend((1..face.len() - 1).map(|i| [face[0], face[i], face[i + 1]]));
+    }
+    let corner_normals: Option<Vec<[Tuple; 3]>> = match hard_edge_angle(shape) {
+        Some(angle) => {
+            let corners: Vec<[Tuple; 3]> = triangles.iter().map(|corners| corners.map(|c| vertices[c])).collect();
+            Some(surface_normals(&corners, angle))
+        }
+        None => normals.map(|normals| triangles.iter().map(|corners| corners.map(|c| normals[c])).collect()),
+    };
+
+    let mut mesh = Group::new();
+    for (t, corners) in triangles.iter().enumerate() {
+        let [p1, p2, p3] = corners.map(|c| vertices[c]);
+        let triangle_colors = colors.as_ref().map(|colors| corners.map(|c| colors[c]));
+        let triangle: Arc<dyn Object> = match &corner_normals {
+            Some(normals) => {
+                let [n1, n2, n3] = normals[t];
+                let mut triangle = SmoothTriangle::new(p1, p2, p3, n1, n2, n3);
+                triangle.colors = triangle_colors;
+                triangle.materials = materials.clone();
+                Arc::new(triangle)
+            }
+            None => {
+                let mut triangle = Triangle::new(p1, p2, p3);
+                triangle.colors = triangle_colors;
+                triangle.materials = materials.clone();
+                Arc::new(triangle)
+            }
+        };
+        mesh.add_child(triangle);
+    }
+    if let Some(threshold) = divide_threshold(shape, Some(DEFAULT_DIVIDE_THRESHOLD)) {
+        mesh.divide(threshold);
+    }
+
+    Arc::new(mesh)
+}
+
+/// Returns the greatest common divisor of two numbers, by Euclid's algorithm.
+fn greatest_common_divisor(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { greatest_common_divisor(b, a % b) }
+}
+
+/// Creates a `sweep` object: a group of smooth triangles forming a tube of `radius` (default 0.1) around a
+/// curve, with `segments` pieces along the curve (default 128) and `sides` around it (default 16). The
+/// group is divided into sub-groups like a mesh.
+///
+/// The `curve` is one of:
+/// - helix: `radius` (default 1), `height` (default 1) and `turns` (default 1)
+/// - torus_knot: `p` (default 2), `q` (default 3), `radius` (default 1) and `minor_radius` (default 0.5)
+///
+/// # Panics
+///
+/// Panics if the curve is missing or unknown, a helix has no direction, `p` and `q` of a torus knot have a common
+/// divisor, the radius of the tube is not greater than 0, or there are fewer than 3 segments or sides.
+fn create_sweep(shape: &Yaml) -> Arc<dyn Object> {
+    let entry = &shape["curve"];
+    let curve_type = entry["type"].as_str().expect("sweep needs a curve with a type");
+    let winding = |key: &str, default: i64| {
+        let count = entry[key].as_i64().unwrap_or(default);
+        if count < 1 {
+            panic!("{} of a torus knot must be at least 1, got {}", key, count);
+        }
+        count as u32
+    };
+    let curve = match curve_type {
+        "helix" => {
+            let radius = get_f64_default(&entry["radius"], 1.0);
+            let height = get_f64_default(&entry["height"], 1.0);
+            let turns = get_f64_default(&entry["turns"], 1.0);
+            // The direction of the helix is lost when it neither goes around nor up
+            if height == 0.0 && (radius == 0.0 || turns == 0.0) {
+                panic!("a helix needs a height, or a radius and turns, to have a direction, got radius {}, height {} and turns {}", radius, height, turns);
+            }
+            Curve::Helix { radius, height, turns }
+        }
+        "torus_knot" => {
+            let (p, q) = (winding("p", 2), winding("q", 3));
+            // With a common divisor the curve goes around the same knot more than once
+            if greatest_common_divisor(p, q) > 1 {
+                panic!("p and q of a torus knot must have no common divisor, got {} and {}", p, q);
+            }
+            Curve::TorusKnot {
+                p,
+                q,
+                radius: get_f64_default(&entry["radius"], 1.0),
+                minor_radius: get_f64_default(&entry["minor_radius"], 0.5),
+            }
         }
+        _ => panic!("Unknown sweep curve: {}", curve_type),
+    };
+    let radius = get_f64_default(&shape["radius"], 0.1);
+    if radius.is_nan() || radius <= 0.0 {
+        panic!("the radius of a sweep must be greater than 0, got {}", radius);
+    }
+    let segments = get_count(&shape["segments"], 3, "segments of a sweep").unwrap_or(128);
+    let sides = get_count(&shape["sides"], 3, "sides of a sweep").unwrap_or(16);
+    let tube = sweep(&curve, radius, segments, sides);
+    let materials: Arc<[Material]> = Arc::from([create_object_material(shape)]);
+
+    let mut group = Group::new();
+    for face in &tube.faces {
+        let [p1, p2, p3] = face.map(|i| tube.positions[i]);
+        let [n1, n2, n3] = face.map(|i| tube.normals[i]);
+        let mut triangle = SmoothTriangle::new(p1, p2, p3, n1, n2, n3);
+        triangle.materials = materials.clone();
+        group.add_child(Arc::new(triangle));
+    }
+    if let Some(threshold) = divide_threshold(shape, Some(DEFAULT_DIVIDE_THRESHOLD)) {
+        group.divide(threshold);
+    }
+
+    Arc::new(group)
+}
+
+/// The number of children at which the groups of OBJ meshes are divided into sub-groups, unless set otherwise.
+const DEFAULT_DIVIDE_THRESHOLD: usize = 8;
+
+/// Returns the number of children at which a group is divided into sub-groups, see `Group`, from its
+/// `divide` property: a number, `true` for the default of 8, or `false` to not divide it.
+///
+/// # Arguments
+///
+/// * `shape` - The YAML entry of the group or OBJ file.
+/// * `default` - The threshold used when the entry has no `divide` property.
+fn divide_threshold(shape: &Yaml, default: Option<usize>) -> Option<usize> {
+    match &shape["divide"] {
+        Yaml::BadValue => default,
+        Yaml::Boolean(divide) => divide.then_some(DEFAULT_DIVIDE_THRESHOLD),
+        Yaml::Integer(threshold) if *threshold > 0 => Some(*threshold as usize),
+        _ => panic!("divide must be true, false or a number of children above 0"),
     }
+}
 
+/// Creates a `scatter` object: a group of copies of its `object`, placed, turned and scaled at random.
+///
+/// The copies are placed inside the box between the corners `min` and `max`, or, when a `surface` object is
+/// given, on top of it between the x and z of `min` and `max`, which default to the bounds of the surface.
+/// The surface is part of the group unless it is hidden. `count` gives the number of copies, `rotation` the
+/// largest angles in degrees they are turned by around x, y and z (default `[0, 0, 0]`), `scale` the range of
+/// their sizes (default `[1, 1]`) and `seed` the seed of the random numbers (default 0).
+///
+/// # Panics
+///
+/// Panics if the count is missing or below 1, or the region is missing and cannot be taken from a bounded
+/// surface.
+fn create_scatter(shape: &Yaml) -> Arc<dyn Object> {
+    let count = get_count(&shape["count"], 1, "scatter count").expect("scatter needs a count");
+    let corner = |key: &str| shape[key].as_vec().map(point_from_vec);
+    let region = match &shape["surface"] {
+        Yaml::BadValue => ScatterRegion::Box {
+            min: corner("min").expect("scatter needs min and max corners or a surface"),
+            max: corner("max").expect("scatter needs min and max corners or a surface"),
+        },
+        surface => {
+            let object = create_shape(&inherit_object_properties(shape, surface));
+            let bounds = object.get_aabb();
+            let finite = [bounds.min, bounds.max].iter().all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite());
+            let bounds = finite.then(|| bounds.apply_transform(object.get_transform()));
+            let min = corner("min").or(bounds.map(|b| b.min)).expect("scatter on an unbounded surface needs min and max corners");
+            let max = corner("max").or(bounds.map(|b| b.max)).expect("scatter on an unbounded surface needs min and max corners");
+            // Copies are dropped from above the surface, even when the region ends below its top
+            let top = bounds.map_or(max.y, |b| b.max.y.max(max.y)) + 1.0;
+            ScatterRegion::Surface { object, min, max: Tuple::point(max.x, top, max.z) }
+        }
+    };
+    let rotation = shape["rotation"].as_vec().map_or(Tuple::vector(0.0, 0.0, 0.0), vector_from_vec);
+    let scale = shape["scale"].as_vec().map_or((1.0, 1.0), |scale| (get_f64(&scale[0]), get_f64(&scale[1])));
+    let scatter = Scatter {
+        count,
+        region,
+        rotation: Tuple::vector(rotation.x.to_radians(), rotation.y.to_radians(), rotation.z.to_radians()),
+        scale,
+        seed: shape["seed"].as_i64().unwrap_or(0) as u64,
+    };
+    let object = inherit_object_properties(shape, &shape["object"]);
+    let mut group = Group::new();
+    for copy in scatter_objects(&scatter, |_| create_shape(&object)) {
+        group.add_child(copy);
+    }
+    if let ScatterRegion::Surface { object, .. } = scatter.region {
+        if !shape["surface"]["hidden"].as_bool().unwrap_or(false) {
+            group.add_child(object);
+        }
+    }
     Arc::new(group)
 }
 
-fn create_matrix(transform: &Yaml) -> Matrix {
+/// Creates the layout of a `generate` entry, which places copies of its `object` in the scene.
+///
+/// The `generate` key names the layout, and the other keys give its settings:
+/// - grid: `count` (objects along x, y and z) and `spacing` (default `[1, 1, 1]`)
+/// - box: `count`, `min` and `max` (the corners of the box) and `seed` (default 0)
+/// - plane: `count`, `corner`, `uvec`, `vvec` and `seed` (default 0)
+/// - ring: `count` and `radius` (default 1)
+/// - spiral: `count`, `radius` (default 1) and `end_radius` (default `radius`), `turns` (default 1) and `height` (default 0)
+///
+/// # Panics
+///
+/// Panics if the layout is unknown, a setting without a default is missing, or a count is not a whole
+/// number of at least 1.
+fn create_layout(entry: &Yaml) -> Layout {
+    let layout = entry["generate"].as_str().expect("generate must name a layout");
+    let vector = |key: &str| vector_from_vec(entry[key].as_vec().unwrap_or_else(|| panic!("{} layout needs {}", layout, key)));
+    let point = |key: &str| point_from_vec(entry[key].as_vec().unwrap_or_else(|| panic!("{} layout needs {}", layout, key)));
+    let seed = entry["seed"].as_i64().unwrap_or(0) as u64;
+    let name = format!("{} layout count", layout);
+    let count = || get_count(&entry["count"], 1, &name).unwrap_or_else(|| panic!("{} layout needs a count", layout));
+    match layout {
+        "grid" => {
+            let counts = entry["count"].as_vec().filter(|counts| counts.len() == 3)
+                .expect("grid layout needs a count of objects along x, y and z");
+            let spacing = entry["spacing"].as_vec().map_or(Tuple::vector(1.0, 1.0, 1.0), vector_from_vec);
+            Layout::Grid { counts: [0, 1, 2].map(|i| get_count(&counts[i], 1, "grid layout count").unwrap_or(1)), spacing }
+        }
+        "box" => Layout::Box { count: count(), min: point("min"), max: point("max"), seed },
+        "plane" => Layout::Plane { count: count(), corner: point("corner"), uvec: vector("uvec"), vvec: vector("vvec"), seed },
+        "ring" => Layout::Ring { count: count(), radius: get_f64_default(&entry["radius"], 1.0) },
+        "spiral" => {
+            let start_radius = get_f64_default(&entry["radius"], 1.0);
+            Layout::Spiral {
+                count: count(),
+                start_radius,
+                end_radius: get_f64_default(&entry["end_radius"], start_radius),
+                turns: get_f64_default(&entry["turns"], 1.0),
+                height: get_f64_default(&entry["height"], 0.0),
+            }
+        }
+        _ => panic!("Unknown generate layout: {}", layout),
+    }
+}
+
+fn create_matrix(transform: &Yaml) -> Matrix4 {
+    if !transform["keyframes"].is_badvalue() {
+        return create_animated_matrix(transform);
+    }
     let transform_type = transform["type"].as_str().expect("transform type not found");
     match transform_type {
         "translate" => {
@@ -183,24 +707,17 @@ fn create_matrix(transform: &Yaml) -> Matrix {
             let x = get_f64(&amount[0]);
             let y = get_f64(&amount[1]);
             let z = get_f64(&amount[2]);
-            Matrix::translate(x, y, z)
+            Matrix4::translate(x, y, z)
         }
         "scale" => {
             let amount = transform["amount"].as_vec().expect("amount not found");
             let x = get_f64(&amount[0]);
             let y = get_f64(&amount[1]);
             let z = get_f64(&amount[2]);
-            Matrix::scale(x, y, z)
+            Matrix4::scale(x, y, z)
         }
         "rotate" => {
-            let angle = degrees_to_radians(get_f64(&transform["angle"]));
-            let axis = transform["axis"].as_str().expect("axis not found");
-            match axis {
-                "x" => Matrix::rotate_x(angle),
-                "y" => Matrix::rotate_y(angle),
-                "z" => Matrix::rotate_z(angle),
-                _ => panic!("Unknown axis: {}", axis),
-            }
+            create_rotation(transform, get_f64(&transform["angle"]))
         }
         "shear" => {
             let xy = get_f64(&transform["xy"]);
@@ -209,14 +726,66 @@ fn create_matrix(transform: &Yaml) -> Matrix {
             let yz = get_f64(&transform["yz"]);
             let zx = get_f64(&transform["zx"]);
             let zy = get_f64(&transform["zy"]);
-            Matrix::shear(xy, xz, yx, yz, zx, zy)
+            Matrix4::shear(xy, xz, yx, yz, zx, zy)
+        }
+        "matrix" => {
+            let rows = transform["rows"].as_vec().expect("rows not found");
+            if rows.len() != 4 || rows.iter().any(|row| row.as_vec().map_or(0, |row| row.len()) != 4) {
+                panic!("a matrix transform needs 4 rows of 4 numbers");
+            }
+            let mut m = Matrix4::identity();
+            for (i, row) in rows.iter().enumerate() {
+                for (j, value) in row.as_vec().unwrap().iter().enumerate() {
+                    m.set(i, j, get_f64(value));
+                }
+            }
+            m
         }
         _ => panic!("Unknown transform type: {}", transform_type),
     }
 }
 
-fn create_transforms(transforms: &Array) -> Matrix {
-    let mut m = Matrix::identity(4);
+/// Creates the matrix of a transform whose `amount` or `angle` is given by keyframes, at the time of the frame
+/// being built.
+fn create_animated_matrix(transform: &Yaml) -> Matrix4 {
+    let transform_type = transform["type"].as_str().expect("transform type not found");
+    let keys = transform["keyframes"].as_vec().expect("transform keyframes not found");
+    let interpolation = create_interpolation(&transform["interpolation"]);
+    let (time, fps) = (scene_time(), scene_frame_rate());
+    match transform_type {
+        "translate" | "scale" => {
+            let amount = Track::new(keys.iter()
+                .map(|key| (get_key_time(key, fps), vector_from_vec(key["amount"].as_vec().expect("amount not found"))))
+                .collect(), interpolation).at(time);
+            if transform_type == "translate" {
+                Matrix4::translate(amount.x, amount.y, amount.z)
+            } else {
+                Matrix4::scale(amount.x, amount.y, amount.z)
+            }
+        }
+        "rotate" => {
+            let angle = Track::new(keys.iter()
+                .map(|key| (get_key_time(key, fps), get_f64(&key["angle"])))
+                .collect(), interpolation).at(time);
+            create_rotation(transform, angle)
+        }
+        _ => panic!("{} transforms cannot be animated", transform_type),
+    }
+}
+
+fn create_rotation(transform: &Yaml, degrees: f64) -> Matrix4 {
+    let angle = degrees_to_radians(degrees);
+    let axis = transform["axis"].as_str().expect("axis not found");
+    match axis {
+        "x" => Matrix4::rotate_x(angle),
+        "y" => Matrix4::rotate_y(angle),
+        "z" => Matrix4::rotate_z(angle),
+        _ => panic!("Unknown axis: {}", axis),
+    }
+}
+
+fn create_transforms(transforms: &Array) -> Matrix4 {
+    let mut m = Matrix4::identity();
     for t in transforms.iter().rev() {
         m = m * create_matrix(t);
     }
@@ -245,29 +814,29 @@ fn create_pattern(pattern: &Yaml) -> Pattern {
         "stripe" => {
             Pattern::stripe(get_sub_pattern(&transform, color_a, pattern_a),
                             get_sub_pattern(&transform, color_b, pattern_b),
-                            transform.clone())
+                            transform)
         }
         "gradient" => {
             Pattern::gradient(get_sub_pattern(&transform, color_a, pattern_a),
                               get_sub_pattern(&transform, color_b, pattern_b),
-                              transform.clone())
+                              transform)
         }
         "ring" => {
             Pattern::ring(get_sub_pattern(&transform, color_a, pattern_a),
                           get_sub_pattern(&transform, color_b, pattern_b),
-                          transform.clone())
+                          transform)
         }
         "checker" => {
             Pattern::checker(get_sub_pattern(&transform, color_a, pattern_a),
                               get_sub_pattern(&transform, color_b, pattern_b),
-                              transform.clone())
+                              transform)
         }
         "blend" => {
             let scale = get_f64_default(&pattern["scale"], 0.5);
             Pattern::blend(get_sub_pattern(&transform, color_a, pattern_a),
                            get_sub_pattern(&transform, color_b, pattern_b),
                            scale,
-                           transform.clone())
+                           transform)
         }
         "perturbed" => {
             let scale = get_f64_default(&pattern["scale"], 0.2);
@@ -277,7 +846,8 @@ fn create_pattern(pattern: &Yaml) -> Pattern {
                                scale,
                                octaves as usize,
                                persistence,
-                               transform.clone())
+                               create_noise_settings(pattern),
+                               transform)
         }
         "noise" => {
             let octaves = get_f64_default(&pattern["octaves"], 1.0);
@@ -288,161 +858,2468 @@ fn create_pattern(pattern: &Yaml) -> Pattern {
                            scale,
                            octaves as usize,
                            persistence,
-                           transform.clone())
+                           create_noise_settings(pattern),
+                           transform)
         }
         "image" => {
             let file = pattern["file"].as_str().expect("file not found");
-            Pattern::texture(file, transform.clone())
+            let mut texture = Texture::new(file, create_texture_filter(&pattern["filter"]));
+            texture.wrap = match pattern["wrap"].as_str().unwrap_or("clamp") {
+                "clamp" => TextureWrap::Clamp,
+                "repeat" => TextureWrap::Repeat,
+                "mirror" => TextureWrap::Mirror,
+                other => panic!("unknown texture wrap mode {}", other),
+            };
+            if let Some(scale) = pattern["uv_scale"].as_vec() {
+                texture.scale = (get_f64(&scale[0]), get_f64(&scale[1]));
+            }
+            if let Some(offset) = pattern["uv_offset"].as_vec() {
+                texture.offset = (get_f64(&offset[0]), get_f64(&offset[1]));
+            }
+            texture.projection = create_uv_projection(&pattern["projection"]);
+            Pattern::texture(texture, transform)
+        }
+        "wood" => {
+            let grain = Grain {
+                frequency: get_f64_default(&pattern["frequency"], 4.0),
+                turbulence: get_f64_default(&pattern["turbulence"], 0.1),
+                octaves: get_f64_default(&pattern["octaves"], 3.0) as usize,
+            };
+            Pattern::wood(get_sub_pattern(&transform, color_a, pattern_a),
+                          get_sub_pattern(&transform, color_b, pattern_b),
+                          grain,
+                          transform)
+        }
+        "marble" => {
+            let grain = Grain {
+                frequency: get_f64_default(&pattern["frequency"], 1.0),
+                turbulence: get_f64_default(&pattern["turbulence"], 1.0),
+                octaves: get_f64_default(&pattern["octaves"], 4.0) as usize,
+            };
+            Pattern::marble(get_sub_pattern(&transform, color_a, pattern_a),
+                            get_sub_pattern(&transform, color_b, pattern_b),
+                            grain,
+                            transform)
+        }
+        "brick" => {
+            let size = match pattern["brick_size"].as_vec() {
+                Some(v) => (get_f64(&v[0]), get_f64(&v[1]), get_f64(&v[2])),
+                None => (1.0, 0.5, 0.5),
+            };
+            let layout = BrickLayout {
+                size,
+                mortar: get_f64_default(&pattern["mortar"], 0.05),
+                offset: get_f64_default(&pattern["offset"], 0.5),
+            };
+            Pattern::brick(get_sub_pattern(&transform, color_a, pattern_a),
+                           get_sub_pattern(&transform, color_b, pattern_b),
+                           layout,
+                           transform)
+        }
+        "worley" => {
+            let distance = match pattern["distance"].as_str().unwrap_or("f1") {
+                "f1" => WorleyDistance::F1,
+                "f2" => WorleyDistance::F2,
+                "f2_minus_f1" => WorleyDistance::F2MinusF1,
+                other => panic!("unknown worley distance {}", other),
+            };
+            Pattern::worley(get_sub_pattern(&transform, color_a, pattern_a),
+                            get_sub_pattern(&transform, color_b, pattern_b),
+                            distance,
+                            transform)
         }
-        _ => Pattern::solid(Color::new(0.0, 0.0, 0.0), transform.clone()),
+        "add" | "multiply" | "screen" | "mask" => {
+            let op = match pattern_type {
+                "add" => CompositeOp::Add,
+                "multiply" => CompositeOp::Multiply,
+                "screen" => CompositeOp::Screen,
+                _ => CompositeOp::Mask,
+            };
+            let mask = &pattern["mask"];
+            if op == CompositeOp::Mask && mask.is_badvalue() {
+                panic!("mask pattern needs a mask");
+            }
+            Pattern::composite(op,
+                               get_sub_pattern(&transform, color_a, pattern_a),
+                               get_sub_pattern(&transform, color_b, pattern_b),
+                               if mask.is_badvalue() { None } else { Some(create_pattern(mask)) },
+                               transform)
+        }
+        "uv_checkers" => {
+            Pattern::uv_checkers(get_sub_pattern(&transform, color_a, pattern_a),
+                                 get_sub_pattern(&transform, color_b, pattern_b),
+                                 get_f64_default(&pattern["width"], 2.0),
+                                 get_f64_default(&pattern["height"], 2.0),
+                                 create_uv_projection(&pattern["projection"]),
+                                 transform)
+        }
+        "uv_align_check" => {
+            let color_default = |key: &str, default: Color| match pattern[key].as_vec() {
+                Some(v) => color_from_vec(v),
+                None => default,
+            };
+            let colors = [color_default("main", Color::new(1.0, 1.0, 1.0)),
+                          color_default("ul", Color::new(1.0, 0.0, 0.0)),
+                          color_default("ur", Color::new(1.0, 1.0, 0.0)),
+                          color_default("bl", Color::new(0.0, 1.0, 0.0)),
+                          color_default("br", Color::new(0.0, 1.0, 1.0))];
+            Pattern::uv_align_check(colors, create_uv_projection(&pattern["projection"]), transform)
+        }
+        "cube_map" => {
+            let filter = create_texture_filter(&pattern["filter"]);
+            let faces = match pattern["cross"].as_str() {
+                Some(file) => load_cube_cross(file, filter),
+                None => CUBE_MAP_FACES.map(|face| {
+                    let file = pattern[face].as_str().unwrap_or_else(|| panic!("cube_map {} not found", face));
+                    Texture::new(file, filter)
+                }),
+            };
+            Pattern::cube_map(faces, pattern["inside"].as_bool().unwrap_or(false), transform)
+        }
+        _ => Pattern::solid(Color::new(0.0, 0.0, 0.0), transform),
+    }
+}
+
+/// Reads the `noise`, `frequency` and `w` settings of a noise or perturbed pattern.
+///
+/// `w` moves on by `w_speed` every second of an animation.
+fn create_noise_settings(pattern: &Yaml) -> NoiseSettings {
+    let algorithm = match pattern["noise"].as_str().unwrap_or("perlin") {
+        "perlin" => NoiseAlgorithm::Perlin,
+        "open_simplex2" => NoiseAlgorithm::OpenSimplex2,
+        "value" => NoiseAlgorithm::Value,
+        "cubic" => NoiseAlgorithm::Cubic,
+        other => panic!("unknown noise algorithm {}", other),
+    };
+    NoiseSettings {
+        algorithm,
+        frequency: get_f64_default(&pattern["frequency"], DEFAULT_FREQUENCY),
+        w: get_f64_default(&pattern["w"], 0.0) + get_f64_default(&pattern["w_speed"], 0.0) * scene_time(),
+    }
+}
+
+/// Reads the `projection` setting of a texture space pattern; `None` means the mapping of the shape.
+fn create_uv_projection(projection: &Yaml) -> Option<UvProjection> {
+    match projection.as_str().unwrap_or("shape") {
+        "shape" => None,
+        "spherical" => Some(UvProjection::Spherical),
+        "planar" => Some(UvProjection::Planar),
+        "cylindrical" => Some(UvProjection::Cylindrical),
+        "cubic" => Some(UvProjection::Cubic),
+        other => panic!("unknown uv projection {}", other),
+    }
+}
+
+/// The keys of the images of a cube map pattern, in the order of `CubeFace`.
+const CUBE_MAP_FACES: [&str; 6] = ["left", "front", "right", "back", "up", "down"];
+
+/// Reads the `filter` setting of a texture, which defaults to `nearest`.
+///
+/// # Panics
+///
+/// Panics if the filter is unknown.
+fn create_texture_filter(filter: &Yaml) -> TextureFilter {
+    match filter.as_str().unwrap_or("nearest") {
+        "nearest" => TextureFilter::Nearest,
+        "bilinear" => TextureFilter::Bilinear,
+        "trilinear" => TextureFilter::Trilinear,
+        other => panic!("unknown texture filter {}", other),
     }
 }
 
-fn get_sub_pattern(transform: &Matrix, color: &Yaml, pattern_yaml: &Yaml) -> Pattern {
+fn get_sub_pattern(transform: &Matrix4, color: &Yaml, pattern_yaml: &Yaml) -> Pattern {
     let pattern = if color.is_array() {
-        Pattern::solid(color_from_vec(color.as_vec().unwrap()), transform.clone())
+        Pattern::solid(color_from_vec(color.as_vec().unwrap()), *transform)
     } else {
         create_pattern(pattern_yaml)
     };
     pattern
 }
 
+/// Reads a scalar material channel, which is either a number or a pattern whose brightness gives the value.
+///
+/// # Returns
+///
+/// The scalar value, 1 when the channel is a pattern, and the pattern if there is one.
+fn create_channel(channel: &Yaml, default: f64) -> (f64, Option<Pattern>) {
+    if channel.as_hash().is_some() {
+        (1.0, Some(create_pattern(channel)))
+    } else {
+        (get_f64_default(channel, default), None)
+    }
+}
+
+fn create_diffuse_model(material: &Yaml) -> DiffuseModel {
+    match material["diffuse_model"].as_str().unwrap_or("lambert") {
+        "lambert" => DiffuseModel::Lambert,
+        "oren_nayar" => DiffuseModel::OrenNayar(get_f64_default(&material["sigma"], 0.3)),
+        other => panic!("unknown diffuse model {}", other),
+    }
+}
+
+fn create_specular_model(model: &Yaml) -> SpecularModel {
+    match model.as_str().unwrap_or("phong") {
+        "phong" => SpecularModel::Phong,
+        "blinn_phong" => SpecularModel::BlinnPhong,
+        other => panic!("unknown specular model {}", other),
+    }
+}
+
+fn create_shading_model(model: &Yaml) -> ShadingModel {
+    match model.as_str().unwrap_or("phong") {
+        "phong" => ShadingModel::Phong,
+        "pbr" => ShadingModel::Pbr,
+        other => panic!("unknown shading model {}", other),
+    }
+}
+
+fn create_sky(sky: &Yaml) -> (Sky, f64) {
+    let azimuth = degrees_to_radians(get_f64_default(&sky["sun_azimuth"], 0.0));
+    // A preset can be given on its own, as `sky: noon`, or with settings that change it
+    let (defaults, intensity) = match sky.as_str().or(sky["preset"].as_str()) {
+        Some(name) => Sky::preset(name, azimuth).unwrap_or_else(|| panic!("unknown sky preset {}", name)),
+        None => (Sky::new(degrees_to_radians(45.0), azimuth, 3.0, 0.3), 1.0),
+    };
+    let mut result = Sky::new(
+        degrees_to_radians(get_f64_default(&sky["sun_elevation"], defaults.elevation.to_degrees())),
+        azimuth,
+        get_f64_default(&sky["turbidity"], defaults.turbidity),
+        get_f64_default(&sky["ground_albedo"], defaults.ground_albedo),
+    );
+    result.cloud_cover = get_f64_default(&sky["cloud_cover"], defaults.cloud_cover).clamp(0.0, 1.0);
+    result.temperature = get_f64_default(&sky["temperature"], defaults.temperature);
+    (result, intensity)
+}
+
 fn create_material(material: &Yaml) -> Material {
     let mut m = Material::default();
     if !material.is_badvalue() {
         m.ambient = get_f64_default(&material["ambient"], 0.1);
         m.diffuse = get_f64_default(&material["diffuse"], 0.9);
-        m.specular = get_f64_default(&material["specular"], 0.9);
+        (m.specular, m.channel_maps.specular) = create_channel(&material["specular"], 0.9);
         m.shininess = get_f64_default(&material["shininess"], 200.0);
-        m.reflective = get_f64_default(&material["reflective"], 0.0);
-        m.transparency = get_f64_default(&material["transparency"], 0.0);
+        (m.reflective, m.channel_maps.reflective) = create_channel(&material["reflective"], 0.0);
+        (m.transparency, m.channel_maps.transparency) = create_channel(&material["transparency"], 0.0);
         m.refractive_index = get_f64_default(&material["refractive_index"], 1.0);
+        (m.refraction_roughness, m.channel_maps.refraction_roughness) = create_channel(&material["refraction_roughness"], 0.0);
+        m.refraction_samples = material["refraction_samples"].as_i64().unwrap_or(1).max(1) as usize;
         m.pattern = create_pattern(&material["pattern"]);
+        m.diffuse_model = create_diffuse_model(material);
+        m.specular_model = create_specular_model(&material["specular_model"]);
+        m.model = create_shading_model(&material["model"]);
+        m.metallic = get_f64_default(&material["metallic"], 0.0);
+        m.roughness = get_f64_default(&material["roughness"], 0.5);
+        m.f0 = get_f64_default(&material["f0"], 0.04);
+        m.double_sided = material["double_sided"].as_bool().unwrap_or(true);
+        // A normal map is either just the file, or a file with a filter
+        let normal_map = &material["normal_map"];
+        if let Some(file) = normal_map.as_str() {
+            m.normal_map = Some(Arc::new(Texture::new(file, TextureFilter::Nearest)));
+        } else if let Some(file) = normal_map["file"].as_str() {
+            m.normal_map = Some(Arc::new(Texture::new(file, create_texture_filter(&normal_map["filter"]))));
+        }
+        let bump = &material["bump"];
+        if !bump.is_badvalue() {
+            m.bump = Some(Bump {
+                pattern: create_pattern(&bump["pattern"]),
+                strength: get_f64_default(&bump["strength"], 1.0),
+            });
+        }
     }
     m
 }
 
-fn create_shape(shape: &Yaml) -> Arc<dyn Object> {
-    let object_type = shape["type"].as_str().expect("type not found");
-    let mut s: Arc<dyn Object> = match object_type {
-        "sphere" => Arc::new(Sphere::new()),
-        "glass_sphere" => Arc::new(Sphere::glass_sphere()),
-        "plane" => Arc::new(Plane::new()),
-        "cube" => Arc::new(Cube::new()),
-        "cylinder" => {
-            let minimum = get_f64_default(&shape["minimum"], -f64::INFINITY);
-            let maximum = get_f64_default(&shape["maximum"], f64::INFINITY);
-            let closed = shape["closed"].as_bool().unwrap_or(false);
-            Arc::new(Cylinder::new(minimum, maximum, closed))
-        }
-        "cone" => {
-            let minimum = get_f64_default(&shape["minimum"], -f64::INFINITY);
-            let maximum = get_f64_default(&shape["maximum"], f64::INFINITY);
-            let closed = shape["closed"].as_bool().unwrap_or(false);
-            Arc::new(Cone::new(minimum, maximum, closed))
-        }
-        "triangle" => {
-            let p1 = point_from_vec(&shape["p1"].as_vec().unwrap());
-            let p2 = point_from_vec(&shape["p2"].as_vec().unwrap());
-            let p3 = point_from_vec(&shape["p3"].as_vec().unwrap());
-            Arc::new(Triangle::new(p1, p2, p3))
-        }
-        "torus" => {
-            let minor_radius = get_f64(&shape["minor_radius"]);
-            Arc::new(Torus::new(minor_radius))
-        }
-        "obj_file" => {
-            let file = shape["obj_file"].as_str().unwrap();
-            Arc::new(load_obj_file(file, create_material(&shape["material"])))
-        }
-        "group" => create_group(shape),
-        "csg" => create_csg(shape),
-        _ => panic!("Unknown object type: {}", object_type),
-    };
-    Arc::get_mut(&mut s).unwrap().set_transform(create_transforms(shape["transforms"].as_vec().unwrap_or(&vec![])));
-    Arc::get_mut(&mut s).unwrap().set_material(create_material(&shape["material"]));
-    s
-}
+/// The object properties that groups and CSG objects pass on to their children.
+const INHERITED_OBJECT_PROPERTIES: [&str; 5] = ["cast_shadows", "visible_to_camera", "visible_to_reflections", "shadow_only", "light_groups"];
 
-/// Renders a scene based on YAML string input.
+/// Passes the inheritable object properties of a group or CSG entry on to one of its children.
 ///
-/// This function takes a YAML string that defines a scene, including camera settings, lights, and objects,
-/// and renders it to an image file. The rendering process involves parsing the YAML to extract scene elements,
+/// Properties set on the child itself take precedence over the ones of the parent.
+///
+/// # Arguments
+///
+/// * `parent` - The YAML entry of the group or CSG object.
+/// * `child` - The YAML entry of the child object.
+///
+/// # Returns
+///
+/// A copy of the child entry with the missing properties filled in from the parent.
+fn inherit_object_properties(parent: &Yaml, child: &Yaml) -> Yaml {
+    let mut child_hash = match child.as_hash() {
+        Some(hash) => hash.clone(),
+        None => return child.clone(),
+    };
+    for property in INHERITED_OBJECT_PROPERTIES {
+        let key = Yaml::String(property.to_string());
+        if !child_hash.contains_key(&key) && !parent[property].is_badvalue() {
+            child_hash.insert(key, parent[property].clone());
+        }
+    }
+    Yaml::Hash(child_hash)
+}
+
+/// Creates the material of an object, including the object level properties that are stored in the material.
+fn create_object_material(shape: &Yaml) -> Material {
+    let mut material = create_material(&shape["material"]);
+    material.cast_shadows = shape["cast_shadows"].as_bool().unwrap_or(true);
+    // A shadow only object is not seen at all, it only blocks light
+    let shadow_only = shape["shadow_only"].as_bool().unwrap_or(false);
+    material.visible_to_camera = !shadow_only && shape["visible_to_camera"].as_bool().unwrap_or(true);
+    material.visible_to_reflections = !shadow_only && shape["visible_to_reflections"].as_bool().unwrap_or(true);
+    material.light_groups = get_string_list(&shape["light_groups"]);
+    material
+}
+
+/// The angle in degrees between triangles above which `recompute_normals: true` keeps the edge between them sharp.
+const DEFAULT_HARD_EDGE_ANGLE: f64 = 30.0;
+
+/// Reads the `recompute_normals` of an `obj_file` or `mesh` entry: `true` for `DEFAULT_HARD_EDGE_ANGLE`, or the
+/// angle in degrees between triangles above which the edge between them is kept sharp.
+///
+/// # Returns
+///
+/// The angle, or `None` when the normals are not computed again.
+///
+/// # Panics
+///
+/// Panics if the value is not true, false or an angle from 0 to 180 degrees.
+fn hard_edge_angle(shape: &Yaml) -> Option<f64> {
+    match &shape["recompute_normals"] {
+        Yaml::BadValue => None,
+        Yaml::Boolean(recompute) => recompute.then_some(DEFAULT_HARD_EDGE_ANGLE),
+        angle => match get_f64_default(angle, -1.0) {
+            angle if (0.0..=180.0).contains(&angle) => Some(angle),
+            _ => panic!("recompute_normals must be true, false or an angle from 0 to 180 degrees"),
+        },
+    }
+}
+
+/// Gives a material the object properties stored in another, see `create_object_material`.
+fn copy_object_properties(material: &mut Material, from: &Material) {
+    material.cast_shadows = from.cast_shadows;
+    material.visible_to_camera = from.visible_to_camera;
+    material.visible_to_reflections = from.visible_to_reflections;
+    material.light_groups = from.light_groups.clone();
+}
+
+/// Reads how the triangles of an `obj_file` entry are changed after they are loaded: whether they are turned to
+/// `orient` outward, its `subdivide` steps, those of its `parts`, its `max_triangles` and `recompute_normals`, and
+/// the `materials` of its material slots, which get the object properties of the entry.
+///
+/// # Panics
+///
+/// Panics if a number of steps is negative, `max_triangles` is not above 0, the angle of `recompute_normals` is
+/// not from 0 to 180 degrees or `orient` is not true or false.
+fn create_mesh_options(shape: &Yaml) -> MeshOptions {
+    let steps = |entry: &Yaml| match entry["subdivide"] {
+        Yaml::BadValue => None,
+        Yaml::Integer(steps) if steps >= 0 => Some(steps as usize),
+        _ => panic!("subdivide must be a number of steps of 0 or more"),
+    };
+    let part_subdivisions = shape["parts"].as_hash().into_iter().flatten()
+        .filter_map(|(name, part)| Some((name.as_str()?.to_string(), steps(part)?)))
+        .collect();
+    let max_triangles = match shape["max_triangles"] {
+        Yaml::BadValue => None,
+        Yaml::Integer(max_triangles) if max_triangles > 0 => Some(max_triangles as usize),
+        _ => panic!("max_triangles must be a number of triangles above 0"),
+    };
+    let materials = shape["materials"].as_hash().into_iter().flatten()
+        .map(|(name, material)| {
+            let name = name.as_str().expect("the names of material slots must be strings");
+            let entry = Yaml::Hash(Hash::from_iter([(Yaml::String("material".to_string()), material.clone())]));
+            (name.to_string(), create_object_material(&inherit_object_properties(shape, &entry)))
+        })
+        .collect();
+    let recompute_normals = hard_edge_angle(shape);
+    let orient = match shape["orient"] {
+        Yaml::BadValue => true,
+        Yaml::Boolean(orient) => orient,
+        _ => panic!("orient must be true or false"),
+    };
+    let divide = divide_threshold(shape, Some(DEFAULT_DIVIDE_THRESHOLD));
+    MeshOptions { subdivisions: steps(shape).unwrap_or(0), part_subdivisions, max_triangles, recompute_normals, materials, orient, divide }
+}
+
+/// Gives the named parts of an OBJ model, its groups and objects, the material, transformations and object
+/// properties set for them in the `parts` of its entry.
+///
+/// A part passes on the object properties of the model that it does not set. A part with a `material` gives it to
+/// all its triangles, while the triangles of the others keep the material of the model or of their material slot.
+///
+/// # Arguments
+///
+/// * `mesh` - The group of the model, with a named sub-group for each part.
+/// * `shape` - The YAML entry of the model.
+///
+/// # Panics
+///
+/// Panics if the model has no part with the name of one of the entries.
+fn apply_obj_parts(mesh: &Group, shape: &Yaml) {
+    let Some(parts) = shape["parts"].as_hash() else {
+        return;
+    };
+    for (name, part) in parts {
+        let name = name.as_str().expect("the names of parts must be strings");
+        let id = mesh.child_ids.iter().copied()
+            .find(|id| get_object(*id).name() == Some(name))
+            .unwrap_or_else(|| panic!("{} has no group or object named {}", shape["obj_file"].as_str().unwrap_or_default(), name));
+        let part = inherit_object_properties(shape, part);
+        let material = create_object_material(&part);
+        // The triangles of a part share the materials of their slots with the rest of the model, so the materials
+        // of the part are made once for each list of slots they use
+        let mut part_slots: HashMap<*const Material, Arc<[Material]>> = HashMap::new();
+        // The triangles of a part are already sorted into the sub-groups of its bounding volume hierarchy
+        let mut ids = get_object(id).child_ids();
+        while let Some(triangle) = ids.pop() {
+            let children = get_object(triangle).child_ids();
+            if !children.is_empty() {
+                ids.extend(children);
+                continue;
+            }
+            update_object(triangle, |triangle| {
+                let Some(slots) = triangle.material_slots() else {
+                    return;
+                };
+                let new_slots = part_slots.entry(slots.as_ptr())
+                    .or_insert_with(|| slots.iter()
+                        .map(|slot_material| if part["material"].is_badvalue() {
+                            let mut slot_material = slot_material.clone();
+                            copy_object_properties(&mut slot_material, &material);
+                            slot_material
+                        } else {
+                            material.clone()
+                        })
+                        .collect())
+                    .clone();
+                triangle.set_material_slots(new_slots);
+            });
+        }
+        let transform = create_transforms(part["transforms"].as_vec().unwrap_or(&vec![]));
+        update_object(id, |group| group.set_transform(transform));
+    }
+}
+
+fn create_shape(shape: &Yaml) -> Arc<dyn Object> {
+    let object_type = shape["type"].as_str().expect("type not found");
+    let mut s: Arc<dyn Object> = match object_type {
+        "sphere" => Arc::new(Sphere::new()),
+        "glass_sphere" => Arc::new(Sphere::glass_sphere()),
+        "plane" => {
+            let width = get_f64_default(&shape["width"], f64::INFINITY);
+            let depth = get_f64_default(&shape["depth"], f64::INFINITY);
+            Arc::new(Plane::bounded(width, depth))
+        }
+        "cube" => Arc::new(Cube::new()),
+        "cylinder" => {
+            let minimum = get_f64_default(&shape["minimum"], -f64::INFINITY);
+            let maximum = get_f64_default(&shape["maximum"], f64::INFINITY);
+            let closed = shape["closed"].as_bool().unwrap_or(false);
+            Arc::new(Cylinder::new(minimum, maximum, closed))
+        }
+        "cone" => {
+            let minimum = get_f64_default(&shape["minimum"], -f64::INFINITY);
+            let maximum = get_f64_default(&shape["maximum"], f64::INFINITY);
+            let closed = shape["closed"].as_bool().unwrap_or(false);
+            Arc::new(Cone::new(minimum, maximum, closed))
+        }
+        "triangle" => {
+            let p1 = point_from_vec(&shape["p1"].as_vec().unwrap());
+            let p2 = point_from_vec(&shape["p2"].as_vec().unwrap());
+            let p3 = point_from_vec(&shape["p3"].as_vec().unwrap());
+            let mut triangle = Triangle::new(p1, p2, p3);
+            triangle.colors = vertex_colors(shape);
+            Arc::new(triangle)
+        }
+        "smooth_triangle" => {
+            let p1 = point_from_vec(shape["p1"].as_vec().unwrap());
+            let p2 = point_from_vec(shape["p2"].as_vec().unwrap());
+            let p3 = point_from_vec(shape["p3"].as_vec().unwrap());
+            let n1 = vector_from_vec(shape["n1"].as_vec().unwrap());
+            let n2 = vector_from_vec(shape["n2"].as_vec().unwrap());
+            let n3 = vector_from_vec(shape["n3"].as_vec().unwrap());
+            let mut triangle = SmoothTriangle::new(p1, p2, p3, n1, n2, n3);
+            triangle.colors = vertex_colors(shape);
+            Arc::new(triangle)
+        }
+        "torus" => {
+            let minor_radius = get_f64(&shape["minor_radius"]);
+            Arc::new(Torus::new(minor_radius))
+        }
+        "rounded_box" => Arc::new(RoundedBox::new(get_f64(&shape["radius"]))),
+        "obj_file" => {
+            let file = shape["obj_file"].as_str().unwrap();
+            let options = create_mesh_options(shape);
+            let mesh = if shape["cache"].as_bool().unwrap_or(false) {
+                load_cached_obj_file(file, create_object_material(shape), &options)
+            } else {
+                load_obj_file(file, create_object_material(shape), &options)
+            };
+            let mut mesh = mesh.unwrap_or_else(|e| panic!("{}", e));
+            apply_obj_parts(&mesh, shape);
+            // The triangles of each part were divided as they were loaded, so this sorts the parts
+            if let Some(threshold) = options.divide {
+                let _span = profile_span!(INFO, "divide");
+                mesh.divide(threshold);
+            }
+            Arc::new(mesh)
+        }
+        "group" => create_group(shape),
+        "mesh" => create_mesh(shape),
+        "sweep" => create_sweep(shape),
+        "scatter" => create_scatter(shape),
+        "csg" => create_csg(shape),
+        _ => panic!("Unknown object type: {}", object_type),
+    };
+    let mut transform = create_transforms(shape["transforms"].as_vec().unwrap_or(&vec![]));
+    if !shape["units"].is_badvalue() {
+        // An object given in other units is scaled to the units of the scene before it is transformed
+        let factor = meters_per_unit(&shape["units"]) / scene_unit();
+        transform = transform * Matrix4::scale(factor, factor, factor);
+    }
+    Arc::get_mut(&mut s).unwrap().set_transform(transform);
+    Arc::get_mut(&mut s).unwrap().set_material(create_object_material(shape));
+    s
+}
+
+/// Expands `include` entries found in the scene list of a YAML document.
+///
+/// Every scene entry of the form `- include: other.yaml` is replaced by the contents of the referenced
+/// file, which is resolved relative to `base_dir`. An included file may either be a plain list of scene
+/// objects, or a document with its own `scene` and `lights` sections, in which case its lights are
+/// appended to the lights of the including document. Included files may include other files in turn;
+/// nested paths are resolved relative to the file that contains them.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+/// * `base_dir` - The directory that relative include paths are resolved against.
+///
+/// # Returns
+///
+/// A new YAML document with all includes expanded.
+///
+/// # Panics
+///
+/// Panics if an included file cannot be read or parsed, or if a file ends up including itself.
+fn expand_includes(doc: &Yaml, base_dir: &Path) -> Yaml {
+    let mut include_stack: Vec<PathBuf> = vec![];
+    expand_includes_recursive(doc, base_dir, &mut include_stack)
+}
+
+fn expand_includes_recursive(doc: &Yaml, base_dir: &Path, include_stack: &mut Vec<PathBuf>) -> Yaml {
+    let mut scene: Array = vec![];
+    let mut lights: Array = doc["lights"].as_vec().cloned().unwrap_or_default();
+    let mut templates: Hash = doc["templates"].as_hash().cloned().unwrap_or_default();
+
+    for entry in doc["scene"].as_vec().unwrap_or(&vec![]) {
+        let file = match entry["include"].as_str() {
+            Some(file) => file,
+            None => {
+                scene.push(entry.clone());
+                continue;
+            }
+        };
+        if entry["hidden"].as_bool().unwrap_or(false) {
+            continue;
+        }
+
+        let path = base_dir.join(file);
+        let canonical = fs::canonicalize(&path)
+            .unwrap_or_else(|_| panic!("Included file does not exist: {}", path.display()));
+        if include_stack.contains(&canonical) {
+            panic!("Recursive include of file: {}", path.display());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("Something went wrong reading included file: {}", path.display()));
+        let docs = YamlLoader::load_from_str(&contents)
+            .unwrap_or_else(|e| panic!("Failed to parse included file {}: {}", path.display(), e));
+        let included = match docs.into_iter().next() {
+            Some(Yaml::Array(objects)) => {
+                let mut hash = Hash::new();
+                hash.insert(Yaml::String("scene".to_string()), Yaml::Array(objects));
+                Yaml::Hash(hash)
+            }
+            Some(included) => included,
+            None => continue,
+        };
+
+        include_stack.push(canonical);
+        let include_dir = path.parent().unwrap_or(base_dir).to_path_buf();
+        let included = expand_includes_recursive(&included, &include_dir, include_stack);
+        include_stack.pop();
+
+        scene.extend(included["scene"].as_vec().cloned().unwrap_or_default());
+        lights.extend(included["lights"].as_vec().cloned().unwrap_or_default());
+        for (name, template) in included["templates"].as_hash().cloned().unwrap_or_default() {
+            templates.entry(name).or_insert(template);
+        }
+    }
+
+    let mut hash = doc.as_hash().cloned().unwrap_or_default();
+    hash.insert(Yaml::String("scene".to_string()), Yaml::Array(scene));
+    if !lights.is_empty() {
+        hash.insert(Yaml::String("lights".to_string()), Yaml::Array(lights));
+    }
+    if !templates.is_empty() {
+        hash.insert(Yaml::String("templates".to_string()), Yaml::Hash(templates));
+    }
+    Yaml::Hash(hash)
+}
+
+/// Resolves YAML merge keys (`<<: *anchor`) throughout a document.
+///
+/// The YAML parser already replaces aliases with copies of the anchored node, but it leaves merge keys
+/// untouched. This copies every key of the merged hash (or hashes, when given a list) into the hash
+/// containing the merge key, unless that key is already set explicitly.
+///
+/// # Arguments
+///
+/// * `node` - The YAML node to resolve.
+///
+/// # Returns
+///
+/// A copy of `node` with all merge keys resolved.
+fn resolve_merge_keys(node: &Yaml) -> Yaml {
+    match node {
+        Yaml::Array(items) => Yaml::Array(items.iter().map(resolve_merge_keys).collect()),
+        Yaml::Hash(hash) => {
+            let merge_key = Yaml::String("<<".to_string());
+            let mut resolved = Hash::new();
+            for (key, value) in hash {
+                if *key != merge_key {
+                    resolved.insert(key.clone(), resolve_merge_keys(value));
+                }
+            }
+            let merged: Array = match hash.get(&merge_key) {
+                Some(Yaml::Array(sources)) => sources.clone(),
+                Some(source) => vec![source.clone()],
+                None => vec![],
+            };
+            for source in merged {
+                let source = resolve_merge_keys(&source);
+                let source = source.as_hash().expect("merge key (<<) must refer to a mapping");
+                for (key, value) in source {
+                    resolved.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            Yaml::Hash(resolved)
+        }
+        _ => node.clone(),
+    }
+}
+
+/// Expands `template` references found in the scene list of a YAML document.
+///
+/// Templates are declared in the top-level `templates` section as named shape definitions, in which
+/// any string value of the form `$name` is a placeholder. A scene entry (or group child, or CSG operand)
+/// of the form `template: name` is replaced by a copy of that template, with placeholders replaced by
+/// the values given in the entry's `params`, falling back to the defaults in the template's own `params`.
+/// Any other keys on the entry override those of the template, except `transforms`, which are appended
+/// to the template's transforms.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+///
+/// # Returns
+///
+/// A new YAML document with all template references expanded.
+///
+/// # Panics
+///
+/// Panics if a template is unknown, a placeholder has no value, or a template refers to itself.
+fn expand_templates(doc: &Yaml) -> Yaml {
+    let templates = doc["templates"].as_hash().cloned().unwrap_or_default();
+    let mut template_stack: Vec<String> = vec![];
+    let mut hash = doc.as_hash().cloned().unwrap_or_default();
+    if let Some(scene) = doc["scene"].as_vec() {
+        let scene = scene.iter().map(|entry| expand_template_node(entry, &templates, &mut template_stack)).collect();
+        hash.insert(Yaml::String("scene".to_string()), Yaml::Array(scene));
+    }
+    Yaml::Hash(hash)
+}
+
+fn expand_template_node(node: &Yaml, templates: &Hash, template_stack: &mut Vec<String>) -> Yaml {
+    match node {
+        Yaml::Array(items) => Yaml::Array(items.iter().map(|item| expand_template_node(item, templates, template_stack)).collect()),
+        Yaml::Hash(hash) => match node["template"].as_str() {
+            Some(name) => instantiate_template(name, hash, templates, template_stack),
+            None => Yaml::Hash(hash.iter().map(|(key, value)| (key.clone(), expand_template_node(value, templates, template_stack))).collect()),
+        },
+        _ => node.clone(),
+    }
+}
+
+fn instantiate_template(name: &str, instance: &Hash, templates: &Hash, template_stack: &mut Vec<String>) -> Yaml {
+    if template_stack.iter().any(|n| n == name) {
+        panic!("Recursive use of template: {}", name);
+    }
+    let template = templates.get(&Yaml::String(name.to_string()))
+        .and_then(|t| t.as_hash())
+        .unwrap_or_else(|| panic!("Unknown template: {}", name));
+
+    let params_key = Yaml::String("params".to_string());
+    let mut params = template.get(&params_key).and_then(|p| p.as_hash()).cloned().unwrap_or_default();
+    for (key, value) in instance.get(&params_key).and_then(|p| p.as_hash()).cloned().unwrap_or_default() {
+        params.insert(key, value);
+    }
+
+    let mut body = Hash::new();
+    for (key, value) in template {
+        if *key != params_key {
+            body.insert(key.clone(), substitute_params(value, &params, name));
+        }
+    }
+    let transforms_key = Yaml::String("transforms".to_string());
+    for (key, value) in instance {
+        match key.as_str() {
+            Some("template") | Some("params") => {}
+            Some("transforms") => {
+                let mut transforms = body.get(&transforms_key).and_then(|t| t.as_vec()).cloned().unwrap_or_default();
+                transforms.extend(value.as_vec().cloned().unwrap_or_default());
+                body.insert(transforms_key.clone(), Yaml::Array(transforms));
+            }
+            _ => {
+                body.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    template_stack.push(name.to_string());
+    let expanded = expand_template_node(&Yaml::Hash(body), templates, template_stack);
+    template_stack.pop();
+    expanded
+}
+
+fn substitute_params(node: &Yaml, params: &Hash, template_name: &str) -> Yaml {
+    match node {
+        Yaml::String(value) if value.starts_with('$') => {
+            let param = &value[1..];
+            params.get(&Yaml::String(param.to_string()))
+                .cloned()
+                .unwrap_or_else(|| panic!("Template {} has no value for parameter: {}", template_name, value))
+        }
+        Yaml::Array(items) => Yaml::Array(items.iter().map(|item| substitute_params(item, params, template_name)).collect()),
+        Yaml::Hash(hash) => Yaml::Hash(hash.iter().map(|(key, value)| (key.clone(), substitute_params(value, params, template_name))).collect()),
+        _ => node.clone(),
+    }
+}
+
+/// Replaces references to named materials in the scene list of a YAML document.
+///
+/// Materials are declared in the top-level `materials` section, by name. An object's `material` can be
+/// just the name of one of them, or a mapping with `use: name` whose other keys override the ones of the
+/// named material. Named materials can themselves be based on another one with `use`.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+///
+/// # Returns
+///
+/// A new YAML document in which every material is written out in full.
+///
+/// # Panics
+///
+/// Panics if a material is unknown or is based on itself.
+fn expand_materials(doc: &Yaml) -> Yaml {
+    let materials = doc["materials"].as_hash().cloned().unwrap_or_default();
+    let mut hash = doc.as_hash().cloned().unwrap_or_default();
+    if let Some(scene) = doc["scene"].as_vec() {
+        let scene = scene.iter().map(|entry| expand_material_node(entry, &materials)).collect();
+        hash.insert(Yaml::String("scene".to_string()), Yaml::Array(scene));
+    }
+    Yaml::Hash(hash)
+}
+
+fn expand_material_node(node: &Yaml, materials: &Hash) -> Yaml {
+    match node {
+        Yaml::Array(items) => Yaml::Array(items.iter().map(|item| expand_material_node(item, materials)).collect()),
+        Yaml::Hash(hash) => Yaml::Hash(hash.iter().map(|(key, value)| {
+            let value = if key.as_str() == Some("material") {
+                resolve_material(value, materials, &mut vec![])
+            } else if let (Some("materials"), Yaml::Hash(slots)) = (key.as_str(), value) {
+                // The materials of the material slots of an OBJ file
+                Yaml::Hash(slots.iter().map(|(slot, material)| (slot.clone(), resolve_material(material, materials, &mut vec![]))).collect())
+            } else {
+                expand_material_node(value, materials)
+            };
+            (key.clone(), value)
+        }).collect()),
+        _ => node.clone(),
+    }
+}
+
+fn resolve_material(material: &Yaml, materials: &Hash, material_stack: &mut Vec<String>) -> Yaml {
+    let (name, overrides) = match material {
+        Yaml::String(name) => (name.clone(), Hash::new()),
+        Yaml::Hash(hash) => match material["use"].as_str() {
+            Some(name) => (name.to_string(), hash.clone()),
+            None => return material.clone(),
+        },
+        _ => return material.clone(),
+    };
+    if material_stack.contains(&name) {
+        panic!("Recursive use of material: {}", name);
+    }
+    let named = materials.get(&Yaml::String(name.clone()))
+        .unwrap_or_else(|| panic!("Unknown material: {}", name));
+    material_stack.push(name);
+    let mut resolved = resolve_material(named, materials, material_stack).as_hash().cloned().unwrap_or_default();
+    material_stack.pop();
+    for (key, value) in overrides {
+        if key.as_str() != Some("use") {
+            resolved.insert(key, value);
+        }
+    }
+    Yaml::Hash(resolved)
+}
+
+/// Parses a YAML scene description and expands any `include` directives, merge keys, templates and named materials it contains.
+///
+/// # Arguments
+///
+/// * `contents` - A string slice containing the YAML formatted scene description.
+/// * `base_dir` - The directory that relative include paths are resolved against.
+///
+/// # Returns
+///
+/// The first YAML document found in `contents`, with includes, merge keys, templates and named materials expanded.
+///
+/// # Panics
+///
+/// Panics if the YAML content cannot be parsed, an included file cannot be loaded, or a template or material cannot be expanded.
+pub fn load_scene_doc(contents: &str, base_dir: &Path) -> Yaml {
+    let _span = profile_span!(INFO, "parse_scene");
+    let docs = YamlLoader::load_from_str(contents).unwrap();
+    // Multi document support, doc is a yaml::Yaml
+    let doc = expand_includes(&docs[0], base_dir);
+    let doc = resolve_merge_keys(&doc);
+    expand_materials(&expand_templates(&doc))
+}
+
+/// Settings that control how a scene is rendered, independent of the scene description itself.
+///
+/// # Fields
+///
+/// * `width` - The width of the output image in pixels, before applying anti-aliasing.
+/// * `height` - The height of the output image in pixels, before applying anti-aliasing.
+/// * `aa` - The anti-aliasing factor. A higher value results in smoother edges but increases rendering time.
+/// * `threads` - The number of threads used for rendering, or 0 to use one thread per core.
+/// * `seed` - The seed for noise and random sampling, overriding the `seed` of the scene file.
+/// * `window` - Whether to show the image in a window while it is being rendered.
+/// * `progress` - How rendering progress is reported.
+/// * `debug` - An optional debug visualization to render instead of the shaded image.
+/// * `wireframe` - When set, triangle edges of this barycentric width are drawn over the image.
+/// * `depth` - When set, a normalized grayscale depth map is also written to this file.
+/// * `depth_only` - Whether to write the depth map to the output file instead of rendering the image.
+/// * `depth_far` - The distance that is black in the depth map, or `None` to use the farthest hit.
+/// * `camera` - The name of the camera to render with, from the `cameras` section, or `None` for the default camera.
+/// * `region` - When set, only the pixels from `(x0, y0)` up to but not including `(x1, y1)` are rendered.
+/// * `max_depth` - The number of times rays are followed through reflections and refractions.
+/// * `shadow_samples` - When set, overrides the number of samples along each edge of every area light.
+/// * `max_seconds` - When set, tiles not started within this many seconds are filled with magenta instead of rendered.
+/// * `backend` - Whether the image is rendered on the CPU or, experimentally, on the GPU.
+/// * `quality` - The quality, from 1 to 100, of images written in lossy formats like JPEG.
+/// * `frame` - The number of the frame being rendered, which sets the time of the animation.
+/// * `frames` - When set, every frame of this range is rendered to its own file instead of just `frame`.
+/// * `fps` - The number of frames per second, overriding the `fps` of the scene's `animation` section.
+/// * `denoise` - Whether the noise of sampled effects is smoothed out of the image before it is written.
+/// * `variance` - When set, a heatmap of how noisy each pixel of the image still is is also written to this file.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub width: usize,
+    pub height: usize,
+    pub aa: usize,
+    pub threads: usize,
+    pub seed: Option<u64>,
+    pub window: bool,
+    pub progress: ProgressMode,
+    pub debug: Option<DebugMode>,
+    pub wireframe: Option<f64>,
+    pub depth: Option<String>,
+    pub depth_only: bool,
+    pub depth_far: Option<f64>,
+    pub camera: Option<String>,
+    pub region: Option<(usize, usize, usize, usize)>,
+    pub max_depth: usize,
+    pub shadow_samples: Option<usize>,
+    pub max_seconds: Option<f64>,
+    pub backend: Backend,
+    pub quality: u8,
+    pub frame: usize,
+    pub frames: Option<FrameRange>,
+    pub fps: Option<f64>,
+    pub denoise: bool,
+    pub variance: Option<String>,
+}
+
+impl RenderOptions {
+    /// Creates render options for an image of the given size, without anti-aliasing, using all cores.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the output image in pixels.
+    /// * `height` - The height of the output image in pixels.
+    ///
+    /// # Returns
+    ///
+    /// A new `RenderOptions` instance.
+    #[allow(dead_code)]
+    pub fn new(width: usize, height: usize) -> RenderOptions {
+        RenderOptions { width, height, aa: 1, threads: 0, seed: None, window: false, progress: ProgressMode::Bar, debug: None, wireframe: None, depth: None, depth_only: false, depth_far: None, camera: None, region: None, max_depth: 5, shadow_samples: None, max_seconds: None, backend: Backend::Cpu, quality: DEFAULT_QUALITY, frame: 0, frames: None, fps: None, denoise: false, variance: None }
+    }
+}
+
+/// Renders a scene based on YAML string input.
+///
+/// This function takes a YAML string that defines a scene, including camera settings, lights, and objects,
+/// and renders it to an image file. The rendering process involves parsing the YAML to extract scene elements,
 /// setting up the camera with the specified field of view and transformations, adding lights to the scene,
 /// and creating objects with specified materials and transformations. Finally, it renders the scene using
-/// the camera and saves the rendered image to a file.
+/// the camera and saves the rendered image to a file. Relative `include` paths are resolved against the
+/// current working directory.
+///
+/// # Arguments
+///
+/// * `contents` - A string slice containing the YAML formatted scene description.
+/// * `image_file` - The path where the rendered image will be saved, which may contain tokens like `{frame}`.
+/// * `options` - The image size, anti-aliasing factor and other render settings.
+///
+/// # Panics
+///
+/// This function panics if the YAML content cannot be parsed, if required scene elements like the camera or lights
+/// are not found in the YAML, or if specified objects have unsupported types or missing properties.
+#[allow(dead_code)]
+pub fn render_scene_from_str(contents: &str, image_file: &str, options: &RenderOptions) {
+    let doc = load_scene_doc(contents, Path::new("."));
+    render_scene(&doc, "scene", image_file, options);
+}
+
+fn render_scene(doc: &Yaml, scene_name: &str, image_file: &str, options: &RenderOptions) {
+    set_scene_frame(options.frame, options.fps.unwrap_or_else(|| scene_fps(doc)));
+    set_load_progress_mode(options.progress);
+    let (mut camera, mut scene) = build_scene(doc, options.camera.as_deref(), options.width * options.aa, options.height * options.aa);
+    scene.debug = options.debug;
+    scene.wireframe = options.wireframe;
+    camera.threads = options.threads;
+    camera.progress = options.progress;
+    camera.max_depth = options.max_depth;
+    if let Some(samples) = options.shadow_samples {
+        for light in scene.light.iter_mut() {
+            if let LightType::Area(area) = &mut light.light_type {
+                area.usteps = samples.max(1);
+                area.vsteps = samples.max(1);
+            }
+        }
+    }
+    camera.region = options.region.map(|(x0, y0, x1, y1)| (x0 * options.aa, y0 * options.aa, x1 * options.aa, y1 * options.aa));
+    camera.seed = options.seed.or_else(|| doc["seed"].as_i64().map(|seed| seed as u64));
+    set_noise_seed(camera.seed);
+    camera.deadline = options.max_seconds.map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+    let tokens = OutputTokens {
+        scene: scene_name.to_string(),
+        camera: options.camera.clone().unwrap_or_else(|| "default".to_string()),
+        width: options.width,
+        height: options.height,
+        frame: options.frame,
+        seed: camera.seed,
+    };
+    // Invalid tokens and image formats are reported before the render rather than after it
+    for template in [Some(image_file), options.depth.as_deref(), options.variance.as_deref()].into_iter().flatten() {
+        output_format(&expand_output_name(template, &tokens));
+    }
+    if options.variance.is_some() {
+        assert!(options.aa > 1, "--variance needs --aa of at least 2, the variance is taken over the samples of each pixel");
+        assert!(camera.stereo.is_none(), "--variance is not supported for stereo cameras");
+    }
+    if !options.depth_only {
+        let film = Mutex::new(None);
+        let render = |camera: &Camera| {
+            let image = match options.backend {
+                Backend::Cpu if options.variance.is_some() && !options.window => {
+                    let (image, samples) = camera.render_with_film(&scene, options.aa);
+                    *film.lock().unwrap() = Some(samples);
+                    image
+                }
+                Backend::Gpu => render_on_gpu(camera, &scene),
+                Backend::Cpu if options.window => render_in_window(camera, &scene, options.aa),
+                Backend::Cpu => camera.render(&scene),
+            };
+            if options.variance.is_some() && film.lock().unwrap().is_none() {
+                // The GPU and the preview window do not collect the samples as they go, so they are taken from
+                // the image before it is denoised or exposed
+                *film.lock().unwrap() = Some(FilmBuffer::from_canvas(&image, options.aa));
+            }
+            image
+        };
+        let render = |camera: &Camera| if options.denoise { camera.denoise(&scene, &render(camera)) } else { render(camera) };
+        let mut image = match &camera.stereo {
+            Some(stereo) => camera.render_stereo(stereo, render),
+            None => render(&camera),
+        };
+        if camera.out_of_time() {
+            eprintln!("The render ran out of time after {} seconds, the tiles that were not rendered are magenta", options.max_seconds.unwrap_or(0.0));
+        }
+        camera.auto_expose(&mut image);
+        {
+            let _span = profile_span!(INFO, "write_image");
+            image.write_to_file_with_quality(&output_file(image_file, &tokens), options.aa, options.quality);
+        }
+        if let (Some(variance_file), Some(film)) = (&options.variance, film.into_inner().unwrap()) {
+            film.variance_heatmap().write_to_file_with_quality(&output_file(variance_file, &tokens), 1, options.quality);
+        }
+        let textures = texture_memory();
+        if options.progress != ProgressMode::Quiet && textures.files > 0 {
+            eprintln!("Textures: {} of {} file(s) loaded, {}", textures.loaded, textures.files, format_bytes(textures.bytes));
+        }
+    }
+    let depth_file = if options.depth_only { Some(image_file) } else { options.depth.as_deref() };
+    if let Some(depth_file) = depth_file {
+        camera.render_depth(&scene, options.depth_far).write_to_file_with_quality(&output_file(depth_file, &tokens), options.aa, options.quality);
+    }
+}
+
+/// Expands the tokens in an output file name and creates the directory the file goes in.
+///
+/// # Arguments
+///
+/// * `template` - The output file name, with tokens like `{scene}` or `{frame:04}`.
+/// * `tokens` - The values of the tokens.
+///
+/// # Returns
+///
+/// The name of the file to write.
+///
+/// # Panics
+///
+/// Panics if the name contains an invalid token, or if the directory cannot be created.
+fn output_file(template: &str, tokens: &OutputTokens) -> String {
+    let file = expand_output_name(template, tokens);
+    if let Some(dir) = Path::new(&file).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).unwrap_or_else(|e| panic!("cannot create directory {}: {}", dir.display(), e));
+    }
+    file
+}
+
+/// Builds the camera and scene described by a YAML document.
+///
+/// Texture images that are no longer used, such as those of a scene built before, are removed from the
+/// texture cache afterwards.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document, with includes and templates already expanded.
+/// * `camera` - The name of the camera to use, or `None` for the default camera.
+/// * `width` - The horizontal size of the camera in pixels.
+/// * `height` - The vertical size of the camera in pixels.
+///
+/// # Returns
+///
+/// A tuple containing the `Camera` and the `Scene` with all lights and visible objects added.
+///
+/// # Panics
+///
+/// Panics if required scene elements are missing, or if objects have unsupported types or missing properties.
+pub fn build_scene(doc: &Yaml, camera: Option<&str>, width: usize, height: usize) -> (Camera, Scene) {
+    let _span = profile_span!(INFO, "build_scene");
+    let units = if doc["units"].is_badvalue() { 1.0 } else { meters_per_unit(&doc["units"]) };
+    let scale = get_f64_default(&doc["scale"], 1.0);
+    assert!(scale > 0.0, "scale must be positive");
+    SCENE_UNIT.with(|unit| unit.set(units * scale));
+    let camera = create_camera(doc, camera, width, height);
+    let mut scene = Scene::new();
+    for light in create_lights(doc) {
+        scene.add_light(light);
+    }
+
+    let scene_yaml = doc["scene"].as_vec().expect("scene not found");
+
+    for scene_object in scene_yaml {
+        let hidden = scene_object["hidden"].as_bool().unwrap_or(false);
+        if hidden {
+            continue;
+        }
+        if scene_object["generate"].is_badvalue() {
+            let shape = create_shape(scene_object);
+            scene.add_object(shape);
+        } else {
+            scene.generate(&create_layout(scene_object), |_, _| create_shape(&scene_object["object"]));
+        }
+    }
+    scene.epsilon = match &doc["epsilon"] {
+        Yaml::Real(_) | Yaml::Integer(_) => get_f64(&doc["epsilon"]),
+        Yaml::String(s) if s == "auto" => scene.auto_epsilon(),
+        Yaml::BadValue => scene.auto_epsilon(),
+        _ => panic!("epsilon must be a number or auto"),
+    };
+    scene.shadows = match doc["shadows"].as_str().unwrap_or("transparent") {
+        "opaque" => ShadowMode::Opaque,
+        "transparent" => ShadowMode::Transparent,
+        "tinted" => ShadowMode::Tinted,
+        other => panic!("unknown shadows mode {}", other),
+    };
+    scene.russian_roulette = doc["russian_roulette"].as_bool().unwrap_or(false);
+    if !doc["indirect_clamp"].is_badvalue() {
+        let clamp = get_f64(&doc["indirect_clamp"]);
+        assert!(clamp > 0.0, "indirect_clamp must be positive, got {}", clamp);
+        scene.indirect_clamp = Some(clamp);
+    }
+    scene.sampler = match doc["sampler"].as_str().unwrap_or("random") {
+        "random" => Sampler::Random,
+        "halton" => Sampler::Halton,
+        "sobol" => Sampler::Sobol,
+        "blue_noise" => Sampler::BlueNoise,
+        other => panic!("unknown sampler {}", other),
+    };
+    let ao = &doc["ambient_occlusion"];
+    if !ao.is_badvalue() {
+        scene.ambient_occlusion = Some(AmbientOcclusion {
+            samples: get_count(&ao["samples"], 1, "ambient occlusion samples").unwrap_or(16),
+            radius: get_f64_default(&ao["radius"], 1.0),
+        });
+    }
+    let background = &doc["background"];
+    if !background.is_badvalue() {
+        scene.background = create_background(background);
+    }
+    let fog = &doc["fog"];
+    if !fog.is_badvalue() {
+        scene.fog = Some(Fog {
+            color: color_from_vec(fog["color"].as_vec().expect("fog color not found")),
+            density: get_f64_default(&fog["density"], 0.1),
+            falloff: match fog["falloff"].as_str().unwrap_or("exponential") {
+                "linear" => FogFalloff::Linear,
+                "exponential" => FogFalloff::Exponential,
+                other => panic!("unknown fog falloff {}", other),
+            },
+        });
+    }
+    for volume in doc["volumes"].as_vec().unwrap_or(&vec![]) {
+        scene.volumes.push(create_volume(volume));
+    }
+    let environment = &doc["environment"];
+    if !environment.is_badvalue() {
+        let intensity = get_f64_default(&environment["intensity"], 1.0);
+        let rotation = degrees_to_radians(get_f64_default(&environment["rotation"], 0.0));
+        let light_samples = get_count(&environment["light_samples"], 0, "environment light_samples").unwrap_or(0);
+        scene.environment = Some(match (environment["file"].as_str(), &environment["sky"]) {
+            (Some(file), Yaml::BadValue) => Environment::load(file, intensity, rotation, light_samples),
+            (None, sky) if !sky.is_badvalue() => {
+                let (sky, preset_intensity) = create_sky(sky);
+                let intensity = get_f64_default(&environment["intensity"], preset_intensity);
+                Environment::from_sky(sky, intensity, rotation, light_samples)
+            }
+            (Some(_), _) => panic!("environment needs a file or a sky, not both"),
+            (None, _) => panic!("environment needs a file or a sky"),
+        });
+    }
+    remove_unused_images();
+    scene.freeze();
+
+    (camera, scene)
+}
+
+/// Creates the `Background` described by a `background` YAML node.
+///
+/// # Arguments
+///
+/// * `background` - The YAML node with the `type` of background and its settings.
+///
+/// # Returns
+///
+/// The new `Background`.
+///
+/// # Panics
+///
+/// Panics if the type is unknown, its settings are missing, or the pattern is an image.
+fn create_background(background: &Yaml) -> Background {
+    match background["type"].as_str().unwrap_or("color") {
+        "color" => Background::Color(color_from_vec(background["color"].as_vec().expect("background color not found"))),
+        "gradient" => Background::Gradient {
+            bottom: color_from_vec(background["bottom"].as_vec().expect("background bottom not found")),
+            top: color_from_vec(background["top"].as_vec().expect("background top not found")),
+        },
+        "pattern" => {
+            let pattern = &background["pattern"];
+            if pattern["type"].as_str() == Some("image") {
+                panic!("image patterns cannot be used as background, use an environment instead");
+            }
+            let mut sky = create_pattern(pattern);
+            // A cube map around the scene is seen from inside, unless the scene says otherwise
+            if let PatternType::CubeMap(_, inside) = &mut sky.pattern_type {
+                *inside = pattern["inside"].as_bool().unwrap_or(true);
+            }
+            Background::Pattern(sky)
+        }
+        other => panic!("unknown background type {}", other),
+    }
+}
+
+/// Creates a `Volume` from its YAML description.
+///
+/// # Arguments
+///
+/// * `volume` - The YAML node with the shape, transforms and medium settings of the volume.
+///
+/// # Returns
+///
+/// The new `Volume`.
+///
+/// # Panics
+///
+/// Panics if the shape is unknown, or the number of steps is less than 1.
+fn create_volume(volume: &Yaml) -> Volume {
+    let shape = match volume["shape"].as_str().unwrap_or("cube") {
+        "cube" => VolumeShape::Cube,
+        "sphere" => VolumeShape::Sphere,
+        other => panic!("unknown volume shape {}", other),
+    };
+    let transform = create_transforms(volume["transforms"].as_vec().unwrap_or(&vec![]));
+    let mut v = Volume::new(shape, transform, get_f64_default(&volume["scattering"], 0.1));
+    v.absorption = get_f64_default(&volume["absorption"], 0.0);
+    if let Some(color) = volume["color"].as_vec() {
+        v.color = color_from_vec(color);
+    }
+    if let Some(emission) = volume["emission"].as_vec() {
+        v.emission = color_from_vec(emission);
+    }
+    v.steps = get_count(&volume["steps"], 1, "volume steps").unwrap_or(32);
+    v
+}
+
+fn load_scene_file(path: &str) -> Yaml {
+    let path = Path::new(path);
+    if path.exists() {
+        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
+        load_scene_doc(&contents, path.parent().unwrap_or(Path::new(".")))
+    } else {
+        panic!("File does not exist");
+    }
+}
+
+/// Renders a scene from a YAML file.
+///
+/// This function reads a scene configuration from a YAML file specified by `path`, then renders
+/// the scene to an image file. The rendering process involves creating a camera, lights, and objects
+/// as defined in the YAML file, and then using the camera to render the scene to the specified PNG file.
+/// Relative `include` paths are resolved against the directory containing the scene file.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the YAML file containing the scene configuration.
+/// * `image_file` - The path where the rendered image will be saved, which may contain tokens like `{scene}`.
+/// * `options` - The image size, anti-aliasing factor and other render settings.
+///
+/// # Panics
+///
+/// Panics if the YAML file specified by `path` does not exist or cannot be read, or if the file does not contain a valid scene configuration.
+pub fn render_scene_from_file(path: &str, image_file: &str, options: &RenderOptions) {
+    let doc = load_scene_file(path);
+    let scene_name = Path::new(path).file_stem().map_or_else(|| "scene".to_string(), |stem| stem.to_string_lossy().to_string());
+    match options.frames {
+        Some(frames) => render_frames(&doc, &scene_name, image_file, options, &frames),
+        None => render_scene(&doc, &scene_name, image_file, options),
+    }
+}
+
+/// Renders a range of frames of an animated scene, each to its own file.
+///
+/// The scene is built again for every frame, at the time of that frame.
+///
+/// # Arguments
+///
+/// * `doc` - The parsed YAML scene document.
+/// * `scene_name` - The name of the scene file, for the `{scene}` token of output file names.
+/// * `image_file` - The path where the frames will be saved, with the frame number added if it has no `{frame}` token.
+/// * `options` - The image size, anti-aliasing factor and other render settings.
+/// * `frames` - The frames to render.
+fn render_frames(doc: &Yaml, scene_name: &str, image_file: &str, options: &RenderOptions, frames: &FrameRange) {
+    let image_file = frame_file_template(image_file);
+    let depth = options.depth.as_deref().map(frame_file_template);
+    let variance = options.variance.as_deref().map(frame_file_template);
+    for (i, frame) in (frames.first..=frames.last).enumerate() {
+        if options.progress == ProgressMode::Bar {
+            eprintln!("Frame {} ({} of {})", frame, i + 1, frames.count());
+        }
+        clear_global_objects();
+        let frame_options = RenderOptions { frame, frames: None, depth: depth.clone(), variance: variance.clone(), ..options.clone() };
+        render_scene(doc, scene_name, &image_file, &frame_options);
+    }
+}
+
+/// Loads a scene from a YAML file without rendering it.
+///
+/// This builds the camera, lights and every visible object exactly as rendering would, which makes it
+/// useful for checking that a scene file is valid.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the YAML file containing the scene configuration.
+/// * `width` - The horizontal size of the camera in pixels.
+/// * `height` - The vertical size of the camera in pixels.
+///
+/// # Returns
+///
+/// A tuple containing the `Camera` and the `Scene`.
+///
+/// # Panics
+///
+/// Panics if the YAML file specified by `path` does not exist or cannot be read, or if the file does not contain a valid scene configuration.
+pub fn load_scene_from_file(path: &str, width: usize, height: usize) -> (Camera, Scene) {
+    let doc = load_scene_file(path);
+    build_scene(&doc, None, width, height)
+}
+
+fn add_dependency(file: PathBuf, files: &mut Vec<PathBuf>) -> bool {
+    if files.contains(&file) || file.to_string_lossy().starts_with('$') {
+        return false;
+    }
+    files.push(file);
+    true
+}
+
+fn collect_scene_files(path: &Path, files: &mut Vec<PathBuf>) {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return,
+    };
+    let docs = match YamlLoader::load_from_str(&contents) {
+        Ok(docs) => docs,
+        Err(_) => return,
+    };
+    let doc = match docs.into_iter().next() {
+        Some(doc) => doc,
+        None => return,
+    };
+    let entries = match &doc {
+        Yaml::Array(entries) => entries.clone(),
+        _ => doc["scene"].as_vec().cloned().unwrap_or_default(),
+    };
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    for entry in entries {
+        if let Some(file) = entry["include"].as_str() {
+            let include = base_dir.join(file);
+            if add_dependency(include.clone(), files) {
+                collect_scene_files(&include, files);
+            }
+        }
+    }
+    collect_external_files(&doc, files);
+}
+
+fn collect_external_files(node: &Yaml, files: &mut Vec<PathBuf>) {
+    match node {
+        Yaml::Array(items) => {
+            for item in items {
+                collect_external_files(item, files);
+            }
+        }
+        Yaml::Hash(hash) => {
+            if let Some(file) = node["obj_file"].as_str() {
+                add_dependency(PathBuf::from(file), files);
+            }
+            if node["type"].as_str() == Some("image") {
+                if let Some(file) = node["file"].as_str() {
+                    add_dependency(PathBuf::from(file), files);
+                }
+            }
+            if node["type"].as_str() == Some("cube_map") {
+                for key in CUBE_MAP_FACES.iter().chain(["cross"].iter()) {
+                    if let Some(file) = node[*key].as_str() {
+                        add_dependency(PathBuf::from(file), files);
+                    }
+                }
+            }
+            if let Some(file) = node["normal_map"].as_str().or(node["normal_map"]["file"].as_str()) {
+                add_dependency(PathBuf::from(file), files);
+            }
+            if let Some(file) = node["environment"]["file"].as_str() {
+                add_dependency(PathBuf::from(file), files);
+            }
+            for value in hash.values() {
+                collect_external_files(value, files);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Lists the files a YAML scene file depends on.
+///
+/// The list contains the scene file itself, every file it includes (directly or indirectly), and the
+/// OBJ files, image textures and normal maps referenced by its objects, and its environment image. Files that cannot be read are still listed,
+/// so that a watcher notices when they appear.
 ///
 /// # Arguments
 ///
-/// * `contents` - A string slice containing the YAML formatted scene description.
-/// * `width` - The width of the output image in pixels, before applying anti-aliasing.
-/// * `height` - The height of the output image in pixels, before applying anti-aliasing.
-/// * `png_file` - The path where the rendered image will be saved.
-/// * `aa` - The anti-aliasing factor to be used in rendering. A higher value results in smoother edges but increases rendering time.
+/// * `path` - A string slice that holds the path to the YAML scene file.
 ///
-/// # Panics
+/// # Returns
 ///
-/// This function panics if the YAML content cannot be parsed, if required scene elements like the camera or lights
-/// are not found in the YAML, or if specified objects have unsupported types or missing properties.
-pub fn render_scene_from_str(contents: &str, width: usize, height: usize, png_file: &str, aa: usize) {
-    let docs = YamlLoader::load_from_str(contents).unwrap();
-    // Multi document support, doc is a yaml::Yaml
-    let doc = &docs[0];
+/// A vector with the paths of all files the scene depends on.
+pub fn scene_dependencies(path: &str) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(path)];
+    collect_scene_files(Path::new(path), &mut files);
+    files
+}
 
-    let camera = create_camera(doc, width * aa, height * aa);
-    let mut scene = Scene::new();
-    for light in create_lights(doc) {
-        scene.add_light(light);
-    }
+fn format_point(p: &Tuple) -> String {
+    format!("({}, {}, {})", p.x, p.y, p.z)
+}
 
-    let scene_yaml = doc["scene"].as_vec().expect("scene not found");
+fn count_object_types(node: &Yaml, counts: &mut Vec<(String, usize)>) {
+    if node["hidden"].as_bool().unwrap_or(false) {
+        return;
+    }
+    if !node["generate"].is_badvalue() {
+        for _ in 0..create_layout(node).positions().len() {
+            count_object_types(&node["object"], counts);
+        }
+        return;
+    }
+    if node["type"].as_str() == Some("scatter") {
+        for _ in 0..node["count"].as_i64().unwrap_or(0) {
+            count_object_types(&node["object"], counts);
+        }
+        count_object_types(&node["surface"], counts);
+    }
+    let object_type = node["type"].as_str().unwrap_or("unknown").to_string();
+    match counts.iter_mut().find(|(t, _)| *t == object_type) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((object_type, 1)),
+    }
+    for child in node["children"].as_vec().unwrap_or(&vec![]) {
+        count_object_types(child, counts);
+    }
+    for operand in [&node["left"], &node["right"]] {
+        if operand.as_hash().is_some() {
+            count_object_types(operand, counts);
+        }
+    }
+}
 
-    for scene_object in scene_yaml {
-        let hidden = scene_object["hidden"].as_bool().unwrap_or(false);
-        if !hidden {
-            let shape = create_shape(scene_object);
-            scene.add_object(shape);
+fn count_materials(node: &Yaml, counts: &mut Vec<(Yaml, usize)>) {
+    if node["hidden"].as_bool().unwrap_or(false) {
+        return;
+    }
+    if !node["generate"].is_badvalue() {
+        for _ in 0..create_layout(node).positions().len() {
+            count_materials(&node["object"], counts);
+        }
+        return;
+    }
+    if node["type"].as_str() == Some("scatter") {
+        for _ in 0..node["count"].as_i64().unwrap_or(0) {
+            count_materials(&node["object"], counts);
+        }
+        count_materials(&node["surface"], counts);
+    }
+    if !node["material"].is_badvalue() {
+        match counts.iter_mut().find(|(material, _)| *material == node["material"]) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((node["material"].clone(), 1)),
+        }
+    }
+    for child in node["children"].as_vec().unwrap_or(&vec![]) {
+        count_materials(child, counts);
+    }
+    for operand in [&node["left"], &node["right"]] {
+        if operand.as_hash().is_some() {
+            count_materials(operand, counts);
         }
     }
+}
 
-    let image = camera.render(&scene);
-    image.write_to_file(png_file, aa);
+fn format_yaml_value(value: &Yaml) -> String {
+    match value {
+        Yaml::Real(value) | Yaml::String(value) => value.clone(),
+        Yaml::Integer(value) => value.to_string(),
+        Yaml::Boolean(value) => value.to_string(),
+        Yaml::Array(items) => format!("({})", items.iter().map(format_yaml_value).collect::<Vec<_>>().join(", ")),
+        Yaml::Hash(_) => match &value["color"] {
+            Yaml::BadValue => value["type"].as_str().unwrap_or("...").to_string(),
+            color => format!("{} {}", value["type"].as_str().unwrap_or("..."), format_yaml_value(color)),
+        },
+        _ => "?".to_string(),
+    }
 }
 
-/// Renders a scene from a YAML file.
+/// Describes a material in one line, by the properties it sets.
+fn describe_material(material: &Yaml) -> String {
+    match material.as_hash() {
+        Some(hash) if !hash.is_empty() => hash.iter()
+            .map(|(key, value)| format!("{} {}", key.as_str().unwrap_or("?"), format_yaml_value(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => "default".to_string(),
+    }
+}
+
+/// Produces a human readable summary of a YAML scene file.
 ///
-/// This function reads a scene configuration from a YAML file specified by `path`, then renders
-/// the scene to an image file. The rendering process involves creating a camera, lights, and objects
-/// as defined in the YAML file, and then using the camera to render the scene to the specified PNG file.
+/// The summary lists the camera settings, the lights, the number of objects of each type (including
+/// group children and CSG operands), the total number of primitives created and how many of them are
+/// triangles (which includes the triangles loaded from OBJ files), the world space bounds of the scene,
+/// the materials with the number of objects using each of them, and the files the scene reads.
 ///
 /// # Arguments
 ///
 /// * `path` - A string slice that holds the path to the YAML file containing the scene configuration.
-/// * `width` - The width of the output image in pixels.
-/// * `height` - The height of the output image in pixels.
-/// * `png_file` - The path where the rendered image will be saved.
-/// * `aa` - The anti-aliasing factor to be used in rendering. A higher value results in smoother edges but increases rendering time.
+///
+/// # Returns
+///
+/// A multi-line `String` describing the scene.
 ///
 /// # Panics
 ///
 /// Panics if the YAML file specified by `path` does not exist or cannot be read, or if the file does not contain a valid scene configuration.
-pub fn render_scene_from_file(path: &str, width: usize, height: usize, png_file: &str, aa: usize) {
-    if Path::new(path).exists() {
-        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
-        render_scene_from_str(&contents, width, height, png_file, aa)
+pub fn describe_scene_file(path: &str) -> String {
+    let doc = load_scene_file(path);
+    let objects_before = number_of_objects();
+    let (_, scene) = build_scene(&doc, None, 1, 1);
+    let primitives = number_of_objects() - objects_before;
+
+    let mut info = format!("Scene: {}\n", path);
+    let mut cameras = vec![];
+    if !doc["camera"].is_badvalue() {
+        cameras.push(("Camera".to_string(), &doc["camera"]));
+    }
+    for (name, camera) in doc["cameras"].as_hash().into_iter().flatten() {
+        cameras.push((format!("Camera {}", name.as_str().unwrap_or("?")), camera));
+    }
+    for (label, camera) in cameras {
+        let lens = match &camera["focal_length"] {
+            Yaml::BadValue => format!("fov {} degrees", get_f64(&camera["fov"])),
+            focal_length => format!("focal length {} mm", get_f64(focal_length)),
+        };
+        info.push_str(&format!("{}: {}, from {}, to {}\n",
+                               label,
+                               lens,
+                               format_point(&point_from_vec(camera["from"].as_vec().expect("camera.from not found"))),
+                               format_point(&point_from_vec(camera["to"].as_vec().expect("camera.to not found")))));
+    }
+
+    info.push_str(&format!("Lights: {}\n", scene.light.len()));
+    for light in &scene.light {
+        let kind = match light.light_type {
+            LightType::Point => "point",
+            LightType::Area(..) => "area",
+        };
+        let color = light.intensity;
+        info.push_str(&format!("  {} at {}, intensity ({}, {}, {})\n", kind, format_point(&light.position), color.r, color.g, color.b));
+    }
+
+    let mut counts: Vec<(String, usize)> = vec![];
+    for object in doc["scene"].as_vec().unwrap_or(&vec![]) {
+        count_object_types(object, &mut counts);
+    }
+    let triangles = (objects_before..number_of_objects())
+        .filter(|id| matches!(get_object(*id).geometry(), Some(Geometry::Triangle(..))))
+        .count();
+    info.push_str(&format!("Objects: {} top-level, {} primitives, {} triangles\n", scene.ids.len(), primitives, triangles));
+    for (object_type, count) in counts {
+        info.push_str(&format!("  {}: {}\n", object_type, count));
+    }
+
+    let mut bounds = AABB::new(
+        Tuple::point(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        Tuple::point(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    );
+    for id in &scene.ids {
+        let object = get_object(*id);
+        bounds.adjust_aabb(&object.get_aabb().apply_transform(object.get_transform()));
+    }
+    let finite = [bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z]
+        .iter().all(|v| v.is_finite());
+    if finite {
+        info.push_str(&format!("Bounds: {} to {}\n", format_point(&bounds.min), format_point(&bounds.max)));
     } else {
-        panic!("File does not exist");
+        info.push_str("Bounds: unbounded\n");
+    }
+
+    let mut materials: Vec<(Yaml, usize)> = vec![];
+    for object in doc["scene"].as_vec().unwrap_or(&vec![]) {
+        count_materials(object, &mut materials);
+    }
+    let named = doc["materials"].as_hash().cloned().unwrap_or_default();
+    info.push_str(&format!("Materials: {}\n", materials.len()));
+    for (material, count) in materials {
+        // Named materials were written out in full when the scene was loaded, so they are recognized by their properties
+        let name = named.keys()
+            .find(|name| resolve_material(name, &named, &mut vec![]) == material)
+            .and_then(|name| name.as_str())
+            .map_or_else(String::new, |name| format!("{}, ", name));
+        info.push_str(&format!("  {}{} object(s): {}\n", name, count, describe_material(&material)));
+    }
+    let textures = texture_memory();
+    if textures.files > 0 {
+        info.push_str(&format!("Textures: {} file(s), {} when loaded\n", textures.files, format_bytes(textures.bytes_when_loaded)));
+    }
+    let files = scene_dependencies(path);
+    if files.len() > 1 {
+        info.push_str(&format!("Files: {}\n", files.len() - 1));
+        for file in &files[1..] {
+            let missing = if file.exists() { "" } else { " (missing)" };
+            info.push_str(&format!("  {}{}\n", file.display(), missing));
+        }
+    }
+    info
+}
+
+/// Builds a scene from a YAML file and describes the built scene in the YAML scene format.
+///
+/// The description contains everything the file builds, with meshes from OBJ files written out as
+/// triangles and animated values as they are at the given frame, so it can be loaded again without the
+/// files it was made from, except for images. See `export_scene`.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the YAML file containing the scene configuration.
+/// * `camera` - The name of a camera from the `cameras` section, or `None` for the default camera.
+/// * `frame` - The frame of the animation the scene is built for.
+///
+/// # Returns
+///
+/// The YAML document describing the built scene.
+///
+/// # Panics
+///
+/// Panics if the YAML file specified by `path` does not exist or cannot be read, if the file does not contain a valid scene configuration, or if the scene cannot be exported.
+pub fn export_scene_file(path: &str, camera: Option<&str>, frame: usize) -> Yaml {
+    let doc = load_scene_file(path);
+    set_scene_frame(frame, scene_fps(&doc));
+    let (camera, scene) = build_scene(&doc, camera, 1, 1);
+    export_scene(&camera, &scene)
+}
+
+/// Formats a number of bytes for people to read, in bytes, KB or MB.
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} bytes", bytes),
+        1024..1048576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1048576.0),
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::raytracer::scene_builder_yaml::render_scene_from_file;
+    use std::fs;
+    use std::path::Path;
+    use crate::color::Color;
+    use crate::matrix4::Matrix4;
+    use crate::tuple::Tuple;
+    use crate::raytracer::object::db::get_object;
+    use crate::raytracer::object::Geometry;
+    use crate::raytracer::object::triangle::Triangle;
+    use crate::raytracer::scene_builder_yaml::{build_scene, create_camera_path, create_layout, create_object_material, create_shape, create_transforms, create_volume, describe_scene_file, inherit_object_properties, load_scene_doc, point_from_vec, render_scene_from_file, render_scene_from_str, scene_dependencies, vector_from_vec, RenderOptions};
+
+    fn write_test_file(dir: &Path, name: &str, contents: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(name), contents).unwrap();
+    }
 
     #[test]
     #[ignore]
     fn test_render_scene_from_file() {
-        render_scene_from_file("example1.yaml", 800, 400, "canvas.png",1);
+        render_scene_from_file("example1.yaml", "canvas.png", &RenderOptions::new(800, 400));
+    }
+
+    #[test]
+    fn include_directive_splices_objects_and_lights() {
+        let dir = std::env::temp_dir().join("rray_include_test");
+        write_test_file(&dir.join("parts"), "furniture.yaml", "
+- type: sphere
+- include: more.yaml
+");
+        write_test_file(&dir.join("parts"), "more.yaml", "
+lights:
+  - type: point
+    color: [1, 1, 1]
+    position: [0, 10, 0]
+scene:
+  - type: cube
+");
+        let doc = load_scene_doc("
+lights:
+  - type: point
+    color: [1, 1, 1]
+    position: [-10, 10, -10]
+scene:
+  - type: plane
+  - include: parts/furniture.yaml
+  - include: parts/missing.yaml
+    hidden: true
+", &dir);
+
+        let scene = doc["scene"].as_vec().unwrap();
+        let types: Vec<&str> = scene.iter().map(|s| s["type"].as_str().unwrap()).collect();
+        assert_eq!(types, vec!["plane", "sphere", "cube"]);
+        assert_eq!(doc["lights"].as_vec().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Recursive include")]
+    fn recursive_include_is_rejected() {
+        let dir = std::env::temp_dir().join("rray_recursive_include_test");
+        write_test_file(&dir, "a.yaml", "- include: b.yaml");
+        write_test_file(&dir, "b.yaml", "- include: a.yaml");
+        load_scene_doc("scene:\n  - include: a.yaml\n", &dir);
+    }
+
+    #[test]
+    fn templates_are_instantiated_with_params() {
+        let doc = load_scene_doc("
+templates:
+  column:
+    params:
+      height: 2
+    type: cylinder
+    minimum: 0
+    maximum: $height
+    closed: true
+    transforms:
+      - type: translate
+        amount: [$x, 0, 0]
+scene:
+  - template: column
+    params:
+      x: 1
+  - template: column
+    params:
+      x: -1
+      height: 5
+    closed: false
+    transforms:
+      - type: scale
+        amount: [2, 2, 2]
+", Path::new("."));
+
+        let scene = doc["scene"].as_vec().unwrap();
+        assert_eq!(scene.len(), 2);
+        assert_eq!(scene[0]["type"].as_str(), Some("cylinder"));
+        assert_eq!(scene[0]["maximum"].as_i64(), Some(2));
+        assert_eq!(scene[0]["transforms"][0]["amount"][0].as_i64(), Some(1));
+        assert!(scene[0]["params"].is_badvalue());
+        assert_eq!(scene[1]["maximum"].as_i64(), Some(5));
+        assert_eq!(scene[1]["closed"].as_bool(), Some(false));
+        assert_eq!(scene[1]["transforms"][0]["amount"][0].as_i64(), Some(-1));
+        assert_eq!(scene[1]["transforms"][1]["type"].as_str(), Some("scale"));
+    }
+
+    #[test]
+    fn templates_are_expanded_inside_groups() {
+        let doc = load_scene_doc("
+templates:
+  post:
+    type: cube
+scene:
+  - type: group
+    children:
+      - template: post
+      - template: post
+", Path::new("."));
+
+        let children = doc["scene"][0]["children"].as_vec().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[1]["type"].as_str(), Some("cube"));
+    }
+
+    #[test]
+    #[should_panic(expected = "has no value for parameter: $x")]
+    fn template_with_missing_param_is_rejected() {
+        load_scene_doc("
+templates:
+  post:
+    type: sphere
+    transforms:
+      - type: translate
+        amount: [$x, 0, 0]
+scene:
+  - template: post
+", Path::new("."));
+    }
+
+    #[test]
+    fn rendering_with_a_named_camera() {
+        let doc = load_scene_doc("
+cameras:
+  main: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+  closeup: {fov: 20, from: [0, 1, -2], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: sphere
+", Path::new("."));
+
+        let (camera, _) = build_scene(&doc, None, 10, 10);
+        assert!((camera.field_of_view - 60_f64.to_radians()).abs() < 1e-9);
+        let (camera, _) = build_scene(&doc, Some("closeup"), 10, 10);
+        assert!((camera.field_of_view - 20_f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn camera_keyframes_default_to_the_camera() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+animation:
+  fps: 10
+  camera:
+    interpolation: linear
+    keyframes:
+      - {time: 0}
+      - {frame: 20, from: [4, 1.5, -5]}
+", Path::new("."));
+
+        let camera = &doc["camera"];
+        let path = create_camera_path(&doc["animation"]["camera"], 10.0, point_from_vec(camera["from"].as_vec().unwrap()),
+                                      point_from_vec(camera["to"].as_vec().unwrap()), vector_from_vec(camera["up"].as_vec().unwrap()));
+        assert_eq!(path.keys[1].time, 2.0);
+        assert_eq!(path.at(1.0), (Tuple::point(2.0, 1.5, -5.0), Tuple::point(0.0, 1.0, 0.0), Tuple::vector(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn camera_keyframes_count_frames_at_the_frame_rate_of_the_render() {
+        // Rendering at 20 frames per second with --fps puts frame 20 at 1 second, not at the 2 seconds of the scene's fps
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+animation:
+  fps: 10
+  camera:
+    keyframes:
+      - {time: 0}
+      - {frame: 20, from: [4, 1.5, -5]}
+", Path::new("."));
+
+        let camera = &doc["camera"];
+        let path = create_camera_path(&doc["animation"]["camera"], 20.0, point_from_vec(camera["from"].as_vec().unwrap()),
+                                      point_from_vec(camera["to"].as_vec().unwrap()), vector_from_vec(camera["up"].as_vec().unwrap()));
+        assert_eq!(path.keys[1].time, 1.0);
+    }
+
+    #[test]
+    fn transforms_can_be_keyed_over_time() {
+        // Scenes are built at 0 seconds unless a frame is set
+        let doc = load_scene_doc("
+transforms:
+  - type: translate
+    interpolation: linear
+    keyframes:
+      - {time: -1, amount: [0, 0, 0]}
+      - {time: 1, amount: [0, 2, 0]}
+  - type: rotate
+    axis: y
+    keyframes:
+      - {time: -2, angle: 0}
+      - {time: 2, angle: 180}
+", Path::new("."));
+
+        let transform = create_transforms(doc["transforms"].as_vec().unwrap());
+        assert_eq!(transform, Matrix4::rotate_y(std::f64::consts::FRAC_PI_2) * Matrix4::translate(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn objects_in_other_units_are_scaled_to_the_units_of_the_scene() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0], stereo: {convergence: 5}}
+units: centimeters
+scale: 10
+lights:
+  - {type: point, lumens: 4, position: [-10, 10, -10]}
+scene:
+  - {type: sphere, units: meters, transforms: [{type: translate, amount: [1, 0, 0]}]}
+  - {type: sphere, units: mm}
+", Path::new("."));
+
+        // One unit of the scene is 10 cm
+        let (camera, scene) = build_scene(&doc, None, 10, 10);
+        assert_eq!(*get_object(scene.ids[0]).get_transform(), Matrix4::translate(1.0, 0.0, 0.0) * Matrix4::scale(10.0, 10.0, 10.0));
+        assert!((get_object(scene.ids[1]).get_transform().get(0, 0) - 0.01).abs() < 1e-12);
+        assert!((camera.stereo.unwrap().interocular - 0.65).abs() < 1e-12);
+        assert!((scene.light[0].intensity.r - 4.0 / (4.0 * std::f64::consts::PI * 0.01)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parts_of_obj_files_get_their_own_material_and_transforms() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: obj_file
+    obj_file: examples/triangles.obj
+    divide: false
+    cast_shadows: false
+    material: {pattern: {type: solid, color: [1, 0, 0]}, reflective: 0.5}
+    parts:
+      SecondGroup:
+        material: {pattern: {type: solid, color: [0, 0, 1]}}
+        transforms: [{type: translate, amount: [0, 2, 0]}]
+", Path::new("."));
+
+        let (_, scene) = build_scene(&doc, None, 10, 10);
+        let parts = get_object(scene.ids[0]).child_ids();
+        let triangle = |part: usize| get_object(get_object(parts[part]).child_ids()[0]);
+        assert_eq!(triangle(0).get_material().reflective, 0.5);
+        assert_eq!(*get_object(parts[0]).get_transform(), Matrix4::identity());
+        // The part keeps the object properties of the model, but not the rest of its material
+        let second = triangle(1);
+        assert_eq!(second.get_material().pattern, crate::raytracer::material::pattern::Pattern::solid(Color::new(0.0, 0.0, 1.0), Matrix4::identity()));
+        assert_eq!(second.get_material().reflective, 0.0);
+        assert!(!second.get_material().cast_shadows);
+        assert_eq!(*get_object(parts[1]).get_transform(), Matrix4::translate(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn material_slots_of_obj_files_get_their_materials() {
+        let dir = std::env::temp_dir().join("rray_material_slots_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("slots.obj");
+        std::fs::write(&obj_file, "v 0 0 0\nv 1 0 0\nv 0 1 0\ng body\nf 1 2 3\nusemtl chrome\nf 1 3 2\ng trim\nf 1 2 3\n").unwrap();
+        let doc = load_scene_doc(&format!("
+camera: {{fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}}
+lights:
+  - {{type: point, color: [1, 1, 1], position: [-10, 10, -10]}}
+materials:
+  chrome: {{pattern: {{type: solid, color: [1, 1, 1]}}, reflective: 0.9}}
+scene:
+  - type: obj_file
+    obj_file: {}
+    divide: false
+    material: {{pattern: {{type: solid, color: [1, 0, 0]}}, reflective: 0.2}}
+    materials:
+      chrome: chrome
+    parts:
+      body:
+        cast_shadows: false
+", obj_file.display()), Path::new("."));
+
+        let (_, scene) = build_scene(&doc, None, 10, 10);
+        let parts = get_object(scene.ids[0]).child_ids();
+        let material = |part: usize, triangle: usize| get_object(get_object(parts[part]).child_ids()[triangle]).get_material().clone();
+        // A part without a material keeps the materials of the slots of its triangles
+        assert_eq!((material(0, 0).reflective, material(0, 1).reflective), (0.2, 0.9));
+        assert!(!material(0, 1).cast_shadows);
+        assert_eq!(material(1, 0).reflective, 0.9);
+        assert!(material(1, 0).cast_shadows);
+    }
+
+    #[test]
+    fn generate_entries_place_copies_of_their_object() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - generate: grid
+    count: [2, 1, 3]
+    spacing: [2, 1, 1]
+    object: {type: sphere, transforms: [{type: scale, amount: [0.5, 0.5, 0.5]}]}
+  - type: group
+    cast_shadows: false
+    children:
+      - generate: ring
+        count: 4
+        radius: 3
+        object: {type: cube}
+", Path::new("."));
+
+        let (_, scene) = build_scene(&doc, None, 10, 10);
+        assert_eq!(scene.ids.len(), 7);
+        assert_eq!(*get_object(scene.ids[0]).get_transform(), Matrix4::translate(-1.0, 0.0, -1.0) * Matrix4::scale(0.5, 0.5, 0.5));
+        let ring = match get_object(scene.ids[6]).geometry() {
+            Some(Geometry::Group(children)) => children,
+            _ => panic!("expected a group"),
+        };
+        assert_eq!(ring.len(), 4);
+        assert_eq!(*get_object(ring[2]).get_transform(), Matrix4::translate(-3.0, 0.0, 0.0));
+        assert!(!get_object(ring[2]).get_material().cast_shadows);
+    }
+
+    #[test]
+    fn scatter_objects_are_placed_in_their_region_or_on_their_surface() {
+        let doc = load_scene_doc("
+scene:
+  - type: scatter
+    count: 30
+    seed: 5
+    min: [-2, 0, -2]
+    max: [2, 1, 2]
+    rotation: [0, 360, 0]
+    scale: [0.5, 1]
+    object: {type: sphere}
+  - type: scatter
+    count: 10
+    cast_shadows: false
+    surface: {type: cube, transforms: [{type: scale, amount: [3, 1, 3]}]}
+    object: {type: cone, minimum: 0, maximum: 1, closed: true}
+", Path::new("."));
+
+        let scatter = create_shape(&doc["scene"][0]);
+        assert_eq!(scatter.child_ids().len(), 30);
+        for child in scatter.child_ids() {
+            let m = *get_object(child).get_transform();
+            assert!((-2.0..2.0).contains(&m.get(0, 3)) && (0.0..1.0).contains(&m.get(1, 3)));
+        }
+        let on_cube = create_shape(&doc["scene"][1]);
+        let copies = on_cube.child_ids();
+        assert_eq!(copies.len(), 11);
+        assert!(copies[..10].iter().all(|id| get_object(*id).get_transform().get(1, 3) == 1.0));
+        assert!(copies.iter().all(|id| !get_object(*id).get_material().cast_shadows));
+    }
+
+    #[test]
+    fn meshes_share_vertices_between_their_faces() {
+        use crate::raytracer::object::Object;
+        let doc = load_scene_doc("
+scene:
+  - type: mesh
+    vertices: [[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0]]
+    faces: [[0, 1, 2, 3]]
+    divide: false
+    material: {pattern: {type: solid, color: [1, 1, 1]}, reflective: 0.5}
+  - type: mesh
+    vertices: [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+    normals: [[0, 0, -1], [1, 0, -1], [0, 1, -1]]
+    colors: [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
+    faces: [[0, 1, 2]]
+", Path::new("."));
+
+        let quad = create_shape(&doc["scene"][0]).child_ids();
+        assert_eq!(quad.len(), 2);
+        assert_eq!(get_object(quad[1]).geometry(), Triangle::new(Tuple::point(0.0, 0.0, 0.0), Tuple::point(1.0, 1.0, 0.0), Tuple::point(0.0, 1.0, 0.0)).geometry());
+        assert_eq!(get_object(quad[1]).get_material().reflective, 0.5);
+
+        let smooth = get_object(create_shape(&doc["scene"][1]).child_ids()[0]);
+        let Some(Geometry::Triangle(_, normals)) = smooth.geometry() else { panic!("not a triangle") };
+        assert_eq!(normals[1], Tuple::vector(1.0, 0.0, -1.0));
+        assert_eq!(smooth.vertex_colors().unwrap()[2], Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "a face of a mesh uses vertex 3, but the mesh has 3 vertices")]
+    fn mesh_faces_must_use_existing_vertices() {
+        let doc = load_scene_doc("
+scene:
+  - type: mesh
+    vertices: [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+    faces: [[0, 1, 3]]
+", Path::new("."));
+        create_shape(&doc["scene"][0]);
+    }
+
+    #[test]
+    fn sweeps_are_tubes_of_smooth_triangles_along_their_curve() {
+        let doc = load_scene_doc("
+scene:
+  - type: sweep
+    curve: {type: torus_knot, p: 3, q: 2}
+    radius: 0.2
+    segments: 60
+    sides: 6
+    divide: false
+    material: {pattern: {type: solid, color: [1, 1, 1]}, reflective: 0.5}
+  - type: sweep
+    curve: {type: helix, turns: 3, height: 2}
+    segments: 30
+    sides: 4
+", Path::new("."));
+
+        let knot = create_shape(&doc["scene"][0]).child_ids();
+        assert_eq!(knot.len(), 60 * 6 * 2);
+        assert_eq!(get_object(knot[0]).get_material().reflective, 0.5);
+        let Some(Geometry::Triangle(_, normals)) = get_object(knot[0]).geometry() else { panic!("not a triangle") };
+        assert_ne!(normals[0], normals[1]);
+
+        let helix = create_shape(&doc["scene"][1]);
+        let bounds = helix.get_aabb();
+        // The rings at the ends lean with the helix, so they reach a little less than the radius past its ends
+        assert!(bounds.min.y > -0.1 && bounds.min.y < -0.05);
+        assert!(bounds.max.y > 2.05 && bounds.max.y < 2.1);
+    }
+
+    #[test]
+    fn a_camera_can_be_given_by_its_lens() {
+        let doc = load_scene_doc("
+camera: {focal_length: 18, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: sphere
+", Path::new("."));
+
+        let (camera, _) = build_scene(&doc, None, 20, 10);
+        assert!((camera.field_of_view - std::f64::consts::PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown camera: wide (the scene has: main)")]
+    fn unknown_named_camera_is_rejected() {
+        let doc = load_scene_doc("
+cameras:
+  main: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: sphere
+", Path::new("."));
+        build_scene(&doc, Some("wide"), 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "ambient occlusion samples must be at least 1, got -4")]
+    fn negative_counts_are_rejected() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+ambient_occlusion: {samples: -4}
+scene:
+  - type: sphere
+", Path::new("."));
+        build_scene(&doc, None, 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "area light usteps must be at least 1, got -2")]
+    fn negative_area_light_steps_are_rejected() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: area, color: [1, 1, 1], corner: [-1, 5, -1], uvec: [2, 0, 0], vvec: [0, 0, 2], usteps: -2}
+scene:
+  - type: sphere
+", Path::new("."));
+        build_scene(&doc, None, 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "environment light_samples must be at least 0, got -16")]
+    fn negative_environment_light_samples_are_rejected() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+environment: {sky: {preset: noon}, light_samples: -16}
+scene:
+  - type: sphere
+", Path::new("."));
+        build_scene(&doc, None, 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "volume steps must be at least 1, got -8")]
+    fn negative_volume_steps_are_rejected() {
+        create_volume(&load_scene_doc("{shape: sphere, steps: -8}", Path::new(".")));
+    }
+
+    #[test]
+    #[should_panic(expected = "ring layout count must be at least 1, got -4")]
+    fn negative_layout_counts_are_rejected() {
+        create_layout(&load_scene_doc("{generate: ring, count: -4}", Path::new(".")));
+    }
+
+    #[test]
+    #[should_panic(expected = "grid layout count must be at least 1, got -2")]
+    fn negative_grid_counts_are_rejected() {
+        create_layout(&load_scene_doc("{generate: grid, count: [2, -2, 1]}", Path::new(".")));
+    }
+
+    #[test]
+    #[should_panic(expected = "scatter count must be at least 1, got -30")]
+    fn negative_scatter_counts_are_rejected() {
+        create_shape(&load_scene_doc("{type: scatter, count: -30, min: [0, 0, 0], max: [1, 1, 1], object: {type: sphere}}", Path::new(".")));
+    }
+
+    #[test]
+    #[should_panic(expected = "mesh vertex index must be at least 0, got -1")]
+    fn negative_mesh_vertex_indices_are_rejected() {
+        create_shape(&load_scene_doc("{type: mesh, vertices: [[0, 0, 0], [1, 0, 0], [0, 1, 0]], faces: [[0, 1, -1]]}", Path::new(".")));
+    }
+
+    #[test]
+    #[should_panic(expected = "indirect_clamp must be positive, got 0")]
+    fn indirect_clamp_must_be_positive() {
+        let doc = load_scene_doc("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+indirect_clamp: 0
+scene:
+  - type: sphere
+", Path::new("."));
+        build_scene(&doc, None, 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "--variance needs --aa of at least 2")]
+    fn variance_needs_anti_aliasing() {
+        let options = RenderOptions { variance: Some("variance.png".to_string()), ..RenderOptions::new(4, 4) };
+        render_scene_from_str("
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: sphere
+", "image.png", &options);
+    }
+
+    #[test]
+    fn meshes_can_recompute_their_normals() {
+        let doc = load_scene_doc("
+scene:
+  - type: mesh
+    vertices: [[-1, 0, -1], [1, 0, -1], [1, 0, 1], [-1, 0, 1], [0, 1.5, 0]]
+    faces: [[1, 0, 4], [2, 1, 4], [3, 2, 4], [0, 3, 4]]
+    recompute_normals: 180
+    divide: false
+", Path::new("."));
+        let sides = create_shape(&doc["scene"][0]).child_ids();
+        assert_eq!(sides.len(), 4);
+        let Some(Geometry::Triangle(_, normals)) = get_object(sides[0]).geometry() else { panic!("not a triangle") };
+        // The apex is shared by the four sides, so its normal points straight up
+        assert!(normals[2].subtract(&Tuple::vector(0.0, 1.0, 0.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "a helix needs a height, or a radius and turns, to have a direction")]
+    fn a_sweep_along_a_helix_needs_a_direction() {
+        let doc = load_scene_doc("{type: sweep, curve: {type: helix, turns: 0, height: 0}}", Path::new("."));
+        create_shape(&doc);
+    }
+
+    #[test]
+    #[should_panic(expected = "p and q of a torus knot must have no common divisor, got 2 and 4")]
+    fn torus_knots_need_windings_without_a_common_divisor() {
+        let doc = load_scene_doc("{type: sweep, curve: {type: torus_knot, p: 2, q: 4}}", Path::new("."));
+        create_shape(&doc);
+    }
+
+    #[test]
+    #[should_panic(expected = "the radius of a sweep must be greater than 0, got -0.1")]
+    fn the_tube_of_a_sweep_has_a_positive_radius() {
+        let doc = load_scene_doc("{type: sweep, curve: {type: helix}, radius: -0.1}", Path::new("."));
+        create_shape(&doc);
+    }
+
+    #[test]
+    #[should_panic(expected = "sides of a sweep must be at least 3, got -4")]
+    fn sweeps_need_at_least_three_sides() {
+        let doc = load_scene_doc("{type: sweep, curve: {type: helix}, sides: -4}", Path::new("."));
+        create_shape(&doc);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parts_of_divided_obj_files_get_their_material_on_every_triangle() {
+        let dir = std::env::temp_dir().join("rray_divided_parts_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_file = dir.join("row.obj");
+        let mut contents = String::from("g row\n");
+        for x in 0..4 {
+            contents.push_str(&format!("v {} 0 0\nv {} 0 0\nv {} 1 0\nf {} {} {}\n", x * 2, x * 2 + 1, x * 2, x * 3 + 1, x * 3 + 2, x * 3 + 3));
+        }
+        std::fs::write(&obj_file, contents).unwrap();
+        let doc = load_scene_doc(&format!("
+camera: {{fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}}
+lights:
+  - {{type: point, color: [1, 1, 1], position: [-10, 10, -10]}}
+scene:
+  - type: obj_file
+    obj_file: {}
+    divide: 2
+    parts:
+      row:
+        material: {{pattern: {{type: solid, color: [0, 0, 1]}}, reflective: 0.7}}
+", obj_file.display()), Path::new("."));
+
+        let (_, scene) = build_scene(&doc, None, 10, 10);
+        // The triangles of the part are in the sub-groups it was divided into as it was loaded
+        let part = get_object(get_object(scene.ids[0]).child_ids()[0]).child_ids();
+        assert!(part.iter().all(|id| !get_object(*id).child_ids().is_empty()));
+        let mut ids = part;
+        let mut triangles = 0;
+        while let Some(id) = ids.pop() {
+            let children = get_object(id).child_ids();
+            if children.is_empty() {
+                assert_eq!(get_object(id).get_material().reflective, 0.7);
+                triangles += 1;
+            }
+            ids.extend(children);
+        }
+        assert_eq!(triangles, 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn objects_use_named_materials_with_overrides() {
+        let doc = load_scene_doc("
+materials:
+  glass:
+    transparency: 1.0
+    refractive_index: 1.5
+  tinted_glass:
+    use: glass
+    pattern: {type: solid, color: [0, 0, 1]}
+scene:
+  - type: sphere
+    material: glass
+  - type: group
+    children:
+      - type: cube
+        material:
+          use: tinted_glass
+          refractive_index: 1.3
+", Path::new("."));
+
+        let scene = doc["scene"].as_vec().unwrap();
+        assert_eq!(scene[0]["material"]["refractive_index"].as_f64(), Some(1.5));
+        let cube = &scene[1]["children"][0]["material"];
+        assert_eq!(cube["transparency"].as_f64(), Some(1.0));
+        assert_eq!(cube["refractive_index"].as_f64(), Some(1.3));
+        assert_eq!(cube["pattern"]["type"].as_str(), Some("solid"));
+        assert!(cube["use"].is_badvalue());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown material: steel")]
+    fn unknown_named_material_is_rejected() {
+        load_scene_doc("
+scene:
+  - type: sphere
+    material: steel
+", Path::new("."));
+    }
+
+    #[test]
+    fn merge_keys_copy_anchored_values() {
+        let doc = load_scene_doc("
+scene:
+  - type: sphere
+    material: &shiny
+      color: [1, 0, 0]
+      reflective: 0.5
+  - type: cube
+    material:
+      <<: *shiny
+      color: [0, 0, 1]
+", Path::new("."));
+
+        let material = &doc["scene"][1]["material"];
+        assert_eq!(material["reflective"].as_f64(), Some(0.5));
+        assert_eq!(material["color"][2].as_i64(), Some(1));
+        assert!(material["<<"].is_badvalue());
+    }
+
+    #[test]
+    fn groups_pass_cast_shadows_on_to_their_children() {
+        let doc = load_scene_doc("
+scene:
+  - type: group
+    cast_shadows: false
+    children:
+      - type: sphere
+      - type: cube
+        cast_shadows: true
+", Path::new("."));
+
+        let group = &doc["scene"][0];
+        let sphere = inherit_object_properties(group, &group["children"][0]);
+        let cube = inherit_object_properties(group, &group["children"][1]);
+        assert!(!create_object_material(&sphere).cast_shadows);
+        assert!(create_object_material(&cube).cast_shadows);
+    }
+
+    #[test]
+    fn describe_scene_file_summarizes_the_scene() {
+        let dir = std::env::temp_dir().join("rray_describe_test");
+        write_test_file(&dir, "scene.yaml", "
+camera:
+  fov: 60
+  from: [0, 1.5, -5]
+  to: [0, 1, 0]
+  up: [0, 1, 0]
+lights:
+  - type: point
+    color: [1, 1, 1]
+    position: [-10, 10, -10]
+scene:
+  - type: sphere
+  - type: group
+    children:
+      - type: sphere
+      - type: cube
+        transforms:
+          - type: translate
+            amount: [2, 0, 0]
+  - type: cone
+    hidden: true
+");
+        let info = describe_scene_file(dir.join("scene.yaml").to_str().unwrap());
+        assert!(info.contains("Camera: fov 60 degrees, from (0, 1.5, -5), to (0, 1, 0)"));
+        assert!(info.contains("Lights: 1\n  point at (-10, 10, -10)"));
+        assert!(info.contains("Objects: 2 top-level"));
+        assert!(info.contains("  sphere: 2\n  group: 1\n  cube: 1\n"));
+        assert!(!info.contains("cone"));
+        assert!(info.contains("Bounds: (-1, -1, -1) to (3, 1, 1)"));
+    }
+
+    #[test]
+    fn describe_scene_file_lists_materials_triangles_and_files() {
+        let dir = std::env::temp_dir().join("rray_describe_materials_test");
+        write_test_file(&dir, "scene.yaml", "
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+materials:
+  glass:
+    pattern: {type: solid, color: [1, 1, 1]}
+    transparency: 0.9
+scene:
+  - type: sphere
+    material: glass
+  - type: sphere
+    material: glass
+  - type: triangle
+    p1: [0, 0, 0]
+    p2: [1, 0, 0]
+    p3: [0, 1, 0]
+    material:
+      pattern: {type: checker, color_a: [1, 1, 1], color_b: [0, 0, 0]}
+      reflective: 0.5
+  - type: obj_file
+    obj_file: missing.obj
+    hidden: true
+");
+        let info = describe_scene_file(dir.join("scene.yaml").to_str().unwrap());
+        assert!(info.contains("Objects: 3 top-level, 3 primitives, 1 triangles\n"));
+        assert!(info.contains("Materials: 2\n  glass, 2 object(s): pattern solid (1, 1, 1), transparency 0.9\n  1 object(s): pattern checker, reflective 0.5\n"));
+        assert!(info.contains("Files: 1\n  missing.obj (missing)\n"));
+    }
+
+    #[test]
+    fn scene_dependencies_lists_includes_meshes_and_textures() {
+        let dir = std::env::temp_dir().join("rray_dependencies_test");
+        write_test_file(&dir, "scene.yaml", "
+scene:
+  - type: obj_file
+    obj_file: examples/teapot.obj
+  - include: parts/floor.yaml
+");
+        write_test_file(&dir.join("parts"), "floor.yaml", "
+- type: plane
+  material:
+    pattern:
+      type: image
+      file: examples/earthmap.png
+");
+        let scene = dir.join("scene.yaml");
+        let files = scene_dependencies(scene.to_str().unwrap());
+        assert_eq!(files, vec![
+            scene.clone(),
+            dir.join("parts/floor.yaml"),
+            Path::new("examples/earthmap.png").to_path_buf(),
+            Path::new("examples/teapot.obj").to_path_buf(),
+        ]);
+    }
+}
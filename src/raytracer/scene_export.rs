@@ -0,0 +1,807 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use yaml_rust2::Yaml;
+use yaml_rust2::yaml::Hash;
+
+use crate::color::Color;
+use crate::matrix4::Matrix4;
+use crate::raytracer::camera::{Camera, Exposure, StereoOutput};
+use crate::raytracer::json::json_string;
+use crate::raytracer::light::{Light, LightType};
+use crate::raytracer::material::{DiffuseModel, Material, ShadingModel, SpecularModel};
+use crate::raytracer::material::noise::{NoiseAlgorithm, NoiseSettings};
+use crate::raytracer::material::pattern::{CompositeOp, Pattern, PatternType, WorleyDistance};
+use crate::raytracer::material::texture::{Texture, TextureFilter, TextureWrap, UvProjection};
+use crate::raytracer::object::csg::CsgOperation;
+use crate::raytracer::object::db::get_object;
+use crate::raytracer::object::Geometry;
+use crate::raytracer::sampling::Sampler;
+use crate::raytracer::scene::{Background, FogFalloff, Scene, ShadowMode};
+use crate::raytracer::volume::{Volume, VolumeShape};
+use crate::tuple::Tuple;
+
+/// Describes a built scene in the YAML scene format, so that it can be saved and loaded again.
+///
+/// Everything is written out as it is in memory: meshes loaded from OBJ files become groups of triangles,
+/// the transformations of each object become a single transform, animated values are those of the frame
+/// the scene was built for, and every distinct material is written once to the `materials` section and
+/// used by name. Image textures and environments refer to the files they were loaded from.
+///
+/// # Arguments
+///
+/// * `camera` - The camera the scene is seen through.
+/// * `scene` - The scene.
+///
+/// # Returns
+///
+/// The YAML document describing the camera and the scene.
+///
+/// # Panics
+///
+/// Panics if the scene has an object, pattern or environment that was made in memory rather than read from
+/// a scene or image file, and so cannot be described.
+pub fn export_scene(camera: &Camera, scene: &Scene) -> Yaml {
+    let mut materials = vec![];
+    let objects = scene.ids.iter().map(|id| export_object(*id, &mut materials)).collect();
+
+    let mut doc = Hash::new();
+    insert(&mut doc, "camera", export_camera(camera));
+    insert(&mut doc, "lights", Yaml::Array(scene.light.iter().map(export_light).collect()));
+    if !materials.is_empty() {
+        let materials = materials.iter().enumerate()
+            .map(|(i, material)| (Yaml::String(material_name(i)), export_material(material)))
+            .collect();
+        insert(&mut doc, "materials", Yaml::Hash(materials));
+    }
+    insert(&mut doc, "scene", Yaml::Array(objects));
+    insert(&mut doc, "epsilon", real(scene.epsilon));
+    match scene.shadows {
+        ShadowMode::Transparent => {}
+        ShadowMode::Opaque => insert(&mut doc, "shadows", string("opaque")),
+        ShadowMode::Tinted => insert(&mut doc, "shadows", string("tinted")),
+    }
+    if scene.russian_roulette {
+        insert(&mut doc, "russian_roulette", Yaml::Boolean(true));
+    }
+    if let Some(limit) = scene.indirect_clamp {
+        insert(&mut doc, "indirect_clamp", real(limit));
+    }
+    match scene.sampler {
+        Sampler::Random => {}
+        Sampler::Halton => insert(&mut doc, "sampler", string("halton")),
+        Sampler::Sobol => insert(&mut doc, "sampler", string("sobol")),
+        Sampler::BlueNoise => insert(&mut doc, "sampler", string("blue_noise")),
+    }
+    if let Some(ao) = &scene.ambient_occlusion {
+        insert(&mut doc, "ambient_occlusion", hash(vec![("samples", integer(ao.samples)), ("radius", real(ao.radius))]));
+    }
+    match &scene.background {
+        Background::Color(color) if *color == Color::new(0.0, 0.0, 0.0) => {}
+        Background::Color(color) => insert(&mut doc, "background", hash(vec![("type", string("color")), ("color", rgb(color))])),
+        Background::Gradient { bottom, top } => insert(&mut doc, "background", hash(vec![
+            ("type", string("gradient")), ("bottom", rgb(bottom)), ("top", rgb(top)),
+        ])),
+        Background::Pattern(pattern) => insert(&mut doc, "background", hash(vec![
+            ("type", string("pattern")), ("pattern", export_pattern(pattern)),
+        ])),
+    }
+    if let Some(fog) = &scene.fog {
+        let falloff = match fog.falloff {
+            FogFalloff::Linear => "linear",
+            FogFalloff::Exponential => "exponential",
+        };
+        insert(&mut doc, "fog", hash(vec![("color", rgb(&fog.color)), ("density", real(fog.density)), ("falloff", string(falloff))]));
+    }
+    if !scene.volumes.is_empty() {
+        insert(&mut doc, "volumes", Yaml::Array(scene.volumes.iter().map(export_volume).collect()));
+    }
+    if let Some(environment) = &scene.environment {
+        let source = match (&environment.file, &environment.sky) {
+            (Some(file), _) => ("file", string(file)),
+            (None, Some(sky)) => ("sky", hash(vec![
+                ("sun_elevation", real(sky.elevation.to_degrees())),
+                ("sun_azimuth", real(sky.azimuth.to_degrees())),
+                ("turbidity", real(sky.turbidity)),
+                ("ground_albedo", real(sky.ground_albedo)),
+                ("cloud_cover", real(sky.cloud_cover)),
+                ("temperature", real(sky.temperature)),
+            ])),
+            (None, None) => panic!("environments made in memory cannot be exported"),
+        };
+        insert(&mut doc, "environment", hash(vec![
+            source,
+            ("intensity", real(environment.intensity)),
+            ("rotation", real(environment.rotation.to_degrees())),
+            ("light_samples", integer(environment.light_samples)),
+        ]));
+    }
+    Yaml::Hash(doc)
+}
+
+/// Writes a scene document to a file, as JSON if the file name ends in `.json` and as YAML otherwise.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+/// * `doc` - The scene document, as made by `export_scene`.
+///
+/// # Panics
+///
+/// Panics if the file cannot be written.
+pub fn write_scene_file(path: &str, doc: &Yaml) {
+    let is_json = Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let contents = if is_json { scene_to_json(doc) } else { scene_to_yaml(doc) };
+    fs::write(path, contents).unwrap_or_else(|e| panic!("cannot write scene file {}: {}", path, e));
+}
+
+/// Formats a scene document as YAML, with short lists of numbers like points and colors on one line.
+///
+/// # Arguments
+///
+/// * `doc` - The scene document.
+pub fn scene_to_yaml(doc: &Yaml) -> String {
+    let mut out = String::new();
+    write_yaml_block(doc, 0, false, &mut out);
+    out
+}
+
+/// Formats a scene document as JSON, which the scene loader also reads, since YAML includes JSON.
+///
+/// # Arguments
+///
+/// * `doc` - The scene document.
+pub fn scene_to_json(doc: &Yaml) -> String {
+    let mut out = String::new();
+    write_json(doc, 0, &mut out);
+    out.push('\n');
+    out
+}
+
+/// Returns the name under which a material is written to the `materials` section.
+fn material_name(index: usize) -> String {
+    format!("material{}", index + 1)
+}
+
+fn insert(hash: &mut Hash, key: &str, value: Yaml) {
+    hash.insert(Yaml::String(key.to_string()), value);
+}
+
+fn hash(entries: Vec<(&str, Yaml)>) -> Yaml {
+    let mut hash = Hash::new();
+    for (key, value) in entries {
+        insert(&mut hash, key, value);
+    }
+    Yaml::Hash(hash)
+}
+
+fn string(value: &str) -> Yaml {
+    Yaml::String(value.to_string())
+}
+
+/// Writes a number so that it reads back as exactly the same number.
+fn real(value: f64) -> Yaml {
+    Yaml::Real(format!("{:?}", value))
+}
+
+fn integer(value: usize) -> Yaml {
+    Yaml::Integer(value as i64)
+}
+
+fn xyz(t: &Tuple) -> Yaml {
+    Yaml::Array(vec![real(t.x), real(t.y), real(t.z)])
+}
+
+fn rgb(c: &Color) -> Yaml {
+    Yaml::Array(vec![real(c.r), real(c.g), real(c.b)])
+}
+
+fn export_camera(camera: &Camera) -> Yaml {
+    // The rows of the view transformation are the left, up and backward directions of the camera, and
+    // its inverse takes the origin to the camera position
+    let m = &camera.transform;
+    let row = |i: usize| Tuple::vector(m.get(i, 0), m.get(i, 1), m.get(i, 2));
+    let forward = row(2).negate();
+    let true_up = row(1);
+    let from = m.inverse().multiply_tuple(&Tuple::point(0.0, 0.0, 0.0));
+    let to = from.add(&forward);
+    // When the up vector of a camera is not at right angles to its direction, the left and up rows are
+    // shorter than one, which widens the view. Leaning the up vector forwards by the same amount keeps that.
+    let lean = (1.0 - true_up.magnitude().powi(2)).max(0.0).sqrt();
+    let up = true_up.add(&forward.multiply(lean));
+    let mut entry = Hash::new();
+    insert(&mut entry, "fov", real(camera.field_of_view.to_degrees()));
+    insert(&mut entry, "from", xyz(&from));
+    insert(&mut entry, "to", xyz(&to));
+    insert(&mut entry, "up", xyz(&up));
+    if camera.shift_x != 0.0 {
+        insert(&mut entry, "shift_x", real(camera.shift_x));
+    }
+    if camera.shift_y != 0.0 {
+        insert(&mut entry, "shift_y", real(camera.shift_y));
+    }
+    if let Some(stereo) = &camera.stereo {
+        let output = match stereo.output {
+            StereoOutput::SideBySide => "side_by_side",
+            StereoOutput::Anaglyph => "anaglyph",
+        };
+        insert(&mut entry, "stereo", hash(vec![
+            ("interocular", real(stereo.interocular)), ("convergence", real(stereo.convergence)), ("output", string(output)),
+        ]));
+    }
+    match camera.exposure {
+        None => {}
+        Some(Exposure::Auto) => insert(&mut entry, "exposure", string("auto")),
+        Some(Exposure::Ev100(ev)) => insert(&mut entry, "exposure", real(ev)),
+    }
+    Yaml::Hash(entry)
+}
+
+fn export_light(light: &Light) -> Yaml {
+    let mut entry = Hash::new();
+    match &light.light_type {
+        LightType::Point => {
+            insert(&mut entry, "type", string("point"));
+            insert(&mut entry, "position", xyz(&light.position));
+        }
+        LightType::Area(area) => {
+            insert(&mut entry, "type", string("area"));
+            insert(&mut entry, "corner", xyz(&area.corner));
+            insert(&mut entry, "uvec", xyz(&area.uvec));
+            insert(&mut entry, "vvec", xyz(&area.vvec));
+            insert(&mut entry, "usteps", integer(area.usteps));
+            insert(&mut entry, "vsteps", integer(area.vsteps));
+            insert(&mut entry, "jitter", Yaml::Boolean(area.jitter));
+            insert(&mut entry, "adaptive", Yaml::Boolean(area.adaptive));
+        }
+    }
+    insert(&mut entry, "color", rgb(&light.intensity));
+    if light.inverse_square {
+        // The color of a light given in lumens is scaled by lumens / 4π, so this keeps the intensity as it is
+        insert(&mut entry, "lumens", real(4.0 * PI));
+    }
+    if !light.diffuse {
+        insert(&mut entry, "diffuse", Yaml::Boolean(false));
+    }
+    if !light.specular {
+        insert(&mut entry, "specular", Yaml::Boolean(false));
+    }
+    if !light.groups.is_empty() {
+        insert(&mut entry, "groups", Yaml::Array(light.groups.iter().map(|group| string(group)).collect()));
+    }
+    Yaml::Hash(entry)
+}
+
+/// Describes an object, and the objects it is made of, adding the materials it uses to `materials`.
+fn export_object(id: usize, materials: &mut Vec<Material>) -> Yaml {
+    let object = get_object(id);
+    let geometry = object.geometry().unwrap_or_else(|| panic!("object {} cannot be exported", id));
+    let mut entry = Hash::new();
+    let mut has_material = true;
+    match geometry {
+        Geometry::Sphere => insert(&mut entry, "type", string("sphere")),
+        Geometry::Plane { width, depth } => {
+            insert(&mut entry, "type", string("plane"));
+            // Infinite extents are the default, and cannot be written in JSON
+            if width.is_finite() {
+                insert(&mut entry, "width", real(width));
+            }
+            if depth.is_finite() {
+                insert(&mut entry, "depth", real(depth));
+            }
+        }
+        Geometry::Cube => insert(&mut entry, "type", string("cube")),
+        Geometry::Triangle([p1, p2, p3], [n1, n2, n3]) => {
+            let face_normal = p3.subtract(&p1).cross(&p2.subtract(&p1)).normalize();
+            let flat = n1 == face_normal && n2 == face_normal && n3 == face_normal;
+            insert(&mut entry, "type", string(if flat { "triangle" } else { "smooth_triangle" }));
+            insert(&mut entry, "p1", xyz(&p1));
+            insert(&mut entry, "p2", xyz(&p2));
+            insert(&mut entry, "p3", xyz(&p3));
+            if !flat {
+                insert(&mut entry, "n1", xyz(&n1));
+                insert(&mut entry, "n2", xyz(&n2));
+                insert(&mut entry, "n3", xyz(&n3));
+            }
+            if let Some(colors) = object.vertex_colors() {
+                insert(&mut entry, "colors", Yaml::Array(colors.iter().map(rgb).collect()));
+            }
+        }
+        Geometry::Cylinder { minimum, maximum, closed } | Geometry::Cone { minimum, maximum, closed } => {
+            let is_cylinder = matches!(geometry, Geometry::Cylinder { .. });
+            insert(&mut entry, "type", string(if is_cylinder { "cylinder" } else { "cone" }));
+            // Infinite ends are the default, and cannot be written in JSON
+            if minimum.is_finite() {
+                insert(&mut entry, "minimum", real(minimum));
+            }
+            if maximum.is_finite() {
+                insert(&mut entry, "maximum", real(maximum));
+            }
+            insert(&mut entry, "closed", Yaml::Boolean(closed));
+        }
+        Geometry::Torus(minor_radius) => {
+            insert(&mut entry, "type", string("torus"));
+            insert(&mut entry, "minor_radius", real(minor_radius));
+        }
+        Geometry::RoundedBox(radius) => {
+            insert(&mut entry, "type", string("rounded_box"));
+            insert(&mut entry, "radius", real(radius));
+        }
+        Geometry::Group(children) => {
+            insert(&mut entry, "type", string("group"));
+            insert(&mut entry, "children", Yaml::Array(children.iter().map(|child| export_object(*child, materials)).collect()));
+            has_material = false;
+        }
+        Geometry::Csg(operation, left, right) => {
+            let operation = match operation {
+                CsgOperation::Union => "union",
+                CsgOperation::Intersection => "intersection",
+                CsgOperation::Difference => "difference",
+            };
+            insert(&mut entry, "type", string("csg"));
+            insert(&mut entry, "operation", string(operation));
+            insert(&mut entry, "left", export_object(left, materials));
+            insert(&mut entry, "right", export_object(right, materials));
+            has_material = false;
+        }
+    }
+    if let Some(transforms) = export_transforms(object.get_transform()) {
+        insert(&mut entry, "transforms", transforms);
+    }
+    if has_material {
+        // The object level settings kept in the material are written to the object
+        let mut material = object.get_material().clone();
+        let defaults = Material::default();
+        if !material.cast_shadows {
+            insert(&mut entry, "cast_shadows", Yaml::Boolean(false));
+        }
+        if !material.visible_to_camera {
+            insert(&mut entry, "visible_to_camera", Yaml::Boolean(false));
+        }
+        if !material.visible_to_reflections {
+            insert(&mut entry, "visible_to_reflections", Yaml::Boolean(false));
+        }
+        if !material.light_groups.is_empty() {
+            insert(&mut entry, "light_groups", Yaml::Array(material.light_groups.iter().map(|group| string(group)).collect()));
+        }
+        material.cast_shadows = defaults.cast_shadows;
+        material.visible_to_camera = defaults.visible_to_camera;
+        material.visible_to_reflections = defaults.visible_to_reflections;
+        material.light_groups = defaults.light_groups;
+        let index = match materials.iter().position(|m| *m == material) {
+            Some(index) => index,
+            None => {
+                materials.push(material);
+                materials.len() - 1
+            }
+        };
+        insert(&mut entry, "material", Yaml::String(material_name(index)));
+    }
+    Yaml::Hash(entry)
+}
+
+/// Describes a transformation as a single transform, or returns `None` for the identity.
+fn export_transforms(m: &Matrix4) -> Option<Yaml> {
+    let identity = Matrix4::identity();
+    let is_linear_part = |a: &Matrix4, b: &Matrix4| (0..3).all(|i| (0..3).all(|j| a.get(i, j) == b.get(i, j)));
+    let bottom_row = (0..4).all(|j| m.get(3, j) == identity.get(3, j));
+    let translation = (0..3).all(|i| m.get(i, 3) == 0.0);
+    let diagonal = (0..3).all(|i| (0..3).all(|j| i == j || m.get(i, j) == 0.0));
+    let transform = if m.0 == identity.0 {
+        return None;
+    } else if bottom_row && is_linear_part(m, &identity) {
+        hash(vec![("type", string("translate")), ("amount", Yaml::Array((0..3).map(|i| real(m.get(i, 3))).collect()))])
+    } else if bottom_row && translation && diagonal {
+        hash(vec![("type", string("scale")), ("amount", Yaml::Array((0..3).map(|i| real(m.get(i, i))).collect()))])
+    } else {
+        let rows = (0..4).map(|i| Yaml::Array((0..4).map(|j| real(m.get(i, j))).collect())).collect();
+        hash(vec![("type", string("matrix")), ("rows", Yaml::Array(rows))])
+    };
+    Some(Yaml::Array(vec![transform]))
+}
+
+fn export_material(m: &Material) -> Yaml {
+    let defaults = Material::default();
+    let mut entry = Hash::new();
+    insert(&mut entry, "pattern", export_pattern(&m.pattern));
+    let mut number = |key: &str, value: f64, default: f64| {
+        if value != default {
+            insert(&mut entry, key, real(value));
+        }
+    };
+    number("ambient", m.ambient, defaults.ambient);
+    number("diffuse", m.diffuse, defaults.diffuse);
+    number("shininess", m.shininess, defaults.shininess);
+    number("refractive_index", m.refractive_index, defaults.refractive_index);
+    number("metallic", m.metallic, defaults.metallic);
+    number("roughness", m.roughness, defaults.roughness);
+    number("f0", m.f0, defaults.f0);
+    // A channel is either a number, or a pattern whose brightness gives the value
+    let channels = [
+        ("specular", m.specular, &m.channel_maps.specular, defaults.specular),
+        ("reflective", m.reflective, &m.channel_maps.reflective, defaults.reflective),
+        ("transparency", m.transparency, &m.channel_maps.transparency, defaults.transparency),
+        ("refraction_roughness", m.refraction_roughness, &m.channel_maps.refraction_roughness, defaults.refraction_roughness),
+    ];
+    for (key, value, map, default) in channels {
+        match map {
+            Some(pattern) => insert(&mut entry, key, export_pattern(pattern)),
+            None if value != default => insert(&mut entry, key, real(value)),
+            None => {}
+        }
+    }
+    if m.refraction_samples != defaults.refraction_samples {
+        insert(&mut entry, "refraction_samples", integer(m.refraction_samples));
+    }
+    if let DiffuseModel::OrenNayar(sigma) = m.diffuse_model {
+        insert(&mut entry, "diffuse_model", string("oren_nayar"));
+        insert(&mut entry, "sigma", real(sigma));
+    }
+    if m.specular_model == SpecularModel::BlinnPhong {
+        insert(&mut entry, "specular_model", string("blinn_phong"));
+    }
+    if m.model == ShadingModel::Pbr {
+        insert(&mut entry, "model", string("pbr"));
+    }
+    if let Some(normal_map) = &m.normal_map {
+        insert(&mut entry, "normal_map", hash(vec![
+            ("file", string(texture_file(normal_map))), ("filter", string(texture_filter(normal_map.filter))),
+        ]));
+    }
+    if let Some(bump) = &m.bump {
+        insert(&mut entry, "bump", hash(vec![("pattern", export_pattern(&bump.pattern)), ("strength", real(bump.strength))]));
+    }
+    if !m.double_sided {
+        insert(&mut entry, "double_sided", Yaml::Boolean(false));
+    }
+    Yaml::Hash(entry)
+}
+
+fn texture_file(texture: &Texture) -> &str {
+    texture.image.path().expect("textures made in memory cannot be exported")
+}
+
+fn texture_filter(filter: TextureFilter) -> &'static str {
+    match filter {
+        TextureFilter::Nearest => "nearest",
+        TextureFilter::Bilinear => "bilinear",
+        TextureFilter::Trilinear => "trilinear",
+    }
+}
+
+fn uv_projection(projection: Option<UvProjection>) -> &'static str {
+    match projection {
+        None => "shape",
+        Some(UvProjection::Spherical) => "spherical",
+        Some(UvProjection::Planar) => "planar",
+        Some(UvProjection::Cylindrical) => "cylindrical",
+        Some(UvProjection::Cubic) => "cubic",
+    }
+}
+
+fn export_pattern(pattern: &Pattern) -> Yaml {
+    let mut entry = Hash::new();
+    // The two patterns of a pattern are written as colors when they are solid
+    let sub_pattern = |entry: &mut Hash, name: &str, sub: &Pattern| match &sub.pattern_type {
+        PatternType::Solid(color) => insert(entry, &format!("color_{}", name), rgb(color)),
+        _ => insert(entry, &format!("pattern_{}", name), export_pattern(sub)),
+    };
+    let noise = |entry: &mut Hash, scale: f64, octaves: usize, persistence: f64, settings: &NoiseSettings| {
+        let algorithm = match settings.algorithm {
+            NoiseAlgorithm::Perlin => "perlin",
+            NoiseAlgorithm::OpenSimplex2 => "open_simplex2",
+            NoiseAlgorithm::Value => "value",
+            NoiseAlgorithm::Cubic => "cubic",
+        };
+        insert(entry, "scale", real(scale));
+        insert(entry, "octaves", integer(octaves));
+        insert(entry, "persistence", real(persistence));
+        insert(entry, "noise", string(algorithm));
+        insert(entry, "frequency", real(settings.frequency));
+        insert(entry, "w", real(settings.w));
+    };
+    match &pattern.pattern_type {
+        PatternType::Test => panic!("test patterns cannot be exported"),
+        PatternType::Solid(color) => {
+            insert(&mut entry, "type", string("solid"));
+            insert(&mut entry, "color", rgb(color));
+        }
+        PatternType::Stripe(a, b) | PatternType::Gradient(a, b) | PatternType::Ring(a, b) | PatternType::Checker(a, b) => {
+            let pattern_type = match &pattern.pattern_type {
+                PatternType::Stripe(..) => "stripe",
+                PatternType::Gradient(..) => "gradient",
+                PatternType::Ring(..) => "ring",
+                _ => "checker",
+            };
+            insert(&mut entry, "type", string(pattern_type));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+        }
+        PatternType::Blend(a, b, scale) => {
+            insert(&mut entry, "type", string("blend"));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            insert(&mut entry, "scale", real(*scale));
+        }
+        PatternType::Perturbed(a, scale, octaves, persistence, settings) => {
+            insert(&mut entry, "type", string("perturbed"));
+            sub_pattern(&mut entry, "a", a);
+            noise(&mut entry, *scale, *octaves, *persistence, settings);
+        }
+        PatternType::Noise(a, b, scale, octaves, persistence, settings) => {
+            insert(&mut entry, "type", string("noise"));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            noise(&mut entry, *scale, *octaves, *persistence, settings);
+        }
+        PatternType::Texture(texture) => {
+            let wrap = match texture.wrap {
+                TextureWrap::Clamp => "clamp",
+                TextureWrap::Repeat => "repeat",
+                TextureWrap::Mirror => "mirror",
+            };
+            insert(&mut entry, "type", string("image"));
+            insert(&mut entry, "file", string(texture_file(texture)));
+            insert(&mut entry, "filter", string(texture_filter(texture.filter)));
+            insert(&mut entry, "wrap", string(wrap));
+            insert(&mut entry, "uv_scale", Yaml::Array(vec![real(texture.scale.0), real(texture.scale.1)]));
+            insert(&mut entry, "uv_offset", Yaml::Array(vec![real(texture.offset.0), real(texture.offset.1)]));
+            insert(&mut entry, "projection", string(uv_projection(texture.projection)));
+        }
+        PatternType::CubeMap(faces, inside) => {
+            insert(&mut entry, "type", string("cube_map"));
+            insert(&mut entry, "filter", string(texture_filter(faces[0].filter)));
+            for (face, texture) in ["left", "front", "right", "back", "up", "down"].iter().zip(faces.iter()) {
+                insert(&mut entry, face, string(texture_file(texture)));
+            }
+            insert(&mut entry, "inside", Yaml::Boolean(*inside));
+        }
+        PatternType::Wood(a, b, grain) | PatternType::Marble(a, b, grain) => {
+            let is_wood = matches!(pattern.pattern_type, PatternType::Wood(..));
+            insert(&mut entry, "type", string(if is_wood { "wood" } else { "marble" }));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            insert(&mut entry, "frequency", real(grain.frequency));
+            insert(&mut entry, "turbulence", real(grain.turbulence));
+            insert(&mut entry, "octaves", integer(grain.octaves));
+        }
+        PatternType::Brick(a, b, layout) => {
+            let (width, height, depth) = layout.size;
+            insert(&mut entry, "type", string("brick"));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            insert(&mut entry, "brick_size", Yaml::Array(vec![real(width), real(height), real(depth)]));
+            insert(&mut entry, "mortar", real(layout.mortar));
+            insert(&mut entry, "offset", real(layout.offset));
+        }
+        PatternType::Worley(a, b, distance) => {
+            let distance = match distance {
+                WorleyDistance::F1 => "f1",
+                WorleyDistance::F2 => "f2",
+                WorleyDistance::F2MinusF1 => "f2_minus_f1",
+            };
+            insert(&mut entry, "type", string("worley"));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            insert(&mut entry, "distance", string(distance));
+        }
+        PatternType::Composite(op, a, b, mask) => {
+            let op = match op {
+                CompositeOp::Add => "add",
+                CompositeOp::Multiply => "multiply",
+                CompositeOp::Screen => "screen",
+                CompositeOp::Mask => "mask",
+            };
+            insert(&mut entry, "type", string(op));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            if let Some(mask) = mask {
+                insert(&mut entry, "mask", export_pattern(mask));
+            }
+        }
+        PatternType::UvCheckers(a, b, width, height, projection) => {
+            insert(&mut entry, "type", string("uv_checkers"));
+            sub_pattern(&mut entry, "a", a);
+            sub_pattern(&mut entry, "b", b);
+            insert(&mut entry, "width", real(*width));
+            insert(&mut entry, "height", real(*height));
+            insert(&mut entry, "projection", string(uv_projection(*projection)));
+        }
+        PatternType::UvAlignCheck(colors, projection) => {
+            insert(&mut entry, "type", string("uv_align_check"));
+            for (key, color) in ["main", "ul", "ur", "bl", "br"].iter().zip(colors.iter()) {
+                insert(&mut entry, key, rgb(color));
+            }
+            insert(&mut entry, "projection", string(uv_projection(*projection)));
+        }
+    }
+    if let Some(transforms) = export_transforms(&pattern.transform) {
+        insert(&mut entry, "transforms", transforms);
+    }
+    Yaml::Hash(entry)
+}
+
+fn export_volume(volume: &Volume) -> Yaml {
+    let shape = match volume.shape {
+        VolumeShape::Cube => "cube",
+        VolumeShape::Sphere => "sphere",
+    };
+    let mut entry = Hash::new();
+    insert(&mut entry, "shape", string(shape));
+    if let Some(transforms) = export_transforms(volume.transform()) {
+        insert(&mut entry, "transforms", transforms);
+    }
+    insert(&mut entry, "scattering", real(volume.scattering));
+    insert(&mut entry, "absorption", real(volume.absorption));
+    insert(&mut entry, "color", rgb(&volume.color));
+    insert(&mut entry, "emission", rgb(&volume.emission));
+    insert(&mut entry, "steps", integer(volume.steps));
+    Yaml::Hash(entry)
+}
+
+/// Returns whether a value is written over several lines, rather than inline like a number or a point.
+fn is_block(value: &Yaml) -> bool {
+    match value {
+        Yaml::Hash(hash) => !hash.is_empty(),
+        Yaml::Array(items) => items.iter().any(|item| matches!(item, Yaml::Array(_) | Yaml::Hash(_))),
+        _ => false,
+    }
+}
+
+/// Writes a value on one line, formatting its strings with `string`.
+fn inline(value: &Yaml, string: fn(&str) -> String) -> String {
+    match value {
+        Yaml::Real(value) => value.clone(),
+        Yaml::Integer(value) => value.to_string(),
+        Yaml::Boolean(value) => value.to_string(),
+        Yaml::String(value) => string(value),
+        Yaml::Array(items) => format!("[{}]", items.iter().map(|item| inline(item, string)).collect::<Vec<_>>().join(", ")),
+        Yaml::Hash(_) => "{}".to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+/// Quotes a YAML string, unless it can be written as it is without being read as something else.
+fn yaml_string(value: &str) -> String {
+    let plain = value.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+        && !["true", "false", "yes", "no", "on", "off", "null"].contains(&value.to_ascii_lowercase().as_str());
+    if plain { value.to_string() } else { json_string(value) }
+}
+
+/// Writes a mapping or a list in YAML block style, indented by `indent` spaces, except for the first line
+/// when it follows the `- ` of a list item.
+fn write_yaml_block(value: &Yaml, indent: usize, follows_dash: bool, out: &mut String) {
+    let pad = " ".repeat(indent);
+    let mut first = true;
+    let mut line_start = |out: &mut String| {
+        if !(first && follows_dash) {
+            out.push_str(&pad);
+        }
+        first = false;
+    };
+    match value {
+        Yaml::Hash(hash) => {
+            for (key, value) in hash {
+                line_start(out);
+                out.push_str(&yaml_string(key.as_str().unwrap_or_default()));
+                out.push(':');
+                if is_block(value) {
+                    out.push('\n');
+                    write_yaml_block(value, indent + 2, false, out);
+                } else {
+                    out.push_str(&format!(" {}\n", inline(value, yaml_string)));
+                }
+            }
+        }
+        Yaml::Array(items) => {
+            for item in items {
+                line_start(out);
+                out.push_str("- ");
+                if is_block(item) {
+                    write_yaml_block(item, indent + 2, true, out);
+                } else {
+                    out.push_str(&format!("{}\n", inline(item, yaml_string)));
+                }
+            }
+        }
+        _ => out.push_str(&format!("{}{}\n", pad, inline(value, yaml_string))),
+    }
+}
+
+/// Writes a value as JSON, with mappings and lists of mappings or lists spread over lines indented by `indent` spaces.
+fn write_json(value: &Yaml, indent: usize, out: &mut String) {
+    if !is_block(value) {
+        out.push_str(&inline(value, json_string));
+        return;
+    }
+    let pad = " ".repeat(indent + 2);
+    let (open, close, entries): (char, char, Vec<(Option<&Yaml>, &Yaml)>) = match value {
+        Yaml::Hash(hash) => ('{', '}', hash.iter().map(|(key, value)| (Some(key), value)).collect()),
+        Yaml::Array(items) => ('[', ']', items.iter().map(|item| (None, item)).collect()),
+        _ => unreachable!(),
+    };
+    out.push(open);
+    for (i, (key, value)) in entries.iter().enumerate() {
+        out.push_str(if i == 0 { "\n" } else { ",\n" });
+        out.push_str(&pad);
+        if let Some(key) = key {
+            out.push_str(&format!("{}: ", json_string(key.as_str().unwrap_or_default())));
+        }
+        write_json(value, indent + 2, out);
+    }
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+    out.push(close);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use yaml_rust2::Yaml;
+    use crate::raytracer::scene_builder_yaml::{build_scene, load_scene_doc};
+    use super::{export_scene, scene_to_json, scene_to_yaml};
+
+    const SCENE: &str = "
+camera: {fov: 50, from: [0, 2, -6], to: [0, 1, 0], up: [0, 1, 0], roll: 10}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+  - {type: area, watts: 20, corner: [-1, 5, -1], uvec: [2, 0, 0], vvec: [0, 0, 2], usteps: 2, vsteps: 2, groups: key}
+materials:
+  glass: {pattern: {type: solid, color: [1, 1, 1]}, transparency: 0.9, refractive_index: 1.5}
+scene:
+  - type: plane
+    material:
+      pattern:
+        type: checker
+        color_a: [1, 1, 1]
+        pattern_b: {type: stripe, color_a: [0, 0, 0], color_b: [0.5, 0.5, 0.5], transforms: [{type: rotate, axis: y, angle: 30}]}
+      reflective: {type: gradient, color_a: [0, 0, 0], color_b: [1, 1, 1]}
+  - type: sphere
+    material: glass
+    cast_shadows: false
+    transforms:
+      - {type: scale, amount: [0.5, 0.5, 0.5]}
+      - {type: translate, amount: [1, 0.5, 0]}
+  - type: group
+    children:
+      - {type: cylinder, minimum: 0, maximum: 1, closed: true, material: glass}
+      - {type: smooth_triangle, p1: [0, 0, 0], p2: [1, 0, 0], p3: [0, 1, 0], n1: [0, 0, -1], n2: [0, 1, 0], n3: [1, 0, 0]}
+  - type: csg
+    operation: difference
+    left: {type: cube}
+    right: {type: torus, minor_radius: 0.25, transforms: [{type: rotate, axis: x, angle: 90}]}
+fog: {color: [0.5, 0.5, 0.5], density: 0.05}
+";
+
+    #[test]
+    fn exported_scenes_build_the_same_scene_again() {
+        let (camera, scene) = build_scene(&load_scene_doc(SCENE, Path::new(".")), None, 40, 30);
+        let exported = export_scene(&camera, &scene);
+        assert_eq!(exported["materials"].as_hash().unwrap().len(), 3);
+        assert_eq!(exported["scene"][1]["material"].as_str(), Some("material2"));
+        assert_eq!(exported["scene"][2]["children"][0]["material"].as_str(), Some("material2"));
+        assert_eq!(exported["scene"][3]["right"]["type"].as_str(), Some("torus"));
+
+        let (camera_again, scene_again) = build_scene(&load_scene_doc(&scene_to_yaml(&exported), Path::new(".")), None, 40, 30);
+        for i in 0..16 {
+            assert!((camera_again.transform.0[i] - camera.transform.0[i]).abs() < 1e-9);
+        }
+        assert!((camera_again.field_of_view - camera.field_of_view).abs() < 1e-12);
+        let exported_again = export_scene(&camera_again, &scene_again);
+        for key in ["lights", "materials", "scene", "epsilon", "fog"] {
+            assert_eq!(exported_again[key], exported[key], "{} differs", key);
+        }
+    }
+
+    #[test]
+    fn scenes_can_be_exported_as_json() {
+        let (camera, scene) = build_scene(&load_scene_doc(SCENE, Path::new(".")), None, 40, 30);
+        let exported = export_scene(&camera, &scene);
+        let json = scene_to_json(&exported);
+        assert!(json.starts_with("{\n  \"camera\": {\n    \"fov\": "));
+        let loaded = load_scene_doc(&json, Path::new("."));
+        assert_eq!(loaded, load_scene_doc(&scene_to_yaml(&exported), Path::new(".")));
+        assert_eq!(loaded["lights"][1]["groups"], Yaml::Array(vec![Yaml::String("key".to_string())]));
+    }
+}
@@ -0,0 +1,333 @@
+use std::f64::consts::PI;
+use crate::color::Color;
+use crate::tuple::Tuple;
+
+/// The angular radius of the sun seen from the ground, in radians.
+const SUN_RADIUS: f64 = 0.004_65;
+
+/// The irradiance of the sun above the atmosphere, on a surface facing it.
+const SUN_IRRADIANCE: f64 = 3.2;
+
+/// The factor the luminance of the sky model, in kcd/m², is multiplied by, so that a white surface lit by
+/// the sun and sky is about white.
+const SKY_SCALE: f64 = 1.0 / 12.0;
+
+/// The color temperature of daylight, in kelvin, which leaves the colors of the sky model unchanged.
+const NEUTRAL_TEMPERATURE: f64 = 6500.0;
+
+/// The wavelengths, in micrometers, the attenuation of sunlight by the atmosphere is worked out at for the
+/// red, green and blue channels.
+const WAVELENGTHS: [f64; 3] = [0.680, 0.550, 0.440];
+
+/// An analytic model of a clear sky with a sun, after Preetham, Shirley and Smits, "A Practical Analytic Model
+/// for Daylight" (1999).
+///
+/// The sky is brightest around the sun and towards the horizon, turns deep blue opposite the sun in clear air
+/// and hazy white in turbid air, and goes yellow and red when the sun is low. Below the horizon is a flat
+/// ground that reflects the light of the sun and sky. Clouds turn the sky into an evenly grey overcast sky that
+/// hides the sun, and the color temperature tints all of its light.
+///
+/// # Fields
+///
+/// * `elevation` - The angle of the sun above the horizon, in radians.
+/// * `azimuth` - The angle of the sun around the y axis, from the positive z axis towards the positive x axis, in radians.
+/// * `turbidity` - How hazy the air is, from 2 for a very clear sky to 10 for a hazy one.
+/// * `ground_albedo` - The fraction of the light falling on the ground that it reflects.
+/// * `cloud_cover` - How much of the sky is covered by clouds, from 0 for a clear sky to 1 for an overcast one.
+/// * `temperature` - The color temperature of the light in kelvin, lower is warmer and higher is cooler, 6500 leaves
+///   the colors of the model unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    pub elevation: f64,
+    pub azimuth: f64,
+    pub turbidity: f64,
+    pub ground_albedo: f64,
+    pub cloud_cover: f64,
+    pub temperature: f64,
+}
+
+/// The coefficients of the Perez distribution, which gives the brightness of the sky relative to the zenith.
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn new(turbidity: f64, coefficients: [(f64, f64); 5]) -> Perez {
+        Perez(coefficients.map(|(slope, offset)| slope * turbidity + offset))
+    }
+
+    /// The distribution for a point `theta` away from the zenith and `gamma` away from the sun.
+    fn at(&self, theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / theta.cos().max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+impl Sky {
+    /// Creates a clear sky.
+    ///
+    /// # Arguments
+    ///
+    /// * `elevation` - The angle of the sun above the horizon, in radians. When it is below the horizon the sky
+    ///   is lit as if the sun were on the horizon, but the sun itself is not seen and casts no light.
+    /// * `azimuth` - The angle of the sun around the y axis, from the positive z axis towards the positive x axis, in radians.
+    /// * `turbidity` - How hazy the air is, from 2 for a very clear sky to 10 for a hazy one.
+    /// * `ground_albedo` - The fraction of the light falling on the ground that it reflects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the turbidity is outside the range of the model or the ground albedo is not between 0 and 1.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: f64) -> Sky {
+        assert!((1.7..=10.0).contains(&turbidity), "the turbidity of the sky must be between 1.7 and 10");
+        assert!((0.0..=1.0).contains(&ground_albedo), "the ground albedo of the sky must be between 0 and 1");
+        Sky { elevation, azimuth, turbidity, ground_albedo, cloud_cover: 0.0, temperature: NEUTRAL_TEMPERATURE }
+    }
+
+    /// Creates the sky of a time of day.
+    ///
+    /// The presets are:
+    /// * `sunrise` - The sun just above the horizon in slightly hazy air, with warm light.
+    /// * `golden_hour` - The sun low in the sky, with golden light and long shadows.
+    /// * `noon` - The sun high in a clear sky.
+    /// * `overcast` - A grey sky without a visible sun, with soft, cool light.
+    /// * `night` - A dim, blue moonlit sky, with the moon in place of the sun.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the preset.
+    /// * `azimuth` - The angle of the sun around the y axis, in radians.
+    ///
+    /// # Returns
+    ///
+    /// The sky and the intensity the environment should have, or `None` if there is no preset with the name.
+    pub fn preset(name: &str, azimuth: f64) -> Option<(Sky, f64)> {
+        // Sun elevation in degrees, turbidity, cloud cover, color temperature and intensity
+        let (elevation, turbidity, cloud_cover, temperature, intensity): (f64, f64, f64, f64, f64) = match name {
+            "sunrise" => (3.0, 4.0, 0.0, 4500.0, 2.0),
+            "golden_hour" => (10.0, 3.0, 0.0, 4000.0, 1.5),
+            "noon" => (75.0, 2.5, 0.0, 6500.0, 1.0),
+            "overcast" => (45.0, 6.0, 1.0, 7000.0, 2.0),
+            "night" => (40.0, 2.0, 0.0, 12000.0, 0.15),
+            _ => return None,
+        };
+        let mut sky = Sky::new(elevation.to_radians(), azimuth, turbidity, 0.3);
+        sky.cloud_cover = cloud_cover;
+        sky.temperature = temperature;
+        Some((sky, intensity))
+    }
+
+    /// Returns the factor the light of the sky is multiplied by for its color temperature, which keeps its
+    /// brightness the same.
+    fn tint(&self) -> Color {
+        let tint = blackbody(self.temperature);
+        let neutral = blackbody(NEUTRAL_TEMPERATURE);
+        let relative = Color::new(tint.r / neutral.r, tint.g / neutral.g, tint.b / neutral.b);
+        relative.multiply(1.0 / relative.luminance())
+    }
+
+    /// Returns the normalized direction towards the sun.
+    pub fn sun_direction(&self) -> Tuple {
+        let (sin_e, cos_e) = self.elevation.sin_cos();
+        Tuple::vector(cos_e * self.azimuth.sin(), sin_e, cos_e * self.azimuth.cos())
+    }
+
+    /// Returns the irradiance of the sun on a surface facing it, after passing through the atmosphere, or black
+    /// when the sun is below the horizon.
+    ///
+    /// The light is dimmed by scattering off air molecules, which takes out more blue than red, and off haze,
+    /// in proportion to the length of its path through the air.
+    pub fn sun_irradiance(&self) -> Color {
+        if self.elevation <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let zenith_degrees = 90.0 - self.elevation.to_degrees();
+        let air_mass = 1.0 / ((90.0 - zenith_degrees).to_radians().sin() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let haze = 0.046_08 * self.turbidity - 0.045_86;
+        let [r, g, b] = WAVELENGTHS.map(|lambda| {
+            let rayleigh = (-0.008_735 * air_mass * lambda.powf(-4.08)).exp();
+            let aerosol = (-haze * air_mass * lambda.powf(-1.3)).exp();
+            SUN_IRRADIANCE * rayleigh * aerosol * (1.0 - self.cloud_cover)
+        });
+        Color::new(r, g, b).product(&self.tint())
+    }
+
+    /// Returns a direction towards the sun disk, with every point of the disk equally likely.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - A number in `[0, 1)` that picks the distance from the center of the sun.
+    /// * `t` - A number in `[0, 1)` that picks the angle around the center of the sun.
+    pub fn sample_sun(&self, s: f64, t: f64) -> Tuple {
+        let sun = self.sun_direction();
+        let helper = if sun.y.abs() > 0.9 { Tuple::vector(1.0, 0.0, 0.0) } else { Tuple::vector(0.0, 1.0, 0.0) };
+        let tangent = helper.cross(&sun).normalize();
+        let bitangent = sun.cross(&tangent);
+        let cos_theta = 1.0 - s * (1.0 - SUN_RADIUS.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * t;
+        tangent.multiply(sin_theta * phi.cos()).add(&bitangent.multiply(sin_theta * phi.sin())).add(&sun.multiply(cos_theta)).normalize()
+    }
+
+    /// Returns the radiance of the sky in a direction, not including the sun.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - The normalized direction to look in. Directions below the horizon see the ground, which
+    ///   is given separately by `Sky::radiance`, and are treated as looking at the horizon.
+    pub fn sky_radiance(&self, direction: &Tuple) -> Color {
+        let t = self.turbidity;
+        let theta_sun = (PI / 2.0 - self.elevation).clamp(0.0, PI / 2.0);
+        let theta = direction.y.clamp(0.0, 1.0).acos();
+        let sun = Tuple::vector(theta_sun.sin() * self.azimuth.sin(), theta_sun.cos(), theta_sun.sin() * self.azimuth.cos());
+        let gamma = direction.dot(&sun).clamp(-1.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let polynomial = |coefficients: [[f64; 4]; 3]| {
+            let thetas = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let row = |c: [f64; 4]| c.iter().zip(thetas).map(|(c, x)| c * x).sum::<f64>();
+            t * t * row(coefficients[0]) + t * row(coefficients[1]) + row(coefficients[2])
+        };
+        let zenith_x = polynomial([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = polynomial([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let perez_luminance = Perez::new(t, [(0.1787, -1.4630), (-0.3554, 0.4275), (-0.0227, 5.3251), (0.1206, -2.5771), (-0.0670, 0.3703)]);
+        let perez_x = Perez::new(t, [(-0.0193, -0.2592), (-0.0665, 0.0008), (-0.0004, 0.2125), (-0.0641, -0.8989), (-0.0033, 0.0452)]);
+        let perez_y = Perez::new(t, [(-0.0167, -0.2608), (-0.0950, 0.0092), (-0.0079, 0.2102), (-0.0441, -1.6537), (-0.0109, 0.0529)]);
+        let relative = |perez: &Perez| perez.at(theta, gamma) / perez.at(0.0, theta_sun);
+
+        let luminance = zenith_luminance.max(0.0) * relative(&perez_luminance) * SKY_SCALE;
+        let x = zenith_x * relative(&perez_x);
+        let y = zenith_y * relative(&perez_y);
+        let clear = xyy_to_rgb(x, y, luminance);
+
+        // The CIE overcast sky, three times as bright overhead as at the horizon, with the zenith luminance
+        // given by Krochmann's formula
+        let overcast_zenith = 0.123 + 8.6 * self.elevation.max(0.0).sin();
+        let overcast = overcast_zenith * (1.0 + 2.0 * theta.cos()) / 3.0 * SKY_SCALE;
+        clear.multiply(1.0 - self.cloud_cover)
+            .add(&Color::new(overcast, overcast, overcast).multiply(self.cloud_cover))
+            .product(&self.tint())
+    }
+
+    /// Returns the radiance of the sun disk, the sky and the ground in a direction.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - The normalized direction to look in.
+    /// * `ground` - The radiance of the ground, see `Sky::ground_radiance`.
+    pub fn radiance(&self, direction: &Tuple, ground: &Color) -> Color {
+        if direction.y < 0.0 {
+            return *ground;
+        }
+        let sky = self.sky_radiance(direction);
+        if direction.dot(&self.sun_direction()) >= SUN_RADIUS.cos() {
+            sky.add(&self.sun_irradiance().multiply(1.0 / sun_solid_angle()))
+        } else {
+            sky
+        }
+    }
+
+    /// Returns the radiance of the ground, which reflects the light of the sun and of the sky evenly.
+    ///
+    /// # Arguments
+    ///
+    /// * `sky_irradiance` - The light the sky, without the sun, casts on the ground.
+    pub fn ground_radiance(&self, sky_irradiance: &Color) -> Color {
+        let sun = self.sun_irradiance().multiply(self.elevation.sin().max(0.0));
+        sun.add(sky_irradiance).multiply(self.ground_albedo / PI)
+    }
+}
+
+/// The solid angle the sun covers, in steradians.
+pub fn sun_solid_angle() -> f64 {
+    2.0 * PI * (1.0 - SUN_RADIUS.cos())
+}
+
+/// Returns the color of the light of a black body at a temperature in kelvin, after Tanner Helland's fit.
+fn blackbody(temperature: f64) -> Color {
+    let t = temperature.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2) };
+    let g = if t <= 66.0 { 99.470_802_586_1 * t.ln() - 161.119_568_166_1 } else { 288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2) };
+    let b = if t >= 66.0 { 255.0 } else if t <= 19.0 { 0.0 } else { 138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7 };
+    // A little blue is kept for very warm light, so the tint can always be divided by the neutral one
+    Color::new(r.clamp(1.0, 255.0) / 255.0, g.clamp(1.0, 255.0) / 255.0, b.clamp(1.0, 255.0) / 255.0)
+}
+
+/// Converts a color given by its chromaticity `x`, `y` and luminance to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use super::Sky;
+    use crate::color::Color;
+    use crate::tuple::Tuple;
+
+    #[test]
+    fn the_sky_is_blue_overhead_and_brightest_near_the_sun() {
+        let sky = Sky::new(PI / 4.0, 0.0, 3.0, 0.3);
+        let zenith = sky.sky_radiance(&Tuple::vector(0.0, 1.0, 0.0));
+        assert!(zenith.b > zenith.g && zenith.g > zenith.r);
+        let near_sun = sky.sky_radiance(&Tuple::vector(0.0, 0.8, 0.6).normalize());
+        let opposite = sky.sky_radiance(&Tuple::vector(0.0, 0.8, -0.6).normalize());
+        assert!(near_sun.luminance() > 2.0 * opposite.luminance());
+    }
+
+    #[test]
+    fn a_low_sun_is_dimmer_and_redder() {
+        let noon = Sky::new(PI / 2.0 * 0.9, 0.0, 3.0, 0.3).sun_irradiance();
+        let evening = Sky::new(PI / 36.0, 0.0, 3.0, 0.3).sun_irradiance();
+        assert!(evening.luminance() < noon.luminance());
+        assert!(evening.r / evening.b > noon.r / noon.b);
+        assert_eq!(Sky::new(-0.1, 0.0, 3.0, 0.3).sun_irradiance().luminance(), 0.0);
+    }
+
+    #[test]
+    fn looking_at_the_sun_and_the_ground() {
+        let sky = Sky::new(PI / 6.0, PI / 2.0, 3.0, 0.3);
+        let sun = sky.sun_direction();
+        assert!((sun.x - (PI / 6.0).cos()).abs() < 1e-9 && (sun.y - 0.5).abs() < 1e-9 && sun.z.abs() < 1e-9);
+        let ground = sky.ground_radiance(&Color::new(0.5, 0.5, 0.5));
+        assert!(sky.radiance(&sun, &ground).luminance() > 1000.0);
+        assert_eq!(sky.radiance(&Tuple::vector(0.0, -1.0, 0.0), &ground), ground);
+    }
+
+    #[test]
+    fn time_of_day_presets() {
+        let (noon, intensity) = Sky::preset("noon", 0.0).unwrap();
+        assert_eq!(intensity, 1.0);
+        let (golden_hour, _) = Sky::preset("golden_hour", 0.0).unwrap();
+        assert!(golden_hour.elevation < noon.elevation);
+        let sun = golden_hour.sun_irradiance();
+        assert!(sun.r > sun.g && sun.g > sun.b);
+
+        // An overcast sky hides the sun and is grey
+        let (overcast, _) = Sky::preset("overcast", 0.0).unwrap();
+        assert_eq!(overcast.sun_irradiance().luminance(), 0.0);
+        let zenith = overcast.sky_radiance(&Tuple::vector(0.0, 1.0, 0.0));
+        let horizon = overcast.sky_radiance(&Tuple::vector(1.0, 0.0, 0.0));
+        assert!((zenith.luminance() / horizon.luminance() - 3.0).abs() < 1e-9);
+
+        let (night, intensity) = Sky::preset("night", 0.0).unwrap();
+        let (moon, sun) = (night.sun_irradiance(), noon.sun_irradiance());
+        assert!(intensity < 0.5 && moon.b / moon.r > sun.b / sun.r);
+        assert!(Sky::preset("dusk", 0.0).is_none());
+    }
+}
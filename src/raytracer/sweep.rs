@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+use crate::tuple::Tuple;
+
+/// The step used to estimate the direction of a curve from points on either side, as a fraction of the curve.
+const TANGENT_STEP: f64 = 1e-6;
+
+/// The curves a tube can be swept along by `sweep`.
+///
+/// # Variants
+///
+/// * `Helix` - A coil of `radius` around the y axis, going up from the origin by `height` in `turns` turns.
+/// * `TorusKnot` - The knot that winds `p` times around the y axis and `q` times around a circle of `radius`
+///   in the xz plane, at a distance of `minor_radius` from that circle.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+    Helix { radius: f64, height: f64, turns: f64 },
+    TorusKnot { p: u32, q: u32, radius: f64, minor_radius: f64 },
+}
+
+impl Curve {
+    /// Returns the point of the curve at `t`, which goes from 0 at the start of the curve to 1 at its end.
+    pub fn point(&self, t: f64) -> Tuple {
+        match *self {
+            Curve::Helix { radius, height, turns } => {
+                let angle = 2.0 * PI * turns * t;
+                Tuple::point(radius * angle.cos(), height * t, radius * angle.sin())
+            }
+            Curve::TorusKnot { p, q, radius, minor_radius } => {
+                let angle = 2.0 * PI * t;
+                let (p, q) = (p as f64, q as f64);
+                let distance = radius + minor_radius * (q * angle).cos();
+                Tuple::point(distance * (p * angle).cos(), -minor_radius * (q * angle).sin(), distance * (p * angle).sin())
+            }
+        }
+    }
+
+    /// Returns whether the curve ends where it starts, so a tube along it has no ends.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, Curve::TorusKnot { .. })
+    }
+
+    /// Returns the direction of the curve at `t`, as a unit vector.
+    fn tangent(&self, t: f64) -> Tuple {
+        self.point(t + TANGENT_STEP).subtract(&self.point(t - TANGENT_STEP)).normalize()
+    }
+}
+
+/// The triangles of a tube swept along a curve, made by `sweep`.
+///
+/// # Fields
+///
+/// * `positions` - The corners of the triangles.
+/// * `normals` - The normal at each corner, pointing out of the tube.
+/// * `faces` - The indices of the corners of each triangle, ordered counterclockwise when seen from outside.
+pub struct SweepMesh {
+    pub positions: Vec<Tuple>,
+    pub normals: Vec<Tuple>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+/// Sweeps a circle along a curve, making a tube of triangles.
+///
+/// The circles stay square to the curve and are turned along it as little as possible, so the tube does not
+/// twist. On a closed curve the small turn left where the tube meets its start is spread along the whole tube.
+/// The ends of a tube along an open curve are closed by flat caps.
+///
+/// # Arguments
+///
+/// * `curve` - The curve at the middle of the tube.
+/// * `radius` - The radius of the tube.
+/// * `segments` - The number of pieces the curve is split into.
+/// * `sides` - The number of sides of each circle.
+///
+/// # Returns
+///
+/// The triangles of the tube.
+///
+/// # Panics
+///
+/// Panics if there are fewer than 3 segments or sides.
+pub fn sweep(curve: &Curve, radius: f64, segments: usize, sides: usize) -> SweepMesh {
+    if segments < 3 || sides < 3 {
+        panic!("a sweep needs at least 3 segments and 3 sides, got {} and {}", segments, sides);
+    }
+    let closed = curve.is_closed();
+    let rings = if closed { segments } else { segments + 1 };
+    let ts: Vec<f64> = (0..rings).map(|i| i as f64 / segments as f64).collect();
+    let centers: Vec<Tuple> = ts.iter().map(|&t| curve.point(t)).collect();
+    let tangents: Vec<Tuple> = ts.iter().map(|&t| curve.tangent(t)).collect();
+    let normals = ring_normals(&centers, &tangents, closed);
+
+    let mut mesh = SweepMesh { positions: vec![], normals: vec![], faces: vec![] };
+    for i in 0..rings {
+        let binormal = tangents[i].cross(&normals[i]);
+        for j in 0..sides {
+            let angle = 2.0 * PI * j as f64 / sides as f64;
+            let normal = normals[i].multiply(angle.cos()).add(&binormal.multiply(angle.sin()));
+            mesh.positions.push(centers[i].add(&normal.multiply(radius)));
+            mesh.normals.push(normal);
+        }
+    }
+    let corner = |i: usize, j: usize| (i % rings) * sides + j % sides;
+    for i in 0..segments {
+        for j in 0..sides {
+            let (a, b, c, d) = (corner(i, j), corner(i + 1, j), corner(i + 1, j + 1), corner(i, j + 1));
+            mesh.faces.push([a, d, c]);
+            mesh.faces.push([a, c, b]);
+        }
+    }
+
+    if !closed {
+        for (ring, outward) in [(0, tangents[0].negate()), (rings - 1, tangents[rings - 1])] {
+            // The caps get corners of their own, so they are flat instead of rounded like the tube
+            let center = mesh.positions.len();
+            mesh.positions.push(centers[ring]);
+            mesh.normals.push(outward);
+            for j in 0..sides {
+                mesh.positions.push(mesh.positions[corner(ring, j)]);
+                mesh.normals.push(outward);
+            }
+            for j in 0..sides {
+                let (first, second) = (center + 1 + j, center + 1 + (j + 1) % sides);
+                mesh.faces.push(if ring == 0 { [center, second, first] } else { [center, first, second] });
+            }
+        }
+    }
+    mesh
+}
+
+/// Finds a normal of the curve at every ring of a tube, turning as little as possible from one ring to the
+/// next, by reflecting the normal of each ring into the next twice (the double reflection method).
+///
+/// # Arguments
+///
+/// * `centers` - The points of the curve at the rings.
+/// * `tangents` - The directions of the curve at the rings.
+/// * `closed` - Whether the last ring joins the first, in which case the turn between them is spread out.
+///
+/// # Returns
+///
+/// A unit normal for each ring, square to its tangent.
+fn ring_normals(centers: &[Tuple], tangents: &[Tuple], closed: bool) -> Vec<Tuple> {
+    let first = tangents[0];
+    let helper = if first.x.abs() < 0.9 { Tuple::vector(1.0, 0.0, 0.0) } else { Tuple::vector(0.0, 1.0, 0.0) };
+    let mut normals = vec![first.cross(&helper).normalize()];
+    let reflect = |v: &Tuple, axis: &Tuple| {
+        let length_squared = axis.dot(axis);
+        if length_squared == 0.0 { *v } else { v.subtract(&axis.multiply(2.0 * axis.dot(v) / length_squared)) }
+    };
+    let next = |i: usize, j: usize, normal: &Tuple| {
+        let step = centers[j].subtract(&centers[i]);
+        let normal = reflect(normal, &step);
+        let tangent = reflect(&tangents[i], &step);
+        reflect(&normal, &tangents[j].subtract(&tangent)).normalize()
+    };
+    for i in 1..centers.len() {
+        let normal = next(i - 1, i, &normals[i - 1]);
+        normals.push(normal);
+    }
+
+    if closed {
+        // Carry the last normal around to the first ring and spread the angle it misses by along the tube
+        let last = centers.len() - 1;
+        let around = next(last, 0, &normals[last]);
+        let missed = normals[0].cross(&around).dot(&first).atan2(normals[0].dot(&around));
+        for (i, normal) in normals.iter_mut().enumerate() {
+            let angle = -missed * i as f64 / centers.len() as f64;
+            let binormal = tangents[i].cross(normal);
+            *normal = normal.multiply(angle.cos()).add(&binormal.multiply(angle.sin()));
+        }
+    }
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raytracer::sweep::{sweep, Curve};
+
+    #[test]
+    fn a_tube_along_a_helix_has_caps_at_its_ends() {
+        let curve = Curve::Helix { radius: 1.0, height: 2.0, turns: 2.0 };
+        let mesh = sweep(&curve, 0.25, 32, 8);
+        // The sides of the tube and a fan of triangles at each end
+        assert_eq!(mesh.faces.len(), 32 * 8 * 2 + 2 * 8);
+        for i in 0..=32 {
+            let center = curve.point(i as f64 / 32.0);
+            for j in 0..8 {
+                let k = i * 8 + j;
+                let offset = mesh.positions[k].subtract(&center);
+                assert!((offset.magnitude() - 0.25).abs() < 1e-9);
+                assert!((offset.normalize().dot(&mesh.normals[k]) - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_of_a_tube_face_outwards() {
+        let curve = Curve::TorusKnot { p: 2, q: 3, radius: 1.0, minor_radius: 0.4 };
+        let mesh = sweep(&curve, 0.1, 120, 12);
+        assert_eq!(mesh.faces.len(), 120 * 12 * 2);
+        for [a, b, c] in mesh.faces {
+            let [p1, p2, p3] = [a, b, c].map(|i| mesh.positions[i]);
+            let front = p2.subtract(&p1).cross(&p3.subtract(&p1));
+            assert!(front.dot(&mesh.normals[a]) > 0.0);
+        }
+    }
+
+    #[test]
+    fn the_rings_of_a_closed_tube_join_without_a_twist() {
+        let curve = Curve::TorusKnot { p: 3, q: 2, radius: 1.0, minor_radius: 0.3 };
+        let mesh = sweep(&curve, 0.1, 200, 8);
+        // Each corner of the last ring lines up with the same corner of the first ring
+        let last = 199 * 8;
+        for j in 0..8 {
+            let closest = (0..8).max_by(|&a, &b| {
+                mesh.normals[last + j].dot(&mesh.normals[a]).total_cmp(&mesh.normals[last + j].dot(&mesh.normals[b]))
+            });
+            assert_eq!(closest, Some(j));
+        }
+    }
+}
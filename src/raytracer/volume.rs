@@ -0,0 +1,189 @@
+use crate::color::Color;
+use crate::matrix4::Matrix4;
+use crate::raytracer::ray::Ray;
+use crate::tuple::Tuple;
+
+/// The shape of the region a volume fills, before its transformation.
+///
+/// # Variants
+///
+/// * `Cube` - The cube from -1 to 1 on every axis.
+/// * `Sphere` - The sphere with radius 1 around the origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VolumeShape {
+    Cube,
+    Sphere,
+}
+
+/// A region filled with a homogeneous participating medium, like fog, smoke or dusty air.
+///
+/// Light travelling through the region is absorbed and scattered away, so objects behind it are dimmed,
+/// and light from the scene's lights is scattered towards the viewer, so lit parts of the region glow and
+/// shadows cast through it show up as light shafts.
+///
+/// # Fields
+///
+/// * `shape` - The shape of the region.
+/// * `transform` - The transformation from volume space to world space.
+/// * `inverse` - The inverse of the transformation from volume space to world space, kept to avoid inverting it for every ray.
+/// * `absorption` - The fraction of light absorbed per unit of distance.
+/// * `scattering` - The fraction of light scattered per unit of distance.
+/// * `color` - The color of the scattered light, as a fraction of the light it comes from.
+/// * `emission` - Light emitted by the medium itself per unit of distance, for glowing fog.
+/// * `steps` - The number of samples taken along each ray through the region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    pub shape: VolumeShape,
+    transform: Matrix4,
+    inverse: Matrix4,
+    pub absorption: f64,
+    pub scattering: f64,
+    pub color: Color,
+    pub emission: Color,
+    pub steps: usize,
+}
+
+impl Volume {
+    /// Creates a new `Volume` of white, scattering only medium.
+    ///
+    /// # Arguments
+    ///
+    /// * `shape` - The shape of the region.
+    /// * `transform` - The transformation from volume space to world space.
+    /// * `scattering` - The fraction of light scattered per unit of distance.
+    ///
+    /// # Returns
+    ///
+    /// A new `Volume` with no absorption or emission, sampled 32 times along each ray.
+    pub fn new(shape: VolumeShape, transform: Matrix4, scattering: f64) -> Volume {
+        Volume {
+            shape,
+            transform,
+            inverse: transform.inverse(),
+            absorption: 0.0,
+            scattering,
+            color: Color::new(1.0, 1.0, 1.0),
+            emission: Color::new(0.0, 0.0, 0.0),
+            steps: 32,
+        }
+    }
+
+    /// Returns the transformation from volume space to world space.
+    pub fn transform(&self) -> &Matrix4 {
+        &self.transform
+    }
+
+    /// Returns the fraction of light lost per unit of distance, by absorption and scattering together.
+    pub fn extinction(&self) -> f64 {
+        self.absorption + self.scattering
+    }
+
+    /// Finds where a ray enters and leaves the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The ray.
+    ///
+    /// # Returns
+    ///
+    /// The `t` values along the ray where it enters and leaves the region, or `None` if it misses the region.
+    /// The entry can be negative when the ray starts inside the region.
+    pub fn interval(&self, r: &Ray) -> Option<(f64, f64)> {
+        let r = r.transform(&self.inverse);
+        match self.shape {
+            VolumeShape::Cube => {
+                let mut t_min = f64::NEG_INFINITY;
+                let mut t_max = f64::INFINITY;
+                let axes = [(r.origin.x, r.direction.x), (r.origin.y, r.direction.y), (r.origin.z, r.direction.z)];
+                for (origin, direction) in axes {
+                    if direction.abs() < f64::EPSILON {
+                        if origin.abs() > 1.0 {
+                            return None;
+                        }
+                        continue;
+                    }
+                    let t1 = (-1.0 - origin) / direction;
+                    let t2 = (1.0 - origin) / direction;
+                    t_min = t_min.max(t1.min(t2));
+                    t_max = t_max.min(t1.max(t2));
+                }
+                if t_min < t_max { Some((t_min, t_max)) } else { None }
+            }
+            VolumeShape::Sphere => {
+                let origin = Tuple::vector(r.origin.x, r.origin.y, r.origin.z);
+                let a = r.direction.dot(&r.direction);
+                let b = 2.0 * r.direction.dot(&origin);
+                let c = origin.dot(&origin) - 1.0;
+                let discriminant = b * b - 4.0 * a * c;
+                if discriminant <= 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                Some(((-b - root) / (2.0 * a), (-b + root) / (2.0 * a)))
+            }
+        }
+    }
+
+    /// Finds the part of a ray segment that lies inside the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The ray.
+    /// * `t_max` - The end of the segment; it starts at the ray origin.
+    ///
+    /// # Returns
+    ///
+    /// The `t` values where the segment enters and leaves the region, or `None` if it does not pass through it.
+    pub fn segment(&self, r: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let (t0, t1) = self.interval(r)?;
+        let (t0, t1) = (t0.max(0.0), t1.min(t_max));
+        if t0 < t1 { Some((t0, t1)) } else { None }
+    }
+
+    /// Returns the fraction of light that makes it through the region along a ray segment.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - The ray.
+    /// * `t_max` - The end of the segment; it starts at the ray origin.
+    ///
+    /// # Returns
+    ///
+    /// A value from 0.0, when all light is lost, to 1.0, when the segment misses the region.
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> f64 {
+        match self.segment(r, t_max) {
+            Some((t0, t1)) => (-self.extinction() * (t1 - t0) * r.direction.magnitude()).exp(),
+            None => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Volume, VolumeShape};
+    use crate::matrix4::Matrix4;
+    use crate::raytracer::ray::Ray;
+    use crate::tuple::Tuple;
+
+    #[test]
+    fn finding_where_a_ray_passes_through_a_volume() {
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let cube = Volume::new(VolumeShape::Cube, Matrix4::scale(2.0, 2.0, 2.0), 0.1);
+        assert_eq!(cube.interval(&r), Some((3.0, 7.0)));
+        assert_eq!(cube.segment(&r, 4.0), Some((3.0, 4.0)));
+        let sphere = Volume::new(VolumeShape::Sphere, Matrix4::identity(), 0.1);
+        assert_eq!(sphere.interval(&r), Some((4.0, 6.0)));
+        let miss = Ray::new(Tuple::point(0.0, 3.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        assert_eq!(sphere.interval(&miss), None);
+        assert_eq!(cube.interval(&miss), None);
+    }
+
+    #[test]
+    fn light_is_attenuated_through_a_volume() {
+        let r = Ray::new(Tuple::point(0.0, 0.0, -5.0), Tuple::vector(0.0, 0.0, 1.0));
+        let mut sphere = Volume::new(VolumeShape::Sphere, Matrix4::identity(), 0.25);
+        sphere.absorption = 0.25;
+        assert!((sphere.transmittance(&r, 10.0) - (-1.0_f64).exp()).abs() < 1e-9);
+        assert_eq!(sphere.transmittance(&r, 2.0), 1.0);
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::raytracer::object::db::clear_global_objects;
+use crate::raytracer::scene_builder_yaml::{render_scene_from_file, scene_dependencies, RenderOptions};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Returns the modification time of every file in `files`, or `None` for files that cannot be read.
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Renders a preview of the scene, reporting errors instead of aborting.
+///
+/// All objects left over from the previous render are removed from the global object storage
+/// first, so that repeated renders don't keep accumulating objects.
+fn render_preview(scene: &str, output: &str, options: &RenderOptions) {
+    clear_global_objects();
+    let result = std::panic::catch_unwind(|| {
+        render_scene_from_file(scene, output, options);
+    });
+    match result {
+        Ok(_) => println!("Rendered {} to {}", scene, output),
+        Err(_) => eprintln!("Failed to render {}, waiting for changes", scene),
+    }
+}
+
+/// Watches a scene file and re-renders a preview whenever it or one of its dependencies changes.
+///
+/// The scene file, the files it includes, and the OBJ files and textures it references are polled
+/// for changes. The preview is rendered at the requested size divided by `preview_scale`, without
+/// anti-aliasing, to keep the edit-preview loop fast. This function never returns.
+///
+/// # Arguments
+///
+/// * `scene` - The path to the YAML scene file.
+/// * `output` - The path where the preview image is written.
+/// * `options` - The settings of the full size render.
+/// * `preview_scale` - The factor the image size is divided by for previews.
+pub fn watch_scene(scene: &str, output: &str, options: &RenderOptions, preview_scale: usize) {
+    let preview = RenderOptions {
+        width: (options.width / preview_scale).max(1),
+        height: (options.height / preview_scale).max(1),
+        aa: 1,
+        frames: None,
+        ..options.clone()
+    };
+
+    let mut files = scene_dependencies(scene);
+    let mut times = modification_times(&files);
+    println!("Watching {} file(s) for changes, press Ctrl-C to stop", files.len());
+    render_preview(scene, output, &preview);
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if modification_times(&files) == times {
+            continue;
+        }
+        // Give editors a moment to finish writing before reading the files
+        thread::sleep(POLL_INTERVAL);
+        render_preview(scene, output, &preview);
+        files = scene_dependencies(scene);
+        times = modification_times(&files);
+    }
+}
@@ -0,0 +1,381 @@
+// This module provides SIMD implementations of the tuple and 4x4 matrix operations that dominate the inner loops of
+// the ray tracer. It is only compiled with the `simd` feature. On x86_64 it uses AVX2 and FMA when the build enables
+// them (for example with `RUSTFLAGS="-C target-cpu=native"`) and SSE2 otherwise; on aarch64 it uses NEON. Other
+// architectures use the same scalar code as builds without the feature.
+
+use crate::tuple::Tuple;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2", target_feature = "fma"))]
+mod arch {
+    use std::arch::x86_64::*;
+    use crate::tuple::Tuple;
+
+    #[inline(always)]
+    unsafe fn load(t: &Tuple) -> __m256d {
+        // SAFETY: `Tuple` is `repr(C)` with four `f64` fields, so the pointer to the whole tuple covers the
+        // four values that are read. The load is unaligned.
+        _mm256_loadu_pd(t as *const Tuple as *const f64)
+    }
+
+    #[inline(always)]
+    unsafe fn store(v: __m256d) -> Tuple {
+        let mut t = Tuple::new(0.0, 0.0, 0.0, 0.0);
+        // SAFETY: as in `load`, the pointer to the whole tuple covers the four values that are written.
+        _mm256_storeu_pd(&mut t as *mut Tuple as *mut f64, v);
+        t
+    }
+
+    /// Adds the four lanes of each of four vectors, returning the four sums.
+    #[inline(always)]
+    unsafe fn horizontal_sums(p0: __m256d, p1: __m256d, p2: __m256d, p3: __m256d) -> __m256d {
+        let h01 = _mm256_hadd_pd(p0, p1);
+        let h23 = _mm256_hadd_pd(p2, p3);
+        let crossed = _mm256_permute2f128_pd(h01, h23, 0x21);
+        let blended = _mm256_blend_pd(h01, h23, 0b1100);
+        _mm256_add_pd(crossed, blended)
+    }
+
+    pub fn dot(a: &Tuple, b: &Tuple) -> f64 {
+        // SAFETY: the target supports AVX2, and `load` reads only inside the tuples.
+        unsafe {
+            let p = _mm256_mul_pd(load(a), load(b));
+            let sum = _mm_add_pd(_mm256_castpd256_pd128(p), _mm256_extractf128_pd(p, 1));
+            _mm_cvtsd_f64(_mm_add_sd(sum, _mm_unpackhi_pd(sum, sum)))
+        }
+    }
+
+    pub fn cross(a: &Tuple, b: &Tuple) -> Tuple {
+        // SAFETY: the target supports AVX2 and FMA, and `load` reads only inside the tuples.
+        unsafe {
+            let (a, b) = (load(a), load(b));
+            // (y, z, x, w) and (z, x, y, w)
+            let a_yzx = _mm256_permute4x64_pd(a, 0b11_00_10_01);
+            let a_zxy = _mm256_permute4x64_pd(a, 0b11_01_00_10);
+            let b_yzx = _mm256_permute4x64_pd(b, 0b11_00_10_01);
+            let b_zxy = _mm256_permute4x64_pd(b, 0b11_01_00_10);
+            let c = _mm256_fmsub_pd(a_yzx, b_zxy, _mm256_mul_pd(a_zxy, b_yzx));
+            // The w of the product is w * w - w * w, which is not zero for NaN and infinite components
+            store(_mm256_blend_pd(c, _mm256_setzero_pd(), 0b1000))
+        }
+    }
+
+    pub fn multiply_tuple(m: &[f64; 16], t: &Tuple) -> Tuple {
+        // SAFETY: every row starts at most 12 elements into the 16 of the matrix, so the four values read
+        // for it are inside the array.
+        unsafe {
+            let t = load(t);
+            let m = m.as_ptr();
+            let p0 = _mm256_mul_pd(_mm256_loadu_pd(m), t);
+            let p1 = _mm256_mul_pd(_mm256_loadu_pd(m.add(4)), t);
+            let p2 = _mm256_mul_pd(_mm256_loadu_pd(m.add(8)), t);
+            let p3 = _mm256_mul_pd(_mm256_loadu_pd(m.add(12)), t);
+            store(horizontal_sums(p0, p1, p2, p3))
+        }
+    }
+
+    pub fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+        let mut result = [0.0; 16];
+        // SAFETY: every row starts at most 12 elements into the 16 of its matrix, so the four values read or
+        // written for it are inside the array.
+        unsafe {
+            let b = b.as_ptr();
+            let rows = [_mm256_loadu_pd(b), _mm256_loadu_pd(b.add(4)), _mm256_loadu_pd(b.add(8)), _mm256_loadu_pd(b.add(12))];
+            let out = result.as_mut_ptr();
+            for i in 0..4 {
+                let mut row = _mm256_mul_pd(_mm256_set1_pd(a[i * 4]), rows[0]);
+                row = _mm256_fmadd_pd(_mm256_set1_pd(a[i * 4 + 1]), rows[1], row);
+                row = _mm256_fmadd_pd(_mm256_set1_pd(a[i * 4 + 2]), rows[2], row);
+                row = _mm256_fmadd_pd(_mm256_set1_pd(a[i * 4 + 3]), rows[3], row);
+                _mm256_storeu_pd(out.add(i * 4), row);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(all(target_feature = "avx2", target_feature = "fma"))))]
+mod arch {
+    use std::arch::x86_64::*;
+    use crate::tuple::Tuple;
+
+    /// Loads the x and y, and the z and w components of a tuple.
+    #[inline(always)]
+    unsafe fn load(t: &Tuple) -> (__m128d, __m128d) {
+        // SAFETY: `Tuple` is `repr(C)` with four `f64` fields, so the pointer to the whole tuple covers the
+        // two pairs of values that are read. The loads are unaligned.
+        let p = t as *const Tuple as *const f64;
+        (_mm_loadu_pd(p), _mm_loadu_pd(p.add(2)))
+    }
+
+    #[inline(always)]
+    unsafe fn store(xy: __m128d, zw: __m128d) -> Tuple {
+        let mut t = Tuple::new(0.0, 0.0, 0.0, 0.0);
+        // SAFETY: as in `load`, the pointer to the whole tuple covers the two pairs of values that are written.
+        let p = &mut t as *mut Tuple as *mut f64;
+        _mm_storeu_pd(p, xy);
+        _mm_storeu_pd(p.add(2), zw);
+        t
+    }
+
+    /// Multiplies a row of a matrix by a tuple, leaving the two halves of the dot product in the lanes.
+    ///
+    /// `row` must point to four readable values.
+    #[inline(always)]
+    unsafe fn row_products(row: *const f64, t: (__m128d, __m128d)) -> __m128d {
+        _mm_add_pd(_mm_mul_pd(_mm_loadu_pd(row), t.0), _mm_mul_pd(_mm_loadu_pd(row.add(2)), t.1))
+    }
+
+    /// Adds the lanes of each of two vectors, returning the two sums.
+    #[inline(always)]
+    unsafe fn horizontal_sums(a: __m128d, b: __m128d) -> __m128d {
+        _mm_add_pd(_mm_unpacklo_pd(a, b), _mm_unpackhi_pd(a, b))
+    }
+
+    pub fn dot(a: &Tuple, b: &Tuple) -> f64 {
+        // SAFETY: SSE2 is part of x86_64, and `load` reads only inside the tuples.
+        unsafe {
+            let ((a_xy, a_zw), (b_xy, b_zw)) = (load(a), load(b));
+            let sum = _mm_add_pd(_mm_mul_pd(a_xy, b_xy), _mm_mul_pd(a_zw, b_zw));
+            _mm_cvtsd_f64(_mm_add_sd(sum, _mm_unpackhi_pd(sum, sum)))
+        }
+    }
+
+    pub fn cross(a: &Tuple, b: &Tuple) -> Tuple {
+        // SAFETY: SSE2 is part of x86_64, and `load` reads only inside the tuples.
+        unsafe {
+            let ((a_xy, a_zw), (b_xy, b_zw)) = (load(a), load(b));
+            // x and y of the product: (y, z) * (z, x) - (z, x) * (y, z)
+            let a_yz = _mm_shuffle_pd(a_xy, a_zw, 0b01);
+            let a_zx = _mm_unpacklo_pd(a_zw, a_xy);
+            let b_yz = _mm_shuffle_pd(b_xy, b_zw, 0b01);
+            let b_zx = _mm_unpacklo_pd(b_zw, b_xy);
+            let xy = _mm_sub_pd(_mm_mul_pd(a_yz, b_zx), _mm_mul_pd(a_zx, b_yz));
+            // z of the product: x * by - y * bx
+            let p = _mm_mul_pd(a_xy, _mm_shuffle_pd(b_xy, b_xy, 0b01));
+            let z = _mm_sub_sd(p, _mm_unpackhi_pd(p, p));
+            store(xy, _mm_unpacklo_pd(z, _mm_setzero_pd()))
+        }
+    }
+
+    pub fn multiply_tuple(m: &[f64; 16], t: &Tuple) -> Tuple {
+        // SAFETY: every row starts at most 12 elements into the 16 of the matrix, so the four values read
+        // for it are inside the array.
+        unsafe {
+            let t = load(t);
+            let m = m.as_ptr();
+            let xy = horizontal_sums(row_products(m, t), row_products(m.add(4), t));
+            let zw = horizontal_sums(row_products(m.add(8), t), row_products(m.add(12), t));
+            store(xy, zw)
+        }
+    }
+
+    pub fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+        let mut result = [0.0; 16];
+        // SAFETY: every pair of values read or written starts at most 14 elements into the 16 of its matrix.
+        unsafe {
+            let (b, out) = (b.as_ptr(), result.as_mut_ptr());
+            for i in 0..4 {
+                let mut left = _mm_setzero_pd();
+                let mut right = _mm_setzero_pd();
+                for k in 0..4 {
+                    let factor = _mm_set1_pd(a[i * 4 + k]);
+                    left = _mm_add_pd(left, _mm_mul_pd(factor, _mm_loadu_pd(b.add(k * 4))));
+                    right = _mm_add_pd(right, _mm_mul_pd(factor, _mm_loadu_pd(b.add(k * 4 + 2))));
+                }
+                _mm_storeu_pd(out.add(i * 4), left);
+                _mm_storeu_pd(out.add(i * 4 + 2), right);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use std::arch::aarch64::*;
+    use crate::tuple::Tuple;
+
+    /// Loads the x and y, and the z and w components of a tuple.
+    #[inline(always)]
+    unsafe fn load(t: &Tuple) -> (float64x2_t, float64x2_t) {
+        // SAFETY: `Tuple` is `repr(C)` with four `f64` fields, so the pointer to the whole tuple covers the
+        // two pairs of values that are read.
+        let p = t as *const Tuple as *const f64;
+        (vld1q_f64(p), vld1q_f64(p.add(2)))
+    }
+
+    #[inline(always)]
+    unsafe fn store(xy: float64x2_t, zw: float64x2_t) -> Tuple {
+        let mut t = Tuple::new(0.0, 0.0, 0.0, 0.0);
+        // SAFETY: as in `load`, the pointer to the whole tuple covers the two pairs of values that are written.
+        let p = &mut t as *mut Tuple as *mut f64;
+        vst1q_f64(p, xy);
+        vst1q_f64(p.add(2), zw);
+        t
+    }
+
+    /// Multiplies a row of a matrix by a tuple, leaving the two halves of the dot product in the lanes.
+    ///
+    /// `row` must point to four readable values.
+    #[inline(always)]
+    unsafe fn row_products(row: *const f64, t: (float64x2_t, float64x2_t)) -> float64x2_t {
+        vfmaq_f64(vmulq_f64(vld1q_f64(row), t.0), vld1q_f64(row.add(2)), t.1)
+    }
+
+    pub fn dot(a: &Tuple, b: &Tuple) -> f64 {
+        // SAFETY: NEON is part of aarch64, and `load` reads only inside the tuples.
+        unsafe {
+            let ((a_xy, a_zw), (b_xy, b_zw)) = (load(a), load(b));
+            vaddvq_f64(vfmaq_f64(vmulq_f64(a_xy, b_xy), a_zw, b_zw))
+        }
+    }
+
+    pub fn cross(a: &Tuple, b: &Tuple) -> Tuple {
+        // SAFETY: NEON is part of aarch64, and `load` reads only inside the tuples.
+        unsafe {
+            let ((a_xy, a_zw), (b_xy, b_zw)) = (load(a), load(b));
+            // x and y of the product: (y, z) * (z, x) - (z, x) * (y, z)
+            let a_yz = vextq_f64::<1>(a_xy, a_zw);
+            let a_zx = vzip1q_f64(a_zw, a_xy);
+            let b_yz = vextq_f64::<1>(b_xy, b_zw);
+            let b_zx = vzip1q_f64(b_zw, b_xy);
+            let xy = vfmsq_f64(vmulq_f64(a_yz, b_zx), a_zx, b_yz);
+            // z of the product: x * by - y * bx
+            let p = vmulq_f64(a_xy, vextq_f64::<1>(b_xy, b_xy));
+            let z = vgetq_lane_f64::<0>(p) - vgetq_lane_f64::<1>(p);
+            store(xy, vsetq_lane_f64::<0>(z, vdupq_n_f64(0.0)))
+        }
+    }
+
+    pub fn multiply_tuple(m: &[f64; 16], t: &Tuple) -> Tuple {
+        // SAFETY: every row starts at most 12 elements into the 16 of the matrix, so the four values read
+        // for it are inside the array.
+        unsafe {
+            let t = load(t);
+            let m = m.as_ptr();
+            let xy = vpaddq_f64(row_products(m, t), row_products(m.add(4), t));
+            let zw = vpaddq_f64(row_products(m.add(8), t), row_products(m.add(12), t));
+            store(xy, zw)
+        }
+    }
+
+    pub fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+        let mut result = [0.0; 16];
+        // SAFETY: every pair of values read or written starts at most 14 elements into the 16 of its matrix.
+        unsafe {
+            let (b, out) = (b.as_ptr(), result.as_mut_ptr());
+            for i in 0..4 {
+                let mut left = vdupq_n_f64(0.0);
+                let mut right = vdupq_n_f64(0.0);
+                for k in 0..4 {
+                    let factor = vdupq_n_f64(a[i * 4 + k]);
+                    left = vfmaq_f64(left, factor, vld1q_f64(b.add(k * 4)));
+                    right = vfmaq_f64(right, factor, vld1q_f64(b.add(k * 4 + 2)));
+                }
+                vst1q_f64(out.add(i * 4), left);
+                vst1q_f64(out.add(i * 4 + 2), right);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod arch {
+    use crate::tuple::Tuple;
+
+    pub fn dot(a: &Tuple, b: &Tuple) -> f64 {
+        a.x * b.x + a.y * b.y + a.z * b.z + a.w * b.w
+    }
+
+    pub fn cross(a: &Tuple, b: &Tuple) -> Tuple {
+        Tuple::vector(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+    }
+
+    pub fn multiply_tuple(m: &[f64; 16], t: &Tuple) -> Tuple {
+        let row = |i: usize| m[i * 4] * t.x + m[i * 4 + 1] * t.y + m[i * 4 + 2] * t.z + m[i * 4 + 3] * t.w;
+        Tuple::new(row(0), row(1), row(2), row(3))
+    }
+
+    pub fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+        let mut result = [0.0; 16];
+        for i in 0..4 {
+            for j in 0..4 {
+                result[i * 4 + j] = (0..4).map(|k| a[i * 4 + k] * b[k * 4 + j]).sum();
+            }
+        }
+        result
+    }
+}
+
+/// Calculates the dot product of two tuples, including `w`.
+#[inline]
+pub fn dot(a: &Tuple, b: &Tuple) -> f64 {
+    arch::dot(a, b)
+}
+
+/// Calculates the cross product of two vectors. The `w` of the result is 0.
+#[inline]
+pub fn cross(a: &Tuple, b: &Tuple) -> Tuple {
+    arch::cross(a, b)
+}
+
+/// Multiplies a 4x4 matrix by a tuple.
+///
+/// # Arguments
+///
+/// * `m` - The 16 elements of the matrix in row-major order.
+/// * `t` - The tuple to transform.
+#[inline]
+pub fn multiply_tuple(m: &[f64; 16], t: &Tuple) -> Tuple {
+    arch::multiply_tuple(m, t)
+}
+
+/// Multiplies two 4x4 matrices.
+///
+/// # Arguments
+///
+/// * `a` - The 16 elements of the left matrix in row-major order.
+/// * `b` - The 16 elements of the right matrix in row-major order.
+///
+/// # Returns
+///
+/// The 16 elements of the product in row-major order.
+#[inline]
+pub fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+    arch::multiply(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cross, dot, multiply, multiply_tuple};
+    use crate::tuple::Tuple;
+
+    const M: [f64; 16] = [1.0, 2.0, 3.0, 4.0, 5.5, 6.5, 7.5, 8.5, -9.0, 10.0, -11.0, 12.0, 0.25, -0.5, 0.75, 1.0];
+    const N: [f64; 16] = [-2.0, 1.0, 2.0, 3.0, 3.0, 2.0, 1.0, -1.0, 4.0, 3.0, 6.0, 5.0, 1.0, 2.0, 7.0, 8.0];
+
+    #[test]
+    fn tuple_products_match_the_scalar_formulas() {
+        let a = Tuple::new(1.0, -2.0, 3.5, 0.5);
+        let b = Tuple::new(-4.0, 0.25, 6.0, 2.0);
+        assert_eq!(dot(&a, &b), 1.0 * -4.0 + -2.0 * 0.25 + 3.5 * 6.0 + 0.5 * 2.0);
+        let c = cross(&a, &b);
+        assert_eq!((c.x, c.y, c.z, c.w), (-2.0 * 6.0 - 3.5 * 0.25, 3.5 * -4.0 - 1.0 * 6.0, 1.0 * 0.25 - -2.0 * -4.0, 0.0));
+    }
+
+    #[test]
+    fn matrix_products_match_the_scalar_formulas() {
+        let t = Tuple::new(1.0, 2.0, 3.0, 1.0);
+        let r = multiply_tuple(&M, &t);
+        let row = |i: usize| M[i * 4] + M[i * 4 + 1] * 2.0 + M[i * 4 + 2] * 3.0 + M[i * 4 + 3];
+        assert_eq!((r.x, r.y, r.z, r.w), (row(0), row(1), row(2), row(3)));
+
+        let p = multiply(&M, &N);
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected: f64 = (0..4).map(|k| M[i * 4 + k] * N[k * 4 + j]).sum();
+                assert!((p[i * 4 + j] - expected).abs() < 1e-12);
+            }
+        }
+    }
+}
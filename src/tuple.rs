@@ -4,10 +4,14 @@
 
 use std::ops::{Sub, Add, Mul, Div};
 use crate::EPSILON;
+#[cfg(feature = "simd")]
+use crate::simd;
 
 /// Represents a tuple in 3D space, which can be a point or a vector based on `w`.
 /// A `w` of 1.0 indicates a point, and 0.0 indicates a vector.
+/// The components are laid out in order so that they can be loaded into SIMD registers directly.
 #[derive(Debug, Clone, Copy)]
+#[repr(C)]
 pub struct Tuple {
     pub x: f64,
     pub y: f64,
@@ -98,11 +102,19 @@ impl Tuple {
     }
 
     /// Calculates the dot product of two vectors.
+    #[cfg(not(feature = "simd"))]
     pub fn dot(&self, other: &Tuple) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
     }
 
+    /// Calculates the dot product of two vectors, with SIMD instructions.
+    #[cfg(feature = "simd")]
+    pub fn dot(&self, other: &Tuple) -> f64 {
+        simd::dot(self, other)
+    }
+
     /// Calculates the cross product of two vectors.
+    #[cfg(not(feature = "simd"))]
     pub fn cross(&self, other: &Tuple) -> Tuple {
         Tuple::vector(
             self.y * other.z - self.z * other.y,
@@ -111,6 +123,12 @@ impl Tuple {
         )
     }
 
+    /// Calculates the cross product of two vectors, with SIMD instructions.
+    #[cfg(feature = "simd")]
+    pub fn cross(&self, other: &Tuple) -> Tuple {
+        simd::cross(self, other)
+    }
+
     /// Reflects a vector off a surface, given the surface's normal vector.
     pub fn reflect(&self, normal: &Tuple) -> Tuple {
         self.subtract(&normal.multiply(2.0 * self.dot(normal)))
@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rray::raytracer::progress::{CancelToken, ProgressMode, TileProgress};
+use rray::raytracer::scene_builder_yaml::{build_scene, load_scene_doc};
+
+const SCENE: &str = "
+camera: {fov: 60, from: [0, 1.5, -5], to: [0, 1, 0], up: [0, 1, 0]}
+lights:
+  - {type: point, color: [1, 1, 1], position: [-10, 10, -10]}
+scene:
+  - type: sphere
+";
+
+#[test]
+fn embedding_programs_can_follow_and_cancel_renders() {
+    let doc = load_scene_doc(SCENE, Path::new("."));
+    let (mut camera, scene) = build_scene(&doc, None, 40, 20);
+    camera.progress = ProgressMode::Quiet;
+    camera.threads = 1;
+
+    let tiles = AtomicUsize::new(0);
+    camera.render_with(&scene, Some(&|tile: &TileProgress| {
+        tiles.fetch_add(1, Ordering::Relaxed);
+        assert!(tile.percent() > 0.0);
+    }), None);
+    assert_eq!(tiles.into_inner(), 6);
+
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let tiles = AtomicUsize::new(0);
+    camera.render_with(&scene, Some(&|_: &TileProgress| {
+        tiles.fetch_add(1, Ordering::Relaxed);
+    }), Some(&cancel));
+    assert_eq!(tiles.into_inner(), 0);
+}
+
+#[test]
+fn embedding_programs_can_render_to_memory() {
+    let doc = load_scene_doc(SCENE, Path::new("."));
+    let (mut camera, scene) = build_scene(&doc, None, 40, 20);
+    camera.progress = ProgressMode::Quiet;
+    let image = camera.render_to_image(&scene);
+    assert_eq!(image.dimensions(), (40, 20));
+    // The sphere is in the middle of the image, against the black background
+    assert_ne!(image.get_pixel(20, 10).0, [0, 0, 0, 255]);
+    assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+}